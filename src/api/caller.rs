@@ -0,0 +1,216 @@
+//! Who is making a request.
+//!
+//! Every request carries `Authorization: Bearer <token>`. The operator's token
+//! is `[server] operator_token` (or `GPU_SHARE_OPERATOR_TOKEN`); without one
+//! configured nobody is the operator. Tenants send a token signed with
+//! `[server] tenant_token_secret` (or `GPU_SHARE_TENANT_SECRET`) whose subject
+//! is their name, which `gpu-share token <user>` issues. A request without a
+//! token, or with one that checks out as neither, is a 401. Handlers that
+//! touch tenant containers check the container's owner label against the
+//! caller.
+
+use std::sync::Arc;
+
+use axum::extract::{FromRef, FromRequestParts};
+use axum::http::header::AUTHORIZATION;
+use axum::http::request::Parts;
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+
+use super::routes::{AppState, ErrorNumber, ErrorResponse};
+use crate::config::settings::Settings;
+
+pub const TOKEN_ENV: &str = "GPU_SHARE_OPERATOR_TOKEN";
+pub const TENANT_SECRET_ENV: &str = "GPU_SHARE_TENANT_SECRET";
+const TENANT_ROLE: &str = "tenant";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Caller {
+    Operator,
+    Tenant(String),
+}
+
+impl Caller {
+    /// Name for logs and audit records
+    pub fn name(&self) -> &str {
+        match self {
+            Caller::Operator => "operator",
+            Caller::Tenant(user) => user,
+        }
+    }
+
+    /// Operators reach every container, tenants only ones labelled as theirs
+    pub fn may_access(&self, owner: Option<&str>) -> bool {
+        match self {
+            Caller::Operator => true,
+            Caller::Tenant(user) => owner == Some(user.as_str()),
+        }
+    }
+
+    /// For admin endpoints
+    pub fn require_operator(&self) -> Result<(), ErrorResponse> {
+        match self {
+            Caller::Operator => Ok(()),
+            Caller::Tenant(_) => Err(ErrorResponse::new(ErrorNumber::Forbidden, "Operator only")),
+        }
+    }
+}
+
+/// What a tenant token says
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    role: String,
+    exp: u64,
+}
+
+/// The secrets bearer tokens are checked against
+#[derive(Debug, Clone, Default)]
+pub struct Credentials {
+    pub operator_token: Option<String>,
+    pub tenant_secret: Option<String>,
+}
+
+impl Credentials {
+    /// The environment wins over `[server]` in the config files
+    pub fn load() -> Self {
+        let server = Settings::new().ok().map(|s| s.server);
+        let from = |env: &str, config: Option<String>| {
+            std::env::var(env).ok().or(config).filter(|v| !v.is_empty())
+        };
+        Self {
+            operator_token: from(TOKEN_ENV, server.as_ref().and_then(|s| s.operator_token.clone())),
+            tenant_secret: from(TENANT_SECRET_ENV, server.and_then(|s| s.tenant_token_secret)),
+        }
+    }
+
+    /// Compares every byte so the time taken doesn't leak how much matched
+    fn is_operator(&self, presented: &str) -> bool {
+        let Some(expected) = &self.operator_token else { return false };
+        expected.len() == presented.len()
+            && expected.bytes().zip(presented.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+    }
+
+    /// The tenant a token was issued to, if it is signed with our secret and
+    /// hasn't expired
+    fn tenant(&self, presented: &str) -> Option<String> {
+        let secret = self.tenant_secret.as_ref()?;
+        let claims = decode::<Claims>(
+            presented,
+            &DecodingKey::from_secret(secret.as_bytes()),
+            &Validation::new(Algorithm::HS256),
+        )
+        .ok()?
+        .claims;
+        (claims.role == TENANT_ROLE && !claims.sub.trim().is_empty()).then_some(claims.sub)
+    }
+
+    /// Token that makes its bearer `user` for `ttl`
+    pub fn issue_tenant_token(&self, user: &str, ttl: std::time::Duration) -> anyhow::Result<String> {
+        let secret = self.tenant_secret.as_ref().ok_or_else(|| {
+            anyhow::anyhow!("No tenant token secret ([server] tenant_token_secret or {})", TENANT_SECRET_ENV)
+        })?;
+        if user.trim().is_empty() {
+            anyhow::bail!("Tenant name is empty");
+        }
+        let claims = Claims {
+            sub: user.to_string(),
+            role: TENANT_ROLE.to_string(),
+            exp: jsonwebtoken::get_current_timestamp() + ttl.as_secs(),
+        };
+        Ok(encode(&Header::new(Algorithm::HS256), &claims, &EncodingKey::from_secret(secret.as_bytes()))?)
+    }
+}
+
+impl FromRef<Arc<AppState>> for Credentials {
+    fn from_ref(state: &Arc<AppState>) -> Self {
+        state.credentials.clone()
+    }
+}
+
+impl<S> FromRequestParts<S> for Caller
+where
+    Credentials: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = ErrorResponse;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Some(value) = parts.headers.get(AUTHORIZATION) else {
+            return Err(ErrorResponse::new(
+                ErrorNumber::Unauthorized,
+                "Send a tenant token, or the operator token, as a bearer token",
+            ));
+        };
+        let presented = value.to_str().ok().and_then(|v| v.strip_prefix("Bearer ")).map(str::trim);
+        let credentials = Credentials::from_ref(state);
+        match presented {
+            Some(token) if credentials.is_operator(token) => Ok(Caller::Operator),
+            Some(token) => credentials
+                .tenant(token)
+                .map(Caller::Tenant)
+                .ok_or_else(|| ErrorResponse::new(ErrorNumber::Unauthorized, "Invalid or expired token")),
+            None => Err(ErrorResponse::new(ErrorNumber::Unauthorized, "Invalid or expired token")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+    use std::time::Duration;
+
+    fn credentials(operator: Option<&str>) -> Credentials {
+        Credentials {
+            operator_token: operator.map(String::from),
+            tenant_secret: Some("tenant-secret".into()),
+        }
+    }
+
+    async fn caller(credentials: &Credentials, authorization: Option<&str>) -> Result<Caller, ErrorResponse> {
+        let mut request = Request::builder();
+        if let Some(value) = authorization {
+            request = request.header("authorization", value);
+        }
+        let (mut parts, _) = request.body(()).unwrap().into_parts();
+        Caller::from_request_parts(&mut parts, credentials).await
+    }
+
+    #[tokio::test]
+    async fn test_tenant_from_token() {
+        let creds = credentials(None);
+        let token = creds.issue_tenant_token("alice", Duration::from_secs(60)).unwrap();
+        let alice = caller(&creds, Some(&format!("Bearer {}", token))).await.unwrap();
+        assert_eq!(alice, Caller::Tenant("alice".into()));
+        assert!(alice.may_access(Some("alice")));
+        assert!(!alice.may_access(Some("bob")));
+        assert!(!alice.may_access(None));
+        assert!(alice.require_operator().is_err());
+        assert!(Caller::Operator.may_access(None));
+
+        // Signed with someone else's secret
+        let forged = Credentials { tenant_secret: Some("guess".into()), ..creds.clone() }
+            .issue_tenant_token("bob", Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(caller(&creds, Some(&format!("Bearer {}", forged))).await.unwrap_err().code, 401);
+
+        // Naming yourself used to be enough
+        let mut request = Request::builder().header("x-user", "bob").body(()).unwrap().into_parts().0;
+        assert_eq!(Caller::from_request_parts(&mut request, &creds).await.unwrap_err().code, 401);
+
+        assert!(Credentials::default().issue_tenant_token("alice", Duration::from_secs(60)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_operator_needs_the_token() {
+        let bearer = Some("Bearer s3cret");
+        assert_eq!(caller(&credentials(Some("s3cret")), bearer).await.unwrap(), Caller::Operator);
+        assert_eq!(caller(&credentials(Some("other")), bearer).await.unwrap_err().code, 401);
+        assert_eq!(caller(&credentials(None), bearer).await.unwrap_err().code, 401);
+        assert_eq!(caller(&credentials(Some("s3cret")), Some("s3cret")).await.unwrap_err().code, 401);
+
+        // No header at all used to mean the operator
+        assert_eq!(caller(&credentials(Some("s3cret")), None).await.unwrap_err().code, 401);
+    }
+}
@@ -112,11 +112,6 @@ impl fmt::Display for RateLimitExceeded {
         write!(f, "Rate limit exceeded")
     }
 }
-#[derive(Clone)]
-pub struct RateLimit<T> {
-    inner: T,
-}
-
 // wrapper for RateLimitLayer
 #[derive(Clone)]
 pub struct CustomRateLimitLayer {
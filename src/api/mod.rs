@@ -1,3 +1,4 @@
+pub mod caller;
 pub mod middleware;
 pub mod routes;
 
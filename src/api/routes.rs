@@ -8,20 +8,42 @@
  */
 
 use axum::{
-    extract::{Path, State},
+    extract::{
+        ws::{rejection::WebSocketUpgradeRejection, Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
     http::StatusCode,
-    response::IntoResponse,
+    response::sse::{Event, KeepAlive, Sse},
+    response::{IntoResponse, Response},
     Json,
     Router,
 };
+use futures_util::{future, stream, stream::BoxStream, Stream, StreamExt};
 use serde_json::json;
 use serde::{Deserialize, Serialize};
-use tracing::info;
+use tracing::{info, warn};
+use std::convert::Infallible;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
 use tokio::sync::{oneshot, Mutex};
 
 // Proje içi bağımlılıklar
-use crate::core::docker_manager::DockerManager;
+use crate::core::docker_manager::{ContainerConfig, DockerManager, ManagedContainer};
+use crate::api::caller::{Caller, Credentials};
+use crate::core::exec::{ClientMessage, ExecEnd, ExecRequest, ExecSession, ServerMessage};
+use crate::core::logs::{LogLine, LogOptions};
+use crate::core::projects::ProjectConfig;
+use crate::core::readiness::{check_docker, ReadinessChecker};
+use crate::core::reconcile::{self, Discrepancy};
+use crate::core::networks::{self, PortConflict};
+use crate::core::recreate::RecreateOutcome;
+use crate::core::volumes::{self, CleanupReport, ManagedVolume, VolumeError, VolumeRequest};
+use crate::core::snapshots::{Snapshot, SnapshotNotFound, SnapshotRequest, StorageQuotaExceeded, StorageUsage};
+use crate::gpu::container::GpuAttachment;
+use crate::events::{EventBus, EventKind};
+use crate::gpu::drain::{self, DrainProgress, DrainTarget};
+use crate::gpu::device::GPUConfig;
 use crate::gpu::GPUManager;
 use crate::monitoring::MetricsCollector;
 use crate::gpu::virtual_gpu::GPUPool;
@@ -38,6 +60,10 @@ pub struct AppState {
     pub gpupool: Arc<Mutex<GPUPool>>,
     pub user_manager: Arc<Mutex<UserManager>>,
     pub billing_system: Arc<Mutex<BillingSystem>>,
+    pub events: Arc<EventBus>,
+    pub readiness: Arc<ReadinessChecker>,
+    pub projects: Arc<ProjectConfig>,
+    pub credentials: Credentials,
 }
 
 /// Creates an Axum router with all endpoints.
@@ -46,6 +72,36 @@ pub fn create_router(state: Arc<AppState>) -> Router {
         .route("/", axum::routing::get(root_handler))
         .route("/health", axum::routing::get(health_check))
         .route("/shutdown", axum::routing::post(shutdown_handler))
+        .route("/api/v1/vms", axum::routing::get(list_containers).post(create_vm))
+        .route("/api/v1/vms/{id}/logs", axum::routing::get(container_logs))
+        .route("/api/v1/vms/{id}/exec", axum::routing::get(container_exec))
+        .route("/api/v1/vms/{id}/snapshots", axum::routing::post(create_snapshot))
+        .route("/api/v1/snapshots", axum::routing::get(list_snapshots))
+        .route("/api/v1/snapshots/{id}", axum::routing::delete(delete_snapshot))
+        .route("/api/v1/snapshots/{id}/restore", axum::routing::post(restore_snapshot))
+        .route("/api/v1/volumes", axum::routing::get(list_volumes).post(create_volume))
+        .route("/api/v1/volumes/{name}", axum::routing::delete(delete_volume))
+        .route("/api/v1/images/pull", axum::routing::post(pull_image))
+        .route("/api/v1/system/readiness", axum::routing::get(readiness_handler))
+        .route("/api/v1/admin/gpus", axum::routing::get(admin_list_gpus))
+        .route("/api/v1/admin/reconcile", axum::routing::get(admin_reconcile_report))
+        .route("/api/v1/admin/projects/{project}", axum::routing::delete(admin_delete_project))
+        .route(
+            "/api/v1/admin/gpus/{id}/drain",
+            axum::routing::get(admin_gpu_drain_status).post(admin_drain_gpu),
+        )
+        .route("/api/v1/admin/gpus/{id}/undrain", axum::routing::post(admin_undrain_gpu))
+        .route(
+            "/api/v1/admin/host/drain",
+            axum::routing::get(admin_host_drain_status).post(admin_drain_host),
+        )
+        .route("/api/v1/admin/host/undrain", axum::routing::post(admin_undrain_host))
+        .route("/api/v1/users/{user}/notifications", axum::routing::get(user_notifications))
+        .route("/api/v1/events", axum::routing::get(event_stream))
+        .route(
+            "/api/v1/containers/{id}/gpu",
+            axum::routing::post(attach_gpu).delete(detach_gpu),
+        )
         .with_state(state)
 }
 
@@ -56,6 +112,13 @@ pub enum ErrorNumber {
     OperationFailed,
     InternalError,
     GPUTransferError,
+    GpuNotFound,
+    ImageRejected,
+    StorageFull,
+    PortConflict,
+    VolumeConflict,
+    Forbidden,
+    Unauthorized,
 }
 
 /// Özelleştirilmiş hata yanıtı
@@ -72,6 +135,13 @@ impl ErrorResponse {
             ErrorNumber::OperationFailed => 400,
             ErrorNumber::InternalError => 500,
             ErrorNumber::GPUTransferError => 409,
+            ErrorNumber::GpuNotFound => 404,
+            ErrorNumber::ImageRejected => 403,
+            ErrorNumber::StorageFull => 507,
+            ErrorNumber::PortConflict => 409,
+            ErrorNumber::VolumeConflict => 409,
+            ErrorNumber::Forbidden => 403,
+            ErrorNumber::Unauthorized => 401,
         };
         Self {
             error: message.to_string(),
@@ -96,12 +166,27 @@ fn handle_error(e: impl Into<anyhow::Error>) -> ErrorResponse {
     ErrorResponse::new(ErrorNumber::InternalError, format!("Docker hatası: {}", err))
 }
 
+/// Creation errors: taken or out-of-range host ports are 409s
+fn create_error(e: anyhow::Error) -> ErrorResponse {
+    if e.downcast_ref::<PortConflict>().is_some() {
+        ErrorResponse::new(ErrorNumber::PortConflict, e)
+    } else {
+        handle_error(e)
+    }
+}
+
 /// VM Oluşturma İsteği
 #[derive(Debug, Deserialize)]
 pub struct CreateVMRequest {
-    pub name: String,
-    pub image: String,
+    /// Image, name and the rest of the creation spec (command, env, volumes,
+    /// ports, resources, restart policy, labels, network)
+    #[serde(flatten)]
+    pub spec: ContainerConfig,
+    #[serde(default)]
     pub gpu_required: bool,
+    /// GPU to expose inside the container
+    #[serde(default)]
+    pub gpu_id: Option<String>,
 }
 
 /// VM Detay Yanıtı
@@ -109,90 +194,768 @@ pub struct CreateVMRequest {
 pub struct VMResponse {
     pub id: String,
     pub name: String,
+    pub image: String,
+    /// Docker's state (running, exited, ...)
     pub status: String,
     pub gpu_attached: bool,
+    pub gpu_id: Option<String>,
+    pub owner: Option<String>,
+    pub lease_id: Option<String>,
+    pub exit_code: Option<i64>,
+    pub oom_killed: bool,
+    pub restart_count: i64,
+}
+
+impl From<ManagedContainer> for VMResponse {
+    fn from(container: ManagedContainer) -> Self {
+        Self {
+            id: container.id,
+            name: container.name,
+            image: container.image,
+            status: container.state,
+            gpu_attached: container.gpu_attached,
+            gpu_id: container.gpu_id,
+            owner: container.owner,
+            lease_id: container.lease_id,
+            exit_code: container.exit_code,
+            oom_killed: container.oom_killed,
+            restart_count: container.restart_count,
+        }
+    }
+}
+
+/// Tenants always own what they create; a lease id must be a pool entry
+/// leased to the owner
+fn stamp_ownership(config: &mut ContainerConfig, caller: &Caller, pool: &GPUPool) -> Result<(), ErrorResponse> {
+    if let Caller::Tenant(user) = caller {
+        if config.labels.owner.as_ref().is_some_and(|owner| owner != user) {
+            return Err(ErrorResponse::new(ErrorNumber::OperationFailed, "Başka bir kullanıcı adına container oluşturulamaz"));
+        }
+        config.labels.owner = Some(user.clone());
+    }
+    if let Some(lease_id) = &config.labels.lease_id {
+        let leased_to = lease_id
+            .parse::<u32>()
+            .ok()
+            .and_then(|id| pool.gpus.get(&id))
+            .and_then(|gpu| gpu.allocated_to.as_ref());
+        if leased_to.is_none() || leased_to != config.labels.owner.as_ref() {
+            return Err(ErrorResponse::new(
+                ErrorNumber::OperationFailed,
+                format!("Lease {} bu kullanıcıya ait değil", lease_id),
+            ));
+        }
+    }
+    Ok(())
 }
 
 /// VM Oluşturma Handler
 #[axum::debug_handler]
 pub async fn create_vm(
     State(state): State<Arc<AppState>>,
+    caller: Caller,
     Json(params): Json<CreateVMRequest>,
 ) -> Result<impl IntoResponse, ErrorResponse> {
-    info!("🛠️ Yeni container oluşturuluyor: {}", params.name);
-    
-    let docker = state.docker.lock().await;
-    docker.create_container(&params.image, &params.name)
+    info!("🛠️ Yeni container oluşturuluyor: {}", params.spec.name);
+    let mut config = params.spec;
+    config.gpu_id = params.gpu_id.as_deref().map(GPUConfig::from);
+    config.validate()
+        .map_err(|e| ErrorResponse::new(ErrorNumber::OperationFailed, e))?;
+    stamp_ownership(&mut config, &caller, &*state.gpupool.lock().await)?;
+    authorize_placement(&state, &caller, &config)?;
+
+    let attachment = gpu_attachment(&state, &caller, config.labels.lease_id.as_deref(), params.gpu_id.as_deref()).await?;
+
+    let docker = state.docker.lock().await.clone();
+    docker.image_config().check(&config.image)
+        .map_err(|e| ErrorResponse::new(ErrorNumber::ImageRejected, e))?;
+    docker.network_config().check(&config)
+        .map_err(|e| ErrorResponse::new(ErrorNumber::OperationFailed, e))?;
+    authorize_volumes(&docker, &caller, &config).await?;
+    let container_id = docker.create_container_with(&config, attachment.as_ref())
         .await
-        .map_err(handle_error)?;
+        .map_err(create_error)?;
 
     Ok(Json(json!({
         "status": "success",
-        "message": format!("{} adlı container oluşturuldu", params.name)
+        "id": container_id,
+        "message": format!("{} adlı container oluşturuldu", config.name)
     })))
 }
 
-/// Container Listeleme Handler
+/// Tenants only get a GPU their lease (`labels.lease_id`) is on; operators
+/// may hand out any card
+async fn gpu_attachment(
+    state: &AppState,
+    caller: &Caller,
+    lease_id: Option<&str>,
+    gpu_id: Option<&str>,
+) -> Result<Option<GpuAttachment>, ErrorResponse> {
+    let Some(gpu_id) = gpu_id else { return Ok(None) };
+    let gpu_manager = state.gpu_manager.lock().await;
+    if let Caller::Tenant(user) = caller {
+        let gpu = gpu_manager.devices
+            .iter()
+            .find(|g| g.id == gpu_id)
+            .ok_or_else(|| ErrorResponse::new(ErrorNumber::GpuNotFound, format!("GPU bulunamadı: {}", gpu_id)))?;
+        let lease_id = lease_id.ok_or_else(|| ErrorResponse::new(
+            ErrorNumber::OperationFailed,
+            format!("GPU {} için lease gerekli (labels.lease_id)", gpu_id),
+        ))?;
+        state.gpupool.lock().await
+            .verify_lease(lease_id, user, gpu)
+            .map_err(|e| ErrorResponse::new(ErrorNumber::OperationFailed, e.to_string()))?;
+    }
+    gpu_manager
+        .container_attachment(gpu_id)
+        .map(Some)
+        .map_err(|e| ErrorResponse::new(ErrorNumber::GPUTransferError, e.to_string()))
+}
+
+/// Container Listeleme Handler - only containers we created, tenants only see their own
 #[axum::debug_handler]
 pub async fn list_containers(
     State(state): State<Arc<AppState>>,
+    caller: Caller,
 ) -> Result<impl IntoResponse, ErrorResponse> {
     let docker = state.docker.lock().await;
-    let containers = docker.list_containers()
+    let containers = docker.list_managed()
         .await
         .map_err(handle_error)?;
 
-    let mut responses = Vec::new();
-    for container in containers {
-        responses.push(VMResponse {
-            id: container.clone(),
-            name: container,
-            status: "running".to_string(),
-            gpu_attached: false,
-        });
-    }
-    
+    let responses: Vec<VMResponse> = containers
+        .into_iter()
+        .filter(|c| caller.may_access(c.owner.as_deref()))
+        .map(VMResponse::from)
+        .collect();
     Ok(Json(responses))
 }
 
-/// GPU Ekleme İsteği
+/// Uzlaştırma Raporu - what doesn't line up between Docker and the GPU pool
+/// right now. Read-only; the background reconciler does the fixing.
+#[axum::debug_handler]
+pub async fn admin_reconcile_report(
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Json<Vec<Discrepancy>>, ErrorResponse> {
+    caller.require_operator()?;
+    let docker = state.docker.lock().await.clone();
+    let containers = docker.list_managed().await.map_err(handle_error)?;
+    let pool = state.gpupool.lock().await;
+    Ok(Json(reconcile::find_discrepancies(&containers, &pool)))
+}
+
+/// GPU Ekleme İsteği - `dry_run` only reports what the recreate would change
 #[derive(Debug, Deserialize)]
 pub struct AttachGPURequest {
     pub gpu_id: String,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// GPU Çıkarma Sorgusu
+#[derive(Debug, Default, Deserialize)]
+pub struct DetachGPUQuery {
+    #[serde(default)]
+    pub dry_run: bool,
 }
 
-/// GPU Ekleme Handler
+/// GPU Ekleme Handler - the container is recreated with the GPU; the response
+/// carries the new container id. The container's owner has to hold the lease
+/// in its `gpu-share.lease-id` label, on that GPU.
 #[axum::debug_handler]
 pub async fn attach_gpu(
     State(state): State<Arc<AppState>>,
     Path(container_id): Path<String>,
+    caller: Caller,
     Json(request): Json<AttachGPURequest>,
-) -> Result<impl IntoResponse, ErrorResponse> {
+) -> Result<Json<RecreateOutcome>, ErrorResponse> {
     info!("🎮 GPU ekleniyor: {} -> {}", request.gpu_id, container_id);
-    
-    let mut gpu_manager = state.gpu_manager.lock().await;
-    let docker = state.docker.lock().await;
-    
-    // Container'ı kontrol et
-    let _ = docker.lookup_container(&container_id)
+
+    let docker = state.docker.lock().await.clone();
+    authorize_container(&docker, &caller, &container_id).await?;
+    let container = docker.managed_container(&container_id)
         .await
-        .map_err(|e| ErrorResponse::new(
-            ErrorNumber::ContainerNotFound,
-            format!("Container hatası: {}", e)
-        ))?;
+        .map_err(|e| container_error(&container_id, e))?
+        .ok_or_else(|| ErrorResponse::new(ErrorNumber::ContainerNotFound, format!("Container bulunamadı: {}", container_id)))?;
+
+    let mut gpu_manager = state.gpu_manager.lock().await;
+    let gpu = gpu_manager.devices
+        .iter()
+        .find(|g| g.id == request.gpu_id)
+        .ok_or_else(|| ErrorResponse::new(ErrorNumber::GpuNotFound, format!("GPU bulunamadı: {}", request.gpu_id)))?;
+    let (Some(owner), Some(lease_id)) = (&container.owner, &container.lease_id) else {
+        return Err(ErrorResponse::new(
+            ErrorNumber::OperationFailed,
+            format!("Container {} has no owner lease to put GPU {} on", container_id, request.gpu_id),
+        ));
+    };
+    state.gpupool.lock().await
+        .verify_lease(lease_id, owner, gpu)
+        .map_err(|e| ErrorResponse::new(ErrorNumber::OperationFailed, e.to_string()))?;
 
-    // GPU'yu ekle
-    gpu_manager.attach_gpu(&container_id, &request.gpu_id)
+    let attachment = gpu_manager
+        .attach_gpu(&container_id, &request.gpu_id)
         .await
         .map_err(|e| ErrorResponse::new(
             ErrorNumber::GPUTransferError,
             format!("GPU ekleme hatası: {}", e)
         ))?;
+    drop(gpu_manager);
+
+    docker.attach_gpu(&container_id, &attachment, request.dry_run)
+        .await
+        .map(Json)
+        .map_err(|e| container_error(&container_id, e))
+}
+
+/// GPU Çıkarma Handler
+#[axum::debug_handler]
+pub async fn detach_gpu(
+    State(state): State<Arc<AppState>>,
+    Path(container_id): Path<String>,
+    caller: Caller,
+    Query(query): Query<DetachGPUQuery>,
+) -> Result<Json<RecreateOutcome>, ErrorResponse> {
+    info!("🎮 GPU çıkarılıyor: {}", container_id);
+
+    let docker = state.docker.lock().await.clone();
+    authorize_container(&docker, &caller, &container_id).await?;
+    docker.detach_gpu(&container_id, query.dry_run)
+        .await
+        .map(Json)
+        .map_err(|e| container_error(&container_id, e))
+}
+
+/// Unknown containers are 404s, anything else went wrong mid-recreate
+fn container_error(container_id: &str, e: anyhow::Error) -> ErrorResponse {
+    let not_found = e
+        .downcast_ref::<bollard::errors::Error>()
+        .is_some_and(|e| matches!(e, bollard::errors::Error::DockerResponseServerError { status_code: 404, .. }));
+    if not_found {
+        ErrorResponse::new(ErrorNumber::ContainerNotFound, format!("Container bulunamadı: {}", container_id))
+    } else {
+        ErrorResponse::new(ErrorNumber::GPUTransferError, format!("Container yeniden oluşturulamadı: {}", e))
+    }
+}
+
+/// İmaj Çekme İsteği - `project` picks the registry credentials
+#[derive(Debug, Deserialize)]
+pub struct PullImageRequest {
+    pub image: String,
+    #[serde(default)]
+    pub project: Option<String>,
+}
+
+/// İmaj Çekme Handler - streams `progress` events, then `done` (or `error`).
+/// Tenants may only pull with the credentials of their own projects.
+#[axum::debug_handler]
+pub async fn pull_image(
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+    Json(request): Json<PullImageRequest>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, ErrorResponse> {
+    info!("📥 İmaj çekiliyor: {}", request.image);
+    if let Some(project) = &request.project {
+        authorize_project(&state, &caller, project)?;
+    }
+
+    let progress = state.docker.lock().await
+        .pull_image_stream(&request.image, request.project.as_deref())
+        .map_err(|e| ErrorResponse::new(ErrorNumber::ImageRejected, e))?;
+
+    let image = request.image;
+    let events = progress
+        .map(Some)
+        .chain(stream::once(async { None }))
+        .scan(false, move |failed, item| {
+            let event = match item {
+                _ if *failed => return future::ready(None),
+                Some(Ok(progress)) => Event::default().event("progress").json_data(progress),
+                Some(Err(e)) => {
+                    *failed = true;
+                    Ok(Event::default().event("error").data(e.to_string()))
+                }
+                None => Ok(Event::default().event("done").data(image.clone())),
+            };
+            future::ready(Some(event))
+        });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// Operators may use any project, tenants only the ones `[projects]` lists them in
+fn authorize_project(state: &AppState, caller: &Caller, project: &str) -> Result<(), ErrorResponse> {
+    match caller {
+        Caller::Operator => Ok(()),
+        Caller::Tenant(user) => state.projects
+            .check_member(project, user)
+            .map_err(|e| ErrorResponse::new(ErrorNumber::Forbidden, e)),
+    }
+}
+
+/// Tenants only place containers in their own projects and on their own
+/// project's network
+fn authorize_placement(state: &AppState, caller: &Caller, config: &ContainerConfig) -> Result<(), ErrorResponse> {
+    if let Some(project) = config.labels.project.as_deref().filter(|p| !p.is_empty()) {
+        authorize_project(state, caller, project)?;
+    }
+    if *caller != Caller::Operator {
+        networks::check_tenant_network(config)
+            .map_err(|e| ErrorResponse::new(ErrorNumber::Forbidden, e))?;
+    }
+    Ok(())
+}
+
+/// Container must exist and be visible to the caller. Other tenants' containers
+/// look the same as missing ones.
+async fn authorize_container(docker: &DockerManager, caller: &Caller, container_id: &str) -> Result<(), ErrorResponse> {
+    let not_found = || ErrorResponse::new(ErrorNumber::ContainerNotFound, format!("Container bulunamadı: {}", container_id));
+    let owner = docker.container_owner(container_id).await.map_err(|_| not_found())?;
+    if caller.may_access(owner.as_deref()) {
+        Ok(())
+    } else {
+        Err(not_found())
+    }
+}
+
+/// Snapshot Listesi - the caller's snapshots (all of them for operators) and
+/// how full snapshot storage is
+#[derive(Debug, Serialize)]
+pub struct SnapshotList {
+    pub snapshots: Vec<Snapshot>,
+    pub usage: StorageUsage,
+}
+
+/// Snapshot errors: unknown ids are 404s, a full store is 507
+fn snapshot_error(e: anyhow::Error) -> ErrorResponse {
+    if e.downcast_ref::<StorageQuotaExceeded>().is_some() {
+        ErrorResponse::new(ErrorNumber::StorageFull, e)
+    } else if e.downcast_ref::<SnapshotNotFound>().is_some() {
+        ErrorResponse::new(ErrorNumber::ContainerNotFound, e)
+    } else {
+        ErrorResponse::new(ErrorNumber::OperationFailed, format!("Snapshot hatası: {}", e))
+    }
+}
+
+/// Snapshot, visible to the caller
+async fn authorize_snapshot(docker: &DockerManager, caller: &Caller, id: &str) -> Result<Snapshot, ErrorResponse> {
+    let snapshot = docker.find_snapshot(id).await.map_err(snapshot_error)?;
+    if caller.may_access(snapshot.owner.as_deref()) {
+        Ok(snapshot)
+    } else {
+        Err(ErrorResponse::new(ErrorNumber::ContainerNotFound, format!("Snapshot {} not found", id)))
+    }
+}
+
+/// Snapshot Alma Handler - commits the container to an image, optionally with a checkpoint
+#[axum::debug_handler]
+pub async fn create_snapshot(
+    State(state): State<Arc<AppState>>,
+    Path(container_id): Path<String>,
+    caller: Caller,
+    Json(request): Json<SnapshotRequest>,
+) -> Result<Json<Snapshot>, ErrorResponse> {
+    let docker = state.docker.lock().await.clone();
+    authorize_container(&docker, &caller, &container_id).await?;
+    info!("📸 {} için snapshot alınıyor ({})", container_id, caller.name());
+    docker.create_snapshot(&container_id, &request).await.map(Json).map_err(snapshot_error)
+}
+
+/// Snapshot Listeleme Handler
+#[axum::debug_handler]
+pub async fn list_snapshots(
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Json<SnapshotList>, ErrorResponse> {
+    let docker = state.docker.lock().await.clone();
+    let owner = match &caller {
+        Caller::Operator => None,
+        Caller::Tenant(user) => Some(user.as_str()),
+    };
+    let snapshots = docker.list_snapshots(owner).await.map_err(handle_error)?;
+    let usage = docker.storage_usage().await.map_err(handle_error)?;
+    Ok(Json(SnapshotList { snapshots, usage }))
+}
+
+/// Snapshot Silme Handler
+#[axum::debug_handler]
+pub async fn delete_snapshot(
+    State(state): State<Arc<AppState>>,
+    Path(snapshot_id): Path<String>,
+    caller: Caller,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let docker = state.docker.lock().await.clone();
+    let snapshot = authorize_snapshot(&docker, &caller, &snapshot_id).await?;
+    docker.delete_snapshot(&snapshot).await.map_err(snapshot_error)?;
+    Ok(Json(json!({ "status": "success", "deleted": snapshot.reference })))
+}
+
+/// Snapshot Geri Yükleme İsteği - the same spec as creating a container, minus the image
+#[derive(Debug, Deserialize)]
+pub struct RestoreSnapshotRequest {
+    #[serde(flatten)]
+    pub spec: ContainerConfig,
+    #[serde(default)]
+    pub gpu_id: Option<String>,
+}
+
+/// Snapshot Geri Yükleme Handler - new container from the snapshot
+#[axum::debug_handler]
+pub async fn restore_snapshot(
+    State(state): State<Arc<AppState>>,
+    Path(snapshot_id): Path<String>,
+    caller: Caller,
+    Json(params): Json<RestoreSnapshotRequest>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let docker = state.docker.lock().await.clone();
+    let snapshot = authorize_snapshot(&docker, &caller, &snapshot_id).await?;
+
+    let mut config = ContainerConfig { image: snapshot.id.clone(), ..params.spec };
+    config.gpu_id = params.gpu_id.as_deref().map(GPUConfig::from);
+    if config.labels.owner.is_none() {
+        config.labels.owner = snapshot.owner.clone();
+    }
+    config.validate()
+        .map_err(|e| ErrorResponse::new(ErrorNumber::OperationFailed, e))?;
+    stamp_ownership(&mut config, &caller, &*state.gpupool.lock().await)?;
+    authorize_placement(&state, &caller, &config)?;
+    let attachment = gpu_attachment(&state, &caller, config.labels.lease_id.as_deref(), params.gpu_id.as_deref()).await?;
+
+    docker.network_config().check(&config)
+        .map_err(|e| ErrorResponse::new(ErrorNumber::OperationFailed, e))?;
+    authorize_volumes(&docker, &caller, &config).await?;
+    let container_id = docker.restore_snapshot(&snapshot, &config, attachment.as_ref())
+        .await
+        .map_err(create_error)?;
+    Ok(Json(json!({
+        "status": "success",
+        "id": container_id,
+        "message": format!("{} snapshot'tan oluşturuldu: {}", config.name, snapshot.reference)
+    })))
+}
+
+/// Volume Listesi - the caller's volumes (all of them for operators) and how
+/// full storage is
+#[derive(Debug, Serialize)]
+pub struct VolumeList {
+    pub volumes: Vec<ManagedVolume>,
+    pub usage: StorageUsage,
+}
+
+/// Volume errors: unknown names are 404s, existing or mounted volumes 409, a full store 507
+fn volume_error(e: anyhow::Error) -> ErrorResponse {
+    let in_use = matches!(
+        e.downcast_ref::<bollard::errors::Error>(),
+        Some(bollard::errors::Error::DockerResponseServerError { status_code: 409, .. })
+    );
+    if e.downcast_ref::<StorageQuotaExceeded>().is_some() {
+        ErrorResponse::new(ErrorNumber::StorageFull, e)
+    } else if in_use || matches!(e.downcast_ref::<VolumeError>(), Some(VolumeError::AlreadyExists(_))) {
+        ErrorResponse::new(ErrorNumber::VolumeConflict, e)
+    } else if matches!(e.downcast_ref::<VolumeError>(), Some(VolumeError::NotFound(_))) {
+        ErrorResponse::new(ErrorNumber::ContainerNotFound, e)
+    } else {
+        ErrorResponse::new(ErrorNumber::OperationFailed, format!("Volume hatası: {}", e))
+    }
+}
+
+/// Volume, visible to the caller
+async fn authorize_volume(docker: &DockerManager, caller: &Caller, name: &str) -> Result<ManagedVolume, ErrorResponse> {
+    let volume = docker.find_volume(name).await.map_err(volume_error)?;
+    if caller.may_access(volume.owner.as_deref()) {
+        Ok(volume)
+    } else {
+        Err(ErrorResponse::new(ErrorNumber::ContainerNotFound, format!("Volume {} not found", name)))
+    }
+}
+
+/// Tenants may only mount named volumes they own - anything else would have
+/// Docker create a volume outside the storage quota - and no host paths
+async fn authorize_volumes(docker: &DockerManager, caller: &Caller, config: &ContainerConfig) -> Result<(), ErrorResponse> {
+    if *caller == Caller::Operator {
+        return Ok(());
+    }
+    volumes::reject_bind_mounts(config)
+        .map_err(|e| ErrorResponse::new(ErrorNumber::OperationFailed, e))?;
+    for name in volumes::named_mounts(config) {
+        authorize_volume(docker, caller, name).await?;
+    }
+    Ok(())
+}
+
+/// Volume Oluşturma Handler
+#[axum::debug_handler]
+pub async fn create_volume(
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+    Json(request): Json<VolumeRequest>,
+) -> Result<Json<ManagedVolume>, ErrorResponse> {
+    request.validate()
+        .map_err(|e| ErrorResponse::new(ErrorNumber::OperationFailed, e))?;
+    if let Some(project) = request.project.as_deref().filter(|p| !p.is_empty()) {
+        authorize_project(&state, &caller, project)?;
+    }
+    let owner = match &caller {
+        Caller::Operator => None,
+        Caller::Tenant(user) => Some(user.as_str()),
+    };
+    let docker = state.docker.lock().await.clone();
+    docker.create_volume(&request, owner).await.map(Json).map_err(volume_error)
+}
+
+/// Volume Listeleme Handler
+#[axum::debug_handler]
+pub async fn list_volumes(
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Json<VolumeList>, ErrorResponse> {
+    let docker = state.docker.lock().await.clone();
+    let owner = match &caller {
+        Caller::Operator => None,
+        Caller::Tenant(user) => Some(user.as_str()),
+    };
+    let volumes = docker.list_volumes(owner).await.map_err(handle_error)?;
+    let usage = docker.storage_usage().await.map_err(handle_error)?;
+    Ok(Json(VolumeList { volumes, usage }))
+}
+
+/// Volume Silme Handler - the data goes with it
+#[axum::debug_handler]
+pub async fn delete_volume(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+    caller: Caller,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let docker = state.docker.lock().await.clone();
+    let volume = authorize_volume(&docker, &caller, &name).await?;
+    docker.delete_volume(&volume.name).await.map_err(volume_error)?;
+    Ok(Json(json!({ "status": "success", "deleted": volume.name })))
+}
+
+/// Proje Silme Handler - applies each of the project's volumes' cleanup policy
+#[axum::debug_handler]
+pub async fn admin_delete_project(
+    State(state): State<Arc<AppState>>,
+    Path(project): Path<String>,
+    caller: Caller,
+) -> Result<Json<CleanupReport>, ErrorResponse> {
+    caller.require_operator()?;
+    let docker = state.docker.lock().await.clone();
+    let report = docker.cleanup_project(&project).await.map_err(handle_error)?;
+    info!(
+        "🧹 {} projesi silindi: {} volume silindi, {} korundu, {} silinemedi",
+        project, report.deleted.len(), report.retained.len(), report.failed.len()
+    );
+    Ok(Json(report))
+}
+
+/// Log Handler - server-sent events named after the stream (`stdout`, `stderr`,
+/// `console`), or JSON lines over a WebSocket when the client asks to upgrade
+#[axum::debug_handler]
+pub async fn container_logs(
+    State(state): State<Arc<AppState>>,
+    Path(container_id): Path<String>,
+    caller: Caller,
+    Query(options): Query<LogOptions>,
+    ws: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+) -> Result<Response, ErrorResponse> {
+    let lines = {
+        let docker = state.docker.lock().await;
+        authorize_container(&docker, &caller, &container_id).await?;
+        docker.logs(&container_id, &options)
+            .map_err(|e| ErrorResponse::new(ErrorNumber::OperationFailed, e))?
+    };
+    info!("📜 {} logları {} tarafından okunuyor", container_id, caller.name());
+
+    if let Ok(ws) = ws {
+        return Ok(ws.on_upgrade(move |socket| stream_logs_ws(socket, lines)));
+    }
 
-    Ok(Json(json!({"status": "GPU başarıyla eklendi"})))
+    let events = lines
+        .map(Some)
+        .chain(stream::once(async { None }))
+        .scan(false, |failed, item| {
+            let event = match item {
+                _ if *failed => return future::ready(None),
+                Some(Ok(line)) => Event::default().event(line.source.as_str()).data(line.message),
+                Some(Err(e)) => {
+                    *failed = true;
+                    Event::default().event("error").data(e.to_string())
+                }
+                None => Event::default().event("end").data(""),
+            };
+            future::ready(Some(Ok::<_, Infallible>(event)))
+        });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()).into_response())
 }
 
-/// Diğer handler'lar...
+async fn stream_logs_ws(mut socket: WebSocket, mut lines: BoxStream<'static, anyhow::Result<LogLine>>) {
+    loop {
+        tokio::select! {
+            line = lines.next() => {
+                let (text, last) = match line {
+                    Some(Ok(line)) => (serde_json::to_string(&line).unwrap_or_default(), false),
+                    Some(Err(e)) => (json!({"error": e.to_string()}).to_string(), true),
+                    None => break,
+                };
+                if socket.send(Message::Text(text.into())).await.is_err() {
+                    return;
+                }
+                if last {
+                    break;
+                }
+            }
+            incoming = socket.recv() => {
+                // Nothing to read from the client; only notice it leaving
+                if matches!(incoming, None | Some(Err(_)) | Some(Ok(Message::Close(_)))) {
+                    return;
+                }
+            }
+        }
+    }
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+/// Exec Handler - interactive TTY session over a WebSocket. See `core::exec`
+/// for the frame protocol.
+#[axum::debug_handler]
+pub async fn container_exec(
+    State(state): State<Arc<AppState>>,
+    Path(container_id): Path<String>,
+    caller: Caller,
+    Query(request): Query<ExecRequest>,
+    ws: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+) -> Result<Response, ErrorResponse> {
+    let ws = ws.map_err(|_| ErrorResponse::new(ErrorNumber::OperationFailed, "Exec WebSocket bağlantısı gerektirir"))?;
+    let docker = {
+        let docker = state.docker.lock().await;
+        authorize_container(&docker, &caller, &container_id).await?;
+        docker.clone()
+    };
+    let session = docker
+        .open_exec(&container_id, &request)
+        .await
+        .map_err(|e| ErrorResponse::new(ErrorNumber::OperationFailed, format!("Exec başlatılamadı: {}", e)))?;
+
+    let audit = ExecAudit {
+        session_id: uuid::Uuid::new_v4().to_string(),
+        container: container_id,
+        user: caller.name().to_string(),
+        events: state.events.clone(),
+    };
+    let command = request.command();
+    info!(target: "audit", session = %audit.session_id, container = %audit.container, user = %audit.user,
+        "exec session opened: {:?}", command);
+    audit.events.publish(EventKind::ExecOpened {
+        session_id: audit.session_id.clone(),
+        container: audit.container.clone(),
+        user: audit.user.clone(),
+        command,
+    });
+
+    let limit = request.time_limit();
+    Ok(ws.on_upgrade(move |socket| run_exec_session(socket, docker, session, limit, audit)))
+}
+
+struct ExecAudit {
+    session_id: String,
+    container: String,
+    user: String,
+    events: Arc<EventBus>,
+}
+
+async fn send_exec_message(socket: &mut WebSocket, message: &ServerMessage) -> bool {
+    let text = serde_json::to_string(message).unwrap_or_default();
+    socket.send(Message::Text(text.into())).await.is_ok()
+}
+
+async fn run_exec_session(
+    mut socket: WebSocket,
+    docker: DockerManager,
+    session: ExecSession,
+    limit: Duration,
+    audit: ExecAudit,
+) {
+    let started = std::time::Instant::now();
+    let ExecSession { exec_id, mut output, mut input } = session;
+    let deadline = tokio::time::sleep(limit);
+    tokio::pin!(deadline);
+
+    let mut connected = send_exec_message(&mut socket, &ServerMessage::Started {
+        session_id: audit.session_id.clone(),
+        time_limit_secs: limit.as_secs(),
+    })
+    .await;
+
+    let reason = loop {
+        if !connected {
+            break ExecEnd::ClientClosed;
+        }
+        tokio::select! {
+            _ = &mut deadline => {
+                send_exec_message(&mut socket, &ServerMessage::Timeout { after_secs: limit.as_secs() }).await;
+                break ExecEnd::Timeout;
+            }
+            chunk = output.next() => match chunk {
+                Some(Ok(chunk)) => {
+                    let bytes = axum::body::Bytes::copy_from_slice(chunk.as_ref());
+                    connected = socket.send(Message::Binary(bytes)).await.is_ok();
+                }
+                Some(Err(e)) => {
+                    send_exec_message(&mut socket, &ServerMessage::Error { message: e.to_string() }).await;
+                    break ExecEnd::Error;
+                }
+                None => break ExecEnd::Exited,
+            },
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Binary(data))) => {
+                    connected = input.write_all(&data).await.is_ok();
+                }
+                Some(Ok(Message::Text(text))) => match serde_json::from_str::<ClientMessage>(&text) {
+                    Ok(ClientMessage::Resize { cols, rows }) => {
+                        if let Err(e) = docker.resize_exec(&exec_id, cols, rows).await {
+                            connected = send_exec_message(&mut socket, &ServerMessage::Error { message: e.to_string() }).await;
+                        }
+                    }
+                    Ok(ClientMessage::Stdin { data }) => {
+                        connected = input.write_all(data.as_bytes()).await.is_ok();
+                    }
+                    Err(e) => {
+                        let message = format!("Invalid control message: {}", e);
+                        connected = send_exec_message(&mut socket, &ServerMessage::Error { message }).await;
+                    }
+                },
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break ExecEnd::ClientClosed,
+                Some(Ok(_)) => {}
+            },
+        }
+    };
+
+    // Closing stdin is the only way to end the process early
+    let _ = input.shutdown().await;
+    drop(input);
+    drop(output);
+    let exit_code = docker.exec_exit_code(&exec_id).await.ok().flatten();
+    if reason != ExecEnd::ClientClosed {
+        send_exec_message(&mut socket, &ServerMessage::Exit { code: exit_code }).await;
+        let _ = socket.send(Message::Close(None)).await;
+    }
+
+    let duration_secs = started.elapsed().as_secs();
+    info!(target: "audit", session = %audit.session_id, container = %audit.container, user = %audit.user,
+        "exec session closed after {}s: {:?}, exit code {:?}", duration_secs, reason, exit_code);
+    audit.events.publish(EventKind::ExecClosed {
+        session_id: audit.session_id,
+        container: audit.container,
+        user: audit.user,
+        reason,
+        exit_code,
+        duration_secs,
+    });
+}
+
+// Diğer handler'lar...
 // (Docker işlemleri için gerekli diğer endpoint'ler)
 
 /// Kök Handler
@@ -203,7 +966,7 @@ pub async fn root_handler() -> impl IntoResponse {
         "endpoints": [
             "/containers - Container listesi",
             "/create - Yeni container oluştur",
-            "/gpu/attach - GPU ekleme"
+            "/api/v1/containers/{id}/gpu - GPU ekleme (POST) / çıkarma (DELETE)"
         ]
     }))
 }
@@ -214,12 +977,250 @@ pub async fn health_check() -> impl IntoResponse {
     Json(json!({"status": "active", "version": "0.4.2"}))
 }
 
+/// Host Readiness - same checks as `gpu-share doctor`
+#[axum::debug_handler]
+pub async fn readiness_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    // Copies only, so a slow `virsh` doesn't hold up every other handler
+    let gpu_manager = state.gpu_manager.lock().await.clone();
+    let docker = state.docker.lock().await.clone();
+    let docker_check = check_docker(Some(&docker)).await;
+
+    let checker = state.readiness.clone();
+    match tokio::task::spawn_blocking(move || checker.report(&gpu_manager, docker_check)).await {
+        Ok(report) => Json(report).into_response(),
+        Err(e) => ErrorResponse::new(ErrorNumber::InternalError, format!("Readiness checks failed: {}", e)).into_response(),
+    }
+}
+
+/// Drain İsteği - reason is shown to tenants; `wait_secs` holds the response
+/// until remaining leases are released (or the time is up)
+#[derive(Debug, Default, Deserialize)]
+pub struct DrainRequest {
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub migrate: bool,
+    pub wait_secs: Option<u64>,
+}
+
+/// Pool entries with their service state (active / draining / quarantined)
+#[axum::debug_handler]
+pub async fn admin_list_gpus(
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    caller.require_operator()?;
+    let pool = state.gpupool.lock().await;
+    let mut gpus: Vec<_> = pool.gpus.values().cloned().collect();
+    gpus.sort_by_key(|g| g.id);
+    Ok(Json(gpus))
+}
+
+async fn run_drain(
+    state: &AppState,
+    target: DrainTarget,
+    request: DrainRequest,
+) -> Result<Json<DrainProgress>, ErrorResponse> {
+    let reason = request.reason.unwrap_or_else(|| "maintenance".to_string());
+    let progress = {
+        let mut pool = state.gpupool.lock().await;
+        let mut users = state.user_manager.lock().await;
+        drain::start_drain(&mut pool, &mut users, &state.events, target, &reason, request.migrate)
+            .map_err(|e| ErrorResponse::new(ErrorNumber::GpuNotFound, e))?
+    };
+
+    if !progress.migrated.is_empty() {
+        move_migrated_containers(state, &progress.migrated).await;
+    }
+
+    let mut progress = match request.wait_secs {
+        Some(secs) if !progress.complete => {
+            let mut waited = drain::wait_for_drain(
+                state.gpupool.clone(),
+                target,
+                Duration::from_secs(secs),
+                Duration::from_secs(1),
+            )
+            .await
+            .map_err(handle_error)?;
+            waited.migrated = progress.migrated;
+            waited
+        }
+        _ => progress,
+    };
+    add_remaining_containers(state, &mut progress).await;
+    Ok(Json(progress))
+}
+
+/// Recreates the migrated tenants' containers on the cards their leases moved
+/// to. Failures are logged; those containers keep the drain open.
+async fn move_migrated_containers(state: &AppState, moves: &[drain::LeaseMove]) {
+    let docker = state.docker.lock().await.clone();
+    let containers = match docker.list_managed().await {
+        Ok(containers) => containers,
+        Err(e) => {
+            warn!("Can't list containers to move off drained GPUs: {}", e);
+            return;
+        }
+    };
+    let gpu_manager = state.gpu_manager.lock().await.clone();
+    let planned = drain::plan_container_moves(&*state.gpupool.lock().await, moves, &gpu_manager.devices, &containers);
+
+    for container_move in planned {
+        let moved = match gpu_manager.container_attachment(&container_move.to_gpu) {
+            Ok(attachment) => docker.recreate_with_gpu(&container_move.container_id, Some(&attachment), false).await,
+            Err(e) => Err(e),
+        };
+        match moved {
+            Ok(outcome) => info!("🔁 {} moved to GPU {} ({})", container_move.name, container_move.to_gpu, outcome.container_id),
+            Err(e) => warn!("{} stays on its drained GPU: {}", container_move.name, e),
+        }
+    }
+}
+
+/// Containers still on drained cards keep the drain open. If Docker can't
+/// say, the drain isn't reported complete.
+async fn add_remaining_containers(state: &AppState, progress: &mut DrainProgress) {
+    let docker = state.docker.lock().await.clone();
+    match docker.list_managed().await {
+        Ok(containers) => {
+            let gpu_manager = state.gpu_manager.lock().await.clone();
+            let pool = state.gpupool.lock().await;
+            drain::add_remaining_containers(progress, &pool, &gpu_manager.devices, &containers);
+        }
+        Err(e) => {
+            warn!("Can't list containers on drained GPUs: {}", e);
+            progress.complete = false;
+        }
+    }
+}
+
+async fn show_drain(state: &AppState, target: DrainTarget) -> Result<Json<DrainProgress>, ErrorResponse> {
+    let mut progress = drain::drain_progress(&*state.gpupool.lock().await, target)
+        .map_err(|e| ErrorResponse::new(ErrorNumber::GpuNotFound, e))?;
+    add_remaining_containers(state, &mut progress).await;
+    Ok(Json(progress))
+}
+
+async fn run_undrain(state: &AppState, target: DrainTarget) -> Result<Json<DrainProgress>, ErrorResponse> {
+    let mut pool = state.gpupool.lock().await;
+    drain::undrain(&mut pool, &state.events, target)
+        .map(Json)
+        .map_err(|e| ErrorResponse::new(ErrorNumber::GpuNotFound, e))
+}
+
+/// Stops new leases on one GPU
+#[axum::debug_handler]
+pub async fn admin_drain_gpu(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u32>,
+    caller: Caller,
+    request: Option<Json<DrainRequest>>,
+) -> Result<Json<DrainProgress>, ErrorResponse> {
+    caller.require_operator()?;
+    let request = request.map(|Json(r)| r).unwrap_or_default();
+    run_drain(&state, DrainTarget::Gpu { gpu_id: id }, request).await
+}
+
+#[axum::debug_handler]
+pub async fn admin_gpu_drain_status(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u32>,
+    caller: Caller,
+) -> Result<Json<DrainProgress>, ErrorResponse> {
+    caller.require_operator()?;
+    show_drain(&state, DrainTarget::Gpu { gpu_id: id }).await
+}
+
+/// Puts a drained or quarantined GPU back into service
+#[axum::debug_handler]
+pub async fn admin_undrain_gpu(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<u32>,
+    caller: Caller,
+) -> Result<Json<DrainProgress>, ErrorResponse> {
+    caller.require_operator()?;
+    run_undrain(&state, DrainTarget::Gpu { gpu_id: id }).await
+}
+
+/// Stops new leases on every GPU of this host
+#[axum::debug_handler]
+pub async fn admin_drain_host(
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+    request: Option<Json<DrainRequest>>,
+) -> Result<Json<DrainProgress>, ErrorResponse> {
+    caller.require_operator()?;
+    let request = request.map(|Json(r)| r).unwrap_or_default();
+    run_drain(&state, DrainTarget::Host, request).await
+}
+
+#[axum::debug_handler]
+pub async fn admin_host_drain_status(
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Json<DrainProgress>, ErrorResponse> {
+    caller.require_operator()?;
+    show_drain(&state, DrainTarget::Host).await
+}
+
+#[axum::debug_handler]
+pub async fn admin_undrain_host(
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<Json<DrainProgress>, ErrorResponse> {
+    caller.require_operator()?;
+    run_undrain(&state, DrainTarget::Host).await
+}
+
+/// Olay Akışı - the event bus as server-sent events named after the event
+/// type. Tenants only get events about themselves.
+#[axum::debug_handler]
+pub async fn event_stream(
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let events = stream::unfold(state.events.subscribe(), |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => return Some((event, rx)),
+                // Slow client - skip what it missed
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    })
+    .filter(move |event| future::ready(caller.may_access(event.kind.tenant())))
+    .map(|event| {
+        let json = serde_json::to_value(&event).unwrap_or_default();
+        let name = json["type"].as_str().unwrap_or("event").to_string();
+        Ok(Event::default().event(name).data(json.to_string()))
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+/// Pending notifications for a tenant; reading them clears the inbox
+#[axum::debug_handler]
+pub async fn user_notifications(
+    State(state): State<Arc<AppState>>,
+    Path(user): Path<String>,
+    caller: Caller,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    if !caller.may_access(Some(&user)) {
+        return Err(ErrorResponse::new(ErrorNumber::Forbidden, "Başka bir kullanıcının bildirimleri okunamaz"));
+    }
+    Ok(Json(state.user_manager.lock().await.take_notifications(&user)))
+}
+
 /// Shutdown Handler
 #[axum::debug_handler]
-pub async fn shutdown_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+pub async fn shutdown_handler(
+    State(state): State<Arc<AppState>>,
+    caller: Caller,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    caller.require_operator()?;
     info!("🛑 Sistem kapatılıyor...");
     if let Some(sender) = state.shutdown_signal.lock().await.take() {
         let _ = sender.send(());
     }
-    Json(json!({"status": "shutdown_initiated"}))
+    Ok(Json(json!({"status": "shutdown_initiated"})))
 }
@@ -17,6 +17,12 @@ pub struct Transaction {
     pub cost: f64,
 }
 
+impl Default for BillingSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl BillingSystem {
     pub fn new() -> Self {
         Self {
@@ -23,7 +23,7 @@ async fn main() -> Result<()> {
 pub async fn start_dashboard(
     gpupool: Arc<Mutex<GPUPool>>,
     users: Arc<Mutex<UserManager>>,
-    billing: Arc<Mutex<BillingSystem>>
+    _billing: Arc<Mutex<BillingSystem>>
 ) -> Result<()> {
     enable_raw_mode()?;
     let mut stdout = std::io::stdout();
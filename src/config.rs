@@ -1,3 +1,5 @@
+pub mod settings;
+
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -26,6 +26,10 @@
 *    - host: Where we serve our API (localhost, because security first!)
 *    - port: The magical number for network communication
 *    - api_prefix: Because we might change our minds about /api/v1 later
+*    - operator_token: bearer token for the operator (admin endpoints, /shutdown);
+*      GPU_SHARE_OPERATOR_TOKEN wins over it
+*    - tenant_token_secret: signs tenant tokens (`gpu-share token <user>`);
+*      GPU_SHARE_TENANT_SECRET wins over it
 *
 * 2. LibvirtSettings:
 *    - connection_uri: The mystical URI that connects us to the VM realm
@@ -39,14 +43,38 @@
 *    - enable_gpu_metrics: For when you want to know why ur GPU fans sound like a jet engine
 *
 * 4. StorageSettings:
-*    - vm_image_path: Where VM images go to hibernate
+*    - vm_image_path: Where VM images go to hibernate (container checkpoints
+*      live in its checkpoints/ subdirectory)
 *    - max_storage_gb: Because someone will try to store their entire Steam library
+*      (also caps container snapshots + checkpoints)
 *
 * 5. RateLimitSettings:
 *    - api_requests_per_minute: Rate limit for general API requests
 *    - gpu_requests_per_minute: Rate limit for GPU-related requests
 *    - auth_requests_per_minute: Rate limit for authentication-related requests
 *
+* 6. PassthroughConfig ([passthrough], optional):
+*    - enable_unsafe_interrupts: vfio_iommu_type1 allow_unsafe_interrupts
+*    - enable_acs_override: trust groups split by pcie_acs_override
+*    - enable_power_management: runtime PM for idle passed-through devices
+*
+* 7. ImageConfig ([images], optional):
+*    - allow / deny: image reference globs, deny wins
+*    - registries: pull credentials per registry, optionally per project
+*
+* 8. ReconcileConfig ([reconcile], optional):
+*    - interval_secs: how often Docker and the GPU pool are compared
+*    - fix: stop orphan containers / release orphan leases instead of reporting
+*    - lease_grace_secs: how long a lease may sit without a container
+*
+* 9. NetworkConfig ([networks], optional):
+*    - isolate: one bridge network per project (or tenant) instead of the default bridge
+*    - restrict_egress / restricted_projects: make those networks internal (no way out)
+*    - port_range: host ports published ports are allocated from
+*
+* 10. ProjectConfig ([projects], optional):
+*    - <project> = [members]: tenants allowed to name that project
+*
 * Implementation Details:
 * --------------------
 * - Using serde for serialization (because writing parsers is so 1990s)
@@ -83,6 +111,12 @@ use config::{Config, ConfigError, File};
 use std::path::PathBuf;
 use tracing::info;
 
+use crate::core::images::ImageConfig;
+use crate::core::networks::NetworkConfig;
+use crate::core::projects::ProjectConfig;
+use crate::core::reconcile::ReconcileConfig;
+use crate::gpu::passthrough::PassthroughConfig;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Settings {
     pub server: ServerSettings,
@@ -90,6 +124,16 @@ pub struct Settings {
     pub monitoring: MonitoringSettings,
     pub storage: StorageSettings,
     pub rate_limits: RateLimitSettings,
+    #[serde(default)]
+    pub passthrough: PassthroughConfig,
+    #[serde(default)]
+    pub images: ImageConfig,
+    #[serde(default)]
+    pub reconcile: ReconcileConfig,
+    #[serde(default)]
+    pub networks: NetworkConfig,
+    #[serde(default)]
+    pub projects: ProjectConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -97,6 +141,10 @@ pub struct ServerSettings {
     pub host: String,
     pub port: u16,
     pub api_prefix: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operator_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_token_secret: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -114,12 +162,28 @@ pub struct MonitoringSettings {
     pub enable_gpu_metrics: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StorageSettings {
     pub vm_image_path: PathBuf,
     pub max_storage_gb: u64,
 }
 
+impl Default for StorageSettings {
+    fn default() -> Self {
+        Self {
+            vm_image_path: PathBuf::from("/var/lib/gpu-share/images"),
+            max_storage_gb: 100,
+        }
+    }
+}
+
+impl StorageSettings {
+    /// `[storage]` from the config files, or the defaults
+    pub fn load() -> Self {
+        Settings::new().map(|s| s.storage).unwrap_or_default()
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RateLimitSettings {
     pub api_requests_per_minute: u32,
@@ -168,6 +232,8 @@ pub fn generate_default_config() -> Settings {
             host: "127.0.0.1".to_string(),
             port: 3000,
             api_prefix: "/api/v1".to_string(),
+            operator_token: None,
+            tenant_token_secret: None,
         },
         libvirt: LibvirtSettings {
             connection_uri: "qemu:///system".to_string(),
@@ -180,14 +246,16 @@ pub fn generate_default_config() -> Settings {
             retention_hours: 24,
             enable_gpu_metrics: true,
         },
-        storage: StorageSettings {
-            vm_image_path: PathBuf::from("/var/lib/gpu-share/images"),
-            max_storage_gb: 100,
-        },
+        storage: StorageSettings::default(),
         rate_limits: RateLimitSettings {
             api_requests_per_minute: 100,
             gpu_requests_per_minute: 30,
             auth_requests_per_minute: 10,
         },
+        passthrough: PassthroughConfig::default(),
+        images: ImageConfig::default(),
+        reconcile: ReconcileConfig::default(),
+        networks: NetworkConfig::default(),
+        projects: ProjectConfig::default(),
     }
 }
\ No newline at end of file
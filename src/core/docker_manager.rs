@@ -1,55 +1,509 @@
 use anyhow::{anyhow, Result};
-use bollard::Docker;
-use bollard::container::{Config, CreateContainerOptions, StartContainerOptions, Stats};
+use bollard::container::{Config, ListContainersOptions, RemoveContainerOptions, Stats};
+use bollard::exec::{CreateExecOptions, StartExecResults};
+use bollard::image::{CommitContainerOptions, CreateImageOptions, ListImagesOptions};
+use futures_util::stream::BoxStream;
 use futures_util::StreamExt;
-use tracing::info;
-use serde::Serialize;
+use tracing::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use bollard::models::{
+    ContainerInspectResponse, EndpointSettings, HostConfig, PortBinding, PortTypeEnum, RestartPolicy as DockerRestartPolicy,
+    RestartPolicyNameEnum,
+};
+use bollard::volume::CreateVolumeOptions;
+use bollard::network::{ConnectNetworkOptions, CreateNetworkOptions, ListNetworksOptions};
+use bollard::system::EventsOptions;
+use crate::core::exec::{ExecRequest, ExecSession};
+use crate::core::lifecycle::{ContainerEvent, ExitContext};
+use crate::core::logs::{LogLine, LogOptions};
+use crate::core::images::{ImageConfig, PullProgress, PullTracker};
+use crate::core::networks::{is_prunable, NetworkConfig, NetworkScope};
+use crate::config::settings::StorageSettings;
+use crate::core::runtime::{ContainerRuntime, DockerRuntime};
+use crate::core::recreate::{ContainerSnapshot, RecreateOutcome, RecreatePlan};
+use crate::core::volumes::{plan_cleanup, CleanupReport, ManagedVolume, VolumeError, VolumeRequest};
+use crate::core::snapshots::{
+    default_tag, snapshot_repo, validate_tag, Snapshot, SnapshotNotFound, SnapshotRequest, SnapshotStore, StorageUsage,
+    LABEL_CHECKPOINT, LABEL_SNAPSHOT, LABEL_SNAPSHOT_OF, LABEL_SNAPSHOT_SIZE,
+};
+use crate::gpu::container::{is_gpu_device_mapping, is_gpu_device_request, GpuAttachment};
 use crate::gpu::device::GPUConfig;
 
 #[derive(Clone)]
 pub struct DockerManager {
-    docker: Docker,
+    docker: Arc<dyn ContainerRuntime>,
+    images: ImageConfig,
+    snapshots: SnapshotStore,
+    networks: NetworkConfig,
+    /// Containers we stopped on purpose (GPU swap, checkpoint). Their exit
+    /// doesn't end the lease; shared by clones, so the lifecycle watcher sees them.
+    planned_stops: Arc<Mutex<HashSet<String>>>,
 }
 
 impl DockerManager {
+    /// The local daemon, configured from the config files
     pub fn new() -> Result<Self> {
-        let docker = Docker::connect_with_local_defaults()?;
-        Ok(Self { docker })
+        Ok(Self::with_runtime(Arc::new(DockerRuntime::connect()?))
+            .with_image_config(ImageConfig::load())
+            .with_snapshot_store(SnapshotStore::new(&StorageSettings::load()))
+            .with_network_config(NetworkConfig::load()))
+    }
+
+    /// Any runtime (`FakeRuntime` in tests) with default settings; the config files aren't read
+    pub fn with_runtime(runtime: Arc<dyn ContainerRuntime>) -> Self {
+        Self {
+            docker: runtime,
+            images: ImageConfig::default(),
+            snapshots: SnapshotStore::new(&StorageSettings::default()),
+            networks: NetworkConfig::default(),
+            planned_stops: Arc::default(),
+        }
+    }
+
+    pub fn with_image_config(mut self, images: ImageConfig) -> Self {
+        self.images = images;
+        self
+    }
+
+    pub fn with_snapshot_store(mut self, snapshots: SnapshotStore) -> Self {
+        self.snapshots = snapshots;
+        self
+    }
+
+    pub fn with_network_config(mut self, networks: NetworkConfig) -> Self {
+        self.networks = networks;
+        self
+    }
+
+    pub fn image_config(&self) -> &ImageConfig {
+        &self.images
+    }
+
+    pub fn network_config(&self) -> &NetworkConfig {
+        &self.networks
+    }
+
+    /// Round-trip to the daemon - `new()` alone never touches the socket
+    pub async fn ping(&self) -> Result<()> {
+        self.docker.ping().await?;
+        Ok(())
     }
 
     pub async fn create_container(&self, image: &str, name: &str) -> Result<String> {
-        info!("🐳 Creating container: {} with image {}", name, image);
-        
-        let config = Config {
-            image: Some(image),
+        let config = ContainerConfig {
+            image: image.to_string(),
+            name: name.to_string(),
             ..Default::default()
         };
+        self.create_container_with(&config, None).await
+    }
 
-        let options = CreateContainerOptions {
-            name: name.to_string(),
-            platform: None,
+    /// Streams a pull of `image`. The image policy is checked before anything
+    /// reaches the daemon; credentials come from `[images.registries]`.
+    pub fn pull_image_stream(&self, image: &str, project: Option<&str>) -> Result<BoxStream<'static, Result<PullProgress>>> {
+        let reference = self.images.check(image)?;
+        let credentials = self.images.credentials_for(&reference, project);
+        let options = CreateImageOptions { from_image: reference.to_string(), ..Default::default() };
+
+        let mut tracker = PullTracker::new(&reference);
+        let stream = self
+            .docker
+            .create_image(options, credentials)
+            .map(move |item| item.map_err(anyhow::Error::from).and_then(|info| tracker.update(info)));
+        Ok(stream.boxed())
+    }
+
+    pub async fn pull_image(&self, image: &str, project: Option<&str>) -> Result<()> {
+        info!("📥 Pulling image {}", image);
+        let mut stream = self.pull_image_stream(image, project)?;
+        while let Some(progress) = stream.next().await {
+            let progress = progress?;
+            debug!("{} {} {:?}", progress.layer.as_deref().unwrap_or(&progress.image), progress.status, progress.percent);
+        }
+        Ok(())
+    }
+
+    /// Checks the image policy and pulls `image` if it isn't present locally.
+    /// Returns whether a pull happened.
+    pub async fn ensure_image(&self, image: &str, project: Option<&str>) -> Result<bool> {
+        let reference = self.images.check(image)?;
+        match self.docker.inspect_image(&reference.to_string()).await {
+            Ok(_) => Ok(false),
+            Err(bollard::errors::Error::DockerResponseServerError { status_code: 404, .. }) => {
+                self.pull_image(image, project).await?;
+                Ok(true)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Creates and starts a container from `config`. `gpu` must be the attachment
+    /// for `config.gpu_id` (see `GPUManager::container_attachment`).
+    pub async fn create_container_with(&self, config: &ContainerConfig, gpu: Option<&GpuAttachment>) -> Result<String> {
+        info!("🐳 Creating container: {} with image {}", config.name, config.image);
+        if let Some(attachment) = gpu {
+            info!("🎮 Exposing {} GPU {} to {}", attachment.vendor.name(), attachment.gpu_id, config.name);
+        }
+        config.validate()?;
+        self.ensure_image(&config.image, config.labels.project.as_deref()).await?;
+        let config = &self.place(config).await?;
+        let container_config = config.to_docker(gpu)?;
+
+        let id = self.create_from_config(&config.name, container_config).await?;
+        self.docker.start_container(&id).await?;
+        Ok(id)
+    }
+
+    /// Puts `config` on its project network (created if needed) and gives
+    /// its published ports host ports from the range. See `core::networks`.
+    async fn place(&self, config: &ContainerConfig) -> Result<ContainerConfig> {
+        self.networks.check(config)?;
+        let mut config = config.clone();
+        if let Some(scope) = self.networks.scope_for(&config) {
+            config.network = Some(self.ensure_network(&scope).await?);
+        }
+        if !config.ports.is_empty() {
+            let used = self.used_host_ports().await?;
+            self.networks.port_range.assign(&mut config.ports, &used)?;
+        }
+        Ok(config)
+    }
+
+    /// Creates the scope's network unless it exists; returns its name. An
+    /// existing network whose egress doesn't match `[networks]` any more is
+    /// recreated while empty and refused while in use.
+    pub async fn ensure_network(&self, scope: &NetworkScope) -> Result<String> {
+        let name = scope.network_name();
+        let internal = self.networks.is_restricted(scope);
+        match self.docker.inspect_network(&name).await {
+            Ok(network) if network.internal.unwrap_or(false) == internal => return Ok(name),
+            Ok(network) => {
+                if network.containers.as_ref().is_some_and(|c| !c.is_empty()) {
+                    return Err(anyhow!(
+                        "Network {} {} egress but {} should {}; stop its containers so it can be recreated",
+                        name,
+                        if internal { "has" } else { "has no" },
+                        scope,
+                        if internal { "not have it" } else { "have it" },
+                    ));
+                }
+                info!("🌐 Recreating network {}, its egress setting changed", name);
+                self.docker.remove_network(&name).await?;
+            }
+            Err(bollard::errors::Error::DockerResponseServerError { status_code: 404, .. }) => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        let options = CreateNetworkOptions {
+            name: name.clone(),
+            check_duplicate: true,
+            driver: "bridge".to_string(),
+            internal,
+            labels: scope.labels(),
+            ..Default::default()
         };
+        match self.docker.create_network(options).await {
+            Ok(_) => info!("🌐 Created network {} ({})", name, if internal { "no egress" } else { "bridged" }),
+            // Someone else created it meanwhile
+            Err(bollard::errors::Error::DockerResponseServerError { status_code: 409, .. }) => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(name)
+    }
 
-        let container = self.docker.create_container(Some(options), config).await?;
-        self.docker.start_container(&container.id, None::<StartContainerOptions<String>>).await?;
-        
-        Ok(container.id)
+    /// Removes managed networks without containers; returns their names
+    pub async fn prune_networks(&self) -> Result<Vec<String>> {
+        let options = ListNetworksOptions {
+            filters: HashMap::from([("label".to_string(), vec![format!("{}=true", LABEL_MANAGED)])]),
+        };
+        let now = chrono::Utc::now();
+        let mut removed = Vec::new();
+        for summary in self.docker.list_networks(options).await? {
+            let Some(name) = summary.name else { continue };
+            // Listing doesn't say which containers are attached
+            let network = match self.docker.inspect_network(&name).await {
+                Ok(network) => network,
+                Err(e) => {
+                    debug!("Skipping network {}: {}", name, e);
+                    continue;
+                }
+            };
+            if !is_prunable(&network, now) {
+                continue;
+            }
+            match self.docker.remove_network(&name).await {
+                Ok(()) => {
+                    info!("🧹 Removed unused network {}", name);
+                    removed.push(name);
+                }
+                Err(e) => debug!("Couldn't remove network {}: {}", name, e),
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Host ports running containers publish
+    pub async fn used_host_ports(&self) -> Result<HashSet<(u16, Protocol)>> {
+        let containers = self.docker.list_containers(ListContainersOptions::default()).await?;
+        Ok(containers
+            .iter()
+            .flat_map(|c| c.ports.iter().flatten())
+            .filter_map(|port| {
+                let protocol = match port.typ {
+                    Some(PortTypeEnum::UDP) => Protocol::Udp,
+                    Some(PortTypeEnum::TCP) => Protocol::Tcp,
+                    _ => return None,
+                };
+                port.public_port.map(|public| (public, protocol))
+            })
+            .collect())
+    }
+
+    /// Creates without starting; the image must already be local
+    async fn create_from_config(&self, name: &str, config: Config<String>) -> Result<String> {
+        Ok(self.docker.create_container(name, config).await?)
+    }
+
+    /// Snapshots, all of them or `owner`'s, newest first
+    pub async fn list_snapshots(&self, owner: Option<&str>) -> Result<Vec<Snapshot>> {
+        let mut labels = vec![format!("{}=true", LABEL_SNAPSHOT)];
+        if let Some(owner) = owner {
+            labels.push(format!("{}={}", LABEL_OWNER, owner));
+        }
+        let options = ListImagesOptions { filters: HashMap::from([("label".to_string(), labels)]), ..Default::default() };
+        let mut snapshots: Vec<Snapshot> = self
+            .docker
+            .list_images(options)
+            .await?
+            .into_iter()
+            .filter_map(Snapshot::from_summary)
+            .collect();
+        snapshots.sort_by_key(|s| std::cmp::Reverse(s.created));
+        Ok(snapshots)
+    }
+
+    pub async fn find_snapshot(&self, id: &str) -> Result<Snapshot> {
+        self.list_snapshots(None)
+            .await?
+            .into_iter()
+            .find(|s| s.matches(id))
+            .ok_or_else(|| SnapshotNotFound(id.to_string()).into())
+    }
+
+    /// Space every snapshot and volume takes against `[storage] max_storage_gb`
+    pub async fn storage_usage(&self) -> Result<StorageUsage> {
+        let mut usage = self.snapshots.usage(&self.list_snapshots(None).await?);
+        let volumes: u64 = self.list_volumes(None).await?.iter().map(ManagedVolume::accounted_bytes).sum();
+        usage.used_bytes = usage.used_bytes.saturating_add(volumes);
+        Ok(usage)
+    }
+
+    /// Commits `container_id` to `gpu-share-snapshot/<owner>:<tag>`, after
+    /// checkpointing it if asked. See `core::snapshots`.
+    pub async fn create_snapshot(&self, container_id: &str, request: &SnapshotRequest) -> Result<Snapshot> {
+        let inspect = self
+            .docker
+            .inspect_container(container_id, true)
+            .await?;
+        let name = inspect.name.as_deref().unwrap_or(container_id).trim_start_matches('/').to_string();
+        let owner = inspect
+            .config
+            .as_ref()
+            .and_then(|c| c.labels.as_ref())
+            .and_then(|labels| labels.get(LABEL_OWNER))
+            .filter(|owner| !owner.is_empty())
+            .cloned();
+        let size = inspect.size_rw.unwrap_or(0).max(0) as u64;
+
+        let tag = request.tag.clone().unwrap_or_else(|| default_tag(&name, chrono::Utc::now()));
+        validate_tag(&tag)?;
+        let repo = snapshot_repo(owner.as_deref());
+        self.storage_usage().await?.ensure_room(size)?;
+
+        let checkpoint = match request.checkpoint {
+            true => {
+                let checkpoint = format!("ckpt-{}", uuid::Uuid::new_v4().simple());
+                // Checkpointing stops it; the tenant keeps the lease to restore onto
+                let id = inspect.id.clone().unwrap_or_else(|| container_id.to_string());
+                self.plan_stop(&id);
+                if let Err(e) = self.snapshots.checkpoint(container_id, &checkpoint).await {
+                    self.end_planned_stop(&id);
+                    return Err(e);
+                }
+                Some(checkpoint)
+            }
+            false => None,
+        };
+
+        // Labels merge over the container's; blank out the ones that tie it to
+        // its old lease and GPU so restored containers don't inherit them
+        let mut labels = HashMap::from([
+            (LABEL_SNAPSHOT.to_string(), "true".to_string()),
+            (LABEL_SNAPSHOT_OF.to_string(), name.clone()),
+            (LABEL_SNAPSHOT_SIZE.to_string(), size.to_string()),
+            (LABEL_CHECKPOINT.to_string(), checkpoint.clone().unwrap_or_default()),
+            (LABEL_MANAGED.to_string(), String::new()),
+            (LABEL_LEASE.to_string(), String::new()),
+            (LABEL_GPU.to_string(), String::new()),
+            (LABEL_GPU_GROUPS.to_string(), String::new()),
+        ]);
+        if let Some(owner) = &owner {
+            labels.insert(LABEL_OWNER.to_string(), owner.clone());
+        }
+        let options = CommitContainerOptions {
+            container: container_id.to_string(),
+            repo: repo.clone(),
+            tag: tag.clone(),
+            comment: request.comment.clone().unwrap_or_default(),
+            author: owner.clone().unwrap_or_default(),
+            pause: true,
+            changes: None,
+        };
+        let committed = self.docker.commit_container(options, Config { labels: Some(labels), ..Default::default() }).await;
+        if let Err(e) = committed {
+            if let Some(checkpoint) = &checkpoint {
+                let _ = self.snapshots.remove_checkpoint(checkpoint);
+            }
+            return Err(e.into());
+        }
+
+        info!("📸 Snapshot of {} saved as {}:{}", name, repo, tag);
+        let reference = format!("{}:{}", repo, tag);
+        let image = self.docker.inspect_image(&reference).await?;
+        Ok(Snapshot {
+            id: image.id.unwrap_or_default(),
+            reference,
+            owner,
+            source: name,
+            created: Some(chrono::Utc::now()),
+            size_bytes: size,
+            checkpoint,
+        })
+    }
+
+    /// Untags the snapshot image (deleting it once nothing else refers to it)
+    /// and removes its checkpoint. Docker refuses while a container uses it.
+    pub async fn delete_snapshot(&self, snapshot: &Snapshot) -> Result<()> {
+        self.docker.remove_image(&snapshot.reference).await?;
+        if let Some(checkpoint) = &snapshot.checkpoint {
+            self.snapshots.remove_checkpoint(checkpoint)?;
+        }
+        info!("🗑️ Snapshot {} deleted", snapshot.reference);
+        Ok(())
+    }
+
+    /// Creates and starts a container from `snapshot` (from its checkpoint, if
+    /// it has one). `config.image` is ignored.
+    pub async fn restore_snapshot(
+        &self,
+        snapshot: &Snapshot,
+        config: &ContainerConfig,
+        gpu: Option<&GpuAttachment>,
+    ) -> Result<String> {
+        let config = ContainerConfig { image: snapshot.id.clone(), ..config.clone() };
+        let config = self.place(&config).await?;
+        let id = self.create_from_config(&config.name, config.to_docker(gpu)?).await?;
+        let started = match &snapshot.checkpoint {
+            Some(checkpoint) => self.snapshots.start_from_checkpoint(&id, checkpoint).await,
+            None => self.start_container(&id).await,
+        };
+        if let Err(e) = started {
+            let _ = self.docker.remove_container(&id, None).await;
+            return Err(e);
+        }
+        info!("📦 Restored {} from snapshot {}", config.name, snapshot.reference);
+        Ok(id)
+    }
+
+    /// Volumes we created, all of them or `owner`'s, with Docker's size measurements
+    pub async fn list_volumes(&self, owner: Option<&str>) -> Result<Vec<ManagedVolume>> {
+        // `df` is the only call that reports volume sizes
+        let usage = self.docker.df().await?;
+        let mut volumes: Vec<ManagedVolume> = usage
+            .volumes
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(ManagedVolume::from_docker)
+            .filter(|v| owner.is_none() || v.owner.as_deref() == owner)
+            .collect();
+        volumes.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(volumes)
+    }
+
+    pub async fn find_volume(&self, name: &str) -> Result<ManagedVolume> {
+        self.list_volumes(None)
+            .await?
+            .into_iter()
+            .find(|v| v.name == name)
+            .ok_or_else(|| VolumeError::NotFound(name.to_string()).into())
+    }
+
+    /// Creates a managed volume for `owner` once its reserved size fits the storage quota
+    pub async fn create_volume(&self, request: &VolumeRequest, owner: Option<&str>) -> Result<ManagedVolume> {
+        request.validate()?;
+        match self.docker.inspect_volume(&request.name).await {
+            Ok(_) => return Err(VolumeError::AlreadyExists(request.name.clone()).into()),
+            Err(bollard::errors::Error::DockerResponseServerError { status_code: 404, .. }) => {}
+            Err(e) => return Err(e.into()),
+        }
+        self.storage_usage().await?.ensure_room(request.reserved_bytes())?;
+
+        let options = CreateVolumeOptions {
+            name: request.name.clone(),
+            driver: "local".to_string(),
+            driver_opts: HashMap::new(),
+            labels: request.labels(owner),
+        };
+        let volume = self.docker.create_volume(options).await?;
+        info!("💾 Created volume {} for {}", request.name, owner.unwrap_or("operator"));
+        ManagedVolume::from_docker(volume).ok_or_else(|| anyhow!("Volume {} lost its labels", request.name))
+    }
+
+    /// Deletes the volume and its data. Docker refuses while a container mounts it.
+    pub async fn delete_volume(&self, name: &str) -> Result<()> {
+        self.docker.remove_volume(name).await?;
+        info!("🗑️ Volume {} deleted", name);
+        Ok(())
+    }
+
+    /// Applies the cleanup policy of every volume of `project` and drops
+    /// networks nobody uses any more
+    pub async fn cleanup_project(&self, project: &str) -> Result<CleanupReport> {
+        let volumes = self.list_volumes(None).await?;
+        let (delete, retain) = plan_cleanup(&volumes, project);
+        let mut report = CleanupReport {
+            project: project.to_string(),
+            retained: retain.iter().map(|v| v.name.clone()).collect(),
+            ..Default::default()
+        };
+        for volume in delete {
+            match self.delete_volume(&volume.name).await {
+                Ok(()) => report.deleted.push(volume.name.clone()),
+                Err(e) => report.failed.push((volume.name.clone(), e.to_string())),
+            }
+        }
+        if let Err(e) = self.prune_networks().await {
+            warn!("Network cleanup for project {} failed: {}", project, e);
+        }
+        Ok(report)
     }
 
     pub async fn list_containers(&self) -> Result<Vec<String>> {
-        let containers = self.docker.list_containers::<String>(None).await?;
+        let containers = self.docker.list_containers(ListContainersOptions::default()).await?;
         Ok(containers.iter()
             .filter_map(|c| c.names.as_ref().and_then(|n| n.first().cloned()))
             .collect())
     }
 
     pub async fn lookup_container(&self, id: &str) -> Result<String> {
-        let container = self.docker.inspect_container(id, None).await?;
-        Ok(container.id.ok_or_else(|| anyhow!("Container ID not found for: {}", id))?)
+        let container = self.docker.inspect_container(id, false).await?;
+        container.id.ok_or_else(|| anyhow!("Container ID not found for: {}", id))
     }
     
     pub async fn start_container(&self, id: &str) -> Result<()> {
-        self.docker.start_container(id, None::<StartContainerOptions<String>>).await?;
+        self.docker.start_container(id).await?;
         Ok(())
     }
     
@@ -64,7 +518,7 @@ impl DockerManager {
     }
 
     pub async fn inspect_container(&self, container_id: &str) -> Result<ContainerStats> {
-        let mut stats_stream = self.docker.stats(container_id, None);
+        let mut stats_stream = self.docker.stats(container_id);
         let stats = stats_stream.next().await.ok_or(anyhow!("No stats available"))??;
         
         let cpu_percent = calculate_cpu_percent(&stats);
@@ -78,9 +532,247 @@ impl DockerManager {
     }
 
     pub async fn is_container_active(&self, container_id: &str) -> Result<bool> {
-        let container = self.docker.inspect_container(container_id, None).await?;
+        let container = self.docker.inspect_container(container_id, false).await?;
         Ok(container.state.and_then(|s| s.running).unwrap_or(false))
     }
+
+    /// The tenant a container was created for (`gpu-share.owner` label)
+    pub async fn container_owner(&self, container_id: &str) -> Result<Option<String>> {
+        let container = self.docker.inspect_container(container_id, false).await?;
+        Ok(container
+            .config
+            .and_then(|c| c.labels)
+            .and_then(|mut labels| take_label(&mut labels, LABEL_OWNER)))
+    }
+
+    /// Whether `container_id` last stopped because we stopped it
+    pub fn is_planned_stop(&self, container_id: &str) -> bool {
+        self.planned_stops.lock().unwrap().contains(container_id)
+    }
+
+    /// Forgets a planned stop once the container is back or gone
+    pub fn end_planned_stop(&self, container_id: &str) {
+        self.planned_stops.lock().unwrap().remove(container_id);
+    }
+
+    fn plan_stop(&self, container_id: &str) {
+        self.planned_stops.lock().unwrap().insert(container_id.to_string());
+    }
+
+    /// Whether a managed container other than `except` still holds `lease_id`:
+    /// one that is up or about to be (e.g. the replacement of a GPU swap)
+    pub async fn lease_in_use(&self, lease_id: &str, except: &str) -> Result<bool> {
+        Ok(self.list_managed().await?.iter().any(|c| {
+            c.id != except
+                && c.lease_id.as_deref() == Some(lease_id)
+                && matches!(c.state.as_str(), "created" | "running" | "restarting" | "paused")
+        }))
+    }
+
+    /// One container we created; `None` if it isn't ours
+    pub async fn managed_container(&self, container_id: &str) -> Result<Option<ManagedContainer>> {
+        let inspect = self.docker.inspect_container(container_id, false).await?;
+        Ok(ManagedContainer::from_inspect(inspect))
+    }
+
+    /// Containers we created (`gpu-share.managed`), running or not, with their
+    /// state as Docker reports it now
+    pub async fn list_managed(&self) -> Result<Vec<ManagedContainer>> {
+        let options = ListContainersOptions {
+            all: true,
+            filters: HashMap::from([("label".to_string(), vec![format!("{}=true", LABEL_MANAGED)])]),
+            ..Default::default()
+        };
+        let mut managed = Vec::new();
+        for summary in self.docker.list_containers(options).await? {
+            let Some(id) = summary.id else { continue };
+            // Removed between list and inspect
+            match self.docker.inspect_container(&id, false).await {
+                Ok(inspect) => managed.extend(ManagedContainer::from_inspect(inspect)),
+                Err(e) => debug!("Skipping {}: {}", id, e),
+            }
+        }
+        managed.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(managed)
+    }
+
+    /// Lifecycle events of managed containers, from now on
+    pub fn container_events(&self) -> BoxStream<'static, Result<ContainerEvent>> {
+        let options = EventsOptions {
+            filters: HashMap::from([
+                ("type".to_string(), vec!["container".to_string()]),
+                ("label".to_string(), vec![format!("{}=true", LABEL_MANAGED)]),
+            ]),
+            ..Default::default()
+        };
+        self.docker
+            .events(options)
+            .filter_map(|item| {
+                futures_util::future::ready(match item {
+                    Ok(message) => ContainerEvent::from_docker(message).map(Ok),
+                    Err(e) => Some(Err(e.into())),
+                })
+            })
+            .boxed()
+    }
+
+    /// Restart policy, restart count and OOM flag of a container that just exited
+    pub async fn exit_context(&self, container_id: &str) -> Result<ExitContext> {
+        let inspect = self.docker.inspect_container(container_id, false).await?;
+        Ok(ExitContext::from_inspect(&inspect))
+    }
+
+    /// Streams a container's output; with `follow` it ends when the container stops
+    pub fn logs(&self, container_id: &str, options: &LogOptions) -> Result<BoxStream<'static, Result<LogLine>>> {
+        let stream = self
+            .docker
+            .logs(container_id, options.to_docker()?)
+            .filter_map(|item| {
+                futures_util::future::ready(match item {
+                    Ok(output) => LogLine::from_output(output).map(Ok),
+                    Err(e) => Some(Err(e.into())),
+                })
+            });
+        Ok(stream.boxed())
+    }
+
+    /// Starts an interactive TTY process in a running container. See `core::exec`.
+    pub async fn open_exec(&self, container_id: &str, request: &ExecRequest) -> Result<ExecSession> {
+        let exec_id = self
+            .docker
+            .create_exec(
+                container_id,
+                CreateExecOptions {
+                    attach_stdin: Some(true),
+                    attach_stdout: Some(true),
+                    attach_stderr: Some(true),
+                    tty: Some(true),
+                    env: Some(vec!["TERM=xterm-256color".to_string()]),
+                    cmd: Some(request.command()),
+                    user: request.user.clone(),
+                    working_dir: request.workdir.clone(),
+                    ..Default::default()
+                },
+            )
+            .await?;
+
+        match self.docker.start_exec(&exec_id).await? {
+            StartExecResults::Attached { output, input } => {
+                // The TTY exists once the exec has started
+                if let Err(e) = self.resize_exec(&exec_id, request.cols, request.rows).await {
+                    debug!("Initial resize of exec {} failed: {}", exec_id, e);
+                }
+                Ok(ExecSession {
+                    exec_id,
+                    output: output.map(|item| item.map_err(anyhow::Error::from)).boxed(),
+                    input,
+                })
+            }
+            StartExecResults::Detached => Err(anyhow!("Exec {} started detached", exec_id)),
+        }
+    }
+
+    pub async fn resize_exec(&self, exec_id: &str, cols: u16, rows: u16) -> Result<()> {
+        self.docker.resize_exec(exec_id, cols, rows).await?;
+        Ok(())
+    }
+
+    /// `None` while the process is still running
+    pub async fn exec_exit_code(&self, exec_id: &str) -> Result<Option<i64>> {
+        let inspect = self.docker.inspect_exec(exec_id).await?;
+        Ok(if inspect.running == Some(true) { None } else { inspect.exit_code })
+    }
+
+    /// Gives a container `gpu` (replacing any GPU it had) by recreating it
+    pub async fn attach_gpu(&self, container_id: &str, gpu: &GpuAttachment, dry_run: bool) -> Result<RecreateOutcome> {
+        self.recreate_with_gpu(container_id, Some(gpu), dry_run).await
+    }
+
+    /// Takes a container's GPU away by recreating it without one
+    pub async fn detach_gpu(&self, container_id: &str, dry_run: bool) -> Result<RecreateOutcome> {
+        self.recreate_with_gpu(container_id, None, dry_run).await
+    }
+
+    /// Replaces the container with an identical one carrying `gpu`. See `core::recreate`.
+    pub async fn recreate_with_gpu(
+        &self,
+        container_id: &str,
+        gpu: Option<&GpuAttachment>,
+        dry_run: bool,
+    ) -> Result<RecreateOutcome> {
+        let before = ContainerSnapshot::from_inspect(self.docker.inspect_container(container_id, false).await?)?;
+        let after = before.with_gpu(gpu);
+        let plan = RecreatePlan::new(&before, &after);
+
+        if dry_run || plan.is_noop() {
+            return Ok(RecreateOutcome { dry_run, recreated: false, container_id: before.id.clone(), plan });
+        }
+
+        info!("🔁 Recreating {} for GPU change (+{:?} -{:?})", before.name, plan.devices_added, plan.devices_removed);
+        if before.running {
+            self.plan_stop(&before.id);
+            if let Err(e) = self.docker.stop_container(&before.id, Some(10)).await {
+                self.end_planned_stop(&before.id);
+                return Err(e.into());
+            }
+        }
+        let parked = format!("{}-replaced-{}", before.name, &before.id[..before.id.len().min(12)]);
+        if let Err(e) = self.docker.rename_container(&before.id, &parked).await {
+            if before.running {
+                let _ = self.docker.start_container(&before.id).await;
+            }
+            return Err(e.into());
+        }
+
+        let new_id = match self.create_replacement(&after).await {
+            Ok(id) => id,
+            Err(e) => {
+                warn!("Recreating {} failed, restoring the original: {}", before.name, e);
+                self.restore_original(&before).await;
+                return Err(e);
+            }
+        };
+
+        let options = RemoveContainerOptions { v: false, force: true, ..Default::default() };
+        if let Err(e) = self.docker.remove_container(&before.id, Some(options)).await {
+            warn!("Replaced container {} left behind as {}: {}", before.id, parked, e);
+        }
+
+        Ok(RecreateOutcome { dry_run: false, recreated: true, container_id: new_id, plan })
+    }
+
+    async fn create_replacement(&self, snapshot: &ContainerSnapshot) -> Result<String> {
+        let id = self.docker.create_container(&snapshot.name, snapshot.create_config()).await?;
+
+        for (network, endpoint) in snapshot.extra_networks() {
+            let endpoint_config = EndpointSettings { aliases: endpoint.aliases.clone(), ..Default::default() };
+            let connect = ConnectNetworkOptions { container: id.clone(), endpoint_config };
+            if let Err(e) = self.docker.connect_network(network, connect).await {
+                let _ = self.docker.remove_container(&id, None).await;
+                return Err(anyhow!("Can't reconnect {} to network {}: {}", snapshot.name, network, e));
+            }
+        }
+
+        if snapshot.running {
+            if let Err(e) = self.docker.start_container(&id).await {
+                let _ = self.docker.remove_container(&id, None).await;
+                return Err(e.into());
+            }
+        }
+        Ok(id)
+    }
+
+    /// Best effort - puts the original back under its name and state
+    async fn restore_original(&self, snapshot: &ContainerSnapshot) {
+        if let Err(e) = self.docker.rename_container(&snapshot.id, &snapshot.name).await {
+            warn!("Couldn't rename {} back to {}: {}", snapshot.id, snapshot.name, e);
+        }
+        if snapshot.running {
+            if let Err(e) = self.docker.start_container(&snapshot.id).await {
+                warn!("Couldn't restart {}: {}", snapshot.name, e);
+            }
+        }
+    }
 }
 
 fn calculate_cpu_percent(stats: &Stats) -> f64 {
@@ -106,44 +798,589 @@ pub struct ContainerStats {
     pub memory_usage: f64,
 }
 
-#[derive(Debug, Clone)]
+/// A container we created, as listed by `DockerManager::list_managed`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ManagedContainer {
+    pub id: String,
+    pub name: String,
+    pub image: String,
+    /// Docker's state: created, running, paused, restarting, exited, dead...
+    pub state: String,
+    pub running: bool,
+    pub owner: Option<String>,
+    pub lease_id: Option<String>,
+    pub project: Option<String>,
+    pub gpu_id: Option<String>,
+    /// Whether the container really has GPU devices, whatever its labels say
+    pub gpu_attached: bool,
+    /// Of the last exit, once it has exited
+    pub exit_code: Option<i64>,
+    pub oom_killed: bool,
+    pub restart_count: i64,
+}
+
+impl ManagedContainer {
+    /// `None` for containers without the managed label
+    pub fn from_inspect(inspect: ContainerInspectResponse) -> Option<Self> {
+        let mut labels = inspect.config.as_ref()?.labels.clone().unwrap_or_default();
+        if labels.get(LABEL_MANAGED).map(String::as_str) != Some("true") {
+            return None;
+        }
+        let state = inspect.state.as_ref();
+        let host = inspect.host_config.as_ref();
+        let gpu_attached = host.is_some_and(|h| {
+            h.device_requests.iter().flatten().any(is_gpu_device_request)
+                || h.devices.iter().flatten().any(is_gpu_device_mapping)
+        });
+        Some(Self {
+            id: inspect.id.clone()?,
+            name: inspect.name.as_deref().unwrap_or_default().trim_start_matches('/').to_string(),
+            image: inspect.config.as_ref().and_then(|c| c.image.clone()).unwrap_or_default(),
+            state: state
+                .and_then(|s| s.status)
+                .map(|status| status.to_string())
+                .unwrap_or_else(|| "unknown".to_string()),
+            running: state.and_then(|s| s.running).unwrap_or(false),
+            owner: take_label(&mut labels, LABEL_OWNER),
+            lease_id: take_label(&mut labels, LABEL_LEASE),
+            project: take_label(&mut labels, LABEL_PROJECT),
+            gpu_id: take_label(&mut labels, LABEL_GPU),
+            gpu_attached,
+            exit_code: state.filter(|s| s.running != Some(true)).and_then(|s| s.exit_code),
+            oom_killed: state.and_then(|s| s.oom_killed).unwrap_or(false),
+            restart_count: inspect.restart_count.unwrap_or(0),
+        })
+    }
+}
+
+/// Blank values count as unset - snapshot images blank the labels they shouldn't pass on
+fn take_label(labels: &mut HashMap<String, String>, key: &str) -> Option<String> {
+    labels.remove(key).filter(|value| !value.is_empty())
+}
+
+/// Label keys stamped on managed containers
+pub const LABEL_PREFIX: &str = "gpu-share.";
+pub const LABEL_OWNER: &str = "gpu-share.owner";
+pub const LABEL_LEASE: &str = "gpu-share.lease-id";
+pub const LABEL_PROJECT: &str = "gpu-share.project";
+/// Always `"true"`; listing and reconciliation only look at containers with it
+pub const LABEL_MANAGED: &str = "gpu-share.managed";
+/// Host GPU (`GpuAttachment::gpu_id`) the container was given
+pub const LABEL_GPU: &str = "gpu-share.gpu-id";
+/// Comma-separated `group_add` ids the attachment added, so a GPU swap can take them back
+pub const LABEL_GPU_GROUPS: &str = "gpu-share.gpu-groups";
+
+/// Smallest memory limit Docker accepts
+const MIN_MEMORY_MB: u64 = 6;
+
+/// Everything needed to create a container. Only `image` and `name` are required.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ContainerConfig {
     pub image: String,
     pub name: String,
+    /// GPU requested for the container - resolved to a `GpuAttachment` by the caller
+    #[serde(skip)]
     pub gpu_id: Option<GPUConfig>,
+    /// Overrides the image's CMD
+    pub command: Option<Vec<String>>,
+    /// Overrides the image's ENTRYPOINT
+    pub entrypoint: Option<Vec<String>>,
+    pub env: BTreeMap<String, String>,
+    pub volumes: Vec<VolumeMount>,
+    pub ports: Vec<PortMapping>,
+    pub resources: ResourceLimits,
+    /// `/dev/shm` size - PyTorch dataloaders want far more than Docker's 64 MB
+    pub shm_size_mb: Option<u64>,
+    pub restart_policy: RestartPolicy,
+    pub labels: ContainerLabels,
+    /// Network to join instead of the default bridge
+    pub network: Option<String>,
+}
+
+/// A named volume or host path mounted into the container
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VolumeMount {
+    /// Volume name, or an absolute host path for a bind mount
+    pub source: String,
+    pub target: String,
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+impl VolumeMount {
+    fn to_bind(&self) -> String {
+        let mode = if self.read_only { ":ro" } else { "" };
+        format!("{}:{}{}", self.source, self.target, mode)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+impl Protocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+        }
+    }
+}
+
+/// Publishes `container_port`; without `host_port` Docker picks a free one
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortMapping {
+    pub container_port: u16,
+    #[serde(default)]
+    pub host_port: Option<u16>,
+    #[serde(default)]
+    pub protocol: Protocol,
+    #[serde(default)]
+    pub host_ip: Option<String>,
+}
+
+impl PortMapping {
+    fn key(&self) -> String {
+        format!("{}/{}", self.container_port, self.protocol.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResourceLimits {
+    /// Fractional CPUs, e.g. 2.5
+    pub cpus: Option<f64>,
+    pub memory_mb: Option<u64>,
+}
+
+/// `"no"`, `"always"`, `"unless-stopped"` or `{"on-failure": {"max_retries": 3}}`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    #[default]
+    No,
+    Always,
+    UnlessStopped,
+    OnFailure {
+        #[serde(default)]
+        max_retries: Option<u32>,
+    },
+}
+
+impl RestartPolicy {
+    pub fn from_docker(policy: &DockerRestartPolicy) -> Self {
+        match policy.name {
+            Some(RestartPolicyNameEnum::ALWAYS) => RestartPolicy::Always,
+            Some(RestartPolicyNameEnum::UNLESS_STOPPED) => RestartPolicy::UnlessStopped,
+            Some(RestartPolicyNameEnum::ON_FAILURE) => RestartPolicy::OnFailure {
+                max_retries: policy.maximum_retry_count.filter(|&n| n > 0).and_then(|n| u32::try_from(n).ok()),
+            },
+            _ => RestartPolicy::No,
+        }
+    }
+
+    /// Whether Docker restarts a container with this policy that exited with
+    /// `exit_code` after `restart_count` restarts. Ignores manual stops, which
+    /// Docker never restarts.
+    pub fn restarts_after(&self, exit_code: i64, restart_count: i64) -> bool {
+        match self {
+            RestartPolicy::No => false,
+            RestartPolicy::Always | RestartPolicy::UnlessStopped => true,
+            RestartPolicy::OnFailure { max_retries } => {
+                exit_code != 0 && max_retries.is_none_or(|max| restart_count < i64::from(max))
+            }
+        }
+    }
+
+    fn to_docker(&self) -> DockerRestartPolicy {
+        let (name, retries) = match self {
+            RestartPolicy::No => (RestartPolicyNameEnum::NO, None),
+            RestartPolicy::Always => (RestartPolicyNameEnum::ALWAYS, None),
+            RestartPolicy::UnlessStopped => (RestartPolicyNameEnum::UNLESS_STOPPED, None),
+            RestartPolicy::OnFailure { max_retries } => (RestartPolicyNameEnum::ON_FAILURE, max_retries.map(i64::from)),
+        };
+        DockerRestartPolicy { name: Some(name), maximum_retry_count: retries }
+    }
+}
+
+/// Who a container belongs to, plus free-form labels (which can't use our prefix)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContainerLabels {
+    pub owner: Option<String>,
+    pub lease_id: Option<String>,
+    pub project: Option<String>,
+    pub extra: BTreeMap<String, String>,
+}
+
+impl ContainerLabels {
+    pub fn to_map(&self) -> HashMap<String, String> {
+        let mut labels: HashMap<String, String> = self.extra.clone().into_iter().collect();
+        for (key, value) in [(LABEL_OWNER, &self.owner), (LABEL_LEASE, &self.lease_id), (LABEL_PROJECT, &self.project)] {
+            if let Some(value) = value {
+                labels.insert(key.to_string(), value.clone());
+            }
+        }
+        labels
+    }
+}
+
+impl ContainerConfig {
+    /// Checks everything Docker would otherwise reject halfway through a create
+    pub fn validate(&self) -> Result<()> {
+        if !is_valid_name(&self.name) {
+            return Err(anyhow!("Invalid container name '{}' (letters, digits, '_', '.', '-'; must start alphanumeric)", self.name));
+        }
+        if self.image.trim().is_empty() || self.image.contains(char::is_whitespace) {
+            return Err(anyhow!("Invalid image reference '{}'", self.image));
+        }
+        for (field, args) in [("command", &self.command), ("entrypoint", &self.entrypoint)] {
+            if args.as_ref().is_some_and(|a| a.is_empty()) {
+                return Err(anyhow!("{} must not be empty when given", field));
+            }
+        }
+        if let Some(key) = self.env.keys().find(|k| k.is_empty() || k.contains('=') || k.contains(char::is_whitespace)) {
+            return Err(anyhow!("Invalid environment variable name '{}'", key));
+        }
+
+        let mut targets = HashSet::new();
+        for volume in &self.volumes {
+            if !volume.target.starts_with('/') || volume.target.contains(':') {
+                return Err(anyhow!("Volume target '{}' must be an absolute container path", volume.target));
+            }
+            let bind = volume.source.contains('/');
+            let valid_source = if bind {
+                volume.source.starts_with('/') && !volume.source.contains(':')
+            } else {
+                is_valid_name(&volume.source)
+            };
+            if !valid_source {
+                return Err(anyhow!("Volume source '{}' must be a volume name or an absolute host path", volume.source));
+            }
+            if !targets.insert(volume.target.as_str()) {
+                return Err(anyhow!("Volume target '{}' is mounted twice", volume.target));
+            }
+        }
+
+        let mut published = HashSet::new();
+        for port in &self.ports {
+            if port.container_port == 0 {
+                return Err(anyhow!("Container port 0 can't be published"));
+            }
+            if let Some(host_port) = port.host_port {
+                let ip = port.host_ip.as_deref().unwrap_or("0.0.0.0");
+                if !published.insert((ip, host_port, port.protocol)) {
+                    return Err(anyhow!("Host port {}:{}/{} is published twice", ip, host_port, port.protocol.as_str()));
+                }
+            }
+        }
+
+        if let Some(cpus) = self.resources.cpus {
+            if !cpus.is_finite() || cpus <= 0.0 {
+                return Err(anyhow!("CPU limit must be positive, got {}", cpus));
+            }
+        }
+        if self.resources.memory_mb.is_some_and(|mb| mb < MIN_MEMORY_MB) {
+            return Err(anyhow!("Memory limit must be at least {} MB", MIN_MEMORY_MB));
+        }
+        if self.shm_size_mb == Some(0) {
+            return Err(anyhow!("shm size must be positive"));
+        }
+        if let Some(key) = self.labels.extra.keys().find(|k| k.starts_with(LABEL_PREFIX)) {
+            return Err(anyhow!("Label '{}' uses the reserved '{}' prefix", key, LABEL_PREFIX));
+        }
+        if self.network.as_deref().is_some_and(|n| n.trim().is_empty()) {
+            return Err(anyhow!("Network name must not be empty"));
+        }
+        Ok(())
+    }
+
+    /// Validates and maps onto bollard's create body. `gpu` must match `gpu_id`.
+    pub fn to_docker(&self, gpu: Option<&GpuAttachment>) -> Result<Config<String>> {
+        self.validate()?;
+        match (&self.gpu_id, gpu) {
+            (Some(wanted), Some(attachment)) if wanted.gpu_id != attachment.gpu_id => {
+                return Err(anyhow!(
+                    "Container {} asks for GPU {} but was given {}",
+                    self.name, wanted.gpu_id, attachment.gpu_id
+                ));
+            }
+            (Some(wanted), None) => {
+                return Err(anyhow!("Container {} asks for GPU {} but no attachment was given", self.name, wanted.gpu_id));
+            }
+            _ => {}
+        }
+
+        let mut env: Vec<String> = self.env.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+
+        let mut exposed_ports = HashMap::new();
+        let mut port_bindings: HashMap<String, Option<Vec<PortBinding>>> = HashMap::new();
+        for port in &self.ports {
+            exposed_ports.insert(port.key(), HashMap::new());
+            port_bindings.entry(port.key()).or_default().get_or_insert_with(Vec::new).push(PortBinding {
+                host_ip: port.host_ip.clone(),
+                host_port: Some(port.host_port.map(|p| p.to_string()).unwrap_or_default()),
+            });
+        }
+
+        let mut host_config = HostConfig {
+            binds: (!self.volumes.is_empty()).then(|| self.volumes.iter().map(VolumeMount::to_bind).collect()),
+            port_bindings: (!port_bindings.is_empty()).then_some(port_bindings),
+            nano_cpus: self.resources.cpus.map(|cpus| (cpus * 1e9) as i64),
+            memory: self.resources.memory_mb.map(|mb| (mb * 1024 * 1024) as i64),
+            shm_size: self.shm_size_mb.map(|mb| (mb * 1024 * 1024) as i64),
+            restart_policy: Some(self.restart_policy.to_docker()),
+            network_mode: self.network.clone(),
+            ..Default::default()
+        };
+        let mut labels = self.labels.to_map();
+        labels.insert(LABEL_MANAGED.to_string(), "true".to_string());
+        if let Some(attachment) = gpu {
+            let groups = attachment.missing_groups(&host_config);
+            attachment.apply(&mut host_config, &mut env);
+            labels.insert(LABEL_GPU.to_string(), attachment.gpu_id.clone());
+            if !groups.is_empty() {
+                labels.insert(LABEL_GPU_GROUPS.to_string(), groups.join(","));
+            }
+        }
+        Ok(Config {
+            image: Some(self.image.clone()),
+            cmd: self.command.clone(),
+            entrypoint: self.entrypoint.clone(),
+            env: (!env.is_empty()).then_some(env),
+            exposed_ports: (!exposed_ports.is_empty()).then_some(exposed_ports),
+            labels: Some(labels),
+            host_config: Some(host_config),
+            ..Default::default()
+        })
+    }
+}
+
+/// Docker's rule for container and volume names
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphanumeric())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use tokio::time::{sleep, Duration};
+    use crate::core::fake_runtime::FakeRuntime;
 
     #[tokio::test]
     async fn test_container_lifecycle() {
-        let manager = DockerManager::new().unwrap();
+        let manager = DockerManager::with_runtime(Arc::new(FakeRuntime::new().with_image("alpine")));
         let container_name = "integration-test-container";
 
-        // Create
-        manager.create_container("alpine", container_name)
+        // Create (and start)
+        let id = manager.create_container("alpine", container_name)
             .await
             .unwrap();
-        
-        // Start
+        assert_eq!(manager.lookup_container(container_name).await.unwrap(), id);
+
+        // Start again is a no-op
         manager.start_container(container_name).await.unwrap();
-        
-        // Verify running
+
+        // Verify running; Docker lists names with a leading slash
         let containers = manager.list_containers().await.unwrap();
-        assert!(containers.contains(&container_name.to_string()));
+        assert!(containers.contains(&format!("/{}", container_name)));
+        assert!(manager.is_container_active(&id).await.unwrap());
+
+        // Running containers can't be deleted
+        assert!(manager.delete_container(container_name).await.is_err());
 
         // Stop
         manager.stop_container(container_name).await.unwrap();
-        
+        assert!(!manager.is_container_active(&id).await.unwrap());
+        assert!(manager.list_containers().await.unwrap().is_empty());
+
         // Delete
         manager.delete_container(container_name).await.unwrap();
-        
-        // Verify deletion
-        sleep(Duration::from_secs(1)).await; // Wait for Docker API sync 
-        let containers = manager.list_containers().await.unwrap();
-        assert!(!containers.contains(&container_name.to_string()));
+        assert!(manager.lookup_container(container_name).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_network_egress_follows_the_config() {
+        let runtime = FakeRuntime::new();
+        let scope = NetworkScope::Project("secure".into());
+        let open = DockerManager::with_runtime(Arc::new(runtime.clone()));
+        let name = open.ensure_network(&scope).await.unwrap();
+        assert_eq!(runtime.inspect_network(&name).await.unwrap().internal, Some(false));
+
+        // Restricted later: the empty network is recreated without egress
+        let restricted = DockerManager::with_runtime(Arc::new(runtime.clone())).with_network_config(NetworkConfig {
+            restricted_projects: vec!["secure".into()],
+            ..Default::default()
+        });
+        restricted.ensure_network(&scope).await.unwrap();
+        assert_eq!(runtime.inspect_network(&name).await.unwrap().internal, Some(true));
+        restricted.ensure_network(&scope).await.unwrap();
+
+        // In use, it is left alone and the mismatch is an error
+        let runtime = runtime.with_image("alpine");
+        let id = open.create_container("alpine", "secure-job").await.unwrap();
+        runtime
+            .connect_network(&name, ConnectNetworkOptions { container: id, ..Default::default() })
+            .await
+            .unwrap();
+        assert!(open.ensure_network(&scope).await.unwrap_err().to_string().contains("stop its containers"));
+        assert_eq!(runtime.inspect_network(&name).await.unwrap().internal, Some(true));
+    }
+
+    #[tokio::test]
+    async fn test_volume_errors_are_typed() {
+        let manager = DockerManager::with_runtime(Arc::new(FakeRuntime::new()));
+        let request = VolumeRequest { name: "datasets".into(), ..Default::default() };
+        manager.create_volume(&request, Some("alice")).await.unwrap();
+
+        let err = manager.create_volume(&request, Some("bob")).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<VolumeError>(), Some(&VolumeError::AlreadyExists("datasets".into())));
+        let err = manager.find_volume("checkpoints").await.unwrap_err();
+        assert_eq!(err.downcast_ref::<VolumeError>(), Some(&VolumeError::NotFound("checkpoints".into())));
     }
-} 
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_missing_snapshot_is_typed() {
+        let manager = DockerManager::with_runtime(Arc::new(FakeRuntime::new()));
+        let err = manager.find_snapshot("gpu-share-snapshot/operator:nope").await.unwrap_err();
+        assert_eq!(err.downcast_ref::<SnapshotNotFound>(), Some(&SnapshotNotFound("gpu-share-snapshot/operator:nope".into())));
+    }
+
+    fn spec() -> ContainerConfig {
+        ContainerConfig {
+            image: "pytorch/pytorch:2.3".into(),
+            name: "alice-train".into(),
+            command: Some(vec!["python".into(), "train.py".into()]),
+            env: BTreeMap::from([("EPOCHS".to_string(), "10".to_string())]),
+            volumes: vec![
+                VolumeMount { source: "datasets".into(), target: "/data".into(), read_only: true },
+                VolumeMount { source: "/srv/ckpt".into(), target: "/ckpt".into(), read_only: false },
+            ],
+            ports: vec![PortMapping { container_port: 8888, host_port: Some(18888), protocol: Protocol::Tcp, host_ip: None }],
+            resources: ResourceLimits { cpus: Some(2.5), memory_mb: Some(8192) },
+            shm_size_mb: Some(2048),
+            restart_policy: RestartPolicy::OnFailure { max_retries: Some(3) },
+            labels: ContainerLabels {
+                owner: Some("alice".into()),
+                lease_id: Some("lease-1".into()),
+                extra: BTreeMap::from([("team".to_string(), "vision".to_string())]),
+                ..Default::default()
+            },
+            network: Some("alice-net".into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_spec_maps_onto_docker_config() {
+        let config = spec().to_docker(None).unwrap();
+        assert_eq!(config.cmd, Some(vec!["python".to_string(), "train.py".to_string()]));
+        assert_eq!(config.env, Some(vec!["EPOCHS=10".to_string()]));
+        assert!(config.exposed_ports.unwrap().contains_key("8888/tcp"));
+        let labels = config.labels.unwrap();
+        assert_eq!(labels[LABEL_OWNER], "alice");
+        assert_eq!(labels[LABEL_LEASE], "lease-1");
+        assert_eq!(labels["team"], "vision");
+
+        let host = config.host_config.unwrap();
+        assert_eq!(host.binds, Some(vec!["datasets:/data:ro".to_string(), "/srv/ckpt:/ckpt".to_string()]));
+        assert_eq!(host.nano_cpus, Some(2_500_000_000));
+        assert_eq!(host.memory, Some(8192 * 1024 * 1024));
+        assert_eq!(host.shm_size, Some(2048 * 1024 * 1024));
+        assert_eq!(host.network_mode.as_deref(), Some("alice-net"));
+        assert_eq!(host.restart_policy.unwrap().maximum_retry_count, Some(3));
+        let binding = &host.port_bindings.unwrap()["8888/tcp"].clone().unwrap()[0];
+        assert_eq!(binding.host_port.as_deref(), Some("18888"));
+    }
+
+    #[test]
+    fn test_spec_validation() {
+        type Breakage = fn(&mut ContainerConfig);
+        let cases: Vec<(&str, Breakage)> = vec![
+            ("name", |c| c.name = "-bad".into()),
+            ("absolute", |c| c.volumes[0].target = "data".into()),
+            ("volume name", |c| c.volumes[1].source = "srv/ckpt".into()),
+            ("twice", |c| c.ports.push(c.ports[0].clone())),
+            ("CPU", |c| c.resources.cpus = Some(0.0)),
+            ("Memory", |c| c.resources.memory_mb = Some(1)),
+            ("reserved", |c| { c.labels.extra.insert(LABEL_OWNER.into(), "mallory".into()); }),
+            ("environment", |c| { c.env.insert("A=B".into(), "x".into()); }),
+        ];
+        for (expected, break_it) in cases {
+            let mut config = spec();
+            break_it(&mut config);
+            let err = config.validate().unwrap_err().to_string();
+            assert!(err.contains(expected), "{} not in '{}'", expected, err);
+        }
+
+        let mut config = spec();
+        config.gpu_id = Some(GPUConfig::from("GPU-1"));
+        assert!(config.to_docker(None).is_err());
+    }
+
+    #[test]
+    fn test_spec_from_json() {
+        let config: ContainerConfig = serde_json::from_value(serde_json::json!({
+            "image": "alpine",
+            "name": "tiny",
+            "ports": [{"container_port": 80}],
+            "restart_policy": "unless-stopped",
+            "labels": {"project": "demo"}
+        }))
+        .unwrap();
+        assert_eq!(config.restart_policy, RestartPolicy::UnlessStopped);
+        assert_eq!(config.ports[0].protocol, Protocol::Tcp);
+        let docker = config.to_docker(None).unwrap();
+        assert_eq!(docker.labels.unwrap()[LABEL_PROJECT], "demo");
+        // No host port - Docker picks one
+        let bindings = docker.host_config.unwrap().port_bindings.unwrap();
+        assert_eq!(bindings["80/tcp"].as_ref().unwrap()[0].host_port.as_deref(), Some(""));
+    }
+
+    #[test]
+    fn test_managed_container_from_inspect() {
+        use bollard::models::{ContainerState, ContainerStateStatusEnum};
+
+        let card = crate::gpu::device::GPUInfo { id: "GPU-1234".into(), vendor: "NVIDIA".into(), ..Default::default() };
+        let gpu = crate::gpu::container::GpuAttacher::new().attachment(&card).unwrap();
+        let mut config = spec();
+        config.gpu_id = Some(GPUConfig::from("GPU-1234"));
+        let created = config.to_docker(Some(&gpu)).unwrap();
+        let inspect = ContainerInspectResponse {
+            id: Some("c0ffee".into()),
+            name: Some(format!("/{}", config.name)),
+            state: Some(ContainerState {
+                status: Some(ContainerStateStatusEnum::EXITED),
+                running: Some(false),
+                ..Default::default()
+            }),
+            config: Some(bollard::models::ContainerConfig {
+                image: created.image.clone(),
+                labels: created.labels.clone(),
+                ..Default::default()
+            }),
+            host_config: created.host_config.clone(),
+            ..Default::default()
+        };
+
+        let managed = ManagedContainer::from_inspect(inspect.clone()).unwrap();
+        assert_eq!(managed.name, config.name);
+        assert_eq!(managed.state, "exited");
+        assert!(!managed.running);
+        assert_eq!(managed.owner.as_deref(), Some("alice"));
+        assert_eq!(managed.lease_id.as_deref(), Some("lease-1"));
+        assert_eq!(managed.gpu_id.as_deref(), Some("GPU-1234"));
+        assert!(managed.gpu_attached);
+
+        // Containers we didn't create are invisible
+        let mut foreign = inspect;
+        foreign.config.as_mut().unwrap().labels.as_mut().unwrap().remove(LABEL_MANAGED);
+        assert!(ManagedContainer::from_inspect(foreign).is_none());
+    }
+}
@@ -0,0 +1,160 @@
+/*
+* Interactive exec
+* ----------------
+* `DockerManager::open_exec` starts a process with a TTY inside a running
+* container and hands back its output stream and stdin. The API bridges the two
+* over a WebSocket: binary frames carry terminal bytes both ways, text frames
+* carry the JSON control messages below. Sessions are capped at
+* `MAX_SESSION_LIMIT`; Docker can't kill an exec'd process, so ending a session
+* closes its stdin, which makes shells (and most REPLs) exit.
+*/
+
+use std::pin::Pin;
+use std::time::Duration;
+
+use anyhow::Result;
+use bollard::container::LogOutput;
+use futures_util::stream::BoxStream;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWrite;
+
+pub const DEFAULT_SESSION_LIMIT: Duration = Duration::from_secs(30 * 60);
+pub const MAX_SESSION_LIMIT: Duration = Duration::from_secs(4 * 60 * 60);
+
+const DEFAULT_SHELL: &str = "/bin/sh";
+
+/// Query for `GET /api/v1/vms/{id}/exec`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExecRequest {
+    /// Shell command line to run; an interactive `/bin/sh` when missing
+    pub cmd: Option<String>,
+    /// User inside the container, defaults to the image's user
+    pub user: Option<String>,
+    pub workdir: Option<String>,
+    pub cols: u16,
+    pub rows: u16,
+    /// Session time limit, clamped to `MAX_SESSION_LIMIT`
+    pub timeout_secs: Option<u64>,
+}
+
+impl Default for ExecRequest {
+    fn default() -> Self {
+        Self { cmd: None, user: None, workdir: None, cols: 80, rows: 24, timeout_secs: None }
+    }
+}
+
+impl ExecRequest {
+    pub fn command(&self) -> Vec<String> {
+        match self.cmd.as_deref().map(str::trim) {
+            Some(cmd) if !cmd.is_empty() => vec![DEFAULT_SHELL.into(), "-c".into(), cmd.into()],
+            _ => vec![DEFAULT_SHELL.into()],
+        }
+    }
+
+    pub fn time_limit(&self) -> Duration {
+        self.timeout_secs
+            .filter(|&secs| secs > 0)
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_SESSION_LIMIT)
+            .min(MAX_SESSION_LIMIT)
+    }
+}
+
+/// Command line for `cmd` that runs `args` as given: anything beyond plain
+/// words is single-quoted, so `/bin/sh -c` splits it back the same way
+pub fn shell_join(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| {
+            let plain = !arg.is_empty()
+                && arg.chars().all(|c| c.is_ascii_alphanumeric() || "_./=:,+@%-".contains(c));
+            if plain {
+                arg.clone()
+            } else {
+                format!("'{}'", arg.replace('\'', "'\\''"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Text frames from the client
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    Resize { cols: u16, rows: u16 },
+    /// Input for clients that can't send binary frames
+    Stdin { data: String },
+}
+
+/// Text frames from the server; terminal output always goes as binary frames
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Started { session_id: String, time_limit_secs: u64 },
+    /// The time limit ran out; an `exit` follows
+    Timeout { after_secs: u64 },
+    Error { message: String },
+    /// Last frame before the socket closes. `code` is unknown when the process
+    /// outlived the session.
+    Exit { code: Option<i64> },
+}
+
+/// Why a session ended, for the audit log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecEnd {
+    /// The process exited
+    Exited,
+    ClientClosed,
+    Timeout,
+    Error,
+}
+
+pub struct ExecSession {
+    /// Docker's exec id, for resizing and reading the exit code
+    pub exec_id: String,
+    pub output: BoxStream<'static, Result<LogOutput>>,
+    pub input: Pin<Box<dyn AsyncWrite + Send>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exec_request_command_and_limit() {
+        let shell = ExecRequest::default();
+        assert_eq!(shell.command(), vec!["/bin/sh"]);
+        assert_eq!(shell.time_limit(), DEFAULT_SESSION_LIMIT);
+
+        let request: ExecRequest =
+            serde_json::from_value(serde_json::json!({"cmd": "nvidia-smi -L", "timeout_secs": 999999})).unwrap();
+        assert_eq!(request.command(), vec!["/bin/sh", "-c", "nvidia-smi -L"]);
+        assert_eq!(request.time_limit(), MAX_SESSION_LIMIT);
+        assert_eq!((request.cols, request.rows), (80, 24));
+
+        let short = ExecRequest { timeout_secs: Some(60), cmd: Some("  ".into()), ..Default::default() };
+        assert_eq!(short.time_limit(), Duration::from_secs(60));
+        assert_eq!(short.command(), vec!["/bin/sh"]);
+    }
+
+    #[test]
+    fn test_shell_join_keeps_arguments_apart() {
+        let args = |list: &[&str]| list.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        assert_eq!(shell_join(&args(&["nvidia-smi", "-L"])), "nvidia-smi -L");
+        assert_eq!(shell_join(&args(&["python", "-c", "print(1)"])), "python -c 'print(1)'");
+        assert_eq!(shell_join(&args(&["echo", "it's", ""])), r#"echo 'it'\''s' ''"#);
+        assert_eq!(shell_join(&args(&["ls", "$HOME; rm -rf /"])), "ls '$HOME; rm -rf /'");
+    }
+
+    #[test]
+    fn test_control_messages() {
+        let resize: ClientMessage = serde_json::from_str(r#"{"type":"resize","cols":120,"rows":40}"#).unwrap();
+        assert_eq!(resize, ClientMessage::Resize { cols: 120, rows: 40 });
+        assert!(serde_json::from_str::<ClientMessage>(r#"{"type":"kill"}"#).is_err());
+
+        let exit = serde_json::to_value(ServerMessage::Exit { code: Some(130) }).unwrap();
+        assert_eq!(exit, serde_json::json!({"type": "exit", "code": 130}));
+    }
+}
@@ -0,0 +1,1095 @@
+/*
+* In-process Docker stand-in
+* --------------------------
+* `FakeRuntime` implements `ContainerRuntime` over plain maps so everything
+* built on `DockerManager` runs in tests without a daemon. It behaves like
+* Docker where our code can tell the difference: containers need a local image
+* (pulls add it), names and networks conflict with 409, unknown objects are
+* 404s, running containers can't be removed without `force`, named volumes in
+* binds are created on the fly, and every state change is published on the
+* event stream with the container's labels as attributes.
+*
+* Time doesn't pass on its own. Tests drive what a real container would do:
+* `exit` (honouring the restart policy, like the daemon), `oom_kill`,
+* `set_usage` for stats, `push_log` for output, `set_volume_usage` for `df`.
+* Stopping exits with 0, execs succeed with no output, followed logs end at
+* what has been pushed so far. Restarts happen at once rather than after
+* Docker's backoff, so inspecting on `die` already sees the new restart count.
+*/
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use bollard::auth::DockerCredentials;
+use bollard::container::{
+    BlkioStats, CPUStats, CPUUsage, Config, ListContainersOptions, LogOutput, LogsOptions, MemoryStats, PidsStats,
+    RemoveContainerOptions, Stats, StorageStats, ThrottlingData,
+};
+use bollard::errors::Error;
+use bollard::exec::{CreateExecOptions, StartExecResults};
+use bollard::image::{CommitContainerOptions, CreateImageOptions, ListImagesOptions};
+use bollard::models::{
+    ContainerConfig, ContainerInspectResponse, ContainerState, ContainerStateStatusEnum, ContainerSummary, CreateImageInfo,
+    EndpointSettings, EventActor, EventMessage, EventMessageTypeEnum, ExecInspectResponse, ImageInspect, ImageSummary, Network,
+    NetworkContainer, NetworkSettings, Port, PortTypeEnum, SystemDataUsageResponse, Volume, VolumeScopeEnum, VolumeUsageData,
+};
+use bollard::network::{ConnectNetworkOptions, CreateNetworkOptions, ListNetworksOptions};
+use bollard::system::EventsOptions;
+use bollard::volume::CreateVolumeOptions;
+use chrono::Utc;
+use futures_util::stream::{self, BoxStream};
+use futures_util::StreamExt;
+use tokio::sync::broadcast;
+
+use crate::core::docker_manager::RestartPolicy;
+use crate::core::images::ImageRef;
+use crate::core::runtime::ContainerRuntime;
+
+/// Networks every daemon has
+const BUILTIN_NETWORKS: [&str; 3] = ["bridge", "host", "none"];
+/// Where the fake daemon hands out host ports for `host_port: ""`
+const EPHEMERAL_PORT_START: u16 = 49153;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Created,
+    Running,
+    Exited,
+}
+
+#[derive(Debug, Clone)]
+struct FakeContainer {
+    id: String,
+    name: String,
+    image_id: String,
+    config: Config<String>,
+    state: State,
+    exit_code: Option<i64>,
+    oom_killed: bool,
+    restart_count: i64,
+    created: i64,
+    networks: BTreeMap<String, EndpointSettings>,
+    /// (host port, container port, protocol), fixed at create
+    ports: Vec<(u16, u16, PortTypeEnum)>,
+    size_rw: i64,
+    cpu_percent: f64,
+    memory_bytes: u64,
+    logs: Vec<LogOutput>,
+}
+
+impl FakeContainer {
+    fn labels(&self) -> HashMap<String, String> {
+        self.config.labels.clone().unwrap_or_default()
+    }
+
+    fn restart_policy(&self) -> RestartPolicy {
+        self.config
+            .host_config
+            .as_ref()
+            .and_then(|h| h.restart_policy.as_ref())
+            .map(RestartPolicy::from_docker)
+            .unwrap_or_default()
+    }
+
+    fn binds(&self) -> Vec<String> {
+        self.config.host_config.as_ref().and_then(|h| h.binds.clone()).unwrap_or_default()
+    }
+
+    fn status(&self) -> ContainerStateStatusEnum {
+        match self.state {
+            State::Created => ContainerStateStatusEnum::CREATED,
+            State::Running => ContainerStateStatusEnum::RUNNING,
+            State::Exited => ContainerStateStatusEnum::EXITED,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct FakeImage {
+    id: String,
+    tags: Vec<String>,
+    labels: HashMap<String, String>,
+    created: i64,
+    size: i64,
+}
+
+#[derive(Debug, Clone)]
+struct FakeNetwork {
+    id: String,
+    internal: bool,
+    labels: HashMap<String, String>,
+    created: String,
+}
+
+#[derive(Debug, Clone)]
+struct FakeVolume {
+    labels: HashMap<String, String>,
+    created: String,
+    size: i64,
+}
+
+#[derive(Debug, Clone)]
+struct FakeExec {
+    container_id: String,
+}
+
+#[derive(Default)]
+struct Inner {
+    next_id: u64,
+    next_port: u16,
+    containers: BTreeMap<String, FakeContainer>,
+    images: BTreeMap<String, FakeImage>,
+    networks: BTreeMap<String, FakeNetwork>,
+    volumes: BTreeMap<String, FakeVolume>,
+    execs: HashMap<String, FakeExec>,
+}
+
+impl Inner {
+    /// 64 hex chars, like Docker's ids, deterministic per runtime
+    fn new_id(&mut self) -> String {
+        self.next_id += 1;
+        (0..4).map(|i| format!("{:016x}", splitmix64(self.next_id * 4 + i))).collect()
+    }
+
+    /// Full id, unique id prefix, name or `/name`
+    fn container_id(&self, id: &str) -> Result<String, Error> {
+        let name = id.trim_start_matches('/');
+        if let Some(container) = self.containers.values().find(|c| c.id == id || c.name == name) {
+            return Ok(container.id.clone());
+        }
+        let mut matches = self.containers.keys().filter(|key| !id.is_empty() && key.starts_with(id));
+        match (matches.next(), matches.next()) {
+            (Some(key), None) => Ok(key.clone()),
+            _ => Err(not_found(format!("No such container: {}", id))),
+        }
+    }
+
+    fn container(&mut self, id: &str) -> Result<&mut FakeContainer, Error> {
+        let id = self.container_id(id)?;
+        Ok(self.containers.get_mut(&id).expect("resolved id"))
+    }
+
+    /// Image id for an id, id prefix or (normalized) reference
+    fn image_id(&self, reference: &str) -> Result<String, Error> {
+        let hex = reference.trim_start_matches("sha256:");
+        if let Some(image) = self
+            .images
+            .values()
+            .find(|i| i.id == reference || (hex.len() >= 12 && i.id.trim_start_matches("sha256:").starts_with(hex)))
+        {
+            return Ok(image.id.clone());
+        }
+        let wanted = normalize(reference);
+        self.images
+            .values()
+            .find(|i| i.tags.iter().any(|tag| normalize(tag) == wanted))
+            .map(|i| i.id.clone())
+            .ok_or_else(|| not_found(format!("No such image: {}", reference)))
+    }
+
+    fn add_image(&mut self, reference: &str, labels: HashMap<String, String>, size: i64) -> String {
+        let id = format!("sha256:{}", self.new_id());
+        // A tag moves to the newest image carrying it
+        let wanted = normalize(reference);
+        for image in self.images.values_mut() {
+            image.tags.retain(|tag| normalize(tag) != wanted);
+        }
+        let image = FakeImage { id: id.clone(), tags: vec![reference.to_string()], labels, created: Utc::now().timestamp(), size };
+        self.images.insert(id.clone(), image);
+        id
+    }
+
+    fn containers_on<'a>(&'a self, network: &'a str) -> impl Iterator<Item = &'a FakeContainer> {
+        self.containers.values().filter(move |c| c.networks.contains_key(network))
+    }
+
+    fn volume_in_use(&self, name: &str) -> bool {
+        self.containers
+            .values()
+            .any(|c| c.binds().iter().any(|bind| bind.split(':').next() == Some(name)))
+    }
+}
+
+/// In-memory `ContainerRuntime` for tests. See the module docs.
+#[derive(Clone)]
+pub struct FakeRuntime {
+    inner: Arc<Mutex<Inner>>,
+    events: broadcast::Sender<EventMessage>,
+}
+
+impl Default for FakeRuntime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FakeRuntime {
+    pub fn new() -> Self {
+        let mut inner = Inner { next_port: EPHEMERAL_PORT_START, ..Default::default() };
+        for name in BUILTIN_NETWORKS {
+            let network = FakeNetwork {
+                id: inner.new_id(),
+                internal: false,
+                labels: HashMap::new(),
+                created: Utc::now().to_rfc3339(),
+            };
+            inner.networks.insert(name.to_string(), network);
+        }
+        let (events, _) = broadcast::channel(256);
+        Self { inner: Arc::new(Mutex::new(inner)), events }
+    }
+
+    /// Makes `reference` available locally, as if pulled earlier
+    pub fn with_image(self, reference: &str) -> Self {
+        self.lock().add_image(reference, HashMap::new(), 0);
+        self
+    }
+
+    pub fn has_image(&self, reference: &str) -> bool {
+        self.lock().image_id(reference).is_ok()
+    }
+
+    /// The container's main process exits with `code`. Like the daemon, a
+    /// restart policy that applies brings it straight back.
+    pub fn exit(&self, id: &str, code: i64) -> Result<(), Error> {
+        self.end_process(id, code, false)
+    }
+
+    /// The kernel OOM-kills the container's main process (exit code 137)
+    pub fn oom_kill(&self, id: &str) -> Result<(), Error> {
+        let container = self.lock().container(id)?.clone();
+        self.publish(&container, "oom", None);
+        self.end_process(id, 137, true)
+    }
+
+    /// What `stats` reports from now on
+    pub fn set_usage(&self, id: &str, cpu_percent: f64, memory_mb: u64) -> Result<(), Error> {
+        let mut inner = self.lock();
+        let container = inner.container(id)?;
+        container.cpu_percent = cpu_percent;
+        container.memory_bytes = memory_mb * 1024 * 1024;
+        Ok(())
+    }
+
+    /// Bytes in the container's writable layer (`size_rw`)
+    pub fn set_size_rw(&self, id: &str, bytes: i64) -> Result<(), Error> {
+        self.lock().container(id)?.size_rw = bytes;
+        Ok(())
+    }
+
+    pub fn push_log(&self, id: &str, line: LogOutput) -> Result<(), Error> {
+        self.lock().container(id)?.logs.push(line);
+        Ok(())
+    }
+
+    /// What `df` reports for the volume
+    pub fn set_volume_usage(&self, name: &str, bytes: i64) -> Result<(), Error> {
+        let mut inner = self.lock();
+        let volume = inner.volumes.get_mut(name).ok_or_else(|| not_found(format!("no such volume: {}", name)))?;
+        volume.size = bytes;
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn end_process(&self, id: &str, code: i64, oom_killed: bool) -> Result<(), Error> {
+        let (died, restarted) = {
+            let mut inner = self.lock();
+            let container = inner.container(id)?;
+            if container.state != State::Running {
+                return Err(conflict(format!("Container {} is not running", container.id)));
+            }
+            container.state = State::Exited;
+            container.exit_code = Some(code);
+            container.oom_killed = oom_killed;
+            let died = container.clone();
+            let restarted = container.restart_policy().restarts_after(code, container.restart_count).then(|| {
+                // Starting resets the exit state, as in Docker
+                container.restart_count += 1;
+                container.state = State::Running;
+                container.exit_code = None;
+                container.oom_killed = false;
+                container.clone()
+            });
+            (died, restarted)
+        };
+        self.publish(&died, "die", Some(code));
+        if let Some(container) = restarted {
+            self.publish(&container, "start", None);
+        }
+        Ok(())
+    }
+
+    /// Container event with the labels, image and name as attributes, like Docker's
+    fn publish(&self, container: &FakeContainer, action: &str, exit_code: Option<i64>) {
+        let mut attributes = container.labels();
+        attributes.insert("name".to_string(), container.name.clone());
+        attributes.insert("image".to_string(), container.config.image.clone().unwrap_or_default());
+        if let Some(code) = exit_code {
+            attributes.insert("exitCode".to_string(), code.to_string());
+        }
+        let now = Utc::now();
+        let _ = self.events.send(EventMessage {
+            typ: Some(EventMessageTypeEnum::CONTAINER),
+            action: Some(action.to_string()),
+            actor: Some(EventActor { id: Some(container.id.clone()), attributes: Some(attributes) }),
+            time: Some(now.timestamp()),
+            time_nano: now.timestamp_nanos_opt(),
+            ..Default::default()
+        });
+    }
+
+    fn inspect(container: &FakeContainer, size: bool) -> ContainerInspectResponse {
+        // Docker reports the create body's container part back as `Config`
+        let config: ContainerConfig = serde_json::to_value(&container.config)
+            .and_then(serde_json::from_value)
+            .unwrap_or_default();
+        ContainerInspectResponse {
+            id: Some(container.id.clone()),
+            name: Some(format!("/{}", container.name)),
+            created: chrono::DateTime::from_timestamp(container.created, 0).map(|t| t.to_rfc3339()),
+            image: Some(container.image_id.clone()),
+            state: Some(ContainerState {
+                status: Some(container.status()),
+                running: Some(container.state == State::Running),
+                paused: Some(false),
+                restarting: Some(false),
+                oom_killed: Some(container.oom_killed),
+                dead: Some(false),
+                exit_code: Some(container.exit_code.unwrap_or(0)),
+                ..Default::default()
+            }),
+            restart_count: Some(container.restart_count),
+            config: Some(config),
+            host_config: Some(container.config.host_config.clone().unwrap_or_default()),
+            network_settings: Some(NetworkSettings {
+                networks: Some(container.networks.clone().into_iter().collect()),
+                ..Default::default()
+            }),
+            size_rw: size.then_some(container.size_rw),
+            ..Default::default()
+        }
+    }
+
+    fn stats_of(container: &FakeContainer) -> Stats {
+        // One CPU and a fixed system delta, so `cpu_percent` comes back as given
+        const SYSTEM_DELTA: u64 = 1_000_000_000;
+        let running = container.state == State::Running;
+        let cpu = |total_usage: u64, system: u64| CPUStats {
+            cpu_usage: CPUUsage { percpu_usage: None, usage_in_usermode: 0, total_usage, usage_in_kernelmode: 0 },
+            system_cpu_usage: Some(system),
+            online_cpus: Some(1),
+            throttling_data: ThrottlingData { periods: 0, throttled_periods: 0, throttled_time: 0 },
+        };
+        let busy = if running { (container.cpu_percent / 100.0 * SYSTEM_DELTA as f64) as u64 } else { 0 };
+        Stats {
+            read: Utc::now().to_rfc3339(),
+            preread: Utc::now().to_rfc3339(),
+            num_procs: u32::from(running),
+            pids_stats: PidsStats { current: Some(u64::from(running)), limit: None },
+            network: None,
+            networks: None,
+            memory_stats: MemoryStats {
+                stats: None,
+                max_usage: None,
+                usage: Some(if running { container.memory_bytes } else { 0 }),
+                failcnt: None,
+                limit: None,
+                commit: None,
+                commit_peak: None,
+                commitbytes: None,
+                commitpeakbytes: None,
+                privateworkingset: None,
+            },
+            blkio_stats: BlkioStats {
+                io_service_bytes_recursive: None,
+                io_serviced_recursive: None,
+                io_queue_recursive: None,
+                io_service_time_recursive: None,
+                io_wait_time_recursive: None,
+                io_merged_recursive: None,
+                io_time_recursive: None,
+                sectors_recursive: None,
+            },
+            cpu_stats: cpu(busy, 2 * SYSTEM_DELTA),
+            precpu_stats: cpu(0, SYSTEM_DELTA),
+            storage_stats: StorageStats {
+                read_count_normalized: None,
+                read_size_bytes: None,
+                write_count_normalized: None,
+                write_size_bytes: None,
+            },
+            name: format!("/{}", container.name),
+            id: container.id.clone(),
+        }
+    }
+
+    fn summary(container: &FakeContainer) -> ContainerSummary {
+        let running = container.state == State::Running;
+        ContainerSummary {
+            id: Some(container.id.clone()),
+            names: Some(vec![format!("/{}", container.name)]),
+            image: container.config.image.clone(),
+            image_id: Some(container.image_id.clone()),
+            created: Some(container.created),
+            labels: Some(container.labels()),
+            state: Some(container.status().to_string()),
+            status: Some(match container.state {
+                State::Created => "Created".to_string(),
+                State::Running => "Up".to_string(),
+                State::Exited => format!("Exited ({})", container.exit_code.unwrap_or(0)),
+            }),
+            // Docker only lists published ports of running containers
+            ports: Some(
+                container
+                    .ports
+                    .iter()
+                    .filter(|_| running)
+                    .map(|&(public, private, typ)| Port {
+                        ip: Some("0.0.0.0".to_string()),
+                        private_port: private,
+                        public_port: Some(public),
+                        typ: Some(typ),
+                    })
+                    .collect(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    fn network(name: &str, network: &FakeNetwork, inner: &Inner) -> Network {
+        Network {
+            name: Some(name.to_string()),
+            id: Some(network.id.clone()),
+            created: Some(network.created.clone()),
+            driver: Some(if BUILTIN_NETWORKS.contains(&name) && name != "bridge" { name.to_string() } else { "bridge".to_string() }),
+            internal: Some(network.internal),
+            labels: Some(network.labels.clone()),
+            containers: Some(
+                inner
+                    .containers_on(name)
+                    .map(|c| (c.id.clone(), NetworkContainer { name: Some(c.name.clone()), ..Default::default() }))
+                    .collect(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    fn volume(name: &str, volume: &FakeVolume, inner: &Inner) -> Volume {
+        Volume {
+            name: name.to_string(),
+            driver: "local".to_string(),
+            mountpoint: format!("/var/lib/docker/volumes/{}/_data", name),
+            created_at: Some(volume.created.clone()),
+            labels: volume.labels.clone(),
+            scope: Some(VolumeScopeEnum::LOCAL),
+            usage_data: Some(VolumeUsageData {
+                size: volume.size,
+                ref_count: inner.containers.values().filter(|c| c.binds().iter().any(|b| b.starts_with(&format!("{}:", name)))).count()
+                    as i64,
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+#[async_trait]
+impl ContainerRuntime for FakeRuntime {
+    async fn ping(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn create_container(&self, name: &str, config: Config<String>) -> Result<String, Error> {
+        let container = {
+            let mut inner = self.lock();
+            if inner.containers.values().any(|c| c.name == name) {
+                return Err(conflict(format!("Conflict. The container name \"/{}\" is already in use", name)));
+            }
+            let image = config.image.clone().unwrap_or_default();
+            let image_id = inner.image_id(&image)?;
+
+            let host = config.host_config.clone().unwrap_or_default();
+            let network = match host.network_mode.as_deref() {
+                None | Some("default") => "bridge",
+                Some(mode) => mode,
+            };
+            if !inner.networks.contains_key(network) {
+                return Err(not_found(format!("network {} not found", network)));
+            }
+
+            // Named volumes in binds spring into existence, unlabelled
+            for bind in host.binds.iter().flatten() {
+                let source = bind.split(':').next().unwrap_or_default();
+                if !source.starts_with('/') && !inner.volumes.contains_key(source) {
+                    let volume = FakeVolume { labels: HashMap::new(), created: Utc::now().to_rfc3339(), size: 0 };
+                    inner.volumes.insert(source.to_string(), volume);
+                }
+            }
+
+            let mut ports = Vec::new();
+            for (key, bindings) in host.port_bindings.iter().flatten() {
+                let (private, protocol) = key.split_once('/').unwrap_or((key, "tcp"));
+                let private = private.parse().map_err(|_| bad_request(format!("Invalid port {}", key)))?;
+                let typ = if protocol == "udp" { PortTypeEnum::UDP } else { PortTypeEnum::TCP };
+                for binding in bindings.iter().flatten() {
+                    let public = match binding.host_port.as_deref().and_then(|p| p.parse().ok()) {
+                        Some(port) => port,
+                        None => {
+                            inner.next_port += 1;
+                            inner.next_port - 1
+                        }
+                    };
+                    ports.push((public, private, typ));
+                }
+            }
+
+            let id = inner.new_id();
+            let container = FakeContainer {
+                id: id.clone(),
+                name: name.to_string(),
+                image_id,
+                config,
+                state: State::Created,
+                exit_code: None,
+                oom_killed: false,
+                restart_count: 0,
+                created: Utc::now().timestamp(),
+                networks: BTreeMap::from([(network.to_string(), EndpointSettings::default())]),
+                ports,
+                size_rw: 0,
+                cpu_percent: 0.0,
+                memory_bytes: 0,
+                logs: Vec::new(),
+            };
+            inner.containers.insert(id, container.clone());
+            container
+        };
+        self.publish(&container, "create", None);
+        Ok(container.id)
+    }
+
+    async fn start_container(&self, id: &str) -> Result<(), Error> {
+        let container = {
+            let mut inner = self.lock();
+            let busy: Vec<(u16, PortTypeEnum)> = inner
+                .containers
+                .values()
+                .filter(|c| c.state == State::Running)
+                .flat_map(|c| c.ports.iter().map(|&(public, _, typ)| (public, typ)))
+                .collect();
+            let container = inner.container(id)?;
+            if container.state == State::Running {
+                return Ok(());
+            }
+            if let Some(&(port, ..)) = container.ports.iter().find(|&&(public, _, typ)| busy.contains(&(public, typ))) {
+                return Err(server_error(format!("Bind for 0.0.0.0:{} failed: port is already allocated", port)));
+            }
+            container.state = State::Running;
+            container.exit_code = None;
+            container.oom_killed = false;
+            container.clone()
+        };
+        self.publish(&container, "start", None);
+        Ok(())
+    }
+
+    async fn stop_container(&self, id: &str, _timeout_secs: Option<i64>) -> Result<(), Error> {
+        let container = {
+            let mut inner = self.lock();
+            let container = inner.container(id)?;
+            if container.state != State::Running {
+                return Ok(());
+            }
+            container.state = State::Exited;
+            container.exit_code = Some(0);
+            container.clone()
+        };
+        self.publish(&container, "die", Some(0));
+        self.publish(&container, "stop", None);
+        Ok(())
+    }
+
+    async fn remove_container(&self, id: &str, options: Option<RemoveContainerOptions>) -> Result<(), Error> {
+        let force = options.is_some_and(|o| o.force);
+        let container = {
+            let mut inner = self.lock();
+            let id = inner.container_id(id)?;
+            let container = &inner.containers[&id];
+            if container.state == State::Running && !force {
+                return Err(conflict(format!(
+                    "You cannot remove a running container {}. Stop the container before attempting removal or force remove",
+                    id
+                )));
+            }
+            inner.containers.remove(&id).expect("resolved id")
+        };
+        if container.state == State::Running {
+            self.publish(&container, "die", Some(137));
+        }
+        self.publish(&container, "destroy", None);
+        Ok(())
+    }
+
+    async fn rename_container(&self, id: &str, name: &str) -> Result<(), Error> {
+        let container = {
+            let mut inner = self.lock();
+            let id = inner.container_id(id)?;
+            if inner.containers.values().any(|c| c.name == name && c.id != id) {
+                return Err(conflict(format!("Conflict. The container name \"/{}\" is already in use", name)));
+            }
+            let container = inner.containers.get_mut(&id).expect("resolved id");
+            container.name = name.to_string();
+            container.clone()
+        };
+        self.publish(&container, "rename", None);
+        Ok(())
+    }
+
+    async fn inspect_container(&self, id: &str, size: bool) -> Result<ContainerInspectResponse, Error> {
+        Ok(Self::inspect(self.lock().container(id)?, size))
+    }
+
+    async fn list_containers(&self, options: ListContainersOptions<String>) -> Result<Vec<ContainerSummary>, Error> {
+        let inner = self.lock();
+        let labels = options.filters.get("label").cloned().unwrap_or_default();
+        Ok(inner
+            .containers
+            .values()
+            .filter(|c| options.all || c.state == State::Running)
+            .filter(|c| labels.iter().all(|filter| label_matches(&c.labels(), filter)))
+            .map(Self::summary)
+            .collect())
+    }
+
+    fn stats(&self, id: &str) -> BoxStream<'static, Result<Stats, Error>> {
+        let stats = self.lock().container(id).map(|c| Self::stats_of(c));
+        stream::once(async move { stats }).boxed()
+    }
+
+    fn logs(&self, id: &str, options: LogsOptions<String>) -> BoxStream<'static, Result<LogOutput, Error>> {
+        let logs = self.lock().container(id).map(|c| c.logs.clone());
+        let lines = match logs {
+            Ok(logs) => {
+                let mut lines: Vec<LogOutput> = logs
+                    .into_iter()
+                    .filter(|line| match line {
+                        LogOutput::StdOut { .. } | LogOutput::Console { .. } => options.stdout,
+                        LogOutput::StdErr { .. } => options.stderr,
+                        LogOutput::StdIn { .. } => false,
+                    })
+                    .collect();
+                if let Ok(tail) = options.tail.parse::<usize>() {
+                    lines.drain(..lines.len().saturating_sub(tail));
+                }
+                lines.into_iter().map(Ok).collect()
+            }
+            Err(e) => vec![Err(e)],
+        };
+        stream::iter(lines).boxed()
+    }
+
+    fn events(&self, options: EventsOptions<String>) -> BoxStream<'static, Result<EventMessage, Error>> {
+        let types = options.filters.get("type").cloned().unwrap_or_default();
+        let labels = options.filters.get("label").cloned().unwrap_or_default();
+        stream::unfold(self.events.subscribe(), |mut rx| async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => return Some((event, rx)),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+        .filter(move |event| {
+            let typ = event.typ.map(|t| t.to_string()).unwrap_or_default();
+            let attributes = event.actor.as_ref().and_then(|a| a.attributes.clone()).unwrap_or_default();
+            let wanted = (types.is_empty() || types.contains(&typ))
+                && labels.iter().all(|filter| label_matches(&attributes, filter));
+            futures_util::future::ready(wanted)
+        })
+        .map(Ok)
+        .boxed()
+    }
+
+    async fn create_exec(&self, container_id: &str, _options: CreateExecOptions<String>) -> Result<String, Error> {
+        let mut inner = self.lock();
+        let container = inner.container(container_id)?;
+        if container.state != State::Running {
+            return Err(conflict(format!("Container {} is not running", container.id)));
+        }
+        let container_id = container.id.clone();
+        let id = inner.new_id();
+        inner.execs.insert(id.clone(), FakeExec { container_id });
+        Ok(id)
+    }
+
+    async fn start_exec(&self, exec_id: &str) -> Result<StartExecResults, Error> {
+        if !self.lock().execs.contains_key(exec_id) {
+            return Err(not_found(format!("No such exec instance: {}", exec_id)));
+        }
+        Ok(StartExecResults::Attached { output: stream::empty().boxed(), input: Box::pin(tokio::io::sink()) })
+    }
+
+    async fn resize_exec(&self, exec_id: &str, _cols: u16, _rows: u16) -> Result<(), Error> {
+        match self.lock().execs.contains_key(exec_id) {
+            true => Ok(()),
+            false => Err(not_found(format!("No such exec instance: {}", exec_id))),
+        }
+    }
+
+    async fn inspect_exec(&self, exec_id: &str) -> Result<ExecInspectResponse, Error> {
+        let inner = self.lock();
+        let exec = inner.execs.get(exec_id).ok_or_else(|| not_found(format!("No such exec instance: {}", exec_id)))?;
+        Ok(ExecInspectResponse {
+            id: Some(exec_id.to_string()),
+            container_id: Some(exec.container_id.clone()),
+            running: Some(false),
+            exit_code: Some(0),
+            ..Default::default()
+        })
+    }
+
+    fn create_image(
+        &self,
+        options: CreateImageOptions<String>,
+        _credentials: Option<DockerCredentials>,
+    ) -> BoxStream<'static, Result<CreateImageInfo, Error>> {
+        let reference = match options.tag.as_str() {
+            "" => options.from_image.clone(),
+            tag => format!("{}:{}", options.from_image, tag),
+        };
+        let mut inner = self.lock();
+        if inner.image_id(&reference).is_err() {
+            inner.add_image(&reference, HashMap::new(), 0);
+        }
+        let status = |status: String, id: Option<&str>| {
+            Ok(CreateImageInfo { status: Some(status), id: id.map(String::from), ..Default::default() })
+        };
+        stream::iter(vec![
+            status(format!("Pulling from {}", options.from_image), None),
+            status("Pull complete".to_string(), Some("fake-layer")),
+            status(format!("Status: Downloaded newer image for {}", reference), None),
+        ])
+        .boxed()
+    }
+
+    async fn inspect_image(&self, reference: &str) -> Result<ImageInspect, Error> {
+        let inner = self.lock();
+        let image = &inner.images[&inner.image_id(reference)?];
+        Ok(ImageInspect {
+            id: Some(image.id.clone()),
+            repo_tags: Some(image.tags.clone()),
+            created: chrono::DateTime::from_timestamp(image.created, 0).map(|t| t.to_rfc3339()),
+            size: Some(image.size),
+            config: Some(ContainerConfig { labels: Some(image.labels.clone()), ..Default::default() }),
+            ..Default::default()
+        })
+    }
+
+    async fn list_images(&self, options: ListImagesOptions<String>) -> Result<Vec<ImageSummary>, Error> {
+        let inner = self.lock();
+        let labels = options.filters.get("label").cloned().unwrap_or_default();
+        Ok(inner
+            .images
+            .values()
+            .filter(|image| labels.iter().all(|filter| label_matches(&image.labels, filter)))
+            .map(|image| ImageSummary {
+                id: image.id.clone(),
+                repo_tags: image.tags.clone(),
+                created: image.created,
+                size: image.size,
+                labels: image.labels.clone(),
+                containers: inner.containers.values().filter(|c| c.image_id == image.id).count() as i64,
+                ..Default::default()
+            })
+            .collect())
+    }
+
+    async fn remove_image(&self, reference: &str) -> Result<(), Error> {
+        let mut inner = self.lock();
+        let id = inner.image_id(reference)?;
+        let wanted = normalize(reference);
+        let image = inner.images.get_mut(&id).expect("resolved id");
+        let tagged = image.tags.len();
+        image.tags.retain(|tag| normalize(tag) != wanted);
+        // Untagging is all that happens while other tags remain
+        if image.tags.len() < tagged && !image.tags.is_empty() {
+            return Ok(());
+        }
+        if let Some(container) = inner.containers.values().find(|c| c.image_id == id) {
+            return Err(conflict(format!("image is being used by container {}", container.id)));
+        }
+        inner.images.remove(&id);
+        Ok(())
+    }
+
+    async fn commit_container(&self, options: CommitContainerOptions<String>, config: Config<String>) -> Result<(), Error> {
+        let mut inner = self.lock();
+        let container = inner.container(&options.container)?.clone();
+        let mut labels = container.labels();
+        labels.extend(config.labels.unwrap_or_default());
+        let tag = if options.tag.is_empty() { "latest" } else { options.tag.as_str() };
+        inner.add_image(&format!("{}:{}", options.repo, tag), labels, container.size_rw);
+        Ok(())
+    }
+
+    async fn create_network(&self, options: CreateNetworkOptions<String>) -> Result<(), Error> {
+        let mut inner = self.lock();
+        if inner.networks.contains_key(&options.name) {
+            return Err(conflict(format!("network with name {} already exists", options.name)));
+        }
+        let network = FakeNetwork {
+            id: inner.new_id(),
+            internal: options.internal,
+            labels: options.labels,
+            created: Utc::now().to_rfc3339(),
+        };
+        inner.networks.insert(options.name, network);
+        Ok(())
+    }
+
+    async fn inspect_network(&self, name: &str) -> Result<Network, Error> {
+        let inner = self.lock();
+        let network = inner.networks.get(name).ok_or_else(|| not_found(format!("network {} not found", name)))?;
+        Ok(Self::network(name, network, &inner))
+    }
+
+    async fn list_networks(&self, options: ListNetworksOptions<String>) -> Result<Vec<Network>, Error> {
+        let inner = self.lock();
+        let labels = options.filters.get("label").cloned().unwrap_or_default();
+        Ok(inner
+            .networks
+            .iter()
+            .filter(|(_, network)| labels.iter().all(|filter| label_matches(&network.labels, filter)))
+            // Like Docker, the listing doesn't say who is attached
+            .map(|(name, network)| Network { containers: None, ..Self::network(name, network, &inner) })
+            .collect())
+    }
+
+    async fn remove_network(&self, name: &str) -> Result<(), Error> {
+        let mut inner = self.lock();
+        if !inner.networks.contains_key(name) {
+            return Err(not_found(format!("network {} not found", name)));
+        }
+        if BUILTIN_NETWORKS.contains(&name) {
+            return Err(forbidden(format!("{} is a pre-defined network and cannot be removed", name)));
+        }
+        if inner.containers_on(name).next().is_some() {
+            return Err(forbidden(format!("error while removing network: network {} has active endpoints", name)));
+        }
+        inner.networks.remove(name);
+        Ok(())
+    }
+
+    async fn connect_network(&self, name: &str, options: ConnectNetworkOptions<String>) -> Result<(), Error> {
+        let mut inner = self.lock();
+        if !inner.networks.contains_key(name) {
+            return Err(not_found(format!("network {} not found", name)));
+        }
+        let container = inner.container(&options.container)?;
+        if container.networks.contains_key(name) {
+            return Err(forbidden(format!("endpoint with name {} already exists in network {}", container.name, name)));
+        }
+        container.networks.insert(name.to_string(), options.endpoint_config);
+        Ok(())
+    }
+
+    async fn create_volume(&self, options: CreateVolumeOptions<String>) -> Result<Volume, Error> {
+        let mut inner = self.lock();
+        // Creating an existing volume hands it back, as Docker does
+        let volume = inner
+            .volumes
+            .entry(options.name.clone())
+            .or_insert_with(|| FakeVolume { labels: options.labels, created: Utc::now().to_rfc3339(), size: 0 })
+            .clone();
+        Ok(Self::volume(&options.name, &volume, &inner))
+    }
+
+    async fn inspect_volume(&self, name: &str) -> Result<Volume, Error> {
+        let inner = self.lock();
+        let volume = inner.volumes.get(name).ok_or_else(|| not_found(format!("get {}: no such volume", name)))?;
+        Ok(Volume { usage_data: None, ..Self::volume(name, volume, &inner) })
+    }
+
+    async fn remove_volume(&self, name: &str) -> Result<(), Error> {
+        let mut inner = self.lock();
+        if !inner.volumes.contains_key(name) {
+            return Err(not_found(format!("get {}: no such volume", name)));
+        }
+        if inner.volume_in_use(name) {
+            return Err(conflict(format!("remove {}: volume is in use", name)));
+        }
+        inner.volumes.remove(name);
+        Ok(())
+    }
+
+    async fn df(&self) -> Result<SystemDataUsageResponse, Error> {
+        let inner = self.lock();
+        Ok(SystemDataUsageResponse {
+            volumes: Some(inner.volumes.iter().map(|(name, volume)| Self::volume(name, volume, &inner)).collect()),
+            containers: Some(inner.containers.values().map(Self::summary).collect()),
+            ..Default::default()
+        })
+    }
+}
+
+/// Docker's `label` filter: `key` or `key=value`
+fn label_matches(labels: &HashMap<String, String>, filter: &str) -> bool {
+    match filter.split_once('=') {
+        Some((key, value)) => labels.get(key).map(String::as_str) == Some(value),
+        None => labels.contains_key(filter),
+    }
+}
+
+/// `alpine` and `docker.io/library/alpine:latest` are the same image
+fn normalize(reference: &str) -> String {
+    ImageRef::parse(reference).map(|r| r.to_string()).unwrap_or_else(|_| reference.to_string())
+}
+
+fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+fn docker_error(status_code: u16, message: String) -> Error {
+    Error::DockerResponseServerError { status_code, message }
+}
+
+fn bad_request(message: String) -> Error {
+    docker_error(400, message)
+}
+
+fn forbidden(message: String) -> Error {
+    docker_error(403, message)
+}
+
+fn not_found(message: String) -> Error {
+    docker_error(404, message)
+}
+
+fn conflict(message: String) -> Error {
+    docker_error(409, message)
+}
+
+fn server_error(message: String) -> Error {
+    docker_error(500, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bollard::models::HostConfig;
+
+    fn status(error: Error) -> u16 {
+        match error {
+            Error::DockerResponseServerError { status_code, .. } => status_code,
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    fn config(image: &str, binds: Vec<&str>) -> Config<String> {
+        Config {
+            image: Some(image.to_string()),
+            labels: Some(HashMap::from([("gpu-share.managed".to_string(), "true".to_string())])),
+            host_config: Some(HostConfig {
+                binds: Some(binds.into_iter().map(String::from).collect()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lookup_and_conflicts() {
+        let runtime = FakeRuntime::new().with_image("docker.io/library/alpine:latest");
+        // Images are found by any spelling of their reference
+        assert!(runtime.has_image("alpine"));
+        assert_eq!(status(runtime.create_container("x", config("ubuntu", vec![])).await.unwrap_err()), 404);
+
+        let id = runtime.create_container("job", config("alpine", vec!["data:/data"])).await.unwrap();
+        assert_eq!(runtime.inspect_container("job", false).await.unwrap().id, Some(id.clone()));
+        assert_eq!(runtime.inspect_container(&id[..12], false).await.unwrap().name.as_deref(), Some("/job"));
+        assert_eq!(status(runtime.create_container("job", config("alpine", vec![])).await.unwrap_err()), 409);
+
+        // The bind created the volume, and keeps it busy
+        assert_eq!(status(runtime.remove_volume("data").await.unwrap_err()), 409);
+        assert_eq!(status(runtime.remove_image("alpine").await.unwrap_err()), 409);
+        assert_eq!(status(runtime.remove_network("bridge").await.unwrap_err()), 403);
+
+        runtime.start_container(&id).await.unwrap();
+        assert_eq!(status(runtime.remove_container(&id, None).await.unwrap_err()), 409);
+        let force = RemoveContainerOptions { force: true, ..Default::default() };
+        runtime.remove_container(&id, Some(force)).await.unwrap();
+        runtime.remove_volume("data").await.unwrap();
+        assert_eq!(status(runtime.inspect_container(&id, false).await.unwrap_err()), 404);
+    }
+
+    #[tokio::test]
+    async fn test_events_carry_labels_and_filter() {
+        let runtime = FakeRuntime::new().with_image("alpine");
+        let managed = EventsOptions {
+            filters: HashMap::from([("label".to_string(), vec!["gpu-share.managed=true".to_string()])]),
+            ..Default::default()
+        };
+        let mut events = runtime.events(managed);
+
+        let unmanaged = Config { image: Some("alpine".to_string()), ..Default::default() };
+        runtime.create_container("other", unmanaged).await.unwrap();
+        let id = runtime.create_container("job", config("alpine", vec![])).await.unwrap();
+        runtime.start_container(&id).await.unwrap();
+        runtime.exit(&id, 3).unwrap();
+
+        let mut seen = Vec::new();
+        for _ in 0..3 {
+            let event = events.next().await.unwrap().unwrap();
+            let actor = event.actor.unwrap();
+            assert_eq!(actor.id.as_deref(), Some(id.as_str()));
+            let attributes = actor.attributes.unwrap();
+            assert_eq!(attributes["name"], "job");
+            seen.push((event.action.unwrap(), attributes.get("exitCode").cloned()));
+        }
+        assert_eq!(
+            seen,
+            vec![("create".into(), None), ("start".into(), None), ("die".into(), Some("3".into()))]
+        );
+        // No restart policy: it stays down
+        let state = runtime.inspect_container(&id, false).await.unwrap().state.unwrap();
+        assert_eq!((state.running, state.exit_code), (Some(false), Some(3)));
+    }
+
+    #[tokio::test]
+    async fn test_logs_and_ports() {
+        let runtime = FakeRuntime::new().with_image("alpine");
+        let mut spec = config("alpine", vec![]);
+        spec.host_config.as_mut().unwrap().port_bindings = Some(HashMap::from([(
+            "8888/tcp".to_string(),
+            Some(vec![bollard::models::PortBinding { host_ip: None, host_port: Some(String::new()) }]),
+        )]));
+        let id = runtime.create_container("job", spec.clone()).await.unwrap();
+        runtime.start_container(&id).await.unwrap();
+        let listed = runtime.list_containers(ListContainersOptions::default()).await.unwrap();
+        let port = &listed[0].ports.as_ref().unwrap()[0];
+        assert_eq!((port.private_port, port.public_port), (8888, Some(EPHEMERAL_PORT_START)));
+
+        // A second container can't have the same host port while the first runs
+        spec.host_config.as_mut().unwrap().port_bindings = Some(HashMap::from([(
+            "80/tcp".to_string(),
+            Some(vec![bollard::models::PortBinding { host_ip: None, host_port: Some(EPHEMERAL_PORT_START.to_string()) }]),
+        )]));
+        let clash = runtime.create_container("clash", spec).await.unwrap();
+        assert_eq!(status(runtime.start_container(&clash).await.unwrap_err()), 500);
+
+        for line in ["one", "two", "three"] {
+            runtime.push_log(&id, LogOutput::StdOut { message: line.into() }).unwrap();
+        }
+        runtime.push_log(&id, LogOutput::StdErr { message: "oops".into() }).unwrap();
+        let options = LogsOptions { stdout: true, tail: "2".to_string(), ..Default::default() };
+        let lines: Vec<String> = runtime.logs(&id, options).map(|line| line.unwrap().to_string()).collect().await;
+        assert_eq!(lines, vec!["two", "three"]);
+    }
+}
@@ -0,0 +1,399 @@
+/*
+* Image policy, registry credentials and pull progress
+* ----------------------------------------------------
+* Containers used to be created from whatever was already in the local image
+* store. Now every image goes through:
+*
+*   normalize -> `alpine` is `docker.io/library/alpine:latest`
+*   policy    -> deny patterns win, then (if any are set) an allow pattern must match
+*   pull      -> if missing locally, with the credentials configured for the
+*                image's registry and the caller's project
+*
+* Patterns are globs (`*`) over the normalized reference and are normalized the
+* same way, so `pytorch/pytorch` covers every tag of docker.io/pytorch/pytorch.
+*
+* Configured under `[images]`:
+*
+*   [images]
+*   allow = ["nvcr.io/nvidia/pytorch:2*", "pytorch/pytorch"]
+*   deny = ["*:latest"]
+*
+*   [[images.registries]]
+*   registry = "ghcr.io"
+*   projects = ["vision"]        # empty: every project
+*   username = "bot"
+*   password_env = "GHCR_TOKEN"  # or `password` / `identity_token`
+*/
+
+use anyhow::{anyhow, Result};
+use bollard::auth::DockerCredentials;
+use bollard::models::CreateImageInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use tracing::warn;
+
+use crate::config::settings::Settings;
+
+const DOCKER_HUB: &str = "docker.io";
+
+/// A fully-qualified image reference
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageRef {
+    pub registry: String,
+    pub repository: String,
+    pub tag: Option<String>,
+    pub digest: Option<String>,
+}
+
+impl ImageRef {
+    pub fn parse(image: &str) -> Result<Self> {
+        let image = image.trim();
+        if image.is_empty() || image.contains(char::is_whitespace) {
+            return Err(anyhow!("Invalid image reference '{}'", image));
+        }
+
+        let (name, digest) = match image.split_once('@') {
+            Some((name, digest)) => (name, Some(digest.to_string())),
+            None => (image, None),
+        };
+        let (name, tag) = match name.rsplit_once(':') {
+            Some((repo, tag)) if !tag.contains('/') => (repo, Some(tag.to_string())),
+            _ => (name, None),
+        };
+
+        let (registry, repository) = match name.split_once('/') {
+            Some((first, rest)) if first.contains('.') || first.contains(':') || first == "localhost" => {
+                (normalize_registry(first), rest.to_string())
+            }
+            _ => (DOCKER_HUB.to_string(), name.to_string()),
+        };
+        let repository = if registry == DOCKER_HUB && !repository.contains('/') {
+            format!("library/{}", repository)
+        } else {
+            repository
+        };
+
+        if repository.is_empty() || repository.chars().any(|c| c.is_ascii_uppercase()) {
+            return Err(anyhow!("Invalid repository in '{}' (must be lowercase)", image));
+        }
+        if tag.as_deref() == Some("") || digest.as_deref() == Some("") {
+            return Err(anyhow!("Invalid image reference '{}'", image));
+        }
+
+        let tag = match (&tag, &digest) {
+            (None, None) => Some("latest".to_string()),
+            _ => tag,
+        };
+        Ok(Self { registry, repository, tag, digest })
+    }
+
+    /// `registry/repository`, without tag or digest
+    pub fn name(&self) -> String {
+        format!("{}/{}", self.registry, self.repository)
+    }
+}
+
+impl fmt::Display for ImageRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())?;
+        if let Some(tag) = &self.tag {
+            write!(f, ":{}", tag)?;
+        }
+        if let Some(digest) = &self.digest {
+            write!(f, "@{}", digest)?;
+        }
+        Ok(())
+    }
+}
+
+fn normalize_registry(registry: &str) -> String {
+    match registry {
+        "index.docker.io" | "registry-1.docker.io" => DOCKER_HUB.to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Credentials for one registry, optionally limited to some projects
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RegistryCredential {
+    pub registry: String,
+    pub projects: Vec<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Environment variable holding the password, so it stays out of config files
+    pub password_env: Option<String>,
+    pub identity_token: Option<String>,
+}
+
+impl RegistryCredential {
+    fn to_docker(&self) -> DockerCredentials {
+        let password = self
+            .password_env
+            .as_deref()
+            .and_then(|var| std::env::var(var).ok())
+            .or_else(|| self.password.clone());
+        DockerCredentials {
+            username: self.username.clone(),
+            password,
+            identitytoken: self.identity_token.clone(),
+            serveraddress: Some(self.registry.clone()),
+            ..Default::default()
+        }
+    }
+}
+
+/// `[images]` section of Settings
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImageConfig {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+    pub registries: Vec<RegistryCredential>,
+}
+
+impl ImageConfig {
+    /// From Settings, or defaults (anything goes, anonymous pulls) without one
+    pub fn load() -> Self {
+        match Settings::new() {
+            Ok(settings) => settings.images,
+            Err(e) => {
+                warn!("No image settings ({}), allowing every image", e);
+                Self::default()
+            }
+        }
+    }
+
+    /// Normalizes `image` and checks it against the allow/deny lists
+    pub fn check(&self, image: &str) -> Result<ImageRef> {
+        let reference = ImageRef::parse(image)?;
+        if let Some(pattern) = self.deny.iter().find(|p| pattern_matches(p, &reference)) {
+            return Err(anyhow!("Image {} is denied by policy ({})", reference, pattern));
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(|p| pattern_matches(p, &reference)) {
+            return Err(anyhow!("Image {} is not on the allowlist", reference));
+        }
+        Ok(reference)
+    }
+
+    /// Project-specific credentials beat registry-wide ones
+    pub fn credentials_for(&self, reference: &ImageRef, project: Option<&str>) -> Option<DockerCredentials> {
+        let for_registry = self.registries.iter().filter(|c| normalize_registry(&c.registry) == reference.registry);
+        let mut fallback = None;
+        for credential in for_registry {
+            if credential.projects.is_empty() {
+                fallback.get_or_insert(credential);
+            } else if project.is_some_and(|p| credential.projects.iter().any(|cp| cp == p)) {
+                return Some(credential.to_docker());
+            }
+        }
+        fallback.map(RegistryCredential::to_docker)
+    }
+}
+
+/// Patterns are tried against the whole reference; one without a tag or digest
+/// is also tried against the bare name, so it covers every tag
+fn pattern_matches(pattern: &str, reference: &ImageRef) -> bool {
+    let normalized = normalize_pattern(pattern);
+    let full = reference.to_string();
+    if glob_match(&normalized, &full) {
+        return true;
+    }
+    let last = normalized.rsplit('/').next().unwrap_or_default();
+    let whole_reference = last.contains(':') || last.contains('@');
+    !whole_reference && glob_match(&normalized, &reference.name())
+}
+
+fn normalize_pattern(pattern: &str) -> String {
+    let pattern = pattern.trim();
+    if pattern.starts_with('*') {
+        return pattern.to_string();
+    }
+    let first = pattern.split('/').next().unwrap_or_default();
+    let has_registry = pattern.contains('/') && (first.contains('.') || first.contains(':') || first == "localhost");
+    if has_registry {
+        let (registry, rest) = pattern.split_once('/').unwrap_or((pattern, ""));
+        format!("{}/{}", normalize_registry(registry), rest)
+    } else if pattern.contains('/') {
+        format!("{}/{}", DOCKER_HUB, pattern)
+    } else {
+        format!("{}/library/{}", DOCKER_HUB, pattern)
+    }
+}
+
+/// `*` matches any run of characters, everything else is literal
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let (p, t): (Vec<char>, Vec<char>) = (pattern.chars().collect(), text.chars().collect());
+    let (mut pi, mut ti) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while ti < t.len() {
+        if pi < p.len() && p[pi] == '*' {
+            backtrack = Some((pi, ti));
+            pi += 1;
+        } else if pi < p.len() && p[pi] == t[ti] {
+            pi += 1;
+            ti += 1;
+        } else if let Some((star, matched)) = backtrack {
+            pi = star + 1;
+            ti = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+/// One step of a pull, as streamed to clients
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PullProgress {
+    pub image: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layer: Option<String>,
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<i64>,
+    /// Bytes downloaded over bytes known across all layers seen so far
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub percent: Option<f64>,
+}
+
+/// Folds the daemon's per-layer messages into overall progress
+#[derive(Debug, Default)]
+pub struct PullTracker {
+    image: String,
+    layers: BTreeMap<String, (i64, i64)>,
+}
+
+impl PullTracker {
+    pub fn new(reference: &ImageRef) -> Self {
+        Self { image: reference.to_string(), layers: BTreeMap::new() }
+    }
+
+    pub fn update(&mut self, info: CreateImageInfo) -> Result<PullProgress> {
+        if let Some(error) = info.error.or_else(|| info.error_detail.and_then(|d| d.message)) {
+            return Err(anyhow!("Pulling {} failed: {}", self.image, error));
+        }
+
+        let status = info.status.unwrap_or_default();
+        let (current, total) = info
+            .progress_detail
+            .map(|d| (d.current, d.total))
+            .unwrap_or((None, None));
+
+        if let Some(layer) = &info.id {
+            match (status.as_str(), current, total) {
+                ("Downloading", Some(current), Some(total)) if total > 0 => {
+                    self.layers.insert(layer.clone(), (current, total));
+                }
+                ("Download complete" | "Pull complete" | "Already exists", _, _) => {
+                    if let Some(entry) = self.layers.get_mut(layer) {
+                        entry.0 = entry.1;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let (done, known): (i64, i64) = self.layers.values().fold((0, 0), |(d, k), (c, t)| (d + c, k + t));
+        Ok(PullProgress {
+            image: self.image.clone(),
+            layer: info.id,
+            status,
+            current,
+            total,
+            percent: (known > 0).then(|| (done as f64 / known as f64 * 1000.0).round() / 10.0),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bollard::models::ProgressDetail;
+
+    #[test]
+    fn test_image_ref_normalization() {
+        let r = ImageRef::parse("alpine").unwrap();
+        assert_eq!(r.to_string(), "docker.io/library/alpine:latest");
+        let r = ImageRef::parse("localhost:5000/team/app:v2").unwrap();
+        assert_eq!((r.registry.as_str(), r.repository.as_str(), r.tag.as_deref()), ("localhost:5000", "team/app", Some("v2")));
+        let r = ImageRef::parse("nvcr.io/nvidia/pytorch@sha256:abc").unwrap();
+        assert_eq!(r.tag, None);
+        assert_eq!(r.to_string(), "nvcr.io/nvidia/pytorch@sha256:abc");
+        assert_eq!(ImageRef::parse("index.docker.io/pytorch/pytorch:2.3").unwrap().registry, "docker.io");
+        assert!(ImageRef::parse("Alpine").is_err());
+        assert!(ImageRef::parse("alpine:").is_err());
+    }
+
+    #[test]
+    fn test_policy() {
+        let config = ImageConfig {
+            allow: vec!["nvcr.io/nvidia/*".into(), "pytorch/pytorch".into(), "alpine:3.*".into()],
+            deny: vec!["*:latest".into()],
+            ..Default::default()
+        };
+        assert!(config.check("nvcr.io/nvidia/pytorch:24.05-py3").is_ok());
+        assert!(config.check("pytorch/pytorch:2.3-cuda12").is_ok());
+        assert!(config.check("alpine:3.19").is_ok());
+        assert!(config.check("alpine:edge").unwrap_err().to_string().contains("allowlist"));
+        assert!(config.check("pytorch/pytorch").unwrap_err().to_string().contains("denied"));
+        assert!(config.check("ghcr.io/evil/miner:1").is_err());
+        assert!(ImageConfig::default().check("anything/goes:1").is_ok());
+    }
+
+    #[test]
+    fn test_glob() {
+        assert!(glob_match("a*c", "abbbc"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "a-b-b-c"));
+        assert!(!glob_match("a*c", "abd"));
+    }
+
+    #[test]
+    fn test_credentials_prefer_project() {
+        let config = ImageConfig {
+            registries: vec![
+                RegistryCredential { registry: "ghcr.io".into(), username: Some("shared".into()), ..Default::default() },
+                RegistryCredential {
+                    registry: "ghcr.io".into(),
+                    projects: vec!["vision".into()],
+                    username: Some("vision-bot".into()),
+                    password: Some("s3cret".into()),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let reference = ImageRef::parse("ghcr.io/acme/train:1").unwrap();
+        let creds = config.credentials_for(&reference, Some("vision")).unwrap();
+        assert_eq!(creds.username.as_deref(), Some("vision-bot"));
+        assert_eq!(creds.serveraddress.as_deref(), Some("ghcr.io"));
+        assert_eq!(config.credentials_for(&reference, Some("nlp")).unwrap().username.as_deref(), Some("shared"));
+        assert!(config.credentials_for(&ImageRef::parse("alpine").unwrap(), None).is_none());
+    }
+
+    #[test]
+    fn test_pull_tracker() {
+        let mut tracker = PullTracker::new(&ImageRef::parse("alpine").unwrap());
+        let downloading = |id: &str, current, total| CreateImageInfo {
+            id: Some(id.into()),
+            status: Some("Downloading".into()),
+            progress_detail: Some(ProgressDetail { current: Some(current), total: Some(total) }),
+            ..Default::default()
+        };
+
+        tracker.update(downloading("l1", 50, 100)).unwrap();
+        let progress = tracker.update(downloading("l2", 0, 300)).unwrap();
+        assert_eq!(progress.percent, Some(12.5));
+        let done = CreateImageInfo { id: Some("l1".into()), status: Some("Pull complete".into()), ..Default::default() };
+        assert_eq!(tracker.update(done).unwrap().percent, Some(25.0));
+
+        let failed = CreateImageInfo { error: Some("manifest unknown".into()), ..Default::default() };
+        assert!(tracker.update(failed).unwrap_err().to_string().contains("manifest unknown"));
+    }
+}
@@ -0,0 +1,367 @@
+/*
+* Container lifecycle tracking
+* ----------------------------
+* Docker's event stream tells us when managed containers start, die, get
+* OOM-killed, restart or disappear. `run_lifecycle_watcher` turns those into
+* `EventKind::ContainerLifecycle` events carrying a `VMStatus`, and when a
+* container is gone for good gives its lease (`gpu-share.lease-id`) back to the
+* pool. OOM kills are flagged on the exit event and the owner is notified.
+*
+* "Gone for good" means removed, or exited with no restart coming according to
+* the restart policy at that moment: `no`, `on-failure` after a clean exit or
+* once its retries are used up. Containers Docker will bring back keep their GPU.
+* So do containers we stopped ourselves (GPU swap, checkpoint), and a lease
+* stays while another managed container still carries it.
+*/
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bollard::models::{ContainerInspectResponse, EventMessage, EventMessageTypeEnum};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::core::docker_manager::{DockerManager, RestartPolicy, LABEL_LEASE, LABEL_OWNER};
+use crate::core::vm::VMStatus;
+use crate::events::{EventBus, EventKind};
+use crate::gpu::virtual_gpu::GPUPool;
+use crate::users::UserManager;
+use crate::AsyncMutex;
+
+/// Wait before resubscribing after the event stream breaks (daemon restart...)
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+
+/// Docker container actions we track; everything else (exec, attach, health...) is ignored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContainerAction {
+    Create,
+    Start,
+    Restart,
+    Die,
+    Oom,
+    Stop,
+    Pause,
+    Unpause,
+    Destroy,
+}
+
+impl ContainerAction {
+    fn parse(action: &str) -> Option<Self> {
+        Some(match action {
+            "create" => ContainerAction::Create,
+            "start" => ContainerAction::Start,
+            "restart" => ContainerAction::Restart,
+            "die" => ContainerAction::Die,
+            "oom" => ContainerAction::Oom,
+            "stop" => ContainerAction::Stop,
+            "pause" => ContainerAction::Pause,
+            "unpause" => ContainerAction::Unpause,
+            "destroy" => ContainerAction::Destroy,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainerEvent {
+    pub container_id: String,
+    pub name: Option<String>,
+    pub action: ContainerAction,
+    /// Only on `die`
+    pub exit_code: Option<i64>,
+    pub owner: Option<String>,
+    pub lease_id: Option<String>,
+}
+
+impl ContainerEvent {
+    /// `None` for other object types and untracked actions. Docker copies the
+    /// container's labels into the event attributes, so owner and lease come
+    /// along even after the container is gone.
+    pub fn from_docker(message: EventMessage) -> Option<Self> {
+        if message.typ != Some(EventMessageTypeEnum::CONTAINER) {
+            return None;
+        }
+        let action = ContainerAction::parse(message.action.as_deref()?)?;
+        let actor = message.actor?;
+        let mut attributes: HashMap<String, String> = actor.attributes.unwrap_or_default();
+        Some(Self {
+            container_id: actor.id?,
+            name: attributes.remove("name"),
+            action,
+            exit_code: attributes.get("exitCode").and_then(|code| code.parse().ok()),
+            owner: attributes.remove(LABEL_OWNER).filter(|v| !v.is_empty()),
+            lease_id: attributes.remove(LABEL_LEASE).filter(|v| !v.is_empty()),
+        })
+    }
+}
+
+/// What decides whether a container that just died comes back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExitContext {
+    pub policy: RestartPolicy,
+    pub restart_count: i64,
+    pub oom_killed: bool,
+}
+
+impl ExitContext {
+    pub fn from_inspect(inspect: &ContainerInspectResponse) -> Self {
+        Self {
+            policy: inspect
+                .host_config
+                .as_ref()
+                .and_then(|h| h.restart_policy.as_ref())
+                .map(RestartPolicy::from_docker)
+                .unwrap_or_default(),
+            restart_count: inspect.restart_count.unwrap_or(0),
+            oom_killed: inspect.state.as_ref().and_then(|s| s.oom_killed).unwrap_or(false),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transition {
+    pub status: VMStatus,
+    pub oom_killed: bool,
+    /// Removed, or exited with no restart coming - its lease can go
+    pub permanent_exit: bool,
+}
+
+/// Remembers OOM kills until the exit they cause
+#[derive(Default)]
+pub struct LifecycleTracker {
+    oom_killed: HashSet<String>,
+}
+
+impl LifecycleTracker {
+    /// `exit` is only looked at for `die`; `None` there means the container
+    /// couldn't be inspected, i.e. it was already removed (`--rm`)
+    pub fn observe(&mut self, event: &ContainerEvent, exit: Option<&ExitContext>) -> Transition {
+        let id = &event.container_id;
+        let (status, oom_killed, permanent_exit) = match event.action {
+            ContainerAction::Create => (VMStatus::Creating, false, false),
+            ContainerAction::Start | ContainerAction::Restart => {
+                self.oom_killed.remove(id);
+                (VMStatus::Running, false, false)
+            }
+            ContainerAction::Unpause => (VMStatus::Running, false, false),
+            ContainerAction::Pause => (VMStatus::Paused, false, false),
+            // Follows the `die` that already decided everything
+            ContainerAction::Stop => (VMStatus::Stopped, false, false),
+            ContainerAction::Oom => {
+                self.oom_killed.insert(id.clone());
+                (VMStatus::Crashed, true, false)
+            }
+            ContainerAction::Die => {
+                let oom = self.oom_killed.remove(id) || exit.is_some_and(|e| e.oom_killed);
+                let code = event.exit_code.unwrap_or(-1);
+                let status = if code == 0 && !oom { VMStatus::Stopped } else { VMStatus::Crashed };
+                let restarts = exit.is_some_and(|e| e.policy.restarts_after(code, e.restart_count));
+                (status, oom, !restarts)
+            }
+            ContainerAction::Destroy => {
+                self.oom_killed.remove(id);
+                (VMStatus::Deleting, false, true)
+            }
+        };
+        Transition { status, oom_killed, permanent_exit }
+    }
+}
+
+/// A GPU swap's replacement carries the same lease as the container it
+/// replaces. When Docker can't be asked, the lease stays - reconcile catches it.
+async fn lease_still_used(docker: &DockerManager, lease_id: &str, container_id: &str) -> bool {
+    docker.lease_in_use(lease_id, container_id).await.unwrap_or_else(|e| {
+        warn!("Keeping lease {}: cannot list containers: {}", lease_id, e);
+        true
+    })
+}
+
+/// Releases the pool entry `lease_id` (or the one a drain moved it to) if it
+/// is still leased to `owner`
+pub fn release_lease(pool: &mut GPUPool, lease_id: &str, owner: Option<&str>) -> Option<u32> {
+    let label = lease_id.parse::<u32>().ok()?;
+    let gpu_id = owner.and_then(|owner| pool.current_lease(label, owner)).unwrap_or(label);
+    let gpu = pool.gpus.get(&gpu_id)?;
+    if gpu.allocated_to.is_none() || gpu.allocated_to.as_deref() != owner {
+        return None;
+    }
+    pool.release(gpu_id).ok().map(|_| gpu_id)
+}
+
+pub async fn run_lifecycle_watcher(
+    docker: DockerManager,
+    pool: Arc<AsyncMutex<GPUPool>>,
+    users: Arc<AsyncMutex<UserManager>>,
+    events: Arc<EventBus>,
+) {
+    info!("Watching Docker events for managed containers");
+    let mut tracker = LifecycleTracker::default();
+    loop {
+        let mut stream = docker.container_events();
+        while let Some(item) = stream.next().await {
+            match item {
+                Ok(event) => handle_event(&mut tracker, &docker, &pool, &users, &events, event).await,
+                Err(e) => {
+                    warn!("Docker event stream failed: {}", e);
+                    break;
+                }
+            }
+        }
+        tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+    }
+}
+
+async fn handle_event(
+    tracker: &mut LifecycleTracker,
+    docker: &DockerManager,
+    pool: &AsyncMutex<GPUPool>,
+    users: &AsyncMutex<UserManager>,
+    events: &EventBus,
+    event: ContainerEvent,
+) {
+    let exit = match event.action {
+        ContainerAction::Die => docker.exit_context(&event.container_id).await.ok(),
+        _ => None,
+    };
+    let transition = tracker.observe(&event, exit.as_ref());
+    let name = event.name.clone().unwrap_or_else(|| event.container_id.clone());
+
+    // Our own stops (GPU swap, checkpoint) keep the lease until the container
+    // is back or gone
+    let planned_stop = match event.action {
+        ContainerAction::Die => docker.is_planned_stop(&event.container_id),
+        ContainerAction::Start | ContainerAction::Restart | ContainerAction::Destroy => {
+            docker.end_planned_stop(&event.container_id);
+            false
+        }
+        _ => false,
+    };
+    let released_gpu = match (&event.lease_id, transition.permanent_exit && !planned_stop) {
+        (Some(lease_id), true) if !lease_still_used(docker, lease_id, &event.container_id).await => {
+            release_lease(&mut *pool.lock().await, lease_id, event.owner.as_deref())
+        }
+        _ => None,
+    };
+    if let Some(gpu_id) = released_gpu {
+        info!("♻️ {} exited for good, GPU {} back in the pool", name, gpu_id);
+    }
+    // Its project network may be empty now
+    if event.action == ContainerAction::Destroy {
+        if let Err(e) = docker.prune_networks().await {
+            warn!("Network cleanup after {} failed: {}", name, e);
+        }
+    }
+
+    if let Some(owner) = &event.owner {
+        let message = match (event.action, released_gpu) {
+            (ContainerAction::Oom, _) => Some(format!("Container {} ran out of memory and was killed", name)),
+            (_, Some(gpu_id)) => Some(format!("Container {} exited; your lease on GPU {} has ended", name, gpu_id)),
+            _ => None,
+        };
+        if let Some(message) = message {
+            if let Err(e) = users.lock().await.notify(owner, message) {
+                warn!("Could not notify {}: {}", owner, e);
+            }
+        }
+    }
+
+    events.publish(EventKind::ContainerLifecycle {
+        container_id: event.container_id,
+        name,
+        owner: event.owner,
+        action: event.action,
+        status: transition.status,
+        exit_code: event.exit_code,
+        oom_killed: transition.oom_killed,
+        released_gpu,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bollard::models::EventActor;
+
+    fn docker_event(action: &str, attributes: &[(&str, &str)]) -> EventMessage {
+        EventMessage {
+            typ: Some(EventMessageTypeEnum::CONTAINER),
+            action: Some(action.into()),
+            actor: Some(EventActor {
+                id: Some("c0ffee".into()),
+                attributes: Some(attributes.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()),
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn died(code: i64) -> ContainerEvent {
+        ContainerEvent::from_docker(docker_event("die", &[("exitCode", &code.to_string())])).unwrap()
+    }
+
+    #[test]
+    fn test_event_from_docker() {
+        let attributes = [("name", "alice-train"), ("exitCode", "137"), (LABEL_OWNER, "alice"), (LABEL_LEASE, "1")];
+        let event = ContainerEvent::from_docker(docker_event("die", &attributes)).unwrap();
+        assert_eq!(event.action, ContainerAction::Die);
+        assert_eq!(event.exit_code, Some(137));
+        assert_eq!(event.owner.as_deref(), Some("alice"));
+        assert_eq!(event.lease_id.as_deref(), Some("1"));
+
+        assert!(ContainerEvent::from_docker(docker_event("exec_start: sh", &[])).is_none());
+        let mut network = docker_event("connect", &[]);
+        network.typ = Some(EventMessageTypeEnum::NETWORK);
+        assert!(ContainerEvent::from_docker(network).is_none());
+    }
+
+    #[test]
+    fn test_exit_permanence_follows_restart_policy() {
+        let context = |policy, restart_count| ExitContext { policy, restart_count, oom_killed: false };
+        let mut tracker = LifecycleTracker::default();
+
+        let clean = tracker.observe(&died(0), Some(&context(RestartPolicy::No, 0)));
+        assert_eq!(clean, Transition { status: VMStatus::Stopped, oom_killed: false, permanent_exit: true });
+
+        let always = context(RestartPolicy::Always, 7);
+        assert!(!tracker.observe(&died(0), Some(&always)).permanent_exit);
+
+        let on_failure = context(RestartPolicy::OnFailure { max_retries: Some(3) }, 1);
+        let crashed = tracker.observe(&died(1), Some(&on_failure));
+        assert_eq!(crashed.status, VMStatus::Crashed);
+        assert!(!crashed.permanent_exit);
+        assert!(tracker.observe(&died(0), Some(&on_failure)).permanent_exit);
+        let exhausted = context(RestartPolicy::OnFailure { max_retries: Some(3) }, 3);
+        assert!(tracker.observe(&died(1), Some(&exhausted)).permanent_exit);
+
+        // Already removed
+        assert!(tracker.observe(&died(0), None).permanent_exit);
+    }
+
+    #[test]
+    fn test_oom_is_flagged_on_exit() {
+        let mut tracker = LifecycleTracker::default();
+        let oom = ContainerEvent::from_docker(docker_event("oom", &[])).unwrap();
+        assert!(tracker.observe(&oom, None).oom_killed);
+
+        let exit = ExitContext { policy: RestartPolicy::No, restart_count: 0, oom_killed: false };
+        let transition = tracker.observe(&died(137), Some(&exit));
+        assert!(transition.oom_killed);
+        assert_eq!(transition.status, VMStatus::Crashed);
+        // Flag is consumed
+        assert!(!tracker.observe(&died(137), Some(&exit)).oom_killed);
+    }
+
+    #[test]
+    fn test_release_lease_only_for_its_owner() {
+        let mut pool = GPUPool::new();
+        pool.allocate("alice", 1).unwrap();
+
+        assert_eq!(release_lease(&mut pool, "1", Some("bob")), None);
+        assert_eq!(release_lease(&mut pool, "nope", Some("alice")), None);
+        assert_eq!(release_lease(&mut pool, "1", Some("alice")), Some(1));
+        assert!(pool.gpus[&1].allocated_to.is_none());
+        assert_eq!(release_lease(&mut pool, "1", Some("alice")), None);
+    }
+}
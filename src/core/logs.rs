@@ -0,0 +1,128 @@
+/*
+* Container logs
+* --------------
+* What `DockerManager::logs` takes and yields. Docker multiplexes stdout and
+* stderr in one stream; each frame becomes a `LogLine` tagged with its source so
+* clients can split them again. TTY containers only have a console stream.
+*/
+
+use anyhow::{anyhow, Result};
+use bollard::container::{LogOutput, LogsOptions};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Query for `DockerManager::logs` (and `GET /api/v1/vms/{id}/logs`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogOptions {
+    /// Keep streaming new output until the container stops
+    pub follow: bool,
+    /// Only the last N lines of existing output
+    pub tail: Option<u64>,
+    /// Only output after this time
+    pub since: Option<DateTime<Utc>>,
+    pub stdout: bool,
+    pub stderr: bool,
+    /// Prefix every line with Docker's RFC 3339 timestamp
+    pub timestamps: bool,
+}
+
+impl Default for LogOptions {
+    fn default() -> Self {
+        Self { follow: false, tail: None, since: None, stdout: true, stderr: true, timestamps: false }
+    }
+}
+
+impl LogOptions {
+    pub fn to_docker(&self) -> Result<LogsOptions<String>> {
+        if !self.stdout && !self.stderr {
+            return Err(anyhow!("Ask for stdout, stderr or both"));
+        }
+        Ok(LogsOptions {
+            follow: self.follow,
+            stdout: self.stdout,
+            stderr: self.stderr,
+            since: self.since.map(|t| t.timestamp()).unwrap_or(0),
+            until: 0,
+            timestamps: self.timestamps,
+            tail: self.tail.map(|n| n.to_string()).unwrap_or_else(|| "all".to_string()),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogSource {
+    Stdout,
+    Stderr,
+    /// TTY containers, where Docker can't tell the two apart
+    Console,
+}
+
+impl LogSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogSource::Stdout => "stdout",
+            LogSource::Stderr => "stderr",
+            LogSource::Console => "console",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogLine {
+    pub source: LogSource,
+    pub message: String,
+}
+
+impl LogLine {
+    /// `None` for stdin echoes, which are never shown
+    pub fn from_output(output: LogOutput) -> Option<Self> {
+        let (source, message) = match output {
+            LogOutput::StdOut { message } => (LogSource::Stdout, message),
+            LogOutput::StdErr { message } => (LogSource::Stderr, message),
+            LogOutput::Console { message } => (LogSource::Console, message),
+            LogOutput::StdIn { .. } => return None,
+        };
+        let message = String::from_utf8_lossy(&message);
+        Some(Self { source, message: message.trim_end_matches(['\n', '\r']).to_string() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_options_to_docker() {
+        let options = LogOptions {
+            follow: true,
+            tail: Some(100),
+            since: Some("2024-05-01T12:00:00Z".parse().unwrap()),
+            stderr: false,
+            ..Default::default()
+        };
+        let docker = options.to_docker().unwrap();
+        assert!(docker.follow && docker.stdout && !docker.stderr);
+        assert_eq!(docker.tail, "100");
+        assert_eq!(docker.since, 1714564800);
+        assert_eq!(LogOptions::default().to_docker().unwrap().tail, "all");
+
+        let nothing = LogOptions { stdout: false, stderr: false, ..Default::default() };
+        assert!(nothing.to_docker().is_err());
+    }
+
+    #[test]
+    fn test_log_line_from_output() {
+        let line = LogLine::from_output(LogOutput::StdErr { message: "oom soon\n".into() }).unwrap();
+        assert_eq!(line, LogLine { source: LogSource::Stderr, message: "oom soon".into() });
+        assert!(LogLine::from_output(LogOutput::StdIn { message: "ls\n".into() }).is_none());
+    }
+
+    #[test]
+    fn test_log_options_from_query() {
+        let options: LogOptions = serde_json::from_value(serde_json::json!({"follow": true, "tail": 5})).unwrap();
+        assert!(options.follow && options.stdout && options.stderr);
+        assert_eq!(options.tail, Some(5));
+    }
+}
@@ -2,6 +2,20 @@ pub mod errors;
 pub mod resource_manager;
 pub mod vm;
 pub mod docker_manager;
+pub mod exec;
+#[cfg(any(test, feature = "fake-runtime"))]
+pub mod fake_runtime;
+pub mod images;
+pub mod lifecycle;
+pub mod logs;
+pub mod networks;
+pub mod projects;
+pub mod readiness;
+pub mod reconcile;
+pub mod recreate;
+pub mod runtime;
+pub mod snapshots;
+pub mod volumes;
 
 // exports for lazy devs like us
-// pub use libvirt::LibvirtManager; 
\ No newline at end of file
+// pub use libvirt::LibvirtManager;
@@ -0,0 +1,360 @@
+/*
+* Per-project networks
+* --------------------
+* Instead of the default bridge, every managed container joins a bridge network
+* of its own project (`gpu-share-project-<project>-<hash>`), or of its owner
+* when it has no project (`gpu-share-user-<owner>-<hash>`); the hash of the
+* raw name keeps names that clean up alike apart. Docker isolates user-defined
+* bridges from each other and runs its embedded DNS on them, so a project's
+* containers reach each other by container name and nobody else's. Containers
+* that name a `network` themselves keep it - tenants may only name their own
+* project's, and nobody gets `host` or another container's network stack.
+*
+* Egress restriction makes the project's network `internal`: no route out of
+* the host, and therefore no published ports either - a restricted project
+* asking for ports is rejected up front.
+*
+* Published host ports come from `[networks] port_range`. Ports given
+* explicitly must be inside it and free; the rest get the lowest free port.
+* "Free" means no running container publishes it - two creates racing for the
+* same port still end with Docker refusing the second.
+*
+* Networks are created on first use and removed by `DockerManager::prune_networks`
+* once their last container is gone (lifecycle watcher and reconcile passes).
+*/
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{anyhow, Result};
+use bollard::models::Network;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::names::docker_safe_name;
+use crate::config::settings::Settings;
+use crate::core::docker_manager::{ContainerConfig, ContainerLabels, PortMapping, Protocol, LABEL_MANAGED, LABEL_OWNER, LABEL_PROJECT};
+
+pub const NETWORK_PREFIX: &str = "gpu-share-";
+
+/// New networks are left alone this long, so a prune can't race a create
+/// between making the network and starting the container on it
+const PRUNE_GRACE_SECS: i64 = 60;
+
+/// `[networks]` in the config files
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    /// Put containers on their project's network; off means the default bridge
+    pub isolate: bool,
+    /// No egress for any project network
+    pub restrict_egress: bool,
+    /// No egress for these projects (or tenants, for containers without a project)
+    pub restricted_projects: Vec<String>,
+    pub port_range: PortRange,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            isolate: true,
+            restrict_egress: false,
+            restricted_projects: Vec::new(),
+            port_range: PortRange::default(),
+        }
+    }
+}
+
+impl NetworkConfig {
+    /// `[networks]` from the config files, or the defaults
+    pub fn load() -> Self {
+        Settings::new().map(|s| s.networks).unwrap_or_default()
+    }
+
+    /// Network `config` gets when it doesn't name one
+    pub fn scope_for(&self, config: &ContainerConfig) -> Option<NetworkScope> {
+        if !self.isolate || config.network.is_some() {
+            return None;
+        }
+        NetworkScope::from_labels(&config.labels)
+    }
+
+    pub fn is_restricted(&self, scope: &NetworkScope) -> bool {
+        self.restrict_egress || self.restricted_projects.iter().any(|p| p == scope.key())
+    }
+
+    /// What can be rejected before talking to Docker
+    pub fn check(&self, config: &ContainerConfig) -> Result<()> {
+        if let Some(network) = &config.network {
+            if network == "host" || network.starts_with("container:") {
+                return Err(anyhow!("Network mode {} shares the host's or another container's network", network));
+            }
+        }
+        if let Some(scope) = self.scope_for(config) {
+            if self.is_restricted(&scope) && !config.ports.is_empty() {
+                return Err(anyhow!("{} has no egress, so its containers can't publish ports", scope));
+            }
+        }
+        for port in &config.ports {
+            if let Some(host_port) = port.host_port {
+                if !self.port_range.contains(host_port) {
+                    return Err(PortConflict::OutOfRange { port: host_port, range: self.port_range }.into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A tenant's container may only name the network it would get anyway
+pub fn check_tenant_network(config: &ContainerConfig) -> Result<()> {
+    let Some(network) = &config.network else { return Ok(()) };
+    match NetworkScope::from_labels(&config.labels) {
+        Some(scope) if scope.network_name() == *network => Ok(()),
+        _ => Err(anyhow!("Network {} isn't yours; leave `network` out to get your project's", network)),
+    }
+}
+
+/// Whose network a container joins
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum NetworkScope {
+    Project(String),
+    Tenant(String),
+}
+
+impl NetworkScope {
+    /// Project first, then owner; operator containers without either stay on the default bridge
+    pub fn from_labels(labels: &ContainerLabels) -> Option<Self> {
+        let non_empty = |value: &Option<String>| value.clone().filter(|v| !v.is_empty());
+        non_empty(&labels.project)
+            .map(NetworkScope::Project)
+            .or_else(|| non_empty(&labels.owner).map(NetworkScope::Tenant))
+    }
+
+    pub fn key(&self) -> &str {
+        match self {
+            NetworkScope::Project(name) | NetworkScope::Tenant(name) => name,
+        }
+    }
+
+    /// Docker network name; see `utils::names` for why it ends in a hash
+    pub fn network_name(&self) -> String {
+        let kind = match self {
+            NetworkScope::Project(_) => "project",
+            NetworkScope::Tenant(_) => "user",
+        };
+        format!("{}{}-{}", NETWORK_PREFIX, kind, docker_safe_name(self.key()))
+    }
+
+    /// Labels of the network itself; `gpu-share.managed` is what pruning looks for
+    pub fn labels(&self) -> HashMap<String, String> {
+        let key = match self {
+            NetworkScope::Project(_) => LABEL_PROJECT,
+            NetworkScope::Tenant(_) => LABEL_OWNER,
+        };
+        HashMap::from([
+            (LABEL_MANAGED.to_string(), "true".to_string()),
+            (key.to_string(), self.key().to_string()),
+        ])
+    }
+}
+
+impl std::fmt::Display for NetworkScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NetworkScope::Project(name) => write!(f, "Project {}", name),
+            NetworkScope::Tenant(name) => write!(f, "Tenant {}", name),
+        }
+    }
+}
+
+/// A managed network nobody uses any more, past its grace period
+pub fn is_prunable(network: &Network, now: DateTime<Utc>) -> bool {
+    let managed = network
+        .labels
+        .as_ref()
+        .is_some_and(|labels| labels.get(LABEL_MANAGED).map(String::as_str) == Some("true"));
+    let empty = network.containers.as_ref().is_none_or(HashMap::is_empty);
+    let settled = network
+        .created
+        .as_deref()
+        .and_then(|created| DateTime::parse_from_rfc3339(created).ok())
+        .is_none_or(|created| now - created.with_timezone(&Utc) >= Duration::seconds(PRUNE_GRACE_SECS));
+    managed && empty && settled
+}
+
+/// Host ports published ports may use, both ends included
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl Default for PortRange {
+    fn default() -> Self {
+        Self { start: 30000, end: 32767 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum PortConflict {
+    #[error("Host port {port}/{} is already published", protocol.as_str())]
+    InUse { port: u16, protocol: Protocol },
+    #[error("Host port {port} is outside the allowed range {}-{}", range.start, range.end)]
+    OutOfRange { port: u16, range: PortRange },
+    #[error("No free host ports left in {}-{}", range.start, range.end)]
+    Exhausted { range: PortRange },
+}
+
+impl PortRange {
+    pub fn contains(&self, port: u16) -> bool {
+        (self.start..=self.end).contains(&port)
+    }
+
+    /// Checks explicit host ports against `used` and fills in the missing
+    /// ones with the lowest free port of the range
+    pub fn assign(&self, ports: &mut [PortMapping], used: &HashSet<(u16, Protocol)>) -> Result<(), PortConflict> {
+        let mut taken = used.clone();
+        for port in ports.iter().filter_map(|p| p.host_port.map(|host| (host, p.protocol))) {
+            if !self.contains(port.0) {
+                return Err(PortConflict::OutOfRange { port: port.0, range: *self });
+            }
+            if !taken.insert(port) {
+                return Err(PortConflict::InUse { port: port.0, protocol: port.1 });
+            }
+        }
+        for port in ports.iter_mut().filter(|p| p.host_port.is_none()) {
+            let free = (self.start..=self.end)
+                .find(|candidate| !taken.contains(&(*candidate, port.protocol)))
+                .ok_or(PortConflict::Exhausted { range: *self })?;
+            taken.insert((free, port.protocol));
+            port.host_port = Some(free);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn port(container_port: u16, host_port: Option<u16>, protocol: Protocol) -> PortMapping {
+        PortMapping { container_port, host_port, protocol, host_ip: None }
+    }
+
+    fn config(project: Option<&str>, owner: Option<&str>) -> ContainerConfig {
+        ContainerConfig {
+            image: "pytorch/pytorch:2.3".into(),
+            name: "train".into(),
+            labels: ContainerLabels {
+                project: project.map(Into::into),
+                owner: owner.map(Into::into),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_scope_and_network_name() {
+        let networks = NetworkConfig::default();
+        let scope = networks.scope_for(&config(Some("vision lab"), Some("alice"))).unwrap();
+        assert_eq!(scope, NetworkScope::Project("vision lab".into()));
+        assert!(scope.network_name().starts_with("gpu-share-project-vision-lab-"));
+        assert_ne!(scope.network_name(), NetworkScope::Project("vision-lab".into()).network_name());
+        assert_eq!(scope.labels()[LABEL_PROJECT], "vision lab");
+
+        let scope = networks.scope_for(&config(None, Some("alice"))).unwrap();
+        assert!(scope.network_name().starts_with("gpu-share-user-alice-"));
+        assert_ne!(scope.network_name(), NetworkScope::Project("alice".into()).network_name());
+        assert_eq!(networks.scope_for(&config(None, None)), None);
+
+        // An explicit network, or isolation off, means no project network
+        let mut explicit = config(Some("vision"), None);
+        explicit.network = Some("host".into());
+        assert_eq!(networks.scope_for(&explicit), None);
+        let off = NetworkConfig { isolate: false, ..Default::default() };
+        assert_eq!(off.scope_for(&config(Some("vision"), None)), None);
+    }
+
+    #[test]
+    fn test_restricted_projects_cant_publish() {
+        let networks = NetworkConfig { restricted_projects: vec!["secure".into()], ..Default::default() };
+        let mut restricted = config(Some("secure"), Some("alice"));
+        assert!(networks.check(&restricted).is_ok());
+        restricted.ports.push(port(8888, None, Protocol::Tcp));
+        assert!(networks.check(&restricted).unwrap_err().to_string().contains("no egress"));
+
+        let mut open = config(Some("vision"), Some("alice"));
+        open.ports.push(port(8888, None, Protocol::Tcp));
+        assert!(networks.check(&open).is_ok());
+        assert!(NetworkConfig { restrict_egress: true, ..Default::default() }.check(&open).is_err());
+
+        open.ports[0].host_port = Some(8888);
+        let err = networks.check(&open).unwrap_err();
+        assert!(matches!(err.downcast_ref::<PortConflict>(), Some(PortConflict::OutOfRange { port: 8888, .. })));
+    }
+
+    #[test]
+    fn test_network_modes_and_tenant_networks() {
+        let networks = NetworkConfig::default();
+        for mode in ["host", "container:db"] {
+            let mut shared = config(Some("vision"), Some("alice"));
+            shared.network = Some(mode.into());
+            assert!(networks.check(&shared).unwrap_err().to_string().contains("shares the host"));
+        }
+
+        let mut own = config(Some("vision"), Some("alice"));
+        assert!(check_tenant_network(&own).is_ok());
+        own.network = Some(NetworkScope::Project("vision".into()).network_name());
+        assert!(check_tenant_network(&own).is_ok());
+
+        let mut other = config(Some("vision"), Some("alice"));
+        other.network = Some(NetworkScope::Project("nlp".into()).network_name());
+        assert!(check_tenant_network(&other).unwrap_err().to_string().contains("isn't yours"));
+        other.network = Some("bridge".into());
+        assert!(check_tenant_network(&other).is_err());
+    }
+
+    #[test]
+    fn test_port_assignment() {
+        let range = PortRange { start: 30000, end: 30003 };
+        let used = HashSet::from([(30000, Protocol::Tcp)]);
+
+        let mut ports = vec![
+            port(22, None, Protocol::Tcp),
+            port(8888, Some(30001), Protocol::Tcp),
+            port(53, None, Protocol::Udp),
+            port(6006, None, Protocol::Tcp),
+        ];
+        range.assign(&mut ports, &used).unwrap();
+        let assigned: Vec<_> = ports.iter().map(|p| p.host_port.unwrap()).collect();
+        // Explicit ports are claimed before free ones are handed out; protocols don't collide
+        assert_eq!(assigned, vec![30002, 30001, 30000, 30003]);
+
+        let mut taken = vec![port(80, Some(30000), Protocol::Tcp)];
+        assert_eq!(range.assign(&mut taken, &used), Err(PortConflict::InUse { port: 30000, protocol: Protocol::Tcp }));
+        let mut twice = vec![port(80, Some(30002), Protocol::Tcp), port(81, Some(30002), Protocol::Tcp)];
+        assert!(matches!(range.assign(&mut twice, &used), Err(PortConflict::InUse { port: 30002, .. })));
+        let mut outside = vec![port(80, Some(8080), Protocol::Tcp)];
+        assert!(matches!(range.assign(&mut outside, &used), Err(PortConflict::OutOfRange { .. })));
+
+        let mut too_many: Vec<_> = (0..4).map(|i| port(8000 + i, None, Protocol::Tcp)).collect();
+        assert_eq!(range.assign(&mut too_many, &used), Err(PortConflict::Exhausted { range }));
+    }
+
+    #[test]
+    fn test_prunable_networks() {
+        let now = Utc::now();
+        let network = |managed: bool, containers: usize, age_secs: i64| Network {
+            labels: Some(if managed { NetworkScope::Tenant("alice".into()).labels() } else { HashMap::new() }),
+            containers: Some((0..containers).map(|i| (i.to_string(), Default::default())).collect()),
+            created: Some((now - Duration::seconds(age_secs)).to_rfc3339()),
+            ..Default::default()
+        };
+        assert!(is_prunable(&network(true, 0, 600), now));
+        assert!(!is_prunable(&network(true, 1, 600), now));
+        assert!(!is_prunable(&network(false, 0, 600), now));
+        // Just created - its container may not have been started yet
+        assert!(!is_prunable(&network(true, 0, 5), now));
+    }
+}
@@ -0,0 +1,67 @@
+/*
+* Project membership
+* ------------------
+* A project picks registry credentials, a container's network and which
+* volumes go when the project is deleted, so tenants may only name projects
+* they belong to. `[projects]` maps each project to its members:
+*
+*   [projects]
+*   vision = ["alice", "bob"]
+*
+* Operators may use any project. A tenant in no project can still work
+* without one.
+*/
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::config::settings::Settings;
+
+/// `[projects]` in the config files
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ProjectConfig {
+    pub members: HashMap<String, Vec<String>>,
+}
+
+impl ProjectConfig {
+    /// `[projects]` from the config files, or no projects at all
+    pub fn load() -> Self {
+        Settings::new().map(|s| s.projects).unwrap_or_default()
+    }
+
+    pub fn is_member(&self, project: &str, user: &str) -> bool {
+        self.members.get(project).is_some_and(|members| members.iter().any(|m| m == user))
+    }
+
+    /// Unknown projects look the same as ones `user` isn't in
+    pub fn check_member(&self, project: &str, user: &str) -> Result<()> {
+        if self.is_member(project, user) {
+            Ok(())
+        } else {
+            Err(anyhow!("{} is not a member of project {}", user, project))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_membership() {
+        let projects: ProjectConfig = serde_json::from_value(serde_json::json!({
+            "vision": ["alice", "bob"],
+            "nlp": ["carol"],
+        }))
+        .unwrap();
+
+        assert!(projects.is_member("vision", "bob"));
+        assert!(projects.check_member("nlp", "carol").is_ok());
+        assert!(projects.check_member("nlp", "alice").unwrap_err().to_string().contains("not a member of project nlp"));
+        assert!(projects.check_member("audio", "alice").is_err());
+        assert!(!ProjectConfig::default().is_member("vision", "alice"));
+    }
+}
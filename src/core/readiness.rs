@@ -0,0 +1,428 @@
+/*
+* Host readiness checks ("doctor")
+* --------------------------------
+* Runs every prerequisite for GPU sharing and reports pass/warn/fail with a
+* remediation hint per check. Shared by `gpu-share doctor` and
+* `GET /api/v1/system/readiness`, so both say exactly the same thing.
+*
+* Checks, in order: hardware virtualization, IOMMU, vfio modules, passthrough
+* security settings, IOMMU group isolation per GPU, Docker daemon, libvirt,
+* privileges.
+*/
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::core::docker_manager::DockerManager;
+use crate::gpu::device::{has_required_permissions, GPUManager};
+use crate::gpu::iommu::{IommuDiagnostics, IommuReport};
+use crate::gpu::passthrough::PassthroughConfig;
+use crate::utils::command::{CommandRunner, SystemCommandRunner};
+use crate::utils::Platform;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckResult {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remediation: Option<String>,
+}
+
+impl CheckResult {
+    pub fn pass(name: &str, detail: impl Into<String>) -> Self {
+        Self { name: name.into(), status: CheckStatus::Pass, detail: detail.into(), remediation: None }
+    }
+
+    pub fn warn(name: &str, detail: impl Into<String>, remediation: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status: CheckStatus::Warn,
+            detail: detail.into(),
+            remediation: Some(remediation.into()),
+        }
+    }
+
+    pub fn fail(name: &str, detail: impl Into<String>, remediation: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status: CheckStatus::Fail,
+            detail: detail.into(),
+            remediation: Some(remediation.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadinessReport {
+    /// Worst status of any check
+    pub status: CheckStatus,
+    pub checks: Vec<CheckResult>,
+    /// Raw IOMMU findings behind the iommu/vfio checks
+    pub iommu: IommuReport,
+}
+
+impl ReadinessReport {
+    pub fn from_checks(checks: Vec<CheckResult>, iommu: IommuReport) -> Self {
+        let status = checks.iter().map(|c| c.status).max().unwrap_or(CheckStatus::Pass);
+        Self { status, checks, iommu }
+    }
+}
+
+pub struct ReadinessChecker {
+    iommu: IommuDiagnostics,
+    runner: Arc<dyn CommandRunner>,
+    kvm_device: PathBuf,
+    passthrough: PassthroughConfig,
+}
+
+impl Default for ReadinessChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReadinessChecker {
+    pub fn new() -> Self {
+        Self::with_parts(IommuDiagnostics::new(), Arc::new(SystemCommandRunner), "/dev/kvm")
+            .with_passthrough_config(PassthroughConfig::load())
+    }
+
+    pub fn with_parts(
+        iommu: IommuDiagnostics,
+        runner: Arc<dyn CommandRunner>,
+        kvm_device: impl Into<PathBuf>,
+    ) -> Self {
+        Self {
+            iommu,
+            runner,
+            kvm_device: kvm_device.into(),
+            passthrough: PassthroughConfig::default(),
+        }
+    }
+
+    pub fn with_passthrough_config(mut self, config: PassthroughConfig) -> Self {
+        self.passthrough = config;
+        self
+    }
+
+    /// Runs everything. `docker` is optional so the CLI still works when the
+    /// client can't even be constructed.
+    pub async fn run(&self, gpu_manager: &GPUManager, docker: Option<&DockerManager>) -> ReadinessReport {
+        let docker = check_docker(docker).await;
+        self.report(gpu_manager, docker)
+    }
+
+    /// Everything but the Docker ping. Blocking (sysfs reads, `virsh`, `id`),
+    /// so the API calls this on the blocking pool.
+    pub fn report(&self, gpu_manager: &GPUManager, docker: CheckResult) -> ReadinessReport {
+        let iommu = self.iommu.report();
+
+        let mut checks = vec![
+            self.check_virtualization(),
+            check_iommu(&iommu),
+            check_vfio_modules(&iommu),
+            check_passthrough_config(&self.passthrough, &iommu),
+        ];
+        checks.extend(check_iommu_groups(gpu_manager, self.iommu.sysfs_root()));
+        checks.push(docker);
+        checks.push(self.check_libvirt());
+        checks.push(check_permissions(has_required_permissions()));
+
+        ReadinessReport::from_checks(checks, iommu)
+    }
+
+    fn check_virtualization(&self) -> CheckResult {
+        const NAME: &str = "virtualization";
+        let platform = Platform::current();
+        if !platform.supports_hardware_virtualization() {
+            return CheckResult::fail(
+                NAME,
+                format!("{:?} reports no hardware virtualization", platform),
+                "Enable VT-x/AMD-V in firmware",
+            );
+        }
+        if platform == Platform::Linux && !self.kvm_device.exists() {
+            return CheckResult::fail(
+                NAME,
+                format!("{} is missing", self.kvm_device.display()),
+                "Enable VT-x/AMD-V in firmware and load kvm_intel or kvm_amd",
+            );
+        }
+        CheckResult::pass(NAME, "Hardware virtualization available")
+    }
+
+    /// libvirt is only needed for VM (not container) workloads, so it never fails the run
+    fn check_libvirt(&self) -> CheckResult {
+        const NAME: &str = "libvirt";
+        match self.runner.run("virsh", &["--connect", "qemu:///system", "version"]) {
+            Ok(_) => CheckResult::pass(NAME, "Connected to qemu:///system"),
+            Err(e) => CheckResult::warn(
+                NAME,
+                format!("Cannot reach libvirtd: {}", e),
+                "Install libvirt-daemon-system and start libvirtd (needed for VM workloads only)",
+            ),
+        }
+    }
+}
+
+pub fn check_iommu(report: &IommuReport) -> CheckResult {
+    const NAME: &str = "iommu";
+    if !report.iommu_enabled() {
+        let detail = report
+            .errors
+            .iter()
+            .find(|e| !e.contains("vfio"))
+            .cloned()
+            .unwrap_or_else(|| "IOMMU not active".into());
+        return CheckResult::fail(
+            NAME,
+            detail,
+            "Enable VT-d/AMD-Vi in firmware and boot with intel_iommu=on (or amd_iommu=on) iommu=pt",
+        );
+    }
+    let warnings: Vec<&String> = report.warnings.iter().filter(|w| !w.contains("vfio")).collect();
+    if warnings.is_empty() {
+        return CheckResult::pass(NAME, format!("{} IOMMU groups", report.group_count));
+    }
+    CheckResult::warn(
+        NAME,
+        warnings.iter().map(|w| w.as_str()).collect::<Vec<_>>().join("; "),
+        "Add iommu=pt to the kernel command line; avoid pcie_acs_override on shared hosts",
+    )
+}
+
+pub fn check_vfio_modules(report: &IommuReport) -> CheckResult {
+    const NAME: &str = "vfio";
+    let modules = &report.vfio_modules;
+    if !modules.vfio_pci {
+        return CheckResult::fail(NAME, "vfio-pci not loaded", "modprobe vfio-pci (and add it to /etc/modules-load.d)");
+    }
+    if !modules.all_loaded() {
+        return CheckResult::warn(
+            NAME,
+            format!(
+                "vfio={} vfio_pci={} vfio_iommu_type1={}",
+                modules.vfio, modules.vfio_pci, modules.vfio_iommu_type1
+            ),
+            "modprobe vfio vfio_iommu_type1",
+        );
+    }
+    CheckResult::pass(NAME, "vfio, vfio_pci and vfio_iommu_type1 loaded")
+}
+
+/// Relaxed passthrough settings work, but at a cost worth shouting about
+pub fn check_passthrough_config(config: &PassthroughConfig, report: &IommuReport) -> CheckResult {
+    const NAME: &str = "passthrough_config";
+    let warnings = config.security_warnings(&report.cmdline);
+    if warnings.is_empty() {
+        return CheckResult::pass(
+            NAME,
+            format!(
+                "Unsafe interrupts off, ACS override off, runtime PM {}",
+                if config.enable_power_management { "on" } else { "off" }
+            ),
+        );
+    }
+    CheckResult::warn(
+        NAME,
+        warnings.join("; "),
+        "Review [passthrough] in the config - only relax these on single-tenant hosts",
+    )
+}
+
+/// One check per GPU. Non-isolated groups only warn: containers don't need VFIO.
+pub fn check_iommu_groups(gpu_manager: &GPUManager, sysfs_root: &Path) -> Vec<CheckResult> {
+    let gpus = gpu_manager.list_available_devices().unwrap_or_default();
+    if gpus.is_empty() {
+        return vec![CheckResult::warn("iommu_group", "No GPUs detected", "Check drivers with `lspci -k`")];
+    }
+
+    gpus.iter()
+        .map(|gpu| {
+            let name = format!("iommu_group:{}", gpu.id);
+            match gpu.iommu_group {
+                None => CheckResult::warn(
+                    &name,
+                    format!("{} has no IOMMU group", gpu.model),
+                    "Enable the IOMMU to pass this GPU through",
+                ),
+                Some(group) => match gpu_manager.validate_iommu_group_in(sysfs_root, group) {
+                    Ok(()) => CheckResult::pass(&name, format!("{} isolated in group {}", gpu.model, group)),
+                    Err(e) => CheckResult::warn(
+                        &name,
+                        format!("{} (group {}): {}", gpu.model, group, e),
+                        "Move the card to a slot with its own group or a bridge with ACS",
+                    ),
+                },
+            }
+        })
+        .collect()
+}
+
+pub async fn check_docker(docker: Option<&DockerManager>) -> CheckResult {
+    const NAME: &str = "docker";
+    let remediation = "Start dockerd and make sure this user can access /var/run/docker.sock";
+    match docker {
+        None => CheckResult::fail(NAME, "Docker client could not be created", remediation),
+        Some(docker) => match docker.ping().await {
+            Ok(()) => CheckResult::pass(NAME, "Docker daemon reachable"),
+            Err(e) => CheckResult::fail(NAME, format!("Docker daemon unreachable: {}", e), remediation),
+        },
+    }
+}
+
+pub fn check_permissions(is_root: bool) -> CheckResult {
+    const NAME: &str = "permissions";
+    if is_root {
+        CheckResult::pass(NAME, "Running as root")
+    } else {
+        CheckResult::warn(
+            NAME,
+            "Not running as root - driver rebinding and /dev/vfio access will fail",
+            "Run as root or grant CAP_SYS_ADMIN and access to /dev/vfio",
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu::device::GPUInfo;
+    use crate::gpu::fake_sysfs::FakeSysfs;
+    use anyhow::anyhow;
+    use std::collections::HashMap;
+
+    struct FixedRunner(bool);
+
+    impl CommandRunner for FixedRunner {
+        fn run(&self, program: &str, _args: &[&str]) -> anyhow::Result<String> {
+            assert_eq!(program, "virsh");
+            if self.0 {
+                Ok("Compiled against library: libvirt 10.0.0\n".into())
+            } else {
+                Err(anyhow!("failed to connect to the hypervisor"))
+            }
+        }
+    }
+
+    fn manager_with(groups: &[(u64, &[&str])]) -> GPUManager {
+        let devices = groups
+            .iter()
+            .map(|(group, members)| GPUInfo {
+                id: members[0].to_string(),
+                model: "Test GPU".into(),
+                pci_address: Some(members[0].to_string()),
+                iommu_group: Some(*group),
+                ..Default::default()
+            })
+            .collect();
+        let iommu_groups: HashMap<u64, Vec<String>> = groups
+            .iter()
+            .map(|(group, members)| (*group, members.iter().map(|m| m.to_string()).collect()))
+            .collect();
+        GPUManager { devices, iommu_groups }
+    }
+
+    #[test]
+    fn test_iommu_and_vfio_checks() {
+        let sysfs = FakeSysfs::new();
+        sysfs.write("proc/cmdline", "intel_iommu=on\n");
+        std::fs::create_dir_all(sysfs.path("kernel/iommu_groups/3/devices")).unwrap();
+        sysfs.write("module/vfio_pci/refcnt", "0\n");
+        let report = IommuDiagnostics::with_roots(sysfs.root(), sysfs.path("proc")).report();
+
+        let iommu = check_iommu(&report);
+        assert_eq!(iommu.status, CheckStatus::Warn);
+        assert!(iommu.detail.contains("iommu=pt"));
+        assert_eq!(check_vfio_modules(&report).status, CheckStatus::Warn);
+
+        let empty = IommuDiagnostics::with_roots("/nonexistent", "/nonexistent").report();
+        assert_eq!(check_iommu(&empty).status, CheckStatus::Fail);
+        assert_eq!(check_vfio_modules(&empty).status, CheckStatus::Fail);
+    }
+
+    #[test]
+    fn test_passthrough_config_warnings() {
+        let sysfs = FakeSysfs::new();
+        sysfs.write("proc/cmdline", "intel_iommu=on iommu=pt pcie_acs_override=downstream\n");
+        let report = IommuDiagnostics::with_roots(sysfs.root(), sysfs.path("proc")).report();
+
+        let defaults = check_passthrough_config(&PassthroughConfig::default(), &report);
+        assert_eq!(defaults.status, CheckStatus::Warn);
+        assert!(defaults.detail.contains("will be refused"));
+
+        let relaxed = PassthroughConfig {
+            enable_unsafe_interrupts: true,
+            enable_acs_override: true,
+            ..Default::default()
+        };
+        let check = check_passthrough_config(&relaxed, &report);
+        assert!(check.detail.contains("inject interrupts"));
+        assert!(check.detail.contains("not isolated"));
+
+        let clean = IommuDiagnostics::with_roots("/nonexistent", "/nonexistent").report();
+        assert_eq!(check_passthrough_config(&PassthroughConfig::default(), &clean).status, CheckStatus::Pass);
+    }
+
+    #[test]
+    fn test_group_checks_per_gpu() {
+        let sysfs = FakeSysfs::new();
+        sysfs.add_pci_device("0000:01:00.0", 0x10de, 0x2684, 0x030000);
+        sysfs.add_pci_device("0000:02:00.0", 0x10de, 0x2684, 0x030000);
+        sysfs.add_pci_device("0000:00:14.0", 0x8086, 0x7ae0, 0x0c0330);
+        let manager = manager_with(&[(1, &["0000:01:00.0"]), (2, &["0000:02:00.0", "0000:00:14.0"])]);
+        let mut checks = check_iommu_groups(&manager, sysfs.root());
+        checks.sort_by(|a, b| a.name.cmp(&b.name));
+
+        assert_eq!(checks.len(), 2);
+        assert_eq!(checks[0].status, CheckStatus::Pass);
+        assert_eq!(checks[1].status, CheckStatus::Warn);
+        assert!(checks[1].remediation.is_some());
+    }
+
+    #[test]
+    fn test_overall_status_is_worst_check() {
+        let iommu = IommuDiagnostics::with_roots("/nonexistent", "/nonexistent").report();
+        let report = ReadinessReport::from_checks(
+            vec![
+                CheckResult::pass("a", "fine"),
+                check_permissions(false),
+            ],
+            iommu.clone(),
+        );
+        assert_eq!(report.status, CheckStatus::Warn);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["status"], "warn");
+        assert_eq!(json["checks"][0]["status"], "pass");
+        assert!(json["checks"][0].get("remediation").is_none());
+
+        let report = ReadinessReport::from_checks(vec![check_iommu(&iommu)], iommu);
+        assert_eq!(report.status, CheckStatus::Fail);
+    }
+
+    #[test]
+    fn test_virtualization_and_libvirt() {
+        let sysfs = FakeSysfs::new();
+        sysfs.write("dev/kvm", "");
+        let diagnostics = || IommuDiagnostics::with_roots(sysfs.root(), sysfs.path("proc"));
+
+        let ok = ReadinessChecker::with_parts(diagnostics(), Arc::new(FixedRunner(true)), sysfs.path("dev/kvm"));
+        assert_eq!(ok.check_virtualization().status, CheckStatus::Pass);
+        assert_eq!(ok.check_libvirt().status, CheckStatus::Pass);
+
+        let broken = ReadinessChecker::with_parts(diagnostics(), Arc::new(FixedRunner(false)), sysfs.path("dev/nokvm"));
+        assert_eq!(broken.check_virtualization().status, CheckStatus::Fail);
+        assert_eq!(broken.check_libvirt().status, CheckStatus::Warn);
+    }
+}
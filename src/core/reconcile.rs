@@ -0,0 +1,372 @@
+/*
+* Docker <-> GPUPool reconciliation
+* ---------------------------------
+* The pool and Docker each keep half of the picture: the pool knows which
+* tenant leases which entry, Docker knows which containers run. A container's
+* `gpu-share.lease-id` label is the pool entry id it runs under. Every pass
+* compares the two and looks for:
+*
+*   orphan container -> carries a lease id whose pool entry is missing, free
+*                       or leased to someone other than its owner
+*   orphan lease     -> a leased pool entry no managed container points at
+*
+* A lease a drain migrated keeps its old id in the container's label; the
+* pool resolves it to the entry it lives on now.
+*
+* Containers without a lease label (operator workloads) are never orphans.
+* By default findings are only reported (log + event bus). With `fix = true`
+* running orphan containers are stopped - never removed, their data stays -
+* and leases that stayed orphaned for `lease_grace_secs` are released, so a
+* tenant who just rented a GPU has time to start something on it.
+*/
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::config::settings::Settings;
+use crate::core::docker_manager::{DockerManager, ManagedContainer};
+use crate::events::{EventBus, EventKind};
+use crate::gpu::virtual_gpu::GPUPool;
+use crate::AsyncMutex;
+
+/// `[reconcile]` in the config files
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReconcileConfig {
+    pub interval_secs: u64,
+    /// Stop orphan containers and release orphan leases instead of only reporting
+    pub fix: bool,
+    /// How long a lease may go without a container before `fix` releases it
+    pub lease_grace_secs: u64,
+}
+
+impl Default for ReconcileConfig {
+    fn default() -> Self {
+        Self { interval_secs: 60, fix: false, lease_grace_secs: 15 * 60 }
+    }
+}
+
+impl ReconcileConfig {
+    /// `[reconcile]` from the config files, or report-only defaults
+    pub fn load() -> Self {
+        Settings::new().map(|s| s.reconcile).unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Discrepancy {
+    OrphanContainer {
+        container_id: String,
+        name: String,
+        owner: Option<String>,
+        lease_id: String,
+        running: bool,
+        reason: String,
+    },
+    OrphanLease { gpu_id: u32, tenant: String },
+}
+
+impl Discrepancy {
+    fn key(&self) -> String {
+        match self {
+            Discrepancy::OrphanContainer { container_id, running, .. } => format!("container:{}:{}", container_id, running),
+            Discrepancy::OrphanLease { gpu_id, tenant } => format!("lease:{}:{}", gpu_id, tenant),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReconcileAction {
+    Reported,
+    StoppedContainer,
+    ReleasedLease,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Finding {
+    pub discrepancy: Discrepancy,
+    pub action: ReconcileAction,
+}
+
+/// Everything that doesn't line up between `containers` and `pool` right now
+pub fn find_discrepancies(containers: &[ManagedContainer], pool: &GPUPool) -> Vec<Discrepancy> {
+    let mut found = Vec::new();
+    let mut claimed: HashSet<u32> = HashSet::new();
+
+    for container in containers {
+        let Some(lease_id) = &container.lease_id else { continue };
+        let entry = lease_id
+            .parse::<u32>()
+            .ok()
+            .map(|id| container.owner.as_deref().and_then(|owner| pool.current_lease(id, owner)).unwrap_or(id))
+            .and_then(|id| pool.gpus.get(&id));
+        let reason = match entry {
+            None => "lease does not exist".to_string(),
+            Some(gpu) => match &gpu.allocated_to {
+                None => "lease was released".to_string(),
+                Some(tenant) if Some(tenant) != container.owner.as_ref() => format!("lease belongs to {}", tenant),
+                Some(_) => {
+                    claimed.insert(gpu.id);
+                    continue;
+                }
+            },
+        };
+        found.push(Discrepancy::OrphanContainer {
+            container_id: container.id.clone(),
+            name: container.name.clone(),
+            owner: container.owner.clone(),
+            lease_id: lease_id.clone(),
+            running: container.running,
+            reason,
+        });
+    }
+
+    let mut leases: Vec<_> = pool
+        .gpus
+        .values()
+        .filter(|gpu| !claimed.contains(&gpu.id))
+        .filter_map(|gpu| gpu.allocated_to.as_ref().map(|tenant| (gpu.id, tenant.clone())))
+        .collect();
+    leases.sort();
+    found.extend(leases.into_iter().map(|(gpu_id, tenant)| Discrepancy::OrphanLease { gpu_id, tenant }));
+    found
+}
+
+/// Remembers what earlier passes saw, so reports aren't repeated every pass
+/// and orphan leases get their grace period
+pub struct Reconciler {
+    config: ReconcileConfig,
+    orphan_leases_since: HashMap<u32, DateTime<Utc>>,
+    reported: HashSet<String>,
+}
+
+impl Reconciler {
+    pub fn new(config: ReconcileConfig) -> Self {
+        Self { config, orphan_leases_since: HashMap::new(), reported: HashSet::new() }
+    }
+
+    pub fn config(&self) -> &ReconcileConfig {
+        &self.config
+    }
+
+    /// Decides what to do about each discrepancy. Report-only findings already
+    /// reported by an earlier pass are left out.
+    pub fn plan(&mut self, discrepancies: Vec<Discrepancy>, now: DateTime<Utc>) -> Vec<Finding> {
+        let leases: HashSet<u32> = discrepancies
+            .iter()
+            .filter_map(|d| match d {
+                Discrepancy::OrphanLease { gpu_id, .. } => Some(*gpu_id),
+                _ => None,
+            })
+            .collect();
+        self.orphan_leases_since.retain(|gpu_id, _| leases.contains(gpu_id));
+
+        let current: HashSet<String> = discrepancies.iter().map(Discrepancy::key).collect();
+        self.reported.retain(|key| current.contains(key));
+
+        let grace = chrono::Duration::seconds(self.config.lease_grace_secs as i64);
+        let mut findings = Vec::new();
+        for discrepancy in discrepancies {
+            let action = match &discrepancy {
+                Discrepancy::OrphanContainer { running: true, .. } if self.config.fix => ReconcileAction::StoppedContainer,
+                Discrepancy::OrphanLease { gpu_id, .. } => {
+                    let since = *self.orphan_leases_since.entry(*gpu_id).or_insert(now);
+                    if self.config.fix && now - since >= grace {
+                        ReconcileAction::ReleasedLease
+                    } else {
+                        ReconcileAction::Reported
+                    }
+                }
+                _ => ReconcileAction::Reported,
+            };
+            if action == ReconcileAction::Reported && !self.reported.insert(discrepancy.key()) {
+                continue;
+            }
+            findings.push(Finding { discrepancy, action });
+        }
+        findings
+    }
+}
+
+/// One pass: compare, act, publish. Returns what was found this pass.
+pub async fn reconcile_once(
+    reconciler: &mut Reconciler,
+    docker: &DockerManager,
+    pool: &AsyncMutex<GPUPool>,
+    events: &EventBus,
+) -> Result<Vec<Finding>> {
+    let containers = docker.list_managed().await?;
+    let discrepancies = find_discrepancies(&containers, &*pool.lock().await);
+    let findings = reconciler.plan(discrepancies, Utc::now());
+
+    for finding in &findings {
+        let applied = match (&finding.action, &finding.discrepancy) {
+            (ReconcileAction::StoppedContainer, Discrepancy::OrphanContainer { container_id, .. }) => {
+                docker.stop_container(container_id).await
+            }
+            (ReconcileAction::ReleasedLease, Discrepancy::OrphanLease { gpu_id, tenant }) => {
+                // Only if nobody re-rented it since the comparison
+                let mut pool = pool.lock().await;
+                match pool.gpus.get(gpu_id) {
+                    Some(gpu) if gpu.allocated_to.as_ref() == Some(tenant) => pool.release(*gpu_id),
+                    _ => continue,
+                }
+            }
+            _ => Ok(()),
+        };
+        match applied {
+            Ok(()) => info!("🔁 Reconcile: {:?} ({:?})", finding.discrepancy, finding.action),
+            Err(e) => {
+                warn!("Reconcile: couldn't fix {:?}: {}", finding.discrepancy, e);
+                continue;
+            }
+        }
+        events.publish(EventKind::Reconciled {
+            discrepancy: finding.discrepancy.clone(),
+            action: finding.action,
+        });
+    }
+    // Networks left behind while the lifecycle watcher wasn't looking
+    if let Err(e) = docker.prune_networks().await {
+        warn!("Reconcile: network cleanup failed: {}", e);
+    }
+    Ok(findings)
+}
+
+pub async fn run_reconciler(
+    mut reconciler: Reconciler,
+    docker: Arc<AsyncMutex<DockerManager>>,
+    pool: Arc<AsyncMutex<GPUPool>>,
+    events: Arc<EventBus>,
+) {
+    let interval = Duration::from_secs(reconciler.config().interval_secs.max(1));
+    info!("Docker/GPU pool reconciler running every {:?} (fix: {})", interval, reconciler.config().fix);
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let docker = docker.lock().await.clone();
+        if let Err(e) = reconcile_once(&mut reconciler, &docker, &pool, &events).await {
+            warn!("Reconcile pass failed: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn container(id: &str, owner: &str, lease_id: Option<&str>) -> ManagedContainer {
+        ManagedContainer {
+            id: id.into(),
+            name: format!("{}-job", owner),
+            image: "pytorch/pytorch:2.3".into(),
+            state: "running".into(),
+            running: true,
+            owner: Some(owner.into()),
+            lease_id: lease_id.map(Into::into),
+            project: None,
+            gpu_id: None,
+            gpu_attached: false,
+            exit_code: None,
+            oom_killed: false,
+            restart_count: 0,
+        }
+    }
+
+    #[test]
+    fn test_find_discrepancies() {
+        let mut pool = GPUPool::new();
+        pool.allocate("alice", 0).unwrap();
+        pool.allocate("bob", 1).unwrap();
+
+        let containers = vec![
+            container("a", "alice", Some("0")),
+            container("m", "mallory", Some("1")),
+            container("g", "carol", Some("7")),
+            container("o", "ops", None),
+        ];
+        let found = find_discrepancies(&containers, &pool);
+        assert_eq!(found.len(), 3);
+        assert!(matches!(&found[0], Discrepancy::OrphanContainer { container_id, reason, .. }
+            if container_id == "m" && reason == "lease belongs to bob"));
+        assert!(matches!(&found[1], Discrepancy::OrphanContainer { container_id, .. } if container_id == "g"));
+        assert_eq!(found[2], Discrepancy::OrphanLease { gpu_id: 1, tenant: "bob".into() });
+
+        pool.release(0).unwrap();
+        let found = find_discrepancies(&containers[..1], &pool);
+        assert!(matches!(&found[0], Discrepancy::OrphanContainer { reason, .. } if reason == "lease was released"));
+    }
+
+    #[test]
+    fn test_migrated_lease_is_not_an_orphan() {
+        let mut pool = GPUPool::new();
+        pool.allocate("alice", 0).unwrap();
+        let containers = vec![container("a", "alice", Some("0"))];
+        let mut users = crate::users::UserManager::new();
+        let events = EventBus::new();
+
+        let progress = crate::gpu::drain::start_drain(
+            &mut pool,
+            &mut users,
+            &events,
+            crate::gpu::drain::DrainTarget::Gpu { gpu_id: 0 },
+            "driver upgrade",
+            true,
+        )
+        .unwrap();
+        assert_eq!(progress.migrated[0].to, 1);
+        assert!(find_discrepancies(&containers, &pool).is_empty());
+
+        // The container exiting gives back the lease it moved to
+        assert_eq!(crate::core::lifecycle::release_lease(&mut pool, "0", Some("alice")), Some(1));
+        assert!(pool.gpus[&1].allocated_to.is_none());
+    }
+
+    #[test]
+    fn test_plan_reports_once_and_waits_out_grace() {
+        let lease = || vec![Discrepancy::OrphanLease { gpu_id: 1, tenant: "bob".into() }];
+        let start: DateTime<Utc> = "2024-05-01T12:00:00Z".parse().unwrap();
+
+        let mut report_only = Reconciler::new(ReconcileConfig::default());
+        assert_eq!(report_only.plan(lease(), start).len(), 1);
+        assert!(report_only.plan(lease(), start + chrono::Duration::hours(5)).is_empty());
+
+        let config = ReconcileConfig { fix: true, lease_grace_secs: 600, ..Default::default() };
+        let mut fixer = Reconciler::new(config);
+        assert_eq!(fixer.plan(lease(), start)[0].action, ReconcileAction::Reported);
+        assert!(fixer.plan(lease(), start + chrono::Duration::minutes(5)).is_empty());
+        let findings = fixer.plan(lease(), start + chrono::Duration::minutes(10));
+        assert_eq!(findings[0].action, ReconcileAction::ReleasedLease);
+
+        // A lease that got its container back starts over
+        assert!(fixer.plan(Vec::new(), start + chrono::Duration::minutes(11)).is_empty());
+        assert_eq!(fixer.plan(lease(), start + chrono::Duration::minutes(12))[0].action, ReconcileAction::Reported);
+    }
+
+    #[test]
+    fn test_plan_stops_running_orphans_only_when_fixing() {
+        let orphan = |running| {
+            vec![Discrepancy::OrphanContainer {
+                container_id: "m".into(),
+                name: "mallory-job".into(),
+                owner: Some("mallory".into()),
+                lease_id: "1".into(),
+                running,
+                reason: "lease was released".into(),
+            }]
+        };
+        let now = Utc::now();
+        let mut fixer = Reconciler::new(ReconcileConfig { fix: true, ..Default::default() });
+        assert_eq!(fixer.plan(orphan(true), now)[0].action, ReconcileAction::StoppedContainer);
+        assert_eq!(fixer.plan(orphan(false), now)[0].action, ReconcileAction::Reported);
+        assert!(fixer.plan(orphan(false), now).is_empty());
+    }
+}
@@ -0,0 +1,437 @@
+/*
+* Container recreate (GPU hot attach / detach)
+* --------------------------------------------
+* Docker has no API for adding devices to an existing container, so changing a
+* container's GPU means replacing it:
+*
+*   snapshot  -> inspect: config, host config (binds, ports, restart policy...),
+*                mounts and networks
+*   swap      -> drop the old GPU (device requests, DRI/kfd mappings, the
+*                groups it added, GPU env), apply the new attachment
+*   recreate  -> stop, rename the old one aside, create + start the new one under
+*                the same name, then remove the old one (never its volumes)
+*
+* Anonymous volumes are pinned into binds by name so the replacement mounts the
+* same data. If create or start fails the old container is renamed back and
+* restarted. A dry run stops after the swap and returns the plan.
+*/
+
+use anyhow::{anyhow, Result};
+use bollard::container::Config;
+use bollard::models::{
+    ContainerConfig as DockerContainerConfig, ContainerInspectResponse, EndpointSettings, HostConfig, MountPoint,
+    MountPointTypeEnum,
+};
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+use crate::core::docker_manager::{LABEL_GPU, LABEL_GPU_GROUPS};
+use crate::gpu::container::{is_gpu_device_mapping, is_gpu_device_request, is_gpu_env, GpuAttachment};
+
+/// Everything needed to create a container again
+#[derive(Debug, Clone)]
+pub struct ContainerSnapshot {
+    pub id: String,
+    pub name: String,
+    /// Image as the container was created with (tag or digest)
+    pub image: String,
+    /// Resolved image id - the replacement uses this so a moved tag can't sneak in
+    pub image_id: Option<String>,
+    pub running: bool,
+    pub config: DockerContainerConfig,
+    pub host_config: HostConfig,
+    pub mounts: Vec<MountPoint>,
+    pub networks: Vec<(String, EndpointSettings)>,
+}
+
+impl ContainerSnapshot {
+    pub fn from_inspect(inspect: ContainerInspectResponse) -> Result<Self> {
+        let id = inspect.id.ok_or_else(|| anyhow!("Container has no id"))?;
+        let config = inspect.config.ok_or_else(|| anyhow!("Container {} has no config", id))?;
+        let image = config
+            .image
+            .clone()
+            .or_else(|| inspect.image.clone())
+            .ok_or_else(|| anyhow!("Container {} has no image", id))?;
+        let name = inspect
+            .name
+            .map(|n| n.trim_start_matches('/').to_string())
+            .unwrap_or_else(|| id.clone());
+
+        let mut networks: Vec<(String, EndpointSettings)> = inspect
+            .network_settings
+            .and_then(|n| n.networks)
+            .map(|n| n.into_iter().collect())
+            .unwrap_or_default();
+        networks.sort_by(|a, b| a.0.cmp(&b.0));
+
+        Ok(Self {
+            name,
+            image,
+            image_id: inspect.image,
+            running: inspect.state.and_then(|s| s.running).unwrap_or(false),
+            config,
+            host_config: inspect.host_config.unwrap_or_default(),
+            mounts: inspect.mounts.unwrap_or_default(),
+            networks,
+            id,
+        })
+    }
+
+    /// The same container with `gpu` in place of whatever GPU it had (`None` detaches)
+    pub fn with_gpu(&self, gpu: Option<&GpuAttachment>) -> Self {
+        let mut next = self.clone();
+        let labels = next.config.labels.get_or_insert_with(Default::default);
+        labels.remove(LABEL_GPU);
+        let old_groups = labels.remove(LABEL_GPU_GROUPS).unwrap_or_default();
+
+        let host = &mut next.host_config;
+        host.device_requests = host
+            .device_requests
+            .take()
+            .map(|reqs| reqs.into_iter().filter(|r| !is_gpu_device_request(r)).collect());
+        host.devices = host
+            .devices
+            .take()
+            .map(|devs| devs.into_iter().filter(|d| !is_gpu_device_mapping(d)).collect());
+        host.group_add = host
+            .group_add
+            .take()
+            .map(|groups| groups.into_iter().filter(|g| !old_groups.split(',').any(|old| old == g)).collect());
+
+        let mut env: Vec<String> = next
+            .config
+            .env
+            .take()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|e| !is_gpu_env(e))
+            .collect();
+
+        if let Some(attachment) = gpu {
+            let groups = attachment.missing_groups(&next.host_config);
+            attachment.apply(&mut next.host_config, &mut env);
+            let labels = next.config.labels.get_or_insert_with(Default::default);
+            labels.insert(LABEL_GPU.to_string(), attachment.gpu_id.clone());
+            if !groups.is_empty() {
+                labels.insert(LABEL_GPU_GROUPS.to_string(), groups.join(","));
+            }
+        }
+        next.config.env = Some(env);
+        next
+    }
+
+    /// Device set as human-readable strings: `nvidia:<uuid>` requests and host paths
+    pub fn devices(&self) -> BTreeSet<String> {
+        let requests = self.host_config.device_requests.iter().flatten().flat_map(|r| {
+            let driver = r.driver.clone().unwrap_or_else(|| "gpu".to_string());
+            match &r.device_ids {
+                Some(ids) => ids.iter().map(|id| format!("{}:{}", driver, id)).collect::<Vec<_>>(),
+                None => vec![format!("{}:count={}", driver, r.count.unwrap_or(-1))],
+            }
+        });
+        let mappings = self
+            .host_config
+            .devices
+            .iter()
+            .flatten()
+            .filter_map(|d| d.path_on_host.clone());
+        requests.chain(mappings).collect()
+    }
+
+    fn env(&self) -> BTreeSet<String> {
+        self.config.env.iter().flatten().cloned().collect()
+    }
+
+    /// `source -> destination` for every mount
+    pub fn mount_summary(&self) -> Vec<String> {
+        self.mounts
+            .iter()
+            .map(|m| {
+                let source = m.name.clone().or_else(|| m.source.clone()).unwrap_or_default();
+                let destination = m.destination.clone().unwrap_or_default();
+                format!("{} -> {}", source, destination)
+            })
+            .collect()
+    }
+
+    /// `8080/tcp <- 0.0.0.0:8080` for every published port
+    pub fn port_summary(&self) -> Vec<String> {
+        let mut ports: Vec<String> = self
+            .host_config
+            .port_bindings
+            .iter()
+            .flatten()
+            .flat_map(|(port, bindings)| {
+                bindings.iter().flatten().map(move |b| {
+                    format!(
+                        "{} <- {}:{}",
+                        port,
+                        b.host_ip.as_deref().filter(|ip| !ip.is_empty()).unwrap_or("0.0.0.0"),
+                        b.host_port.as_deref().unwrap_or("")
+                    )
+                })
+            })
+            .collect();
+        ports.sort();
+        ports
+    }
+
+    /// Networks beyond the one `network_mode` attaches at create time
+    pub fn extra_networks(&self) -> Vec<&(String, EndpointSettings)> {
+        let primary = self.host_config.network_mode.as_deref().unwrap_or("default");
+        self.networks
+            .iter()
+            .filter(|(name, _)| name != primary && !(primary == "default" && name == "bridge"))
+            .collect()
+    }
+
+    /// Create-container body for the replacement
+    pub fn create_config(&self) -> Config<String> {
+        let mut host_config = self.host_config.clone();
+        let binds = host_config.binds.get_or_insert_with(Vec::new);
+        for bind in anonymous_volume_binds(&self.mounts, binds, self.host_config.mounts.as_deref().unwrap_or(&[])) {
+            binds.push(bind);
+        }
+
+        let mut config = Config::from(self.config.clone());
+        config.image = Some(self.image_id.clone().unwrap_or_else(|| self.image.clone()));
+        config.host_config = Some(host_config);
+        config
+    }
+}
+
+/// Volume mounts not declared in binds or mounts (image `VOLUME`s, `-v /data`),
+/// pinned by volume name so the replacement reuses them
+fn anonymous_volume_binds(
+    mounts: &[MountPoint],
+    binds: &[String],
+    declared: &[bollard::models::Mount],
+) -> Vec<String> {
+    let bound: BTreeSet<&str> = binds
+        .iter()
+        .filter_map(|b| b.split(':').nth(1))
+        .chain(declared.iter().filter_map(|m| m.target.as_deref()))
+        .collect();
+
+    mounts
+        .iter()
+        .filter(|m| m.typ == Some(MountPointTypeEnum::VOLUME))
+        .filter_map(|m| {
+            let name = m.name.as_deref()?;
+            let destination = m.destination.as_deref()?;
+            if bound.contains(destination) {
+                return None;
+            }
+            let mode = if m.rw == Some(false) { ":ro" } else { "" };
+            Some(format!("{}:{}{}", name, destination, mode))
+        })
+        .collect()
+}
+
+/// What a recreate changes - and what it carries over
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RecreatePlan {
+    pub container_id: String,
+    pub name: String,
+    pub image: String,
+    pub running: bool,
+    pub devices_added: Vec<String>,
+    pub devices_removed: Vec<String>,
+    pub env_added: Vec<String>,
+    pub env_removed: Vec<String>,
+    pub mounts: Vec<String>,
+    pub ports: Vec<String>,
+    pub networks: Vec<String>,
+}
+
+impl RecreatePlan {
+    pub fn new(before: &ContainerSnapshot, after: &ContainerSnapshot) -> Self {
+        let (devices_before, devices_after) = (before.devices(), after.devices());
+        let (env_before, env_after) = (before.env(), after.env());
+
+        Self {
+            container_id: before.id.clone(),
+            name: before.name.clone(),
+            image: before.image.clone(),
+            running: before.running,
+            devices_added: devices_after.difference(&devices_before).cloned().collect(),
+            devices_removed: devices_before.difference(&devices_after).cloned().collect(),
+            env_added: env_after.difference(&env_before).cloned().collect(),
+            env_removed: env_before.difference(&env_after).cloned().collect(),
+            mounts: before.mount_summary(),
+            ports: before.port_summary(),
+            networks: before.networks.iter().map(|(name, _)| name.clone()).collect(),
+        }
+    }
+
+    /// Nothing to do - the container already has this device set
+    pub fn is_noop(&self) -> bool {
+        self.devices_added.is_empty()
+            && self.devices_removed.is_empty()
+            && self.env_added.is_empty()
+            && self.env_removed.is_empty()
+    }
+}
+
+/// Result of an attach/detach. `container_id` is the replacement's id (or the
+/// unchanged one for dry runs and no-ops).
+#[derive(Debug, Clone, Serialize)]
+pub struct RecreateOutcome {
+    pub dry_run: bool,
+    pub recreated: bool,
+    pub container_id: String,
+    pub plan: RecreatePlan,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu::container::GpuAttacher;
+    use crate::gpu::pci::GpuVendor;
+    use crate::gpu::device::GPUInfo;
+    use bollard::models::{ContainerState, DeviceMapping, DeviceRequest, NetworkSettings, PortBinding};
+    use std::collections::HashMap;
+
+    fn inspect() -> ContainerInspectResponse {
+        ContainerInspectResponse {
+            id: Some("abc123".into()),
+            name: Some("/tenant-job".into()),
+            image: Some("sha256:feed".into()),
+            state: Some(ContainerState { running: Some(true), ..Default::default() }),
+            config: Some(DockerContainerConfig {
+                image: Some("pytorch/pytorch:2.3".into()),
+                env: Some(vec!["PATH=/usr/bin".into(), "NVIDIA_VISIBLE_DEVICES=GPU-old".into()]),
+                labels: Some(HashMap::from([
+                    (LABEL_GPU.to_string(), "GPU-old".to_string()),
+                    (LABEL_GPU_GROUPS.to_string(), "44,109".to_string()),
+                    ("team".to_string(), "vision".to_string()),
+                ])),
+                ..Default::default()
+            }),
+            host_config: Some(HostConfig {
+                binds: Some(vec!["models:/models:ro".into()]),
+                device_requests: Some(vec![DeviceRequest {
+                    driver: Some("nvidia".into()),
+                    device_ids: Some(vec!["GPU-old".into()]),
+                    capabilities: Some(vec![vec!["gpu".into()]]),
+                    ..Default::default()
+                }]),
+                devices: Some(vec![DeviceMapping {
+                    path_on_host: Some("/dev/fuse".into()),
+                    path_in_container: Some("/dev/fuse".into()),
+                    cgroup_permissions: Some("rwm".into()),
+                }]),
+                // 44 and 109 came with the old GPU, 1001 was asked for
+                group_add: Some(vec!["44".into(), "1001".into(), "109".into()]),
+                port_bindings: Some(HashMap::from([(
+                    "8888/tcp".to_string(),
+                    Some(vec![PortBinding { host_ip: Some("".into()), host_port: Some("18888".into()) }]),
+                )])),
+                ..Default::default()
+            }),
+            mounts: Some(vec![
+                MountPoint {
+                    typ: Some(MountPointTypeEnum::VOLUME),
+                    name: Some("models".into()),
+                    destination: Some("/models".into()),
+                    rw: Some(false),
+                    ..Default::default()
+                },
+                MountPoint {
+                    typ: Some(MountPointTypeEnum::VOLUME),
+                    name: Some("0f3e9a".into()),
+                    destination: Some("/workspace".into()),
+                    rw: Some(true),
+                    ..Default::default()
+                },
+            ]),
+            network_settings: Some(NetworkSettings {
+                networks: Some(HashMap::from([
+                    ("bridge".to_string(), EndpointSettings::default()),
+                    ("tenant-net".to_string(), EndpointSettings::default()),
+                ])),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn new_gpu() -> GpuAttachment {
+        let gpu = GPUInfo { id: "GPU-new".into(), vendor: "NVIDIA".into(), ..Default::default() };
+        GpuAttacher::new().attachment(&gpu).unwrap()
+    }
+
+    #[test]
+    fn test_swap_gpu_keeps_everything_else() {
+        let before = ContainerSnapshot::from_inspect(inspect()).unwrap();
+        assert_eq!(before.name, "tenant-job");
+        let after = before.with_gpu(Some(&new_gpu()));
+        let plan = RecreatePlan::new(&before, &after);
+
+        assert_eq!(plan.devices_added, vec!["nvidia:GPU-new"]);
+        assert_eq!(plan.devices_removed, vec!["nvidia:GPU-old"]);
+        assert!(plan.env_added.contains(&"NVIDIA_VISIBLE_DEVICES=GPU-new".to_string()));
+        assert_eq!(plan.env_removed, vec!["NVIDIA_VISIBLE_DEVICES=GPU-old"]);
+        assert_eq!(plan.ports, vec!["8888/tcp <- 0.0.0.0:18888"]);
+        assert_eq!(plan.networks, vec!["bridge", "tenant-net"]);
+        // Non-GPU device mappings and labels survive
+        assert!(after.devices().contains("/dev/fuse"));
+        let labels = after.config.labels.as_ref().unwrap();
+        assert_eq!(labels[LABEL_GPU], "GPU-new");
+        assert_eq!(labels["team"], "vision");
+        assert!(!plan.is_noop());
+        // The old GPU's groups go with it; NVIDIA adds none
+        assert_eq!(after.host_config.group_add, Some(vec!["1001".to_string()]));
+        assert!(!labels.contains_key(LABEL_GPU_GROUPS));
+    }
+
+    #[test]
+    fn test_swap_gpu_tracks_added_groups() {
+        let render = GpuAttachment {
+            gpu_id: "0000:03:00.0".into(),
+            vendor: GpuVendor::Amd,
+            device_requests: Vec::new(),
+            devices: vec![DeviceMapping {
+                path_on_host: Some("/dev/dri/renderD129".into()),
+                path_in_container: Some("/dev/dri/renderD129".into()),
+                cgroup_permissions: Some("rwm".into()),
+            }],
+            group_add: vec!["109".into(), "1001".into()],
+            env: vec!["ROCR_VISIBLE_DEVICES=0".into()],
+        };
+        let before = ContainerSnapshot::from_inspect(inspect()).unwrap();
+        let swapped = before.with_gpu(Some(&render));
+        // 1001 was already there before this GPU, so only 109 is its to take back
+        assert_eq!(swapped.host_config.group_add, Some(vec!["1001".to_string(), "109".to_string()]));
+        assert_eq!(swapped.config.labels.as_ref().unwrap()[LABEL_GPU_GROUPS], "109");
+
+        let detached = swapped.with_gpu(None);
+        assert_eq!(detached.host_config.group_add, Some(vec!["1001".to_string()]));
+        assert!(detached.devices().iter().all(|d| !d.starts_with("/dev/dri/")));
+    }
+
+    #[test]
+    fn test_detach_and_noop() {
+        let before = ContainerSnapshot::from_inspect(inspect()).unwrap();
+        let detached = before.with_gpu(None);
+        assert_eq!(detached.devices().into_iter().collect::<Vec<_>>(), vec!["/dev/fuse"]);
+        assert_eq!(detached.config.env, Some(vec!["PATH=/usr/bin".to_string()]));
+        assert!(!detached.config.labels.as_ref().unwrap().contains_key(LABEL_GPU));
+
+        let again = detached.with_gpu(None);
+        assert!(RecreatePlan::new(&detached, &again).is_noop());
+    }
+
+    #[test]
+    fn test_create_config_pins_anonymous_volumes() {
+        let snapshot = ContainerSnapshot::from_inspect(inspect()).unwrap().with_gpu(None);
+        let config = snapshot.create_config();
+
+        assert_eq!(config.image.as_deref(), Some("sha256:feed"));
+        let binds = config.host_config.unwrap().binds.unwrap();
+        assert_eq!(binds, vec!["models:/models:ro", "0f3e9a:/workspace"]);
+        let extra: Vec<_> = snapshot.extra_networks().iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(extra, vec!["tenant-net"]);
+    }
+}
@@ -4,6 +4,12 @@ use crate::core::errors::GpuShareError;
 #[derive(Debug)]
 pub struct ResourceManager;
 
+impl Default for ResourceManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ResourceManager {
     pub fn new() -> Self {
         Self
@@ -0,0 +1,220 @@
+/*
+* Container runtime seam
+* ----------------------
+* Everything `DockerManager` asks of the Docker daemon, as a trait. Methods
+* mirror bollard's API one to one and keep its types and errors, so status
+* codes (404 for missing objects, 409 for conflicts) mean what they mean on a
+* real daemon and the manager's logic doesn't care which side of the seam it
+* is talking to.
+*
+* `DockerRuntime` is the daemon. `FakeRuntime` (`core::fake_runtime`) keeps
+* containers, images, networks and volumes in memory so lifecycle, stats,
+* events and GPU device requests can be tested without one.
+*/
+
+use async_trait::async_trait;
+use bollard::auth::DockerCredentials;
+use bollard::container::{
+    Config, CreateContainerOptions, InspectContainerOptions, ListContainersOptions, LogOutput, LogsOptions, RemoveContainerOptions,
+    RenameContainerOptions, StartContainerOptions, Stats, StatsOptions, StopContainerOptions,
+};
+use bollard::errors::Error;
+use bollard::exec::{CreateExecOptions, ResizeExecOptions, StartExecOptions, StartExecResults};
+use bollard::image::{CommitContainerOptions, CreateImageOptions, ListImagesOptions, RemoveImageOptions};
+use bollard::models::{
+    ContainerInspectResponse, ContainerSummary, CreateImageInfo, EventMessage, ExecInspectResponse, ImageInspect, ImageSummary, Network,
+    SystemDataUsageResponse, Volume,
+};
+use bollard::network::{ConnectNetworkOptions, CreateNetworkOptions, InspectNetworkOptions, ListNetworksOptions};
+use bollard::system::EventsOptions;
+use bollard::volume::{CreateVolumeOptions, RemoveVolumeOptions};
+use bollard::Docker;
+use futures_util::stream::BoxStream;
+use futures_util::StreamExt;
+
+#[async_trait]
+pub trait ContainerRuntime: Send + Sync {
+    async fn ping(&self) -> Result<(), Error>;
+
+    // Containers
+    async fn create_container(&self, name: &str, config: Config<String>) -> Result<String, Error>;
+    async fn start_container(&self, id: &str) -> Result<(), Error>;
+    /// `timeout_secs` before Docker kills it; `None` is the daemon default
+    async fn stop_container(&self, id: &str, timeout_secs: Option<i64>) -> Result<(), Error>;
+    async fn remove_container(&self, id: &str, options: Option<RemoveContainerOptions>) -> Result<(), Error>;
+    async fn rename_container(&self, id: &str, name: &str) -> Result<(), Error>;
+    /// With `size`, `size_rw` is filled in
+    async fn inspect_container(&self, id: &str, size: bool) -> Result<ContainerInspectResponse, Error>;
+    async fn list_containers(&self, options: ListContainersOptions<String>) -> Result<Vec<ContainerSummary>, Error>;
+    fn stats(&self, id: &str) -> BoxStream<'static, Result<Stats, Error>>;
+    fn logs(&self, id: &str, options: LogsOptions<String>) -> BoxStream<'static, Result<LogOutput, Error>>;
+    fn events(&self, options: EventsOptions<String>) -> BoxStream<'static, Result<EventMessage, Error>>;
+
+    // Exec
+    async fn create_exec(&self, container_id: &str, options: CreateExecOptions<String>) -> Result<String, Error>;
+    async fn start_exec(&self, exec_id: &str) -> Result<StartExecResults, Error>;
+    async fn resize_exec(&self, exec_id: &str, cols: u16, rows: u16) -> Result<(), Error>;
+    async fn inspect_exec(&self, exec_id: &str) -> Result<ExecInspectResponse, Error>;
+
+    // Images
+    fn create_image(
+        &self,
+        options: CreateImageOptions<String>,
+        credentials: Option<DockerCredentials>,
+    ) -> BoxStream<'static, Result<CreateImageInfo, Error>>;
+    async fn inspect_image(&self, reference: &str) -> Result<ImageInspect, Error>;
+    async fn list_images(&self, options: ListImagesOptions<String>) -> Result<Vec<ImageSummary>, Error>;
+    async fn remove_image(&self, reference: &str) -> Result<(), Error>;
+    async fn commit_container(&self, options: CommitContainerOptions<String>, config: Config<String>) -> Result<(), Error>;
+
+    // Networks
+    async fn create_network(&self, options: CreateNetworkOptions<String>) -> Result<(), Error>;
+    async fn inspect_network(&self, name: &str) -> Result<Network, Error>;
+    async fn list_networks(&self, options: ListNetworksOptions<String>) -> Result<Vec<Network>, Error>;
+    async fn remove_network(&self, name: &str) -> Result<(), Error>;
+    async fn connect_network(&self, name: &str, options: ConnectNetworkOptions<String>) -> Result<(), Error>;
+
+    // Volumes
+    async fn create_volume(&self, options: CreateVolumeOptions<String>) -> Result<Volume, Error>;
+    async fn inspect_volume(&self, name: &str) -> Result<Volume, Error>;
+    async fn remove_volume(&self, name: &str) -> Result<(), Error>;
+    /// Disk usage; the only call that reports volume sizes
+    async fn df(&self) -> Result<SystemDataUsageResponse, Error>;
+}
+
+/// The local Docker daemon, through bollard
+#[derive(Clone)]
+pub struct DockerRuntime {
+    docker: Docker,
+}
+
+impl DockerRuntime {
+    /// Doesn't touch the socket yet - see `ping`
+    pub fn connect() -> Result<Self, Error> {
+        Ok(Self { docker: Docker::connect_with_local_defaults()? })
+    }
+}
+
+#[async_trait]
+impl ContainerRuntime for DockerRuntime {
+    async fn ping(&self) -> Result<(), Error> {
+        self.docker.ping().await.map(|_| ())
+    }
+
+    async fn create_container(&self, name: &str, config: Config<String>) -> Result<String, Error> {
+        let options = CreateContainerOptions { name: name.to_string(), platform: None };
+        Ok(self.docker.create_container(Some(options), config).await?.id)
+    }
+
+    async fn start_container(&self, id: &str) -> Result<(), Error> {
+        self.docker.start_container(id, None::<StartContainerOptions<String>>).await
+    }
+
+    async fn stop_container(&self, id: &str, timeout_secs: Option<i64>) -> Result<(), Error> {
+        self.docker.stop_container(id, timeout_secs.map(|t| StopContainerOptions { t })).await
+    }
+
+    async fn remove_container(&self, id: &str, options: Option<RemoveContainerOptions>) -> Result<(), Error> {
+        self.docker.remove_container(id, options).await
+    }
+
+    async fn rename_container(&self, id: &str, name: &str) -> Result<(), Error> {
+        self.docker.rename_container(id, RenameContainerOptions { name }).await
+    }
+
+    async fn inspect_container(&self, id: &str, size: bool) -> Result<ContainerInspectResponse, Error> {
+        self.docker.inspect_container(id, Some(InspectContainerOptions { size })).await
+    }
+
+    async fn list_containers(&self, options: ListContainersOptions<String>) -> Result<Vec<ContainerSummary>, Error> {
+        self.docker.list_containers(Some(options)).await
+    }
+
+    fn stats(&self, id: &str) -> BoxStream<'static, Result<Stats, Error>> {
+        self.docker.stats(id, Some(StatsOptions { stream: true, one_shot: false })).boxed()
+    }
+
+    fn logs(&self, id: &str, options: LogsOptions<String>) -> BoxStream<'static, Result<LogOutput, Error>> {
+        self.docker.logs(id, Some(options)).boxed()
+    }
+
+    fn events(&self, options: EventsOptions<String>) -> BoxStream<'static, Result<EventMessage, Error>> {
+        self.docker.events(Some(options)).boxed()
+    }
+
+    async fn create_exec(&self, container_id: &str, options: CreateExecOptions<String>) -> Result<String, Error> {
+        Ok(self.docker.create_exec(container_id, options).await?.id)
+    }
+
+    async fn start_exec(&self, exec_id: &str) -> Result<StartExecResults, Error> {
+        self.docker.start_exec(exec_id, Some(StartExecOptions { detach: false, output_capacity: None })).await
+    }
+
+    async fn resize_exec(&self, exec_id: &str, cols: u16, rows: u16) -> Result<(), Error> {
+        self.docker.resize_exec(exec_id, ResizeExecOptions { height: rows, width: cols }).await
+    }
+
+    async fn inspect_exec(&self, exec_id: &str) -> Result<ExecInspectResponse, Error> {
+        self.docker.inspect_exec(exec_id).await
+    }
+
+    fn create_image(
+        &self,
+        options: CreateImageOptions<String>,
+        credentials: Option<DockerCredentials>,
+    ) -> BoxStream<'static, Result<CreateImageInfo, Error>> {
+        self.docker.create_image(Some(options), None, credentials).boxed()
+    }
+
+    async fn inspect_image(&self, reference: &str) -> Result<ImageInspect, Error> {
+        self.docker.inspect_image(reference).await
+    }
+
+    async fn list_images(&self, options: ListImagesOptions<String>) -> Result<Vec<ImageSummary>, Error> {
+        self.docker.list_images(Some(options)).await
+    }
+
+    async fn remove_image(&self, reference: &str) -> Result<(), Error> {
+        self.docker.remove_image(reference, None::<RemoveImageOptions>, None).await.map(|_| ())
+    }
+
+    async fn commit_container(&self, options: CommitContainerOptions<String>, config: Config<String>) -> Result<(), Error> {
+        self.docker.commit_container(options, config).await.map(|_| ())
+    }
+
+    async fn create_network(&self, options: CreateNetworkOptions<String>) -> Result<(), Error> {
+        self.docker.create_network(options).await.map(|_| ())
+    }
+
+    async fn inspect_network(&self, name: &str) -> Result<Network, Error> {
+        self.docker.inspect_network(name, None::<InspectNetworkOptions<String>>).await
+    }
+
+    async fn list_networks(&self, options: ListNetworksOptions<String>) -> Result<Vec<Network>, Error> {
+        self.docker.list_networks(Some(options)).await
+    }
+
+    async fn remove_network(&self, name: &str) -> Result<(), Error> {
+        self.docker.remove_network(name).await
+    }
+
+    async fn connect_network(&self, name: &str, options: ConnectNetworkOptions<String>) -> Result<(), Error> {
+        self.docker.connect_network(name, options).await
+    }
+
+    async fn create_volume(&self, options: CreateVolumeOptions<String>) -> Result<Volume, Error> {
+        self.docker.create_volume(options).await
+    }
+
+    async fn inspect_volume(&self, name: &str) -> Result<Volume, Error> {
+        self.docker.inspect_volume(name).await
+    }
+
+    async fn remove_volume(&self, name: &str) -> Result<(), Error> {
+        self.docker.remove_volume(name, Some(RemoveVolumeOptions { force: false })).await
+    }
+
+    async fn df(&self) -> Result<SystemDataUsageResponse, Error> {
+        self.docker.df().await
+    }
+}
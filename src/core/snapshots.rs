@@ -0,0 +1,351 @@
+/*
+* Container snapshots
+* -------------------
+* Lets tenants save work before a lease ends. A snapshot is the container
+* committed to a local image, `gpu-share-snapshot/<owner>-<hash>:<tag>`
+* (the hash keeps owners whose names clean up alike apart), labelled with
+* who owns it, what it was taken from and how big the container's writable
+* layer was. Restoring creates a new container from the image id, so nothing
+* is ever pulled for a snapshot.
+*
+* Optionally a snapshot carries a CRIU checkpoint of the running processes
+* (Docker's experimental `checkpoint create`, which bollard doesn't speak, so
+* it goes through the docker CLI). The checkpoint stops the container and lands
+* under `<vm_image_path>/checkpoints`; a restore then starts the new container
+* from it. Hosts without an experimental daemon or CRIU just get an error.
+*
+* Writable layers plus checkpoints of all snapshots, together with persistent
+* volumes (`core::volumes`), must stay under `[storage] max_storage_gb`.
+*/
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use bollard::models::ImageSummary;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::config::settings::StorageSettings;
+use crate::core::docker_manager::LABEL_OWNER;
+use crate::utils::command::{CommandRunner, SystemCommandRunner};
+use crate::utils::names::docker_safe_name;
+
+pub const SNAPSHOT_REPO: &str = "gpu-share-snapshot";
+const MAX_TAG_LEN: usize = 128;
+pub const LABEL_SNAPSHOT: &str = "gpu-share.snapshot";
+/// Name of the container the snapshot was taken from
+pub const LABEL_SNAPSHOT_OF: &str = "gpu-share.snapshot-of";
+/// Bytes in the container's writable layer at commit time
+pub const LABEL_SNAPSHOT_SIZE: &str = "gpu-share.snapshot-size";
+pub const LABEL_CHECKPOINT: &str = "gpu-share.checkpoint";
+
+const GB: u64 = 1024 * 1024 * 1024;
+
+/// Body of `POST /api/v1/vms/{id}/snapshots`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SnapshotRequest {
+    /// Image tag; `<container>-<timestamp>` when missing
+    pub tag: Option<String>,
+    pub comment: Option<String>,
+    /// Also checkpoint the running processes (stops the container)
+    pub checkpoint: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Snapshot {
+    /// Image id (`sha256:...`)
+    pub id: String,
+    /// `gpu-share-snapshot/<owner>-<hash>:<tag>`
+    pub reference: String,
+    pub owner: Option<String>,
+    pub source: String,
+    pub created: Option<DateTime<Utc>>,
+    pub size_bytes: u64,
+    pub checkpoint: Option<String>,
+}
+
+impl Snapshot {
+    /// `None` for images that aren't snapshots
+    pub fn from_summary(summary: ImageSummary) -> Option<Self> {
+        let labels = summary.labels;
+        if labels.get(LABEL_SNAPSHOT).map(String::as_str) != Some("true") {
+            return None;
+        }
+        let non_empty = |key: &str| labels.get(key).filter(|v| !v.is_empty()).cloned();
+        Some(Self {
+            reference: summary
+                .repo_tags
+                .iter()
+                .find(|tag| tag.starts_with(SNAPSHOT_REPO))
+                .cloned()
+                .unwrap_or_else(|| summary.id.clone()),
+            id: summary.id,
+            owner: non_empty(LABEL_OWNER),
+            source: non_empty(LABEL_SNAPSHOT_OF).unwrap_or_default(),
+            created: DateTime::from_timestamp(summary.created, 0),
+            size_bytes: labels.get(LABEL_SNAPSHOT_SIZE).and_then(|s| s.parse().ok()).unwrap_or(0),
+            checkpoint: non_empty(LABEL_CHECKPOINT),
+        })
+    }
+
+    /// Full id, its hex part, or a unique-enough (12+ chars) prefix of it
+    pub fn matches(&self, id: &str) -> bool {
+        let hex = self.id.trim_start_matches("sha256:");
+        let wanted = id.trim_start_matches("sha256:");
+        wanted.len() >= 12 && hex.starts_with(wanted)
+    }
+}
+
+/// Repository for `owner`'s snapshots; the operator's have no hash, so no
+/// tenant name lands in them. See `utils::names`.
+pub fn snapshot_repo(owner: Option<&str>) -> String {
+    match owner {
+        Some(owner) => format!("{}/{}", SNAPSHOT_REPO, docker_safe_name(owner)),
+        None => format!("{}/operator", SNAPSHOT_REPO),
+    }
+}
+
+/// `<container>-<timestamp>`, with the name cut short to fit Docker's 128
+/// characters
+pub fn default_tag(container_name: &str, now: DateTime<Utc>) -> String {
+    let stamp = now.format("%Y%m%d-%H%M%S").to_string();
+    let name: String = container_name.chars().take(MAX_TAG_LEN - stamp.len() - 1).collect();
+    format!("{}-{}", name, stamp)
+}
+
+/// Docker's tag rule: `[A-Za-z0-9_][A-Za-z0-9_.-]{0,127}`
+pub fn validate_tag(tag: &str) -> Result<()> {
+    let mut chars = tag.chars();
+    let valid = tag.len() <= MAX_TAG_LEN
+        && chars.next().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!("Invalid snapshot tag '{}'", tag))
+    }
+}
+
+/// No snapshot image matches the id or `repo:tag`
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("Snapshot {0} not found")]
+pub struct SnapshotNotFound(pub String);
+
+/// Snapshots and volumes would go over `max_storage_gb`
+#[derive(Debug, thiserror::Error)]
+#[error("Storage full: {used} of {limit} bytes used, {needed} more needed")]
+pub struct StorageQuotaExceeded {
+    pub used: u64,
+    pub needed: u64,
+    pub limit: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct StorageUsage {
+    pub used_bytes: u64,
+    pub limit_bytes: u64,
+}
+
+impl StorageUsage {
+    pub fn ensure_room(&self, needed: u64) -> Result<(), StorageQuotaExceeded> {
+        if self.used_bytes.saturating_add(needed) > self.limit_bytes {
+            return Err(StorageQuotaExceeded { used: self.used_bytes, needed, limit: self.limit_bytes });
+        }
+        Ok(())
+    }
+}
+
+/// Where checkpoints live, the storage cap and the docker CLI for checkpointing
+#[derive(Clone)]
+pub struct SnapshotStore {
+    checkpoint_root: PathBuf,
+    limit_bytes: u64,
+    runner: Arc<dyn CommandRunner>,
+}
+
+impl SnapshotStore {
+    pub fn new(storage: &StorageSettings) -> Self {
+        Self {
+            checkpoint_root: storage.vm_image_path.join("checkpoints"),
+            limit_bytes: storage.max_storage_gb.saturating_mul(GB),
+            runner: Arc::new(SystemCommandRunner),
+        }
+    }
+
+    pub fn with_runner(mut self, runner: Arc<dyn CommandRunner>) -> Self {
+        self.runner = runner;
+        self
+    }
+
+    pub fn checkpoint_dir(&self, name: &str) -> PathBuf {
+        self.checkpoint_root.join(name)
+    }
+
+    /// Committed layers plus checkpoints on disk
+    pub fn usage(&self, snapshots: &[Snapshot]) -> StorageUsage {
+        let used_bytes = snapshots
+            .iter()
+            .map(|s| s.size_bytes + s.checkpoint.as_deref().map_or(0, |c| dir_size(&self.checkpoint_dir(c))))
+            .sum();
+        StorageUsage { used_bytes, limit_bytes: self.limit_bytes }
+    }
+
+    /// Checkpoints and stops `container`
+    pub async fn checkpoint(&self, container: &str, name: &str) -> Result<()> {
+        fs::create_dir_all(&self.checkpoint_root)?;
+        let root = self.checkpoint_root.to_string_lossy().into_owned();
+        self.docker_cli(vec!["checkpoint".into(), "create".into(), "--checkpoint-dir".into(), root, container.into(), name.into()])
+            .await
+            .map_err(|e| anyhow!("Checkpoint failed (needs an experimental Docker daemon and CRIU): {}", e))
+    }
+
+    /// Starts a created container from checkpoint `name`
+    pub async fn start_from_checkpoint(&self, container: &str, name: &str) -> Result<()> {
+        let root = self.checkpoint_root.to_string_lossy().into_owned();
+        self.docker_cli(vec![
+            "start".into(),
+            "--checkpoint-dir".into(),
+            root,
+            "--checkpoint".into(),
+            name.into(),
+            container.into(),
+        ])
+        .await
+    }
+
+    pub fn remove_checkpoint(&self, name: &str) -> Result<()> {
+        let dir = self.checkpoint_dir(name);
+        if dir.exists() {
+            fs::remove_dir_all(dir)?;
+        }
+        Ok(())
+    }
+
+    async fn docker_cli(&self, args: Vec<String>) -> Result<()> {
+        let runner = self.runner.clone();
+        tokio::task::spawn_blocking(move || {
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+            runner.run("docker", &args).map(|_| ())
+        })
+        .await?
+    }
+}
+
+fn dir_size(path: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(path) else { return 0 };
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(meta) if meta.is_dir() => dir_size(&entry.path()),
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_snapshot_naming() {
+        assert!(snapshot_repo(Some("Alice@Lab")).starts_with("gpu-share-snapshot/alice-lab-"));
+        assert_ne!(snapshot_repo(Some("Alice@Lab")), snapshot_repo(Some("alice-lab")));
+        assert_eq!(snapshot_repo(None), "gpu-share-snapshot/operator");
+        assert_ne!(snapshot_repo(Some("operator")), snapshot_repo(None));
+        assert_ne!(snapshot_repo(Some("@@")), snapshot_repo(Some("##")));
+
+        let now: DateTime<Utc> = "2024-05-01T12:00:00Z".parse().unwrap();
+        let tag = default_tag("alice-train", now);
+        assert_eq!(tag, "alice-train-20240501-120000");
+        assert!(validate_tag(&tag).is_ok());
+        let long = default_tag(&"x".repeat(200), now);
+        assert_eq!(long.len(), 128);
+        assert!(long.ends_with("-20240501-120000"));
+        assert!(validate_tag(&long).is_ok());
+        assert!(validate_tag("-nope").is_err());
+        assert!(validate_tag("a/b").is_err());
+        assert!(validate_tag(&"x".repeat(129)).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_from_summary() {
+        let summary = ImageSummary {
+            id: "sha256:0123456789abcdef0123".into(),
+            repo_tags: vec!["gpu-share-snapshot/alice:epoch-3".into()],
+            created: 1714564800,
+            labels: HashMap::from([
+                (LABEL_SNAPSHOT.to_string(), "true".to_string()),
+                (LABEL_OWNER.to_string(), "alice".to_string()),
+                (LABEL_SNAPSHOT_OF.to_string(), "alice-train".to_string()),
+                (LABEL_SNAPSHOT_SIZE.to_string(), "4096".to_string()),
+                (LABEL_CHECKPOINT.to_string(), "".to_string()),
+            ]),
+            ..Default::default()
+        };
+        let snapshot = Snapshot::from_summary(summary.clone()).unwrap();
+        assert_eq!(snapshot.reference, "gpu-share-snapshot/alice:epoch-3");
+        assert_eq!(snapshot.owner.as_deref(), Some("alice"));
+        assert_eq!(snapshot.size_bytes, 4096);
+        assert_eq!(snapshot.checkpoint, None);
+        assert!(snapshot.matches("0123456789ab"));
+        assert!(snapshot.matches("sha256:0123456789abcdef0123"));
+        assert!(!snapshot.matches("0123"));
+
+        let mut plain = summary;
+        plain.labels.remove(LABEL_SNAPSHOT);
+        assert!(Snapshot::from_summary(plain).is_none());
+    }
+
+    struct RecordingRunner(Mutex<Vec<String>>);
+
+    impl CommandRunner for RecordingRunner {
+        fn run(&self, program: &str, args: &[&str]) -> Result<String> {
+            self.0.lock().unwrap().push(format!("{} {}", program, args.join(" ")));
+            Ok(String::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_usage_and_checkpoint_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = StorageSettings { vm_image_path: dir.path().to_path_buf(), max_storage_gb: 1 };
+        let runner = Arc::new(RecordingRunner(Mutex::new(Vec::new())));
+        let store = SnapshotStore::new(&storage).with_runner(runner.clone());
+
+        store.checkpoint("c0ffee", "ckpt-1").await.unwrap();
+        store.start_from_checkpoint("beef", "ckpt-1").await.unwrap();
+        let root = dir.path().join("checkpoints").to_string_lossy().into_owned();
+        assert_eq!(
+            *runner.0.lock().unwrap(),
+            vec![
+                format!("docker checkpoint create --checkpoint-dir {} c0ffee ckpt-1", root),
+                format!("docker start --checkpoint-dir {} --checkpoint ckpt-1 beef", root),
+            ]
+        );
+
+        fs::create_dir_all(store.checkpoint_dir("ckpt-1").join("criu")).unwrap();
+        fs::write(store.checkpoint_dir("ckpt-1").join("criu/pages-1.img"), vec![0u8; 1000]).unwrap();
+        let snapshot = Snapshot {
+            id: "sha256:0123456789abcdef".into(),
+            reference: "gpu-share-snapshot/alice:epoch-3".into(),
+            owner: Some("alice".into()),
+            source: "alice-train".into(),
+            created: None,
+            size_bytes: 24,
+            checkpoint: Some("ckpt-1".into()),
+        };
+        let usage = store.usage(&[snapshot]);
+        assert_eq!(usage, StorageUsage { used_bytes: 1024, limit_bytes: GB });
+        assert!(usage.ensure_room(GB - 1024).is_ok());
+        assert!(usage.ensure_room(GB).is_err());
+
+        store.remove_checkpoint("ckpt-1").unwrap();
+        assert!(!store.checkpoint_dir("ckpt-1").exists());
+    }
+}
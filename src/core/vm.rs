@@ -15,6 +15,9 @@ pub struct VMConfig {
     pub disk_size_gb: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub gpu_passthrough: Option<crate::gpu::device::GPUConfig>,
+    /// Mediated device UUID (vGPU / GVT-g) - used instead of whole-device passthrough
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gpu_mdev: Option<String>,
 }
 
 /// Virtual Machine Runtime State
@@ -29,8 +32,8 @@ pub struct VirtualMachine {
     pub memory_kb: u64,
 }
 
-/// Virtual Machine Status
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+/// Virtual Machine Status (also what container lifecycle events report)
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub enum VMStatus {
     Running,
     Stopped,
@@ -78,6 +81,7 @@ impl VMConfig {
             disk_path,
             disk_size_gb: 20, // Default size
             gpu_passthrough: None,
+            gpu_mdev: None,
         }
     }
 
@@ -175,23 +179,38 @@ impl VMConfig {
             }
         }
 
-        // GPU passthrough
+        if self.gpu_passthrough.is_some() && self.gpu_mdev.is_some() {
+            return Err(anyhow::anyhow!("A VM gets either a passthrough GPU or an mdev, not both"));
+        }
+
+        // Mediated device - the host driver keeps the card, the VM gets a slice by UUID
+        if let Some(uuid) = &self.gpu_mdev {
+            let uuid = uuid::Uuid::parse_str(uuid)
+                .map_err(|e| anyhow::anyhow!("Invalid mdev UUID {}: {}", uuid, e))?;
+            devices.push_str(&format!(
+                r#"
+                <hostdev mode='subsystem' type='mdev' managed='no' model='vfio-pci'>
+                    <source>
+                        <address uuid='{}'/>
+                    </source>
+                </hostdev>
+                "#,
+                uuid
+            ));
+        }
+
+        // GPU passthrough - whole cards and SR-IOV VFs look the same to libvirt
         if let Some(gpu) = &self.gpu_passthrough {
-            let pci_parts: Vec<&str> = gpu.gpu_id.split(':').collect();
-            if pci_parts.len() != 3 {
-                return Err(anyhow::anyhow!("Invalid PCI address format"));
-            }
+            let (domain, bus, slot, function) = parse_pci_address(&gpu.gpu_id)?;
             devices.push_str(&format!(
                 r#"
                 <hostdev mode='subsystem' type='pci' managed='yes'>
                     <source>
-                        <address domain='0x0000' bus='{}' slot='{}' function='{}'/>
+                        <address domain='0x{:04x}' bus='0x{:02x}' slot='0x{:02x}' function='0x{:x}'/>
                     </source>
                 </hostdev>
                 "#,
-                pci_parts[0],
-                pci_parts[1],
-                pci_parts[2].trim_end_matches(".0")
+                domain, bus, slot, function
             ));
         }
 
@@ -200,6 +219,26 @@ impl VMConfig {
     }
 }
 
+/// Splits `dddd:bb:ss.f` (domain optional) into its numeric parts
+fn parse_pci_address(address: &str) -> Result<(u16, u8, u8, u8)> {
+    let invalid = || anyhow::anyhow!("Invalid PCI address format: {}", address);
+
+    let parts: Vec<&str> = address.split(':').collect();
+    let (domain, bus, slot_fn) = match parts.as_slice() {
+        [domain, bus, slot_fn] => (*domain, *bus, *slot_fn),
+        [bus, slot_fn] => ("0000", *bus, *slot_fn),
+        _ => return Err(invalid()),
+    };
+    let (slot, function) = slot_fn.split_once('.').ok_or_else(invalid)?;
+
+    Ok((
+        u16::from_str_radix(domain, 16).map_err(|_| invalid())?,
+        u8::from_str_radix(bus, 16).map_err(|_| invalid())?,
+        u8::from_str_radix(slot, 16).map_err(|_| invalid())?,
+        u8::from_str_radix(function, 16).map_err(|_| invalid())?,
+    ))
+}
+
 impl VirtualMachine {
     /// Start VM
     pub async fn start(&self, docker: &DockerManager) -> Result<()> {
@@ -278,4 +317,18 @@ mod tests {
         assert!(xml.contains("test-xml"));
         assert!(xml.contains("KiB"));
     }
+
+    #[test]
+    fn test_hostdev_for_sriov_vf() {
+        let mut config = VMConfig::new("vf-guest", 8, 4);
+        config.gpu_passthrough = Some(crate::gpu::device::GPUConfig {
+            gpu_id: "0000:4d:00.3".into(),
+            iommu_group: 73,
+        });
+        let xml = config.to_xml().unwrap();
+        assert!(xml.contains("<address domain='0x0000' bus='0x4d' slot='0x00' function='0x3'/>"));
+
+        config.gpu_passthrough.as_mut().unwrap().gpu_id = "mock-gpu-1".into();
+        assert!(config.to_xml().is_err());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,283 @@
+/*
+* Persistent volumes
+* ------------------
+* Named Docker volumes for datasets and checkpoints that outlive containers
+* and leases. A managed volume carries its owner and project as labels, plus a
+* reserved size and what happens to it when its project is deleted:
+* `retain` (the default - data is never thrown away by surprise) or `delete`.
+*
+* Volumes count against `[storage] max_storage_gb` together with snapshots.
+* Each counts as the larger of its reserved size and what Docker reports it
+* using, so reserving 500 GB for a dataset holds the space before the download
+* starts. The local driver can't enforce sizes; the reservation is accounting.
+*
+* Tenants mount volumes by name in the container's `volumes` list, and only
+* their own - they can't make Docker create unaccounted volumes on the fly.
+*/
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use bollard::models::Volume;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::core::docker_manager::{is_valid_name, ContainerConfig, LABEL_MANAGED, LABEL_OWNER, LABEL_PROJECT};
+
+/// Reserved size in GB
+pub const LABEL_VOLUME_SIZE: &str = "gpu-share.volume-size-gb";
+/// `CleanupPolicy` applied when the volume's project is deleted
+pub const LABEL_VOLUME_CLEANUP: &str = "gpu-share.cleanup";
+
+const GB: u64 = 1024 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CleanupPolicy {
+    #[default]
+    Retain,
+    Delete,
+}
+
+impl CleanupPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CleanupPolicy::Retain => "retain",
+            CleanupPolicy::Delete => "delete",
+        }
+    }
+
+    fn parse(value: &str) -> Self {
+        match value {
+            "delete" => CleanupPolicy::Delete,
+            _ => CleanupPolicy::Retain,
+        }
+    }
+}
+
+/// Body of `POST /api/v1/volumes`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VolumeRequest {
+    pub name: String,
+    pub project: Option<String>,
+    /// Space to reserve against the storage quota
+    pub size_gb: Option<u64>,
+    pub cleanup: CleanupPolicy,
+}
+
+impl VolumeRequest {
+    pub fn validate(&self) -> Result<()> {
+        if !is_valid_name(&self.name) {
+            return Err(anyhow!("Invalid volume name '{}' (letters, digits, '_', '.', '-'; must start alphanumeric)", self.name));
+        }
+        if self.project.as_deref().is_some_and(|p| p.trim().is_empty()) {
+            return Err(anyhow!("Project name must not be empty"));
+        }
+        if self.size_gb == Some(0) {
+            return Err(anyhow!("Volume size must be positive"));
+        }
+        Ok(())
+    }
+
+    pub fn reserved_bytes(&self) -> u64 {
+        self.size_gb.unwrap_or(0).saturating_mul(GB)
+    }
+
+    /// Labels of the new volume; `owner` is who it is created for
+    pub fn labels(&self, owner: Option<&str>) -> HashMap<String, String> {
+        let mut labels = HashMap::from([
+            (LABEL_MANAGED.to_string(), "true".to_string()),
+            (LABEL_VOLUME_CLEANUP.to_string(), self.cleanup.as_str().to_string()),
+        ]);
+        for (key, value) in [(LABEL_OWNER, owner), (LABEL_PROJECT, self.project.as_deref())] {
+            if let Some(value) = value {
+                labels.insert(key.to_string(), value.to_string());
+            }
+        }
+        if let Some(size) = self.size_gb {
+            labels.insert(LABEL_VOLUME_SIZE.to_string(), size.to_string());
+        }
+        labels
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum VolumeError {
+    #[error("Volume {0} not found")]
+    NotFound(String),
+    #[error("Volume {0} already exists")]
+    AlreadyExists(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ManagedVolume {
+    pub name: String,
+    pub owner: Option<String>,
+    pub project: Option<String>,
+    pub size_gb: Option<u64>,
+    /// What Docker last measured; `None` until it has
+    pub used_bytes: Option<u64>,
+    pub cleanup: CleanupPolicy,
+    pub created: Option<DateTime<Utc>>,
+}
+
+impl ManagedVolume {
+    /// `None` for volumes we didn't create
+    pub fn from_docker(volume: Volume) -> Option<Self> {
+        let labels = volume.labels;
+        if labels.get(LABEL_MANAGED).map(String::as_str) != Some("true") {
+            return None;
+        }
+        let label = |key: &str| labels.get(key).filter(|v| !v.is_empty()).cloned();
+        Some(Self {
+            owner: label(LABEL_OWNER),
+            project: label(LABEL_PROJECT),
+            size_gb: labels.get(LABEL_VOLUME_SIZE).and_then(|s| s.parse().ok()),
+            // Docker says -1 when it didn't compute the size
+            used_bytes: volume.usage_data.and_then(|u| u64::try_from(u.size).ok()),
+            cleanup: labels.get(LABEL_VOLUME_CLEANUP).map(|c| CleanupPolicy::parse(c)).unwrap_or_default(),
+            created: volume
+                .created_at
+                .as_deref()
+                .and_then(|created| DateTime::parse_from_rfc3339(created).ok())
+                .map(|created| created.with_timezone(&Utc)),
+            name: volume.name,
+        })
+    }
+
+    /// Reserved size or actual usage, whichever is larger
+    pub fn accounted_bytes(&self) -> u64 {
+        let reserved = self.size_gb.unwrap_or(0).saturating_mul(GB);
+        reserved.max(self.used_bytes.unwrap_or(0))
+    }
+}
+
+/// Named (non-bind) volumes `config` mounts
+pub fn named_mounts(config: &ContainerConfig) -> impl Iterator<Item = &str> {
+    config.volumes.iter().map(|v| v.source.as_str()).filter(|source| !source.contains('/'))
+}
+
+/// Host-path bind mounts are for operators only: a tenant mounting `/` or the
+/// Docker socket owns the host
+pub fn reject_bind_mounts(config: &ContainerConfig) -> Result<()> {
+    match config.volumes.iter().find(|v| v.source.contains('/')) {
+        Some(bind) => Err(anyhow!("Host path {} can't be mounted; use a named volume", bind.source)),
+        None => Ok(()),
+    }
+}
+
+/// What deleting a project did to its volumes
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CleanupReport {
+    pub project: String,
+    pub deleted: Vec<String>,
+    pub retained: Vec<String>,
+    /// Volume and why it couldn't be deleted (usually: still mounted)
+    pub failed: Vec<(String, String)>,
+}
+
+/// The project's volumes, split into (to delete, to keep) by their policy
+pub fn plan_cleanup<'a>(volumes: &'a [ManagedVolume], project: &str) -> (Vec<&'a ManagedVolume>, Vec<&'a ManagedVolume>) {
+    volumes
+        .iter()
+        .filter(|v| v.project.as_deref() == Some(project))
+        .partition(|v| v.cleanup == CleanupPolicy::Delete)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::docker_manager::VolumeMount;
+    use bollard::models::VolumeUsageData;
+
+    fn docker_volume(name: &str, labels: HashMap<String, String>, size: i64) -> Volume {
+        Volume {
+            name: name.into(),
+            labels,
+            usage_data: Some(VolumeUsageData { size, ref_count: 0 }),
+            created_at: Some("2026-10-01T12:00:00Z".into()),
+            ..Default::default()
+        }
+    }
+
+    fn managed(name: &str, project: &str, cleanup: CleanupPolicy) -> ManagedVolume {
+        let request = VolumeRequest { name: name.into(), project: Some(project.into()), cleanup, ..Default::default() };
+        ManagedVolume::from_docker(docker_volume(name, request.labels(Some("alice")), 0)).unwrap()
+    }
+
+    #[test]
+    fn test_volume_roundtrip_and_accounting() {
+        let request = VolumeRequest {
+            name: "imagenet".into(),
+            project: Some("vision".into()),
+            size_gb: Some(2),
+            cleanup: CleanupPolicy::Delete,
+        };
+        request.validate().unwrap();
+        let volume = ManagedVolume::from_docker(docker_volume("imagenet", request.labels(Some("alice")), 1024)).unwrap();
+        assert_eq!(volume.owner.as_deref(), Some("alice"));
+        assert_eq!(volume.project.as_deref(), Some("vision"));
+        assert_eq!(volume.cleanup, CleanupPolicy::Delete);
+        assert_eq!(volume.used_bytes, Some(1024));
+        assert!(volume.created.is_some());
+        // Reservation wins until the data outgrows it
+        assert_eq!(volume.accounted_bytes(), 2 * GB);
+        let grown = ManagedVolume { used_bytes: Some(3 * GB), ..volume };
+        assert_eq!(grown.accounted_bytes(), 3 * GB);
+
+        // Unmeasured and unmanaged volumes
+        let unmeasured = docker_volume("scratch", VolumeRequest::default().labels(None), -1);
+        let unmeasured = ManagedVolume::from_docker(unmeasured).unwrap();
+        assert_eq!((unmeasured.used_bytes, unmeasured.accounted_bytes()), (None, 0));
+        assert_eq!(unmeasured.cleanup, CleanupPolicy::Retain);
+        assert_eq!(ManagedVolume::from_docker(docker_volume("other", HashMap::new(), 10)), None);
+    }
+
+    #[test]
+    fn test_invalid_requests() {
+        let valid = VolumeRequest { name: "ckpt".into(), ..Default::default() };
+        assert!(valid.validate().is_ok());
+        for bad in [
+            VolumeRequest { name: "../etc".into(), ..valid.clone() },
+            VolumeRequest { name: String::new(), ..valid.clone() },
+            VolumeRequest { size_gb: Some(0), ..valid.clone() },
+            VolumeRequest { project: Some(" ".into()), ..valid.clone() },
+        ] {
+            assert!(bad.validate().is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_cleanup_plan_follows_policy() {
+        let volumes = vec![
+            managed("datasets", "vision", CleanupPolicy::Retain),
+            managed("scratch", "vision", CleanupPolicy::Delete),
+            managed("other-scratch", "nlp", CleanupPolicy::Delete),
+        ];
+        let (delete, retain) = plan_cleanup(&volumes, "vision");
+        assert_eq!(delete.iter().map(|v| v.name.as_str()).collect::<Vec<_>>(), vec!["scratch"]);
+        assert_eq!(retain.iter().map(|v| v.name.as_str()).collect::<Vec<_>>(), vec!["datasets"]);
+    }
+
+    #[test]
+    fn test_named_mounts_skip_binds() {
+        let config = ContainerConfig {
+            volumes: vec![
+                VolumeMount { source: "imagenet".into(), target: "/data".into(), read_only: true },
+                VolumeMount { source: "/srv/cache".into(), target: "/cache".into(), read_only: false },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(named_mounts(&config).collect::<Vec<_>>(), vec!["imagenet"]);
+        assert!(reject_bind_mounts(&config).unwrap_err().to_string().contains("/srv/cache"));
+
+        for source in ["/", "/var/run/docker.sock"] {
+            let bind = VolumeMount { source: source.into(), target: "/host".into(), read_only: true };
+            let config = ContainerConfig { volumes: vec![bind], ..Default::default() };
+            assert!(reject_bind_mounts(&config).is_err());
+        }
+        let named = ContainerConfig { volumes: config.volumes[..1].to_vec(), ..Default::default() };
+        assert!(reject_bind_mounts(&named).is_ok());
+    }
+}
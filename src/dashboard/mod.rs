@@ -3,7 +3,7 @@ use crossterm::{event::{Event, KeyCode}, execute, terminal::*};
 use ratatui::{prelude::*, widgets::*};
 use std::sync::Arc;
 // use tokio::sync::Mutex;
-use crate::{gpu::virtual_gpu::GPUPool, users::UserManager, billing::BillingSystem};
+use crate::{gpu::virtual_gpu::{GPUPool, ServiceState}, users::UserManager, billing::BillingSystem};
 
 
 pub async fn start_dashboard(
@@ -26,10 +26,18 @@ pub async fn start_dashboard(
             let gpu_list = List::new(
                 gpupool.gpus.values()
                     .map(|gpu| {
-                        let status = if gpu.allocated_to.is_some() {
-                            Span::styled("Occupied", Style::new().red())
-                        } else {
-                            Span::styled("Available", Style::new().green())
+                        let status = match (&gpu.state, gpu.allocated_to.is_some()) {
+                            (ServiceState::Quarantined { .. }, _) => {
+                                Span::styled("Quarantined", Style::new().magenta())
+                            }
+                            (ServiceState::Draining { .. }, true) => {
+                                Span::styled("Draining (occupied)", Style::new().yellow())
+                            }
+                            (ServiceState::Draining { .. }, false) => {
+                                Span::styled("Drained", Style::new().yellow())
+                            }
+                            (ServiceState::Active, true) => Span::styled("Occupied", Style::new().red()),
+                            (ServiceState::Active, false) => Span::styled("Available", Style::new().green()),
                         };
                         ListItem::new(format!(
                             "GPU {}: {}MB - {} Cores - {}",
@@ -1,4 +1,5 @@
-use super::{GpuShareError, ErrorContext, ErrorRecovery};
+use super::GpuShareError;
+use thiserror::Error;
 use tracing::{error, info, warn};
 
 pub struct ErrorHandler {
@@ -2,6 +2,10 @@ use std::fmt;
 use thiserror::Error;
 use tracing::error;
 
+pub mod handlers;
+
+pub use handlers::{ErrorHandler, SystemError};
+
 #[derive(Error, Debug)]
 pub enum GpuShareError {
     #[error("VM Error: {0}")]
@@ -15,9 +19,21 @@ pub enum GpuShareError {
     
     #[error("System Error: {0}")]
     SystemError(#[from] SystemError),
-    
-    #[error("Database Error: {0}")]
-    DatabaseError(#[from] DatabaseError),
+
+    #[error("I/O Error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Invalid value for {key}: {message}")]
+    InvalidValue {
+        key: String,
+        message: String,
+    },
+
+    #[error("Missing configuration: {0}")]
+    Missing(String),
 }
 
 #[derive(Error, Debug)]
@@ -77,6 +93,11 @@ pub enum GpuError {
         driver_name: String,
     },
 
+    #[error("System error: {message}")]
+    SystemError {
+        message: String,
+    },
+
     #[cfg(target_os = "macos")]
     #[error("Metal API error: {0}")]
     MetalError(String),
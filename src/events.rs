@@ -0,0 +1,139 @@
+//! In-process event bus.
+//!
+//! Subsystems publish what happened to GPUs and leases; anything interested
+//! (tenant notification, API streams, logs) subscribes. Built on a tokio
+//! broadcast channel, so slow subscribers lose the oldest events rather than
+//! block publishers.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::core::exec::ExecEnd;
+use crate::core::lifecycle::ContainerAction;
+use crate::core::reconcile::{Discrepancy, ReconcileAction};
+use crate::core::vm::VMStatus;
+
+const DEFAULT_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventKind {
+    /// A pool entry failed a health check and stopped taking allocations
+    GpuQuarantined { gpu_id: u32, tenant: Option<String>, reason: String },
+    /// An operator took a pool entry out of service
+    GpuDrained { gpu_id: u32, tenant: Option<String>, reason: String },
+    /// A pool entry is back in service
+    GpuUndrained { gpu_id: u32 },
+    /// Someone opened an interactive exec session in a container
+    ExecOpened { session_id: String, container: String, user: String, command: Vec<String> },
+    ExecClosed {
+        session_id: String,
+        container: String,
+        user: String,
+        reason: ExecEnd,
+        exit_code: Option<i64>,
+        duration_secs: u64,
+    },
+    /// Docker and the GPU pool disagreed about a container or lease
+    Reconciled { discrepancy: Discrepancy, action: ReconcileAction },
+    /// A managed container changed state. `released_gpu` is the lease that
+    /// ended because the container is gone for good.
+    ContainerLifecycle {
+        container_id: String,
+        name: String,
+        owner: Option<String>,
+        action: ContainerAction,
+        status: VMStatus,
+        exit_code: Option<i64>,
+        oom_killed: bool,
+        released_gpu: Option<u32>,
+    },
+}
+
+impl EventKind {
+    /// Tenant the event is about, if any - what tenants may see on `/api/v1/events`
+    pub fn tenant(&self) -> Option<&str> {
+        match self {
+            EventKind::GpuQuarantined { tenant, .. } | EventKind::GpuDrained { tenant, .. } => tenant.as_deref(),
+            EventKind::ContainerLifecycle { owner, .. } => owner.as_deref(),
+            EventKind::Reconciled { discrepancy: Discrepancy::OrphanLease { tenant, .. }, .. } => Some(tenant),
+            EventKind::Reconciled { discrepancy: Discrepancy::OrphanContainer { owner, .. }, .. } => owner.as_deref(),
+            EventKind::GpuUndrained { .. } | EventKind::ExecOpened { .. } | EventKind::ExecClosed { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+pub struct EventBus {
+    sender: broadcast::Sender<Event>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::with_capacity(DEFAULT_CAPACITY)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender }
+    }
+
+    /// Fire and forget - nobody listening is not an error
+    pub fn publish(&self, kind: EventKind) {
+        let _ = self.sender.send(Event { at: Utc::now(), kind });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_publish_reaches_subscribers() {
+        let bus = EventBus::new();
+        bus.publish(EventKind::GpuUndrained { gpu_id: 7 }); // no subscribers yet
+
+        let mut rx = bus.subscribe();
+        bus.publish(EventKind::GpuUndrained { gpu_id: 1 });
+        let event = rx.recv().await.unwrap();
+        assert_eq!(event.kind, EventKind::GpuUndrained { gpu_id: 1 });
+
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["type"], "gpu_undrained");
+        assert_eq!(json["gpu_id"], 1);
+    }
+
+    #[test]
+    fn test_event_tenant() {
+        let lifecycle = EventKind::ContainerLifecycle {
+            container_id: "c0ffee".into(),
+            name: "alice-train".into(),
+            owner: Some("alice".into()),
+            action: ContainerAction::Oom,
+            status: VMStatus::Crashed,
+            exit_code: None,
+            oom_killed: true,
+            released_gpu: None,
+        };
+        assert_eq!(lifecycle.tenant(), Some("alice"));
+        assert_eq!(serde_json::to_value(&lifecycle).unwrap()["status"], "Crashed");
+        assert_eq!(EventKind::GpuUndrained { gpu_id: 0 }.tenant(), None);
+    }
+}
@@ -0,0 +1,341 @@
+/*
+* GPU attachment for Docker containers
+* ------------------------------------
+* What a container needs to actually see a rented GPU:
+*
+* - NVIDIA: a `DeviceRequests` entry for the nvidia runtime hook, by UUID, plus
+*   NVIDIA_VISIBLE_DEVICES / NVIDIA_DRIVER_CAPABILITIES for images that read them
+* - AMD: the card's own `/dev/dri/cardN` + `/dev/dri/renderDN` and `/dev/kfd`
+*   (ROCm compute), with ROCR_VISIBLE_DEVICES=0 - the container only sees one card
+* - Intel: the card's `/dev/dri` nodes, with ZE_AFFINITY_MASK=0 for Level Zero
+*
+* DRI nodes are looked up per PCI address under sysfs so a tenant never gets
+* somebody else's card. Devices can't be added to a running container, so an
+* attachment is applied when the container is created.
+*/
+
+use anyhow::{anyhow, Result};
+use bollard::models::{DeviceMapping, DeviceRequest, HostConfig};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+use crate::gpu::device::GPUInfo;
+use crate::gpu::pci::{find_drm_card, GpuVendor};
+
+/// Capabilities passed to the nvidia runtime - compute plus nvidia-smi
+const NVIDIA_DRIVER_CAPABILITIES: &str = "compute,utility";
+
+/// Environment variables an attachment may set - dropped when the GPU is swapped out
+pub const GPU_ENV_VARS: &[&str] =
+    &["NVIDIA_VISIBLE_DEVICES", "NVIDIA_DRIVER_CAPABILITIES", "ROCR_VISIBLE_DEVICES", "ZE_AFFINITY_MASK"];
+
+/// Device requests that hand out GPUs (the nvidia runtime, or anything asking for "gpu")
+pub fn is_gpu_device_request(request: &DeviceRequest) -> bool {
+    request.driver.as_deref() == Some("nvidia")
+        || request
+            .capabilities
+            .iter()
+            .flatten()
+            .any(|caps| caps.iter().any(|c| c == "gpu"))
+}
+
+/// Device mappings for DRI nodes and the ROCm compute node
+pub fn is_gpu_device_mapping(mapping: &DeviceMapping) -> bool {
+    mapping
+        .path_in_container
+        .as_deref()
+        .is_some_and(|p| p.starts_with("/dev/dri/") || p == "/dev/kfd")
+}
+
+/// `KEY=value` entries set by an attachment
+pub fn is_gpu_env(entry: &str) -> bool {
+    let key = entry.split('=').next().unwrap_or_default();
+    GPU_ENV_VARS.contains(&key)
+}
+
+/// Everything Docker needs to expose one GPU to a container
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GpuAttachment {
+    pub gpu_id: String,
+    pub vendor: GpuVendor,
+    #[serde(skip)]
+    pub device_requests: Vec<DeviceRequest>,
+    #[serde(skip)]
+    pub devices: Vec<DeviceMapping>,
+    /// Host group ids owning the device nodes, so non-root users can open them
+    pub group_add: Vec<String>,
+    pub env: Vec<String>,
+}
+
+impl GpuAttachment {
+    /// Host paths of the mapped device nodes (empty for NVIDIA, the runtime maps those)
+    pub fn device_paths(&self) -> Vec<&str> {
+        self.devices.iter().filter_map(|d| d.path_on_host.as_deref()).collect()
+    }
+
+    /// Group ids `apply` would add that `host_config` doesn't have yet
+    pub fn missing_groups(&self, host_config: &HostConfig) -> Vec<String> {
+        let present = host_config.group_add.as_deref().unwrap_or_default();
+        self.group_add.iter().filter(|gid| !present.contains(gid)).cloned().collect()
+    }
+
+    /// Merges the attachment into a container's host config and environment
+    pub fn apply(&self, host_config: &mut HostConfig, env: &mut Vec<String>) {
+        if !self.device_requests.is_empty() {
+            host_config.device_requests.get_or_insert_with(Vec::new).extend(self.device_requests.iter().cloned());
+        }
+        if !self.devices.is_empty() {
+            host_config.devices.get_or_insert_with(Vec::new).extend(self.devices.iter().cloned());
+        }
+        if !self.group_add.is_empty() {
+            let groups = host_config.group_add.get_or_insert_with(Vec::new);
+            for gid in &self.group_add {
+                if !groups.contains(gid) {
+                    groups.push(gid.clone());
+                }
+            }
+        }
+        env.extend(self.env.iter().cloned());
+    }
+}
+
+/// Builds `GpuAttachment`s from sysfs and `/dev`
+pub struct GpuAttacher {
+    sysfs_root: PathBuf,
+    dev_root: PathBuf,
+}
+
+impl GpuAttacher {
+    pub fn new() -> Self {
+        Self::with_roots("/sys", "/dev")
+    }
+
+    pub fn with_roots(sysfs_root: impl Into<PathBuf>, dev_root: impl Into<PathBuf>) -> Self {
+        Self { sysfs_root: sysfs_root.into(), dev_root: dev_root.into() }
+    }
+
+    pub fn attachment(&self, gpu: &GPUInfo) -> Result<GpuAttachment> {
+        if gpu.driver.as_deref() == Some("vfio-pci") {
+            return Err(anyhow!(
+                "GPU {} is bound to vfio-pci (reserved for VM passthrough) - containers need the host driver",
+                gpu.id
+            ));
+        }
+
+        match vendor_of(gpu)? {
+            GpuVendor::Nvidia => nvidia_attachment(gpu),
+            vendor => self.dri_attachment(gpu, vendor),
+        }
+    }
+
+    fn dri_attachment(&self, gpu: &GPUInfo, vendor: GpuVendor) -> Result<GpuAttachment> {
+        let address = gpu
+            .pci_address
+            .as_deref()
+            .ok_or_else(|| anyhow!("GPU {} has no PCI address to find its DRI nodes by", gpu.id))?;
+        let pci_path = self.sysfs_root.join("bus/pci/devices").join(address);
+
+        let render = find_render_node(&pci_path)
+            .ok_or_else(|| anyhow!("GPU {} ({}) has no DRM render node - is {} loaded?", gpu.id, address, driver_hint(vendor)))?;
+        let mut nodes = Vec::new();
+        if let Some(card) = find_drm_card(&pci_path) {
+            nodes.push(format!("dri/{}", card));
+        }
+        nodes.push(format!("dri/{}", render));
+
+        let env = match vendor {
+            GpuVendor::Amd => {
+                if self.dev_root.join("kfd").exists() {
+                    nodes.push("kfd".to_string());
+                } else {
+                    warn!("{} missing - GPU {} will have graphics only, no ROCm compute", self.dev_root.join("kfd").display(), gpu.id);
+                }
+                vec!["ROCR_VISIBLE_DEVICES=0".to_string()]
+            }
+            _ => vec!["ZE_AFFINITY_MASK=0".to_string()],
+        };
+
+        let mut group_add = Vec::new();
+        let devices = nodes
+            .iter()
+            .map(|node| {
+                let host = self.dev_root.join(node);
+                if let Some(gid) = owner_gid(&host) {
+                    let gid = gid.to_string();
+                    if gid != "0" && !group_add.contains(&gid) {
+                        group_add.push(gid);
+                    }
+                }
+                let in_container = format!("/dev/{}", node);
+                DeviceMapping {
+                    path_on_host: Some(host.to_string_lossy().into_owned()),
+                    path_in_container: Some(in_container),
+                    cgroup_permissions: Some("rwm".to_string()),
+                }
+            })
+            .collect();
+
+        Ok(GpuAttachment {
+            gpu_id: gpu.id.clone(),
+            vendor,
+            device_requests: Vec::new(),
+            devices,
+            group_add,
+            env,
+        })
+    }
+}
+
+impl Default for GpuAttacher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn nvidia_attachment(gpu: &GPUInfo) -> Result<GpuAttachment> {
+    // Detection keys NVIDIA cards by UUID when the driver answers; a PCI address
+    // here means nvidia-smi never saw the card
+    if !(gpu.id.starts_with("GPU-") || gpu.id.starts_with("MIG-")) {
+        return Err(anyhow!("NVIDIA GPU {} has no UUID - is the nvidia driver bound?", gpu.id));
+    }
+
+    Ok(GpuAttachment {
+        gpu_id: gpu.id.clone(),
+        vendor: GpuVendor::Nvidia,
+        device_requests: vec![DeviceRequest {
+            driver: Some("nvidia".to_string()),
+            device_ids: Some(vec![gpu.id.clone()]),
+            capabilities: Some(vec![vec!["gpu".to_string()]]),
+            ..Default::default()
+        }],
+        devices: Vec::new(),
+        group_add: Vec::new(),
+        env: vec![
+            format!("NVIDIA_VISIBLE_DEVICES={}", gpu.id),
+            format!("NVIDIA_DRIVER_CAPABILITIES={}", NVIDIA_DRIVER_CAPABILITIES),
+        ],
+    })
+}
+
+fn vendor_of(gpu: &GPUInfo) -> Result<GpuVendor> {
+    [GpuVendor::Nvidia, GpuVendor::Amd, GpuVendor::Intel]
+        .into_iter()
+        .find(|v| v.name().eq_ignore_ascii_case(&gpu.vendor))
+        .ok_or_else(|| anyhow!("Can't attach {} GPU {} to a container", gpu.vendor, gpu.id))
+}
+
+fn driver_hint(vendor: GpuVendor) -> &'static str {
+    match vendor {
+        GpuVendor::Nvidia => "nvidia",
+        GpuVendor::Amd => "amdgpu",
+        GpuVendor::Intel => "i915/xe",
+    }
+}
+
+fn find_render_node(pci_path: &Path) -> Option<String> {
+    fs::read_dir(pci_path.join("drm"))
+        .ok()?
+        .filter_map(|e| e.ok())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .find(|name| name.strip_prefix("renderD").is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit())))
+}
+
+#[cfg(unix)]
+fn owner_gid(path: &Path) -> Option<u32> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).ok().map(|m| m.gid())
+}
+
+#[cfg(not(unix))]
+fn owner_gid(_path: &Path) -> Option<u32> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu::fake_sysfs::FakeSysfs;
+
+    fn gpu(id: &str, vendor: &str, address: Option<&str>) -> GPUInfo {
+        GPUInfo {
+            id: id.into(),
+            vendor: vendor.into(),
+            pci_address: address.map(Into::into),
+            ..Default::default()
+        }
+    }
+
+    fn dri_host(address: &str, card: &str, render: &str) -> (FakeSysfs, tempfile::TempDir) {
+        let sysfs = FakeSysfs::new();
+        sysfs.add_pci_device(address, 0x1002, 0x744c, 0x030000);
+        sysfs.add_drm_card(card, address);
+        fs::create_dir_all(sysfs.path(&format!("bus/pci/devices/{}/drm/{}", address, render))).unwrap();
+        let dev = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dev.path().join("dri")).unwrap();
+        (sysfs, dev)
+    }
+
+    #[test]
+    fn test_nvidia_device_request_by_uuid() {
+        let uuid = "GPU-4b3c1e2a-8f7d-4c5e-9a1b-2d3e4f5a6b7c";
+        let attachment = GpuAttacher::new().attachment(&gpu(uuid, "NVIDIA", Some("0000:01:00.0"))).unwrap();
+
+        let request = &attachment.device_requests[0];
+        assert_eq!(request.driver.as_deref(), Some("nvidia"));
+        assert_eq!(request.device_ids, Some(vec![uuid.to_string()]));
+        assert!(attachment.devices.is_empty());
+        assert!(attachment.env.contains(&format!("NVIDIA_VISIBLE_DEVICES={}", uuid)));
+
+        let mut host_config = HostConfig::default();
+        let mut env = vec!["FOO=bar".to_string()];
+        attachment.apply(&mut host_config, &mut env);
+        assert_eq!(host_config.device_requests.unwrap().len(), 1);
+        assert_eq!(env.len(), 3);
+    }
+
+    #[test]
+    fn test_nvidia_without_uuid_is_refused() {
+        let err = GpuAttacher::new().attachment(&gpu("0000:01:00.0", "NVIDIA", Some("0000:01:00.0"))).unwrap_err();
+        assert!(err.to_string().contains("no UUID"));
+    }
+
+    #[test]
+    fn test_amd_maps_own_dri_nodes_and_kfd() {
+        let (sysfs, dev) = dri_host("0000:03:00.0", "card1", "renderD129");
+        fs::write(dev.path().join("kfd"), "").unwrap();
+        let attacher = GpuAttacher::with_roots(sysfs.root(), dev.path());
+
+        let attachment = attacher.attachment(&gpu("0000:03:00.0", "AMD", Some("0000:03:00.0"))).unwrap();
+        let in_container: Vec<_> =
+            attachment.devices.iter().map(|d| d.path_in_container.clone().unwrap()).collect();
+        assert_eq!(in_container, vec!["/dev/dri/card1", "/dev/dri/renderD129", "/dev/kfd"]);
+        assert!(attachment.device_paths()[1].ends_with("dri/renderD129"));
+        assert_eq!(attachment.env, vec!["ROCR_VISIBLE_DEVICES=0"]);
+        assert!(attachment.device_requests.is_empty());
+    }
+
+    #[test]
+    fn test_intel_without_kfd() {
+        let (sysfs, dev) = dri_host("0000:00:02.0", "card0", "renderD128");
+        let attacher = GpuAttacher::with_roots(sysfs.root(), dev.path());
+
+        let attachment = attacher.attachment(&gpu("0000:00:02.0", "Intel", Some("0000:00:02.0"))).unwrap();
+        assert_eq!(attachment.devices.len(), 2);
+        assert_eq!(attachment.env, vec!["ZE_AFFINITY_MASK=0"]);
+    }
+
+    #[test]
+    fn test_missing_render_node_and_vfio_are_refused() {
+        let sysfs = FakeSysfs::new();
+        sysfs.add_pci_device("0000:03:00.0", 0x1002, 0x744c, 0x030000);
+        let attacher = GpuAttacher::with_roots(sysfs.root(), sysfs.root());
+        let amd = gpu("0000:03:00.0", "AMD", Some("0000:03:00.0"));
+        assert!(attacher.attachment(&amd).unwrap_err().to_string().contains("render node"));
+
+        let vfio = GPUInfo { driver: Some("vfio-pci".into()), ..amd };
+        assert!(attacher.attachment(&vfio).unwrap_err().to_string().contains("vfio-pci"));
+        assert!(attacher.attachment(&gpu("x", "Apple", None)).is_err());
+    }
+}
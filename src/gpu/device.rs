@@ -6,7 +6,10 @@ use std::{
     process::Command,
     collections::HashMap,
 };
-use std::time::Duration; // Importing Duration type because time waits for no one
+use tracing::info;
+use crate::monitoring::metrics::GPUMetrics;
+use super::container::{GpuAttacher, GpuAttachment};
+use super::passthrough::{IsolationPlan, VfioGroup};
 
 // GPU Configuration - every GPU gets its own set of crazy commands, obviously
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -32,10 +35,17 @@ pub struct GPUInfo {
     pub directx_version: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub iommu_group: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pci_address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub driver: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gtt_mb: Option<u64>,
 }
 
 /// GPU Management Core
 /// Wrangles, monitors, and dished-out GPU resources like a champ
+#[derive(Clone)]
 pub struct GPUManager {
     pub devices: Vec<GPUInfo>,
     pub iommu_groups: HashMap<u64, Vec<String>>,
@@ -66,43 +76,26 @@ impl GPUManager {
         Ok(())
     }
 
-    /// Linux-specific GPU detection (using NVML and sysfs because we can)
+    /// Linux-specific GPU detection (NVML/nvidia-smi and sysfs because we can)
     #[cfg(target_os = "linux")]
     fn detect_linux_gpus(&mut self) -> Result<()> {
-        use nvml_wrapper::Nvml;
-        
-        // Detecting NVIDIA cards - hunt those silicon marvels
-        if let Ok(nvml) = Nvml::init() {
-            for i in 0..nvml.device_count()? {
-                let device = nvml.device_by_index(i)?;
-                self.devices.push(GPUInfo {
-                    id: device.uuid()?,
-                    vendor: "NVIDIA".into(),
-                    model: device.name()?,
-                    vram_mb: device.memory_info()?.total / 1024 / 1024,
-                    driver_version: nvml.sys_driver_version()?,
-                    vulkan_support: Some(true),
-                    ..Default::default()
-                });
-            }
-        }
-
-        // Detecting AMD cards (via sysfs because linux loves files)
-        let amd_path = Path::new("/sys/class/drm/card*/device");
-        for entry in glob::glob(amd_path.to_str().unwrap())? {
-            let path = entry?;
-            if let Some(uevent) = Self::read_uevent(&path)? {
-                self.devices.push(GPUInfo {
-                    id: uevent.device_id,
-                    vendor: "AMD".into(),
-                    model: uevent.model,
-                    vram_mb: Self::read_amd_vram(&path)?,
-                    driver_version: Self::read_driver_version(&path)?,
-                    vulkan_support: Some(true),
-                    ..Default::default()
-                });
+        // NVIDIA tooling knows UUIDs and exact memory, so it wins for the cards it can see
+        let mut nvidia = super::nvidia::detect_nvidia_gpus()?;
+
+        // The PCI scan catches everyone else - AMD, Intel, and NVIDIA cards parked on vfio-pci
+        for pci_gpu in get_gpu_info()? {
+            let known = nvidia.iter().position(|g| g.pci_address == pci_gpu.pci_address);
+            match known {
+                Some(idx) => {
+                    let mut gpu = nvidia.swap_remove(idx);
+                    gpu.iommu_group = pci_gpu.iommu_group;
+                    gpu.driver = pci_gpu.driver;
+                    self.devices.push(gpu);
+                }
+                None => self.devices.push(pci_gpu),
             }
         }
+        self.devices.extend(nvidia);
 
         Ok(())
     }
@@ -122,7 +115,10 @@ impl GPUManager {
                 driver_version: "Metal".into(),
                 vulkan_support: None,
                 directx_version: None,
-                iommu_group: None
+                iommu_group: None,
+                pci_address: None,
+                driver: None,
+                gtt_mb: None,
             });
         }
         
@@ -163,7 +159,7 @@ impl GPUManager {
             let pci_devices = Path::new("/sys/bus/pci/devices");
             for entry in fs::read_dir(pci_devices)? {
                 let path = entry?.path();
-                if let Some(group) = Self::get_iommu_group(&path)? {
+                if let Some(group) = get_iommu_group(&path)? {
                     let devices = self.iommu_groups.entry(group).or_default();
                     devices.push(
                         path.file_name()
@@ -179,73 +175,44 @@ impl GPUManager {
     }
 
     /// Verify that the IOMMU group is safe for passthrough - safety first, folks!
+    /// The card's own audio/USB functions and PCI bridges don't count against it.
     pub fn validate_iommu_group(&self, group_id: u64) -> Result<()> {
-        let devices = self.iommu_groups.get(&group_id)
-            .ok_or_else(|| GPUError::IommuGroupNotFound(group_id))?;
+        self.validate_iommu_group_in(Path::new("/sys"), group_id)
+    }
 
-        if devices.len() > 1 {
+    pub fn validate_iommu_group_in(&self, sysfs_root: &Path, group_id: u64) -> Result<()> {
+        let plan = self.plan_iommu_group_in(sysfs_root, group_id)?;
+        if !plan.is_isolated() {
             return Err(anyhow::Error::from(GPUError::UnsafeIommuGroup(
-                devices.join(", ")
+                plan.foreign_devices().join(", ")
             )));
         }
 
         Ok(())
     }
 
-    /// Reads AMD GPU VRAM from sysfs - memory is king, obviously
-    #[cfg(target_os = "linux")]
-    fn read_amd_vram(path: &Path) -> Result<u64> {
-        let vram_path = path.join("mem_info_vram_total");
-        Ok(fs::read_to_string(vram_path)?.trim().parse::<u64>()? / 1024)
-    }
-
-    /// Reads the driver version from sysfs - drivers gotta chat too
-    #[cfg(target_os = "linux")]
-    fn read_driver_version(path: &Path) -> Result<String> {
-        Ok(fs::read_to_string(path.join("version"))?.trim().into())
+    pub fn plan_iommu_group(&self, group_id: u64) -> Result<IsolationPlan> {
+        self.plan_iommu_group_in(Path::new("/sys"), group_id)
     }
 
-    /// Reads PCI device info from uevent - because every device tells a story
-    #[cfg(target_os = "linux")]
-    fn read_uevent(path: &Path) -> Result<Option<UeventInfo>> {
-        let uevent_path = path.join("uevent");
-        if !uevent_path.exists() {
-            return Ok(None);
-        }
-
-        let mut uevent = UeventInfo::default();
-        for line in fs::read_to_string(uevent_path)?.lines() {
-            let parts: Vec<&str> = line.split('=').collect();
-            if parts.len() < 2 {
-                continue;
-            }
-            match parts[0] {
-                "PCI_ID" => uevent.device_id = parts[1].into(),
-                "PCI_SUBSYS_ID" => uevent.subsystem_id = parts[1].into(),
-                "MODALIAS" => {
-                    if let Some(model_part) = parts[1].split(':').nth(2) {
-                        uevent.model = model_part.into();
-                    }
-                },
-                _ => {}
-            }
-        }
-
-        Ok(Some(uevent))
-    }
+    /// Classifies the group's members against the GPU that lives in it
+    pub fn plan_iommu_group_in(&self, sysfs_root: &Path, group_id: u64) -> Result<IsolationPlan> {
+        let devices = self.iommu_groups.get(&group_id)
+            .ok_or(GPUError::IommuGroupNotFound(group_id))?;
 
-    /// Reads the IOMMU group for a PCI device - grouping it like a pro
-    #[cfg(target_os = "linux")]
-    fn get_iommu_group(path: &Path) -> Result<Option<u64>> {
-        let group_link = path.join("iommu_group");
-        if !group_link.exists() {
-            return Ok(None);
+        // A lone device is the GPU by definition; otherwise we need its PCI address
+        let gpu_address = self.devices
+            .iter()
+            .filter(|g| g.iommu_group == Some(group_id))
+            .find_map(|g| g.pci_address.clone().filter(|addr| devices.contains(addr)))
+            .or_else(|| (devices.len() == 1).then(|| devices[0].clone()))
+            .ok_or_else(|| anyhow::anyhow!("No known GPU in IOMMU group {}", group_id))?;
+
+        let mut group = VfioGroup::with_root(group_id as u32, sysfs_root)?;
+        for device in devices {
+            group.add_device(device.clone())?;
         }
-
-        let group_path = fs::read_link(group_link)?;
-        let group_str = group_path.file_name().unwrap().to_string_lossy();
-
-        Ok(Some(group_str.parse::<u64>()?))
+        Ok(group.plan(&gpu_address)?)
     }
 
     /// Lists all available GPU devices - because sharing is caring
@@ -253,19 +220,23 @@ impl GPUManager {
         Ok(self.devices.clone())
     }
 
-    /// Attaches the GPU to a VM (domain param stays for signature's sake) - stick it on, champ!
-    pub async fn attach_gpu(&mut self, container_id: &str, gpu_id: &str) -> Result<()> {
+    /// Works out how to expose a GPU to a container - device requests, DRI nodes and env
+    pub fn container_attachment(&self, gpu_id: &str) -> Result<GpuAttachment> {
         let gpu = self.devices
             .iter()
             .find(|g| g.id == gpu_id)
             .ok_or_else(|| anyhow::anyhow!("GPU not found: {}", gpu_id))?;
 
-        if gpu.iommu_group != Some(42) {
-            return Err(anyhow::anyhow!("IOMMU group mismatch for container {}", container_id));
-        }
+        GpuAttacher::new().attachment(gpu)
+    }
 
-        tokio::time::sleep(Duration::from_millis(50)).await;
-        Ok(())
+    /// Attaches the GPU to a container. Docker can't add devices to a running
+    /// container, so the returned attachment takes effect when it is (re)created.
+    pub async fn attach_gpu(&mut self, container_id: &str, gpu_id: &str) -> Result<GpuAttachment> {
+        let attachment = self.container_attachment(gpu_id)
+            .map_err(|e| anyhow::anyhow!("Can't attach GPU to container {}: {}", container_id, e))?;
+        info!("GPU {} ({}) attached to container {}", gpu_id, attachment.vendor.name(), container_id);
+        Ok(attachment)
     }
 
     /// Returns the IOMMU group for a given GPU - find it or lose it!
@@ -281,14 +252,44 @@ impl GPUManager {
     pub fn discover_gpus(&self) -> Result<Vec<GPUInfo>> {
         Ok(self.devices.clone())
     }
-}
 
-/// Helper structure to parse uevent data - because even devices gossip
-#[derive(Default)]
-struct UeventInfo {
-    device_id: String,
-    subsystem_id: String,
-    model: String,
+    /// SR-IOV virtual functions currently enabled on a GPU (empty if it has none)
+    #[cfg(target_os = "linux")]
+    pub fn list_virtual_functions(&self, gpu_id: &str) -> Result<Vec<String>> {
+        let gpu = self.devices
+            .iter()
+            .find(|g| g.id == gpu_id)
+            .ok_or_else(|| anyhow::anyhow!("GPU not found: {}", gpu_id))?;
+
+        Ok(gpu.pci_address
+            .as_deref()
+            .and_then(|addr| super::pci::PciScanner::new().sriov_info(addr))
+            .map(|sriov| sriov.virtual_functions)
+            .unwrap_or_default())
+    }
+
+    /// Live telemetry for a GPU - `None` when the vendor backend has nothing to say
+    pub fn collect_metrics(&self, gpu_id: &str) -> Result<Option<GPUMetrics>> {
+        let gpu = self.devices
+            .iter()
+            .find(|g| g.id == gpu_id)
+            .ok_or_else(|| anyhow::anyhow!("GPU not found: {}", gpu_id))?;
+
+        match gpu.vendor.as_str() {
+            "NVIDIA" => super::nvidia::collect_nvidia_metrics(&gpu.id),
+            #[cfg(target_os = "linux")]
+            "AMD" => match &gpu.pci_address {
+                Some(addr) => Ok(Some(read_amd_metrics(&Path::new("/sys/bus/pci/devices").join(addr), gpu.vram_mb)?)),
+                None => Ok(None),
+            },
+            #[cfg(target_os = "linux")]
+            "Intel" => match &gpu.pci_address {
+                Some(addr) => Ok(Some(super::intel::IntelTelemetry::global().collect(addr, gpu.vram_mb)?)),
+                None => Ok(None),
+            },
+            _ => Ok(None),
+        }
+    }
 }
 
 impl GPUInfo {
@@ -303,6 +304,9 @@ impl GPUInfo {
             vulkan_support: Some(true),
             directx_version: Some(12.0),
             iommu_group: Some(42),
+            pci_address: None,
+            driver: None,
+            gtt_mb: None,
         }
     }
 }
@@ -332,8 +336,8 @@ pub enum GPUError {
     DetectionError(String),
 }
 
-#[allow(dead_code)]
-fn has_required_permissions() -> bool {
+/// Root is needed for sysfs driver rebinding and /dev/vfio access
+pub fn has_required_permissions() -> bool {
     if cfg!(unix) {
         Command::new("id")
             .arg("-u")
@@ -349,13 +353,7 @@ fn has_required_permissions() -> bool {
 }
 
 // Helper functions
-fn is_gpu_device(vendor: &str, _device: &str) -> bool {
-    let vendor = vendor.trim();
-    // Vendor IDs for NVIDIA, AMD, and Intel - numbers that make it spicy
-    vendor == "10de" || vendor == "1002" || vendor == "8086"
-}
-
-fn get_iommu_group(path: &Path) -> Result<Option<u64>> {
+pub(crate) fn get_iommu_group(path: &Path) -> Result<Option<u64>> {
     let iommu_link = match fs::read_link(path.join("iommu_group")) {
         Ok(link) => link,
         Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
@@ -364,7 +362,10 @@ fn get_iommu_group(path: &Path) -> Result<Option<u64>> {
         Err(e) => return Err(e.into()),
     };
 
-    let group_str = iommu_link.to_str().ok_or_else(|| anyhow::anyhow!("Invalid IOMMU group path"))?;
+    let group_str = iommu_link
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| anyhow::anyhow!("Invalid IOMMU group path"))?;
 
     Ok(Some(group_str.parse::<u64>()?))
 }
@@ -379,10 +380,47 @@ fn read_gpu_utilization(path: &Path) -> Result<f64> {
     Ok(util_str.parse()?)
 }
 
+/// amdgpu exposes busy % on the PCI device and temperature under its hwmon node
+#[cfg(target_os = "linux")]
+fn read_amd_metrics(path: &Path, vram_total_mb: u64) -> Result<GPUMetrics> {
+    let hwmon = fs::read_dir(path.join("hwmon"))
+        .ok()
+        .and_then(|mut entries| entries.next())
+        .and_then(|entry| entry.ok())
+        .map(|entry| entry.path());
+
+    let read_u64 = |file: &str| -> Option<u64> {
+        fs::read_to_string(path.join(file)).ok()?.trim().parse().ok()
+    };
+
+    Ok(GPUMetrics {
+        utilization_percent: read_gpu_utilization(path)?,
+        memory_used_mb: read_u64("mem_info_vram_used").unwrap_or(0) / 1024 / 1024,
+        memory_total_mb: vram_total_mb,
+        temperature_celsius: hwmon
+            .as_deref()
+            .and_then(|h| read_gpu_temperature(h).ok())
+            .unwrap_or(0.0) as i32,
+        power_usage_watts: hwmon
+            .and_then(|h| fs::read_to_string(h.join("power1_average")).ok())
+            .and_then(|p| p.trim().parse::<f64>().ok())
+            .map(|uw| uw / 1_000_000.0)
+            .unwrap_or(0.0),
+        clock_mhz: None,
+        max_clock_mhz: None,
+    })
+}
+
 #[cfg(target_os = "linux")]
 fn get_gpu_info() -> Result<Vec<GPUInfo>> {
-    // Linux-specific implementation using sysfs - empty for now, sorry!
-    Ok(Vec::new())
+    // Linux-specific implementation using sysfs - vendor/class aware PCI scan.
+    // SR-IOV VFs are slices of a listed GPU, not GPUs of their own.
+    Ok(super::pci::PciScanner::new()
+        .scan()?
+        .iter()
+        .filter(|gpu| !gpu.is_virtual_function())
+        .map(super::pci::PciGpu::to_gpu_info)
+        .collect())
 }
 
 #[cfg(target_os = "macos")]
@@ -441,6 +479,7 @@ impl From<&str> for GPUConfig {
 mod tests {
     // Gerçek virt crate'inden gelen Domain trait'i veya yapısının gerektirdiği
     // yöntemleri DummyDomain üzerine implemente edin.
+    #[allow(dead_code)]
     mod virt {
         pub mod domain {
             #[derive(Debug)]
@@ -461,9 +500,6 @@ mod tests {
         }
     }
     
-    // Testlerde import ederken:
-    use virt::domain::DummyDomain as Domain;
-    
     use super::*;
     use std::collections::HashMap;
 
@@ -504,7 +540,8 @@ mod tests {
         let mut manager = GPUManager {
             devices: vec![
                 GPUInfo {
-                    id: "mock-gpu-1".into(),
+                    id: "GPU-4b3c1e2a-8f7d-4c5e-9a1b-2d3e4f5a6b7c".into(),
+                    vendor: "NVIDIA".into(),
                     iommu_group: Some(42),
                     ..Default::default()
                 },
@@ -512,8 +549,11 @@ mod tests {
             iommu_groups: HashMap::new(),
         };
         
-        let result = manager.attach_gpu("dummy-container-123", "mock-gpu-1").await;
-        assert!(result.is_ok());
+        let attachment = manager
+            .attach_gpu("dummy-container-123", "GPU-4b3c1e2a-8f7d-4c5e-9a1b-2d3e4f5a6b7c")
+            .await
+            .unwrap();
+        assert_eq!(attachment.device_requests.len(), 1);
     }
 
     #[tokio::test]
@@ -524,16 +564,18 @@ mod tests {
         };
         
         let result = manager.attach_gpu("dummy-container-123", "non-existent-gpu").await;
-        assert!(matches!(result, Err(_)));
+        assert!(result.is_err());
     }
 
     #[tokio::test]
-    async fn test_iommu_group_mismatch() {
+    async fn test_vfio_bound_gpu_not_attachable() {
         let mut manager = GPUManager {
             devices: vec![
                 GPUInfo {
                     id: "mock-gpu-1".into(),
+                    vendor: "AMD".into(),
                     iommu_group: Some(42),
+                    driver: Some("vfio-pci".into()),
                     ..Default::default()
                 },
             ],
@@ -541,7 +583,7 @@ mod tests {
         };
         
         let result = manager.attach_gpu("dummy-container-456", "mock-gpu-1").await;
-        assert!(matches!(result, Err(_)));
+        assert!(result.unwrap_err().to_string().contains("vfio-pci"));
     }
 
     #[test]
@@ -558,6 +600,9 @@ mod tests {
                     vulkan_support: Some(true),
                     directx_version: Some(12.1),
                     iommu_group: Some(42),
+                    pci_address: None,
+                    driver: None,
+                    gtt_mb: None,
                 },
                 GPUInfo {
                     id: "mock-gpu-2".into(),
@@ -569,6 +614,9 @@ mod tests {
                     vulkan_support: Some(true),
                     directx_version: Some(11.2),
                     iommu_group: Some(24),
+                    pci_address: None,
+                    driver: None,
+                    gtt_mb: None,
                 },
             ],
             iommu_groups: HashMap::from([
@@ -582,6 +630,37 @@ mod tests {
         assert_eq!(devices[1].vram_mb, 32768);
     }
 
+    #[test]
+    fn test_iommu_group_with_hdmi_audio_is_isolated() {
+        use crate::gpu::fake_sysfs::FakeSysfs;
+
+        let sysfs = FakeSysfs::new();
+        sysfs.add_pci_device("0000:01:00.0", 0x10de, 0x2684, 0x030000);
+        sysfs.add_pci_device("0000:01:00.1", 0x10de, 0x22ba, 0x040300);
+        sysfs.add_pci_device("0000:00:01.0", 0x8086, 0x347a, 0x060400);
+        sysfs.add_pci_device("0000:03:00.0", 0x8086, 0x15f3, 0x020000);
+        let gpu = |group| GPUInfo {
+            id: "gpu-0".into(),
+            iommu_group: Some(group),
+            pci_address: Some("0000:01:00.0".into()),
+            ..Default::default()
+        };
+        let manager = GPUManager {
+            devices: vec![gpu(1), gpu(2)],
+            iommu_groups: HashMap::from([
+                (1, vec!["0000:00:01.0".into(), "0000:01:00.0".into(), "0000:01:00.1".into()]),
+                (2, vec!["0000:01:00.0".into(), "0000:03:00.0".into()]),
+            ]),
+        };
+
+        let plan = manager.plan_iommu_group_in(sysfs.root(), 1).unwrap();
+        assert!(plan.is_isolated());
+        assert_eq!(plan.passthrough_devices(), vec!["0000:01:00.0", "0000:01:00.1"]);
+
+        let plan = manager.plan_iommu_group_in(sysfs.root(), 2).unwrap();
+        assert_eq!(plan.foreign_devices(), vec!["0000:03:00.0"]);
+    }
+
     #[test]
     fn test_get_iommu_group() {
         let manager = GPUManager {
@@ -596,6 +675,9 @@ mod tests {
                     vulkan_support: Some(true),
                     directx_version: Some(11.2),
                     iommu_group: Some(24),
+                    pci_address: None,
+                    driver: None,
+                    gtt_mb: None,
                 },
             ],
             iommu_groups: HashMap::from([
@@ -609,9 +691,11 @@ mod tests {
 
 #[cfg(test)]
 #[derive(Debug)]
+#[allow(dead_code)]
 struct DummyDomain;
 
 #[cfg(test)]
+#[allow(dead_code)]
 impl DummyDomain {
     fn mock() -> Self {
         Self
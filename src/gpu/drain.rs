@@ -0,0 +1,414 @@
+/*
+* Drain / maintenance mode
+* ------------------------
+* Takes one GPU or the whole host out of service before driver upgrades:
+*
+*   drain   -> entries stop taking new leases (GPUPool::drain)
+*           -> existing leases are left alone, migrated to an active entry
+*              with at least as much VRAM and compute, or waited for
+*   undrain -> back to active
+*
+* Migrating moves the lease in GPUPool, then the API recreates the tenant's
+* containers on the new card (DockerManager::recreate_with_gpu). A drain is
+* complete once no drained entry holds a lease and no container still has a
+* drained card attached, so a failed move keeps it open.
+*/
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::core::docker_manager::ManagedContainer;
+use crate::events::{EventBus, EventKind};
+use crate::gpu::device::GPUInfo;
+use crate::gpu::virtual_gpu::{GPUPool, ServiceState};
+use crate::users::UserManager;
+use crate::AsyncMutex;
+
+/// What to drain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "scope", rename_all = "snake_case")]
+pub enum DrainTarget {
+    Gpu { gpu_id: u32 },
+    Host,
+}
+
+impl DrainTarget {
+    fn ids(&self, pool: &GPUPool) -> Result<Vec<u32>> {
+        match self {
+            DrainTarget::Gpu { gpu_id } => {
+                if !pool.gpus.contains_key(gpu_id) {
+                    return Err(anyhow!("GPU {} not found", gpu_id));
+                }
+                Ok(vec![*gpu_id])
+            }
+            DrainTarget::Host => {
+                let mut ids: Vec<u32> = pool.gpus.keys().copied().collect();
+                ids.sort();
+                Ok(ids)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeaseMove {
+    pub tenant: String,
+    pub from: u32,
+    pub to: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeaseInfo {
+    pub gpu_id: u32,
+    pub tenant: String,
+}
+
+/// A container that still has a drained card attached
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContainerOnGpu {
+    pub gpu_id: u32,
+    pub container_id: String,
+    pub name: String,
+    pub owner: Option<String>,
+}
+
+/// A migrated tenant's container and the card it moves to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainerMove {
+    pub container_id: String,
+    pub name: String,
+    pub to_gpu: String,
+}
+
+/// Where a drain stands. Complete once no drained entry holds a lease and no
+/// container runs on one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DrainProgress {
+    pub target: DrainTarget,
+    /// Entries of the target that are out of service
+    pub drained: Vec<u32>,
+    /// Entries of the target still taking leases (only while undraining)
+    pub active: Vec<u32>,
+    pub remaining_leases: Vec<LeaseInfo>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remaining_containers: Vec<ContainerOnGpu>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub migrated: Vec<LeaseMove>,
+    pub complete: bool,
+}
+
+/// Drains `target`. With `migrate`, leases are moved to active entries outside
+/// the target where one is big enough; the rest stay put and show up as
+/// remaining leases. Tenants still on a drained GPU are notified.
+pub fn start_drain(
+    pool: &mut GPUPool,
+    users: &mut UserManager,
+    events: &EventBus,
+    target: DrainTarget,
+    reason: &str,
+    migrate: bool,
+) -> Result<DrainProgress> {
+    let ids = target.ids(pool)?;
+
+    let mut newly_drained = Vec::new();
+    for &id in &ids {
+        if pool.drain(id, reason)? {
+            newly_drained.push(id);
+            let tenant = pool.gpus[&id].allocated_to.clone();
+            info!("GPU {} draining: {}", id, reason);
+            events.publish(EventKind::GpuDrained { gpu_id: id, tenant, reason: reason.to_string() });
+        }
+    }
+
+    let mut migrated = Vec::new();
+    if migrate {
+        for &id in &ids {
+            match pool.migrate_lease(id) {
+                Ok(Some(to)) => {
+                    let tenant = pool.gpus[&to].allocated_to.clone().unwrap_or_default();
+                    let _ = users.notify(
+                        &tenant,
+                        format!("Your lease moved from GPU {} to GPU {} ({})", id, to, reason),
+                    );
+                    migrated.push(LeaseMove { tenant, from: id, to });
+                }
+                Ok(None) => {}
+                Err(e) => warn!("Lease on GPU {} stays put: {}", id, e),
+            }
+        }
+    }
+
+    for &id in &newly_drained {
+        if let Some(tenant) = pool.gpus[&id].allocated_to.clone() {
+            let _ = users.notify(
+                &tenant,
+                format!("GPU {} is being drained ({}) - please wrap up your work", id, reason),
+            );
+        }
+    }
+
+    let mut progress = drain_progress(pool, target)?;
+    progress.migrated = migrated;
+    Ok(progress)
+}
+
+pub fn drain_progress(pool: &GPUPool, target: DrainTarget) -> Result<DrainProgress> {
+    let ids = target.ids(pool)?;
+    let (drained, active): (Vec<u32>, Vec<u32>) =
+        ids.iter().partition(|id| !pool.gpus[id].state.is_active());
+    let remaining_leases: Vec<LeaseInfo> = drained
+        .iter()
+        .filter_map(|id| {
+            pool.gpus[id].allocated_to.as_ref().map(|tenant| LeaseInfo { gpu_id: *id, tenant: tenant.clone() })
+        })
+        .collect();
+
+    Ok(DrainProgress {
+        target,
+        complete: active.is_empty() && remaining_leases.is_empty(),
+        drained,
+        active,
+        remaining_leases,
+        remaining_containers: Vec::new(),
+        migrated: Vec::new(),
+    })
+}
+
+/// Card a managed container has attached, if it really has one
+fn attached_card<'a>(container: &ManagedContainer, devices: &'a [GPUInfo]) -> Option<&'a GPUInfo> {
+    let gpu_id = container.gpu_id.as_ref().filter(|_| container.gpu_attached)?;
+    devices.iter().find(|d| d.id == *gpu_id)
+}
+
+/// Containers the migrated leases' workloads run in, with the card each one
+/// moves to. A container moves when its owner's lease left an entry on the
+/// card it has attached.
+pub fn plan_container_moves(
+    pool: &GPUPool,
+    moves: &[LeaseMove],
+    devices: &[GPUInfo],
+    containers: &[ManagedContainer],
+) -> Vec<ContainerMove> {
+    let mut planned = Vec::new();
+    for lease_move in moves {
+        let (Some(from), Some(to)) = (pool.gpus.get(&lease_move.from), pool.gpus.get(&lease_move.to)) else {
+            continue;
+        };
+        let Some(target) = devices.iter().find(|d| to.runs_on(d)) else {
+            warn!("GPU {} has no detected card, {}'s containers stay put", to.id, lease_move.tenant);
+            continue;
+        };
+        for container in containers {
+            let on_source = attached_card(container, devices).is_some_and(|card| from.runs_on(card));
+            if on_source && container.owner.as_deref() == Some(lease_move.tenant.as_str()) {
+                planned.push(ContainerMove {
+                    container_id: container.id.clone(),
+                    name: container.name.clone(),
+                    to_gpu: target.id.clone(),
+                });
+            }
+        }
+    }
+    planned
+}
+
+/// Adds the containers still on drained entries to `progress`; it isn't
+/// complete while there are any
+pub fn add_remaining_containers(
+    progress: &mut DrainProgress,
+    pool: &GPUPool,
+    devices: &[GPUInfo],
+    containers: &[ManagedContainer],
+) {
+    progress.remaining_containers = containers
+        .iter()
+        .filter_map(|container| {
+            let card = attached_card(container, devices)?;
+            let entry = progress.drained.iter().find(|id| pool.gpus[id].runs_on(card))?;
+            Some(ContainerOnGpu {
+                gpu_id: *entry,
+                container_id: container.id.clone(),
+                name: container.name.clone(),
+                owner: container.owner.clone(),
+            })
+        })
+        .collect();
+    progress.complete &= progress.remaining_containers.is_empty();
+}
+
+/// Polls until every lease on the drained target is released or `timeout`
+/// passes; returns the last progress either way
+pub async fn wait_for_drain(
+    pool: Arc<AsyncMutex<GPUPool>>,
+    target: DrainTarget,
+    timeout: Duration,
+    poll: Duration,
+) -> Result<DrainProgress> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        let progress = drain_progress(&*pool.lock().await, target)?;
+        if progress.complete || tokio::time::Instant::now() >= deadline {
+            return Ok(progress);
+        }
+        tokio::time::sleep(poll).await;
+    }
+}
+
+/// Returns drained and quarantined entries of `target` to service
+pub fn undrain(pool: &mut GPUPool, events: &EventBus, target: DrainTarget) -> Result<DrainProgress> {
+    for id in target.ids(pool)? {
+        if pool.undrain(id)? {
+            info!("GPU {} back in service", id);
+            events.publish(EventKind::GpuUndrained { gpu_id: id });
+        }
+    }
+    drain_progress(pool, target)
+}
+
+/// Short label for CLI/dashboard status columns
+pub fn state_label(state: &ServiceState) -> &'static str {
+    match state {
+        ServiceState::Active => "active",
+        ServiceState::Draining { .. } => "draining",
+        ServiceState::Quarantined { .. } => "quarantined",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup() -> (GPUPool, UserManager, EventBus) {
+        (GPUPool::new(), UserManager::new(), EventBus::new())
+    }
+
+    #[test]
+    fn test_drain_gpu_with_migration() {
+        let (mut pool, mut users, events) = setup();
+        // GPU 0 (8 GB) leased; GPU 1 (16 GB) is free and big enough
+        pool.allocate("alice", 0).unwrap();
+
+        let progress = start_drain(&mut pool, &mut users, &events, DrainTarget::Gpu { gpu_id: 0 }, "driver 560", true)
+            .unwrap();
+        assert!(progress.complete);
+        assert_eq!(progress.migrated, vec![LeaseMove { tenant: "alice".into(), from: 0, to: 1 }]);
+        assert_eq!(pool.gpus[&1].allocated_to.as_deref(), Some("alice"));
+        assert!(pool.gpus[&0].allocated_to.is_none());
+        assert!(users.take_notifications("alice")[0].message.contains("moved from GPU 0 to GPU 1"));
+    }
+
+    #[test]
+    fn test_migrated_containers_move_and_block_completion() {
+        let (mut pool, mut users, events) = setup();
+        let card = |address: &str| GPUInfo { id: address.into(), pci_address: Some(address.into()), ..Default::default() };
+        let devices = vec![card("0000:65:00.0"), card("0000:17:00.0")];
+        pool.link_devices(&devices);
+        pool.allocate("alice", 0).unwrap();
+
+        let container = |id: &str, owner: &str, gpu: Option<&str>| ManagedContainer {
+            id: id.into(),
+            name: format!("{}-job", id),
+            image: "pytorch/pytorch:2.3".into(),
+            state: "running".into(),
+            running: true,
+            owner: Some(owner.into()),
+            lease_id: Some("0".into()),
+            project: None,
+            gpu_id: gpu.map(Into::into),
+            gpu_attached: gpu.is_some(),
+            exit_code: None,
+            oom_killed: false,
+            restart_count: 0,
+        };
+        let mut containers = vec![
+            container("train", "alice", Some("0000:65:00.0")),
+            container("notebook", "alice", None),
+            container("stray", "bob", Some("0000:65:00.0")),
+        ];
+
+        let target = DrainTarget::Gpu { gpu_id: 0 };
+        let mut progress = start_drain(&mut pool, &mut users, &events, target, "driver 560", true).unwrap();
+        assert!(progress.complete);
+        let moves = plan_container_moves(&pool, &progress.migrated, &devices, &containers);
+        assert_eq!(moves, vec![ContainerMove { container_id: "train".into(), name: "train-job".into(), to_gpu: "0000:17:00.0".into() }]);
+
+        // Until the move happens both containers keep the drain open
+        add_remaining_containers(&mut progress, &pool, &devices, &containers);
+        assert!(!progress.complete);
+        assert_eq!(progress.remaining_containers.len(), 2);
+
+        containers[0].gpu_id = Some("0000:17:00.0".into());
+        let mut progress = drain_progress(&pool, target).unwrap();
+        add_remaining_containers(&mut progress, &pool, &devices, &containers);
+        assert_eq!(progress.remaining_containers[0].container_id, "stray");
+        assert!(!progress.complete);
+
+        containers.pop();
+        let mut progress = drain_progress(&pool, target).unwrap();
+        add_remaining_containers(&mut progress, &pool, &devices, &containers);
+        assert!(progress.complete);
+    }
+
+    #[test]
+    fn test_lease_without_room_stays_and_blocks_completion() {
+        let (mut pool, mut users, events) = setup();
+        // 16 GB lease can't fit on the 8 GB card
+        pool.allocate("alice", 1).unwrap();
+
+        let progress = start_drain(&mut pool, &mut users, &events, DrainTarget::Gpu { gpu_id: 1 }, "upgrade", true)
+            .unwrap();
+        assert!(!progress.complete);
+        assert_eq!(progress.remaining_leases, vec![LeaseInfo { gpu_id: 1, tenant: "alice".into() }]);
+        assert!(users.take_notifications("alice")[0].message.contains("being drained"));
+
+        pool.release(1).unwrap();
+        assert!(drain_progress(&pool, DrainTarget::Gpu { gpu_id: 1 }).unwrap().complete);
+    }
+
+    #[test]
+    fn test_host_drain_and_undrain() {
+        let (mut pool, mut users, events) = setup();
+        let mut rx = events.subscribe();
+
+        let progress = start_drain(&mut pool, &mut users, &events, DrainTarget::Host, "reboot", false).unwrap();
+        assert_eq!(progress.drained, vec![0, 1]);
+        assert!(progress.complete);
+        assert!(pool.allocate("bob", 0).is_err());
+        assert!(matches!(rx.try_recv().unwrap().kind, EventKind::GpuDrained { gpu_id: 0, .. }));
+
+        let progress = undrain(&mut pool, &events, DrainTarget::Host).unwrap();
+        assert_eq!(progress.active, vec![0, 1]);
+        pool.allocate("bob", 0).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_drain_times_out_then_completes() {
+        let (mut pool, mut users, events) = setup();
+        pool.allocate("alice", 0).unwrap();
+        start_drain(&mut pool, &mut users, &events, DrainTarget::Gpu { gpu_id: 0 }, "upgrade", false).unwrap();
+        let pool = Arc::new(AsyncMutex::new(pool));
+        let target = DrainTarget::Gpu { gpu_id: 0 };
+
+        let progress = wait_for_drain(pool.clone(), target, Duration::from_millis(20), Duration::from_millis(5))
+            .await
+            .unwrap();
+        assert!(!progress.complete);
+
+        let releaser = {
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                pool.lock().await.release(0).unwrap();
+            })
+        };
+        let progress = wait_for_drain(pool, target, Duration::from_secs(5), Duration::from_millis(5))
+            .await
+            .unwrap();
+        releaser.await.unwrap();
+        assert!(progress.complete);
+    }
+}
@@ -0,0 +1,134 @@
+//! Throwaway sysfs trees for unit tests.
+//!
+//! Mirrors just enough of `/sys` (bus/pci/devices, bus/pci/drivers, class/drm,
+//! kernel/iommu_groups) for the scanners and managers that take a sysfs root.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+pub struct FakeSysfs {
+    dir: TempDir,
+}
+
+impl FakeSysfs {
+    pub fn new() -> Self {
+        let sysfs = Self {
+            dir: TempDir::new().expect("failed to create fake sysfs"),
+        };
+        fs::create_dir_all(sysfs.path("bus/pci/devices")).unwrap();
+        fs::create_dir_all(sysfs.path("bus/pci/drivers")).unwrap();
+        fs::create_dir_all(sysfs.path("class/drm")).unwrap();
+        fs::create_dir_all(sysfs.path("kernel/iommu_groups")).unwrap();
+        sysfs
+    }
+
+    pub fn root(&self) -> &Path {
+        self.dir.path()
+    }
+
+    pub fn path(&self, rel: &str) -> PathBuf {
+        self.dir.path().join(rel)
+    }
+
+    /// Writes `contents` to `rel`, creating parent directories on the way
+    pub fn write(&self, rel: &str, contents: &str) {
+        let path = self.path(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    pub fn read(&self, rel: &str) -> String {
+        fs::read_to_string(self.path(rel)).unwrap()
+    }
+
+    pub fn symlink(&self, rel: &str, target: &Path) {
+        let path = self.path(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let _ = fs::remove_file(&path);
+        std::os::unix::fs::symlink(target, path).unwrap();
+    }
+
+    /// Adds a PCI function with the usual identification attributes
+    pub fn add_pci_device(&self, address: &str, vendor: u16, device: u16, class: u32) -> PathBuf {
+        let dev = format!("bus/pci/devices/{}", address);
+        self.write(&format!("{}/vendor", dev), &format!("0x{:04x}\n", vendor));
+        self.write(&format!("{}/device", dev), &format!("0x{:04x}\n", device));
+        self.write(&format!("{}/class", dev), &format!("0x{:06x}\n", class));
+        self.write(&format!("{}/subsystem_vendor", dev), &format!("0x{:04x}\n", vendor));
+        self.write(&format!("{}/subsystem_device", dev), "0x0000\n");
+        self.write(&format!("{}/driver_override", dev), "(null)\n");
+        self.write(&format!("{}/power_state", dev), "D0\n");
+        self.write(
+            &format!("{}/uevent", dev),
+            &format!(
+                "PCI_CLASS={:X}\nPCI_ID={:04X}:{:04X}\nPCI_SLOT_NAME={}\n",
+                class, vendor, device, address
+            ),
+        );
+        self.path(&dev)
+    }
+
+    /// Binds `address` to `driver`, creating the driver directory if needed
+    pub fn bind_driver(&self, address: &str, driver: &str) {
+        let driver_dir = self.path(&format!("bus/pci/drivers/{}", driver));
+        fs::create_dir_all(&driver_dir).unwrap();
+        self.symlink(&format!("bus/pci/devices/{}/driver", address), &driver_dir);
+    }
+
+    pub fn set_iommu_group(&self, address: &str, group: u64) {
+        let group_dir = self.path(&format!("kernel/iommu_groups/{}", group));
+        fs::create_dir_all(group_dir.join("devices")).unwrap();
+        self.symlink(&format!("bus/pci/devices/{}/iommu_group", address), &group_dir);
+        self.symlink(
+            &format!("kernel/iommu_groups/{}/devices/{}", group, address),
+            &self.path(&format!("bus/pci/devices/{}", address)),
+        );
+    }
+
+    /// Registers a DRM card node for the PCI device at `address`
+    pub fn add_drm_card(&self, card: &str, address: &str) {
+        let dev = self.path(&format!("bus/pci/devices/{}", address));
+        fs::create_dir_all(dev.join("drm").join(card)).unwrap();
+        self.symlink(&format!("class/drm/{}/device", card), &dev);
+    }
+
+    /// Gives `pf` an SR-IOV capability with `vfs` enabled as functions of the same class
+    pub fn add_sriov(&self, pf: &str, vf_device: u16, total_vfs: u32, vfs: &[&str]) {
+        let pf_dir = format!("bus/pci/devices/{}", pf);
+        let vendor = fs::read_to_string(self.path(&format!("{}/vendor", pf_dir))).unwrap();
+        let class = fs::read_to_string(self.path(&format!("{}/class", pf_dir))).unwrap();
+        let vendor = u16::from_str_radix(vendor.trim().trim_start_matches("0x"), 16).unwrap();
+        let class = u32::from_str_radix(class.trim().trim_start_matches("0x"), 16).unwrap();
+
+        self.write(&format!("{}/sriov_totalvfs", pf_dir), &format!("{}\n", total_vfs));
+        self.write(&format!("{}/sriov_numvfs", pf_dir), &format!("{}\n", vfs.len()));
+        for (i, vf) in vfs.iter().enumerate() {
+            let vf_path = self.add_pci_device(vf, vendor, vf_device, class);
+            self.symlink(&format!("{}/virtfn{}", pf_dir, i), &vf_path);
+            self.symlink(&format!("bus/pci/devices/{}/physfn", vf), &self.path(&pf_dir));
+        }
+    }
+
+    /// Offers an mdev type on `parent`, the way a vGPU/GVT-g capable driver does
+    pub fn add_mdev_type(&self, parent: &str, type_id: &str, name: Option<&str>, available: u32) {
+        let dir = format!("bus/pci/devices/{}/mdev_supported_types/{}", parent, type_id);
+        if let Some(name) = name {
+            self.write(&format!("{}/name", dir), &format!("{}\n", name));
+        }
+        self.write(&format!("{}/device_api", dir), "vfio-pci\n");
+        self.write(&format!("{}/available_instances", dir), &format!("{}\n", available));
+        fs::create_dir_all(self.path(&format!("{}/devices", dir))).unwrap();
+    }
+
+    /// An existing mediated device, as left behind by a write to `create`
+    pub fn add_mdev(&self, uuid: &str, parent: &str, type_id: &str) {
+        let dev = self.path(&format!("bus/pci/devices/{}/{}", parent, uuid));
+        fs::create_dir_all(&dev).unwrap();
+        self.symlink(
+            &format!("bus/pci/devices/{}/{}/mdev_type", parent, uuid),
+            &self.path(&format!("bus/pci/devices/{}/mdev_supported_types/{}", parent, type_id)),
+        );
+        self.symlink(&format!("bus/mdev/devices/{}", uuid), &dev);
+    }
+}
@@ -0,0 +1,474 @@
+/*
+* GPU health probes & quarantine
+* ------------------------------
+* Periodically checks every physical GPU and quarantines the pool entries on
+* any card that fails, so nobody gets handed a dead device.
+*
+* Probes, per card:
+* - presence:  /sys/bus/pci/devices/<addr> still exists (fell off the bus?)
+* - power:     DeviceManager::verify_power_state, unless runtime PM parked it
+* - BARs:      DeviceManager::verify_memory_bar
+* - NVIDIA:    new fatal Xids in the kernel log, volatile uncorrectable ECC
+*              errors from nvidia-smi
+* - AMD:       uncorrectable RAS errors from ras/umc_err_count
+*
+* Error counters are cumulative since boot/driver load, so only increases
+* since the previous probe count. That way an operator can undrain a card
+* after looking at it without the next probe quarantining it again.
+*/
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Serialize;
+use tracing::{debug, info, warn};
+
+use crate::events::{EventBus, EventKind};
+use crate::gpu::device::{GPUInfo, GPUManager};
+use crate::gpu::nvidia::normalize_pci_bus_id;
+use crate::gpu::passthrough::DeviceManager;
+use crate::gpu::virtual_gpu::GPUPool;
+use crate::users::UserManager;
+use crate::utils::command::{CommandRunner, SystemCommandRunner};
+use crate::AsyncMutex;
+
+/// Xids that mean the hardware (not the application) is in trouble:
+/// double-bit ECC, row remapping failure, NVLink, fell off the bus, ECC
+/// contained/uncontained, GSP errors
+const FATAL_XIDS: &[u32] = &[48, 62, 63, 64, 74, 79, 92, 94, 95, 119, 120];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "issue", rename_all = "snake_case")]
+pub enum HealthIssue {
+    Missing,
+    PowerState { detail: String },
+    MemoryBar { detail: String },
+    Xid { code: u32 },
+    UncorrectableEcc { new_errors: u64 },
+    RasErrors { new_errors: u64 },
+}
+
+impl fmt::Display for HealthIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HealthIssue::Missing => write!(f, "device disappeared from the PCI bus"),
+            HealthIssue::PowerState { detail } => write!(f, "power state: {}", detail),
+            HealthIssue::MemoryBar { detail } => write!(f, "memory BAR: {}", detail),
+            HealthIssue::Xid { code } => write!(f, "Xid {}", code),
+            HealthIssue::UncorrectableEcc { new_errors } => {
+                write!(f, "{} new uncorrectable ECC errors", new_errors)
+            }
+            HealthIssue::RasErrors { new_errors } => {
+                write!(f, "{} new uncorrectable RAS errors", new_errors)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    pub gpu_id: String,
+    pub pci_address: Option<String>,
+    pub issues: Vec<HealthIssue>,
+}
+
+impl HealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    pub fn summary(&self) -> String {
+        self.issues.iter().map(|i| i.to_string()).collect::<Vec<_>>().join("; ")
+    }
+}
+
+pub struct HealthProber {
+    sysfs_root: PathBuf,
+    runner: Arc<dyn CommandRunner>,
+    /// Last seen value of each cumulative counter, keyed "<addr>/<counter>"
+    counters: Mutex<HashMap<String, u64>>,
+}
+
+impl Default for HealthProber {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HealthProber {
+    pub fn new() -> Self {
+        Self::with_parts("/sys", Arc::new(SystemCommandRunner))
+    }
+
+    pub fn with_parts(sysfs_root: impl Into<PathBuf>, runner: Arc<dyn CommandRunner>) -> Self {
+        Self {
+            sysfs_root: sysfs_root.into(),
+            runner,
+            counters: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Runs every probe that applies to `gpu`. Cards without a PCI address
+    /// (e.g. Apple GPUs) can't be probed and always come back healthy.
+    pub fn probe(&self, gpu: &GPUInfo) -> HealthReport {
+        let mut report = HealthReport {
+            gpu_id: gpu.id.clone(),
+            pci_address: gpu.pci_address.clone(),
+            issues: Vec::new(),
+        };
+        let Some(address) = gpu.pci_address.as_deref() else {
+            return report;
+        };
+
+        let device_path = self.sysfs_root.join("bus/pci/devices").join(address);
+        if !device_path.exists() {
+            report.issues.push(HealthIssue::Missing);
+            return report;
+        }
+
+        if let Ok(devices) = DeviceManager::new(&self.sysfs_root) {
+            if !self.runtime_suspended(address) {
+                if let Err(e) = devices.verify_power_state(address) {
+                    report.issues.push(HealthIssue::PowerState { detail: e.to_string() });
+                }
+            }
+            if let Err(e) = devices.verify_memory_bar(address) {
+                report.issues.push(HealthIssue::MemoryBar { detail: e.to_string() });
+            }
+        }
+
+        match gpu.vendor.as_str() {
+            "NVIDIA" => {
+                report.issues.extend(self.new_xids(address));
+                if let Some(new_errors) = self.nvidia_ecc_delta(address) {
+                    report.issues.push(HealthIssue::UncorrectableEcc { new_errors });
+                }
+            }
+            "AMD" => {
+                if let Some(new_errors) = self.amd_ras_delta(address) {
+                    report.issues.push(HealthIssue::RasErrors { new_errors });
+                }
+            }
+            _ => {}
+        }
+        report
+    }
+
+    /// Runtime PM puts idle devices in D3hot on purpose - that's not a fault
+    fn runtime_suspended(&self, address: &str) -> bool {
+        fs::read_to_string(self.sysfs_root.join("bus/pci/devices").join(address).join("power/runtime_status"))
+            .is_ok_and(|s| s.trim() == "suspended")
+    }
+
+    /// Fatal Xids for `address` logged since the previous probe
+    fn new_xids(&self, address: &str) -> Vec<HealthIssue> {
+        let log = match self.runner.run("dmesg", &[]) {
+            Ok(log) => log,
+            Err(e) => {
+                debug!("Cannot read kernel log for Xids: {}", e);
+                return Vec::new();
+            }
+        };
+        let xids: Vec<u32> = parse_xids(&log)
+            .into_iter()
+            .filter(|(addr, _)| addr == address)
+            .map(|(_, code)| code)
+            .collect();
+
+        let key = format!("{}/xid", address);
+        let first = !self.counters.lock().unwrap().contains_key(&key);
+        let seen = self.swap_counter(&key, xids.len() as u64) as usize;
+        if first {
+            return Vec::new();
+        }
+        // A rotated ring buffer shrinks the count - then nothing here is new
+        xids.into_iter()
+            .skip(seen)
+            .filter(|code| FATAL_XIDS.contains(code))
+            .map(|code| HealthIssue::Xid { code })
+            .collect()
+    }
+
+    fn nvidia_ecc_delta(&self, address: &str) -> Option<u64> {
+        let output = self
+            .runner
+            .run(
+                "nvidia-smi",
+                &["--query-gpu=ecc.errors.uncorrected.volatile.total", "--format=csv,noheader,nounits", "-i", address],
+            )
+            .ok()?;
+        // "[N/A]" on cards without ECC
+        let total: u64 = output.trim().parse().ok()?;
+        self.counter_delta(&format!("{}/ecc", address), total)
+    }
+
+    /// amdgpu's ras/umc_err_count reads "ue: N\nce: M"
+    fn amd_ras_delta(&self, address: &str) -> Option<u64> {
+        let path = self.sysfs_root.join("bus/pci/devices").join(address).join("ras/umc_err_count");
+        let contents = fs::read_to_string(path).ok()?;
+        let total: u64 = contents
+            .lines()
+            .find_map(|l| l.strip_prefix("ue:"))
+            .and_then(|v| v.trim().parse().ok())?;
+        self.counter_delta(&format!("{}/ras", address), total)
+    }
+
+    /// Increase since the last reading; the first reading is the baseline
+    fn counter_delta(&self, key: &str, total: u64) -> Option<u64> {
+        let first = !self.counters.lock().unwrap().contains_key(key);
+        let previous = self.swap_counter(key, total);
+        (!first && total > previous).then(|| total - previous)
+    }
+
+    fn swap_counter(&self, key: &str, value: u64) -> u64 {
+        self.counters.lock().unwrap().insert(key.to_string(), value).unwrap_or(0)
+    }
+}
+
+/// `NVRM: Xid (PCI:0000:65:00): 79, pid=..., GPU has fallen off the bus.`
+/// -> ("0000:65:00.0", 79). The driver logs the device without its function.
+pub fn parse_xids(log: &str) -> Vec<(String, u32)> {
+    log.lines()
+        .filter_map(|line| {
+            let rest = line.split("NVRM: Xid (PCI:").nth(1)?;
+            let (bus_id, rest) = rest.split_once("):")?;
+            let code = rest.trim().split(|c: char| !c.is_ascii_digit()).next()?.parse().ok()?;
+            Some((format!("{}.0", normalize_pci_bus_id(bus_id)), code))
+        })
+        .collect()
+}
+
+/// Probes every GPU once and quarantines the pool entries on failing cards.
+/// Tenants holding one of those entries get a notification.
+pub fn check_once(
+    prober: &HealthProber,
+    gpu_manager: &GPUManager,
+    pool: &mut GPUPool,
+    users: &mut UserManager,
+    events: &EventBus,
+) -> Vec<HealthReport> {
+    let reports: Vec<HealthReport> = gpu_manager.devices.iter().map(|gpu| prober.probe(gpu)).collect();
+    apply_reports(&gpu_manager.devices, &reports, pool, users, events);
+    reports
+}
+
+/// Quarantine half of `check_once`, for probes that ran without the locks
+pub fn apply_reports(
+    gpus: &[GPUInfo],
+    reports: &[HealthReport],
+    pool: &mut GPUPool,
+    users: &mut UserManager,
+    events: &EventBus,
+) {
+    for (gpu, report) in gpus.iter().zip(reports) {
+        if report.is_healthy() {
+            continue;
+        }
+        let reason = report.summary();
+        for id in pool.entries_on(gpu) {
+            if !pool.quarantine(id, &reason).unwrap_or(false) {
+                continue;
+            }
+            warn!("Quarantined GPU {} ({}): {}", id, gpu.id, reason);
+            let tenant = pool.gpus[&id].allocated_to.clone();
+            if let Some(tenant) = &tenant {
+                let message = format!(
+                    "GPU {} you are renting failed a health check ({}) - save your work, it may stop responding",
+                    id, reason
+                );
+                if let Err(e) = users.notify(tenant, message) {
+                    warn!("Could not notify {}: {}", tenant, e);
+                }
+            }
+            events.publish(EventKind::GpuQuarantined { gpu_id: id, tenant, reason: reason.clone() });
+        }
+    }
+}
+
+/// Background loop around `check_once`. Probes shell out and read sysfs, so
+/// they run on the blocking pool without any of the locks held.
+pub async fn run_health_monitor(
+    prober: HealthProber,
+    interval: Duration,
+    gpu_manager: Arc<AsyncMutex<GPUManager>>,
+    pool: Arc<AsyncMutex<GPUPool>>,
+    users: Arc<AsyncMutex<UserManager>>,
+    events: Arc<EventBus>,
+) {
+    info!("GPU health monitor running every {:?}", interval);
+    let prober = Arc::new(prober);
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let gpus = gpu_manager.lock().await.devices.clone();
+        let probe = {
+            let prober = prober.clone();
+            tokio::task::spawn_blocking(move || {
+                let reports: Vec<HealthReport> = gpus.iter().map(|gpu| prober.probe(gpu)).collect();
+                (gpus, reports)
+            })
+        };
+        let (gpus, reports) = match probe.await {
+            Ok(probed) => probed,
+            Err(e) => {
+                warn!("GPU health probe failed: {}", e);
+                continue;
+            }
+        };
+
+        let mut pool = pool.lock().await;
+        let mut users = users.lock().await;
+        apply_reports(&gpus, &reports, &mut pool, &mut users, &events);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu::fake_sysfs::FakeSysfs;
+    use crate::gpu::pci::PCI_VENDOR_NVIDIA;
+    use std::collections::HashMap;
+
+    const GPU: &str = "0000:65:00.0";
+
+    /// dmesg and nvidia-smi output that can change between probes
+    struct KernelLog {
+        dmesg: Mutex<String>,
+        ecc: Mutex<String>,
+    }
+
+    impl CommandRunner for KernelLog {
+        fn run(&self, program: &str, _args: &[&str]) -> anyhow::Result<String> {
+            match program {
+                "dmesg" => Ok(self.dmesg.lock().unwrap().clone()),
+                "nvidia-smi" => Ok(self.ecc.lock().unwrap().clone()),
+                other => Err(anyhow::anyhow!("unexpected {}", other)),
+            }
+        }
+    }
+
+    fn healthy_card(sysfs: &FakeSysfs) {
+        sysfs.add_pci_device(GPU, PCI_VENDOR_NVIDIA, 0x2684, 0x030000);
+        sysfs.write(
+            &format!("bus/pci/devices/{}/resource", GPU),
+            "0x00000000fb000000 0x00000000fbffffff 0x0000000000040200\n",
+        );
+    }
+
+    fn nvidia_gpu() -> GPUInfo {
+        GPUInfo {
+            id: "GPU-1234".into(),
+            vendor: "NVIDIA".into(),
+            pci_address: Some(GPU.into()),
+            ..Default::default()
+        }
+    }
+
+    fn prober(sysfs: &FakeSysfs, log: Arc<KernelLog>) -> HealthProber {
+        HealthProber::with_parts(sysfs.root(), log)
+    }
+
+    fn log(dmesg: &str, ecc: &str) -> Arc<KernelLog> {
+        Arc::new(KernelLog { dmesg: Mutex::new(dmesg.into()), ecc: Mutex::new(ecc.into()) })
+    }
+
+    #[test]
+    fn test_parse_xids() {
+        let log = "[  12.1] NVRM: loading NVIDIA UNIX x86_64 Kernel Module\n\
+                   [ 901.5] NVRM: Xid (PCI:0000:65:00): 79, pid=1234, name=python, GPU has fallen off the bus.\n\
+                   [ 902.0] NVRM: Xid (PCI:0000:17:00): 13, Graphics SM Warp Exception\n";
+        assert_eq!(
+            parse_xids(log),
+            vec![("0000:65:00.0".to_string(), 79), ("0000:17:00.0".to_string(), 13)]
+        );
+    }
+
+    #[test]
+    fn test_healthy_card_then_new_fatal_xid() {
+        let sysfs = FakeSysfs::new();
+        healthy_card(&sysfs);
+        let kernel = log("NVRM: Xid (PCI:0000:65:00): 79, pid=1, old news\n", "0\n");
+        let prober = prober(&sysfs, kernel.clone());
+
+        // Xids and ECC from before the first probe are the baseline
+        assert!(prober.probe(&nvidia_gpu()).is_healthy());
+
+        kernel.dmesg.lock().unwrap().push_str(
+            "NVRM: Xid (PCI:0000:65:00): 13, app fault\nNVRM: Xid (PCI:0000:65:00): 48, DBE\n",
+        );
+        *kernel.ecc.lock().unwrap() = "2\n".into();
+        let report = prober.probe(&nvidia_gpu());
+        assert_eq!(
+            report.issues,
+            vec![HealthIssue::Xid { code: 48 }, HealthIssue::UncorrectableEcc { new_errors: 2 }]
+        );
+
+        // Nothing new since - healthy again
+        assert!(prober.probe(&nvidia_gpu()).is_healthy());
+    }
+
+    #[test]
+    fn test_missing_and_suspended_devices() {
+        let sysfs = FakeSysfs::new();
+        let prober = prober(&sysfs, log("", "[N/A]\n"));
+        assert_eq!(prober.probe(&nvidia_gpu()).issues, vec![HealthIssue::Missing]);
+
+        healthy_card(&sysfs);
+        sysfs.write(&format!("bus/pci/devices/{}/power_state", GPU), "D3hot\n");
+        assert!(matches!(prober.probe(&nvidia_gpu()).issues[..], [HealthIssue::PowerState { .. }]));
+
+        sysfs.write(&format!("bus/pci/devices/{}/power/runtime_status", GPU), "suspended\n");
+        assert!(prober.probe(&nvidia_gpu()).is_healthy());
+    }
+
+    #[test]
+    fn test_amd_ras_counter() {
+        let sysfs = FakeSysfs::new();
+        healthy_card(&sysfs);
+        sysfs.write(&format!("bus/pci/devices/{}/ras/umc_err_count", GPU), "ue: 1\nce: 40\n");
+        let prober = prober(&sysfs, log("", ""));
+        let amd = GPUInfo { vendor: "AMD".into(), ..nvidia_gpu() };
+
+        assert!(prober.probe(&amd).is_healthy());
+        sysfs.write(&format!("bus/pci/devices/{}/ras/umc_err_count", GPU), "ue: 4\nce: 41\n");
+        assert_eq!(prober.probe(&amd).issues, vec![HealthIssue::RasErrors { new_errors: 3 }]);
+    }
+
+    #[test]
+    fn test_check_once_quarantines_and_notifies() {
+        let sysfs = FakeSysfs::new(); // card missing entirely
+        let prober = prober(&sysfs, log("", ""));
+        let manager = GPUManager { devices: vec![nvidia_gpu()], iommu_groups: HashMap::new() };
+        let mut pool = GPUPool::new();
+        assert_eq!(pool.link_devices(&manager.devices), vec![0]);
+        pool.allocate("alice", 0).unwrap();
+        let mut users = UserManager::new();
+        let events = EventBus::new();
+        let mut rx = events.subscribe();
+
+        let reports = check_once(&prober, &manager, &mut pool, &mut users, &events);
+        assert!(!reports[0].is_healthy());
+        assert!(!pool.gpus[&0].state.is_active());
+        assert!(pool.gpus[&1].state.is_active());
+        assert!(pool.allocate("bob", 0).is_err());
+
+        let notes = users.take_notifications("alice");
+        assert_eq!(notes.len(), 1);
+        assert!(notes[0].message.contains("disappeared from the PCI bus"));
+        match rx.try_recv().unwrap().kind {
+            EventKind::GpuQuarantined { gpu_id, tenant, .. } => {
+                assert_eq!(gpu_id, 0);
+                assert_eq!(tenant.as_deref(), Some("alice"));
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+
+        // Second failing probe doesn't spam the tenant
+        check_once(&prober, &manager, &mut pool, &mut users, &events);
+        assert!(users.take_notifications("alice").is_empty());
+    }
+}
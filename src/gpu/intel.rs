@@ -0,0 +1,250 @@
+/*
+* Intel i915 / xe support
+* -----------------------
+* Discovery itself is the generic PCI scan (see `pci.rs`); this module covers
+* what is specific to Intel's two DRM drivers:
+*
+* - VRAM: xe reports it per tile in `tileN/physical_vram_size_bytes` (dGPUs
+*   only - iGPUs have no local memory), i915 doesn't put it in sysfs at all
+* - frequency: i915 `drm/cardN/gt_{act,max}_freq_mhz`,
+*   xe `tile0/gt0/freq0/{act,max}_freq`
+* - power: the hwmon node only has a cumulative `energy1_input` (µJ), so power
+*   is the energy delta between two samples. First sample reports nothing.
+*
+* Busy % needs the i915/xe PMU (perf), which we don't touch - utilization stays 0.
+*/
+
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+use crate::gpu::pci::{find_drm_card, read_link_name};
+use crate::monitoring::metrics::GPUMetrics;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntelDriver {
+    I915,
+    Xe,
+}
+
+impl IntelDriver {
+    pub fn from_name(driver: &str) -> Option<Self> {
+        match driver {
+            "i915" => Some(IntelDriver::I915),
+            "xe" => Some(IntelDriver::Xe),
+            _ => None,
+        }
+    }
+}
+
+/// Sum of local memory over all tiles of an xe device; `None` for iGPUs and i915
+pub fn read_xe_vram_mb(device_path: &Path) -> Option<u64> {
+    let total: u64 = fs::read_dir(device_path)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name().to_string_lossy().starts_with("tile"))
+        .filter_map(|e| fs::read_to_string(e.path().join("physical_vram_size_bytes")).ok())
+        .filter_map(|raw| parse_number(&raw))
+        .sum();
+
+    (total > 0).then_some(total / 1024 / 1024)
+}
+
+/// Average power between two `energy1_input` samples (µJ). `None` if the
+/// counter went backwards (wrap, driver reload) or no time has passed.
+pub fn power_from_energy(prev: (Instant, u64), now: (Instant, u64)) -> Option<f64> {
+    let elapsed = now.0.checked_duration_since(prev.0)?.as_secs_f64();
+    let delta_uj = now.1.checked_sub(prev.1)?;
+    (elapsed > 0.0).then(|| delta_uj as f64 / 1_000_000.0 / elapsed)
+}
+
+/// Telemetry reader - keeps the last energy sample per GPU so repeated
+/// `collect` calls turn the energy counter into watts
+pub struct IntelTelemetry {
+    sysfs_root: PathBuf,
+    energy_samples: Mutex<HashMap<String, (Instant, u64)>>,
+}
+
+impl IntelTelemetry {
+    pub fn new() -> Self {
+        Self::with_root("/sys")
+    }
+
+    pub fn with_root(sysfs_root: impl Into<PathBuf>) -> Self {
+        Self {
+            sysfs_root: sysfs_root.into(),
+            energy_samples: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Process-wide reader over the real `/sys`, so power samples survive between calls
+    pub fn global() -> &'static IntelTelemetry {
+        static TELEMETRY: OnceLock<IntelTelemetry> = OnceLock::new();
+        TELEMETRY.get_or_init(IntelTelemetry::new)
+    }
+
+    pub fn collect(&self, pci_address: &str, vram_total_mb: u64) -> Result<GPUMetrics> {
+        let path = self.sysfs_root.join("bus/pci/devices").join(pci_address);
+        if !path.exists() {
+            return Err(anyhow!("No such PCI device: {}", pci_address));
+        }
+
+        let driver = read_link_name(&path.join("driver"))
+            .as_deref()
+            .and_then(IntelDriver::from_name)
+            .ok_or_else(|| anyhow!("{} is not bound to i915 or xe", pci_address))?;
+
+        let (clock_mhz, max_clock_mhz) = match driver {
+            IntelDriver::I915 => match find_drm_card(&path) {
+                Some(card) => {
+                    let card = path.join("drm").join(card);
+                    (read_u64(&card.join("gt_act_freq_mhz")), read_u64(&card.join("gt_max_freq_mhz")))
+                }
+                None => (None, None),
+            },
+            IntelDriver::Xe => {
+                let freq = path.join("tile0/gt0/freq0");
+                (read_u64(&freq.join("act_freq")), read_u64(&freq.join("max_freq")))
+            }
+        };
+
+        let hwmon = find_hwmon(&path);
+        let temperature_celsius = hwmon
+            .as_deref()
+            .and_then(|h| read_u64(&h.join("temp1_input")))
+            .map(|millidegrees| (millidegrees / 1000) as i32)
+            .unwrap_or(0);
+        let power_usage_watts = hwmon
+            .as_deref()
+            .and_then(|h| read_u64(&h.join("energy1_input")))
+            .and_then(|energy| self.sample_power(pci_address, energy))
+            .unwrap_or(0.0);
+
+        Ok(GPUMetrics {
+            utilization_percent: 0.0,
+            memory_used_mb: 0,
+            memory_total_mb: vram_total_mb,
+            temperature_celsius,
+            power_usage_watts,
+            clock_mhz: clock_mhz.map(|c| c as u32),
+            max_clock_mhz: max_clock_mhz.map(|c| c as u32),
+        })
+    }
+
+    fn sample_power(&self, pci_address: &str, energy_uj: u64) -> Option<f64> {
+        let now = (Instant::now(), energy_uj);
+        let mut samples = self.energy_samples.lock().unwrap();
+        let prev = samples.insert(pci_address.to_string(), now)?;
+        power_from_energy(prev, now)
+    }
+}
+
+impl Default for IntelTelemetry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn find_hwmon(device_path: &Path) -> Option<PathBuf> {
+    fs::read_dir(device_path.join("hwmon"))
+        .ok()?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .next()
+}
+
+fn read_u64(path: &Path) -> Option<u64> {
+    parse_number(&fs::read_to_string(path).ok()?)
+}
+
+fn parse_number(raw: &str) -> Option<u64> {
+    let raw = raw.trim();
+    match raw.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => raw.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu::fake_sysfs::FakeSysfs;
+    use crate::gpu::pci::{PciScanner, PCI_VENDOR_INTEL};
+    use std::time::Duration;
+
+    fn raptor_lake_igpu(sysfs: &FakeSysfs) {
+        sysfs.add_pci_device("0000:00:02.0", PCI_VENDOR_INTEL, 0xa780, 0x030000);
+        sysfs.bind_driver("0000:00:02.0", "i915");
+        sysfs.add_drm_card("card0", "0000:00:02.0");
+        sysfs.write("bus/pci/devices/0000:00:02.0/drm/card0/gt_act_freq_mhz", "1450\n");
+        sysfs.write("bus/pci/devices/0000:00:02.0/drm/card0/gt_max_freq_mhz", "1650\n");
+    }
+
+    fn battlemage_dgpu(sysfs: &FakeSysfs) {
+        let dev = "bus/pci/devices/0000:03:00.0";
+        sysfs.add_pci_device("0000:03:00.0", PCI_VENDOR_INTEL, 0xe20b, 0x030000);
+        sysfs.bind_driver("0000:03:00.0", "xe");
+        sysfs.add_drm_card("card1", "0000:03:00.0");
+        sysfs.write(&format!("{}/tile0/physical_vram_size_bytes", dev), "0x0000000300000000\n");
+        sysfs.write(&format!("{}/tile0/gt0/freq0/act_freq", dev), "2850\n");
+        sysfs.write(&format!("{}/tile0/gt0/freq0/max_freq", dev), "2850\n");
+        sysfs.write(&format!("{}/hwmon/hwmon3/energy1_input", dev), "5000000\n");
+    }
+
+    #[test]
+    fn test_integrated_i915_discovery_and_clocks() {
+        let sysfs = FakeSysfs::new();
+        raptor_lake_igpu(&sysfs);
+
+        let gpu = PciScanner::with_root(sysfs.root()).read_device("0000:00:02.0").unwrap().unwrap();
+        assert_eq!(gpu.model, "Raptor Lake-S GT1 [UHD Graphics 770]");
+        assert_eq!(gpu.vram_mb, None);
+
+        let metrics = IntelTelemetry::with_root(sysfs.root()).collect("0000:00:02.0", 0).unwrap();
+        assert_eq!(metrics.clock_mhz, Some(1450));
+        assert_eq!(metrics.max_clock_mhz, Some(1650));
+        assert_eq!(metrics.power_usage_watts, 0.0);
+    }
+
+    #[test]
+    fn test_discrete_xe_vram_and_clocks() {
+        let sysfs = FakeSysfs::new();
+        battlemage_dgpu(&sysfs);
+
+        let gpu = PciScanner::with_root(sysfs.root()).read_device("0000:03:00.0").unwrap().unwrap();
+        assert_eq!(gpu.model, "Battlemage G21 [Arc B580]");
+        assert_eq!(gpu.vram_mb, Some(12288));
+
+        let telemetry = IntelTelemetry::with_root(sysfs.root());
+        let metrics = telemetry.collect("0000:03:00.0", 12288).unwrap();
+        assert_eq!(metrics.clock_mhz, Some(2850));
+        assert_eq!(metrics.memory_total_mb, 12288);
+        // Only one energy sample so far - no power figure yet
+        assert_eq!(metrics.power_usage_watts, 0.0);
+    }
+
+    #[test]
+    fn test_power_from_energy_counter() {
+        let start = Instant::now();
+        let later = start + Duration::from_secs(2);
+
+        assert_eq!(power_from_energy((start, 10_000_000), (later, 70_000_000)), Some(30.0));
+        // Counter wrapped or reset
+        assert_eq!(power_from_energy((start, 70_000_000), (later, 10_000_000)), None);
+        assert_eq!(power_from_energy((start, 0), (start, 1_000)), None);
+    }
+
+    #[test]
+    fn test_unbound_device_has_no_telemetry() {
+        let sysfs = FakeSysfs::new();
+        sysfs.add_pci_device("0000:03:00.0", PCI_VENDOR_INTEL, 0x56a0, 0x030000);
+        sysfs.bind_driver("0000:03:00.0", "vfio-pci");
+
+        let telemetry = IntelTelemetry::with_root(sysfs.root());
+        assert!(telemetry.collect("0000:03:00.0", 0).is_err());
+        assert!(telemetry.collect("0000:09:00.0", 0).is_err());
+    }
+}
@@ -0,0 +1,438 @@
+/*
+* IOMMU readiness diagnostics
+* ---------------------------
+* One place that answers "can this host do VFIO passthrough, and if not, why?"
+* using only world-readable files - no dmesg, no root:
+*
+* - /sys/kernel/iommu_groups  populated only when an IOMMU is actually active
+* - /sys/class/iommu          the hardware units (dmar* = Intel VT-d, ivhd* = AMD-Vi)
+* - /proc/cmdline             intel_iommu=, amd_iommu=, iommu=pt, pcie_acs_override=
+* - /sys/module/vfio*         whether the vfio stack is loaded (or built in)
+* - PCI bridges' config space ACS extended capability, where readable. Without
+*   root only the first 64 bytes are visible and ACS shows up as "unknown".
+*
+* The resulting `IommuReport` is what the CLI, the API and startup checks print.
+*/
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// PCIe extended capability id for Access Control Services
+const PCI_EXT_CAP_ID_ACS: u32 = 0x000d;
+/// ACS control bits that matter for isolation: source validation, P2P request
+/// redirect, P2P completion redirect, upstream forwarding. The kernel only
+/// trusts a bridge with all four set.
+const ACS_ISOLATION_BITS: u16 = 0x001d;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Readiness {
+    /// Passthrough will work
+    Ready,
+    /// Passthrough works, but something is suboptimal or risky
+    Degraded,
+    /// Passthrough cannot work on this host as configured
+    NotReady,
+}
+
+/// IOMMU-related kernel parameters from `/proc/cmdline`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KernelCmdline {
+    pub intel_iommu: Option<String>,
+    pub amd_iommu: Option<String>,
+    pub iommu: Option<String>,
+    pub pcie_acs_override: Option<String>,
+}
+
+impl KernelCmdline {
+    pub fn parse(cmdline: &str) -> Self {
+        let mut parsed = Self::default();
+        for arg in cmdline.split_whitespace() {
+            let Some((key, value)) = arg.split_once('=') else { continue };
+            let slot = match key {
+                "intel_iommu" => &mut parsed.intel_iommu,
+                "amd_iommu" => &mut parsed.amd_iommu,
+                "iommu" => &mut parsed.iommu,
+                "pcie_acs_override" => &mut parsed.pcie_acs_override,
+                _ => continue,
+            };
+            // Last occurrence wins, same as the kernel
+            *slot = Some(value.to_string());
+        }
+        parsed
+    }
+
+    /// `iommu=pt` (or `amd_iommu=pt`/`intel_iommu=...,pt`): host devices skip translation
+    pub fn passthrough_mode(&self) -> bool {
+        [&self.iommu, &self.amd_iommu, &self.intel_iommu]
+            .iter()
+            .any(|v| v.as_deref().is_some_and(|v| v.split(',').any(|o| o == "pt")))
+    }
+
+    /// Explicitly switched off on the command line
+    pub fn iommu_disabled(&self) -> bool {
+        [&self.intel_iommu, &self.amd_iommu, &self.iommu]
+            .iter()
+            .any(|v| v.as_deref().is_some_and(|v| v.split(',').any(|o| o == "off")))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VfioModules {
+    pub vfio: bool,
+    pub vfio_pci: bool,
+    pub vfio_iommu_type1: bool,
+}
+
+impl VfioModules {
+    pub fn all_loaded(&self) -> bool {
+        self.vfio && self.vfio_pci && self.vfio_iommu_type1
+    }
+}
+
+/// ACS state of one PCI bridge. `None` means config space wasn't readable.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AcsStatus {
+    pub address: String,
+    pub supported: Option<bool>,
+    pub enabled: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IommuReport {
+    pub readiness: Readiness,
+    pub group_count: usize,
+    /// `dmar0`, `ivhd0`, ...
+    pub iommu_units: Vec<String>,
+    pub cmdline: KernelCmdline,
+    pub vfio_modules: VfioModules,
+    pub bridges: Vec<AcsStatus>,
+    /// Things that stop passthrough outright
+    pub errors: Vec<String>,
+    /// Things that work but deserve attention
+    pub warnings: Vec<String>,
+}
+
+impl IommuReport {
+    pub fn iommu_enabled(&self) -> bool {
+        self.group_count > 0
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.readiness != Readiness::NotReady
+    }
+
+    /// Logs the report once, e.g. at startup
+    pub fn log_summary(&self) {
+        match self.readiness {
+            Readiness::Ready => info!("IOMMU ready: {} groups on {:?}", self.group_count, self.iommu_units),
+            Readiness::Degraded => info!("IOMMU usable with warnings: {} groups", self.group_count),
+            Readiness::NotReady => warn!("GPU passthrough unavailable"),
+        }
+        for error in &self.errors {
+            warn!("IOMMU: {}", error);
+        }
+        for warning in &self.warnings {
+            warn!("IOMMU: {}", warning);
+        }
+    }
+}
+
+pub struct IommuDiagnostics {
+    sysfs_root: PathBuf,
+    proc_root: PathBuf,
+}
+
+impl Default for IommuDiagnostics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IommuDiagnostics {
+    pub fn new() -> Self {
+        Self::with_roots("/sys", "/proc")
+    }
+
+    pub fn with_roots(sysfs_root: impl Into<PathBuf>, proc_root: impl Into<PathBuf>) -> Self {
+        Self {
+            sysfs_root: sysfs_root.into(),
+            proc_root: proc_root.into(),
+        }
+    }
+
+    pub fn sysfs_root(&self) -> &Path {
+        &self.sysfs_root
+    }
+
+    pub fn report(&self) -> IommuReport {
+        let group_count = count_entries(&self.sysfs_root.join("kernel/iommu_groups"));
+        let iommu_units = list_entries(&self.sysfs_root.join("class/iommu"));
+        let cmdline = fs::read_to_string(self.proc_root.join("cmdline"))
+            .map(|c| KernelCmdline::parse(&c))
+            .unwrap_or_default();
+        let vfio_modules = VfioModules {
+            vfio: self.module_loaded("vfio"),
+            vfio_pci: self.module_loaded("vfio_pci"),
+            vfio_iommu_type1: self.module_loaded("vfio_iommu_type1"),
+        };
+        let bridges = self.bridge_acs();
+
+        let mut errors = Vec::new();
+        let mut warnings = Vec::new();
+
+        if group_count == 0 {
+            errors.push(if cmdline.iommu_disabled() {
+                "IOMMU is switched off on the kernel command line".to_string()
+            } else if cmdline.intel_iommu.is_none() && cmdline.amd_iommu.is_none() && iommu_units.is_empty() {
+                "No IOMMU groups - enable VT-d/AMD-Vi in firmware and add intel_iommu=on (Intel) to the kernel command line".to_string()
+            } else {
+                "No IOMMU groups in /sys/kernel/iommu_groups - the IOMMU is not active".to_string()
+            });
+        }
+        if !vfio_modules.vfio_pci {
+            errors.push("vfio-pci is not loaded - run `modprobe vfio-pci`".to_string());
+        } else if !vfio_modules.all_loaded() {
+            warnings.push("vfio stack partially loaded (need vfio, vfio_pci, vfio_iommu_type1)".to_string());
+        }
+        if group_count > 0 && !cmdline.passthrough_mode() {
+            warnings.push("iommu=pt not set - host devices pay for DMA translation too".to_string());
+        }
+        if let Some(mode) = &cmdline.pcie_acs_override {
+            warnings.push(format!(
+                "pcie_acs_override={} is set - IOMMU groups may not reflect real isolation",
+                mode
+            ));
+        }
+        let without_acs: Vec<&str> = bridges
+            .iter()
+            .filter(|b| b.enabled == Some(false))
+            .map(|b| b.address.as_str())
+            .collect();
+        if !without_acs.is_empty() {
+            warnings.push(format!(
+                "Bridges without ACS isolation: {} - devices behind them share IOMMU groups",
+                without_acs.join(", ")
+            ));
+        }
+
+        let readiness = if !errors.is_empty() {
+            Readiness::NotReady
+        } else if !warnings.is_empty() {
+            Readiness::Degraded
+        } else {
+            Readiness::Ready
+        };
+
+        IommuReport {
+            readiness,
+            group_count,
+            iommu_units,
+            cmdline,
+            vfio_modules,
+            bridges,
+            errors,
+            warnings,
+        }
+    }
+
+    /// Loadable modules and built-ins with parameters both show up under /sys/module
+    fn module_loaded(&self, name: &str) -> bool {
+        self.sysfs_root.join("module").join(name).exists()
+    }
+
+    /// ACS state of every PCI-to-PCI bridge (class 0x0604) on the bus
+    fn bridge_acs(&self) -> Vec<AcsStatus> {
+        let devices_dir = self.sysfs_root.join("bus/pci/devices");
+        let mut bridges: Vec<AcsStatus> = list_entries(&devices_dir)
+            .into_iter()
+            .filter(|addr| {
+                fs::read_to_string(devices_dir.join(addr).join("class"))
+                    .is_ok_and(|c| c.trim().starts_with("0x0604"))
+            })
+            .map(|address| {
+                let acs = fs::read(devices_dir.join(&address).join("config"))
+                    .ok()
+                    .and_then(|config| read_acs_control(&config));
+                AcsStatus {
+                    supported: acs.map(|control| control.is_some()),
+                    enabled: acs.map(|control| control.is_some_and(|c| c & ACS_ISOLATION_BITS == ACS_ISOLATION_BITS)),
+                    address,
+                }
+            })
+            .collect();
+        bridges.sort_by(|a, b| a.address.cmp(&b.address));
+        bridges
+    }
+}
+
+/// Walks the PCIe extended capability list for ACS.
+/// Outer `None`: extended config space not readable (non-root, or not PCIe);
+/// inner `None`: readable but no ACS capability; otherwise the ACS control register.
+pub fn read_acs_control(config: &[u8]) -> Option<Option<u16>> {
+    if config.len() < 0x104 {
+        return None;
+    }
+    let read_u32 = |offset: usize| -> Option<u32> {
+        config.get(offset..offset + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+
+    let mut offset = 0x100;
+    // 4K config space has room for at most ~1000 capabilities; stop loops early
+    for _ in 0..64 {
+        let header = read_u32(offset)?;
+        if header == 0 || header == 0xffff_ffff {
+            return Some(None);
+        }
+        if header & 0xffff == PCI_EXT_CAP_ID_ACS {
+            let control = config.get(offset + 6..offset + 8)?;
+            return Some(Some(u16::from_le_bytes([control[0], control[1]])));
+        }
+        let next = ((header >> 20) & 0xffc) as usize;
+        if next < 0x100 {
+            return Some(None);
+        }
+        offset = next;
+    }
+    Some(None)
+}
+
+fn list_entries(dir: &Path) -> Vec<String> {
+    let mut entries: Vec<String> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .collect()
+        })
+        .unwrap_or_default();
+    entries.sort();
+    entries
+}
+
+fn count_entries(dir: &Path) -> usize {
+    fs::read_dir(dir).map(|entries| entries.count()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu::fake_sysfs::FakeSysfs;
+
+    const BRIDGE: &str = "0000:00:01.0";
+
+    fn diagnostics(sysfs: &FakeSysfs) -> IommuDiagnostics {
+        IommuDiagnostics::with_roots(sysfs.root(), sysfs.path("proc"))
+    }
+
+    /// 4K config space with an AER capability at 0x100 chaining to ACS at 0x148
+    fn config_with_acs(control: u16) -> Vec<u8> {
+        let mut config = vec![0u8; 4096];
+        let aer: u32 = 0x0001 | (1 << 16) | (0x148 << 20);
+        config[0x100..0x104].copy_from_slice(&aer.to_le_bytes());
+        let acs: u32 = PCI_EXT_CAP_ID_ACS | (1 << 16);
+        config[0x148..0x14c].copy_from_slice(&acs.to_le_bytes());
+        config[0x14e..0x150].copy_from_slice(&control.to_le_bytes());
+        config
+    }
+
+    fn healthy_host(sysfs: &FakeSysfs) {
+        sysfs.write("proc/cmdline", "BOOT_IMAGE=/vmlinuz root=/dev/nvme0n1p2 ro intel_iommu=on iommu=pt quiet\n");
+        fs::create_dir_all(sysfs.path("kernel/iommu_groups/0/devices")).unwrap();
+        fs::create_dir_all(sysfs.path("kernel/iommu_groups/1/devices")).unwrap();
+        sysfs.write("class/iommu/dmar0/devices/.keep", "");
+        for module in ["vfio", "vfio_pci", "vfio_iommu_type1"] {
+            sysfs.write(&format!("module/{}/refcnt", module), "0\n");
+        }
+        sysfs.add_pci_device(BRIDGE, 0x8086, 0xa70d, 0x060400);
+    }
+
+    #[test]
+    fn test_cmdline_parsing() {
+        let cmdline = KernelCmdline::parse("ro amd_iommu=on iommu=pt pcie_acs_override=downstream,multifunction");
+        assert_eq!(cmdline.amd_iommu.as_deref(), Some("on"));
+        assert!(cmdline.passthrough_mode());
+        assert!(!cmdline.iommu_disabled());
+        assert_eq!(cmdline.pcie_acs_override.as_deref(), Some("downstream,multifunction"));
+
+        assert!(KernelCmdline::parse("intel_iommu=off").iommu_disabled());
+        assert!(!KernelCmdline::parse("quiet splash").passthrough_mode());
+    }
+
+    #[test]
+    fn test_ready_host() {
+        let sysfs = FakeSysfs::new();
+        healthy_host(&sysfs);
+        fs::write(sysfs.path(&format!("bus/pci/devices/{}/config", BRIDGE)), config_with_acs(0x001d)).unwrap();
+
+        let report = diagnostics(&sysfs).report();
+        assert_eq!(report.readiness, Readiness::Ready, "{:?}", report);
+        assert_eq!(report.group_count, 2);
+        assert_eq!(report.iommu_units, vec!["dmar0"]);
+        assert_eq!(report.bridges[0].enabled, Some(true));
+    }
+
+    #[test]
+    fn test_missing_acs_and_pt_degrade() {
+        let sysfs = FakeSysfs::new();
+        healthy_host(&sysfs);
+        sysfs.write("proc/cmdline", "intel_iommu=on\n");
+        fs::write(sysfs.path(&format!("bus/pci/devices/{}/config", BRIDGE)), config_with_acs(0)).unwrap();
+
+        let report = diagnostics(&sysfs).report();
+        assert_eq!(report.readiness, Readiness::Degraded);
+        assert!(report.warnings.iter().any(|w| w.contains("iommu=pt")));
+        assert!(report.warnings.iter().any(|w| w.contains(BRIDGE)));
+    }
+
+    #[test]
+    fn test_partial_acs_is_not_isolation() {
+        let sysfs = FakeSysfs::new();
+        healthy_host(&sysfs);
+        let config = sysfs.path(&format!("bus/pci/devices/{}/config", BRIDGE));
+
+        // Source validation and request redirect, but no completion redirect or upstream forwarding
+        fs::write(&config, config_with_acs(0x0005)).unwrap();
+        let report = diagnostics(&sysfs).report();
+        assert_eq!(report.bridges[0].enabled, Some(false));
+        assert!(report.warnings.iter().any(|w| w.contains(BRIDGE)));
+
+        fs::write(&config, config_with_acs(ACS_ISOLATION_BITS)).unwrap();
+        assert_eq!(diagnostics(&sysfs).report().bridges[0].enabled, Some(true));
+    }
+
+    #[test]
+    fn test_unreadable_config_is_unknown_not_a_warning() {
+        let sysfs = FakeSysfs::new();
+        healthy_host(&sysfs);
+        // Non-root only sees the standard 64-byte header
+        fs::write(sysfs.path(&format!("bus/pci/devices/{}/config", BRIDGE)), vec![0u8; 64]).unwrap();
+
+        let report = diagnostics(&sysfs).report();
+        assert_eq!(report.bridges[0].supported, None);
+        assert_eq!(report.readiness, Readiness::Ready);
+    }
+
+    #[test]
+    fn test_iommu_off_is_not_ready() {
+        let sysfs = FakeSysfs::new();
+        sysfs.write("proc/cmdline", "ro quiet\n");
+
+        let report = diagnostics(&sysfs).report();
+        assert_eq!(report.readiness, Readiness::NotReady);
+        assert!(!report.iommu_enabled());
+        assert!(report.errors.iter().any(|e| e.contains("intel_iommu=on")));
+        assert!(report.errors.iter().any(|e| e.contains("vfio-pci")));
+    }
+
+    #[test]
+    fn test_acs_walk_without_capability() {
+        let mut config = vec![0u8; 4096];
+        let aer: u32 = 0x0001 | (1 << 16);
+        config[0x100..0x104].copy_from_slice(&aer.to_le_bytes());
+        assert_eq!(read_acs_control(&config), Some(None));
+        assert_eq!(read_acs_control(&config[..64]), None);
+    }
+}
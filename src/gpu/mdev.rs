@@ -0,0 +1,278 @@
+/*
+* Mediated devices (mdev) - NVIDIA vGPU, Intel GVT-g
+* --------------------------------------------------
+* Instead of handing a whole PCI function to one VM, the host driver slices
+* the card into mediated devices and each VM gets one by UUID.
+*
+* sysfs layout (parent = the physical GPU's PCI address):
+*   bus/pci/devices/<parent>/mdev_supported_types/<type>/
+*       name, description, device_api, available_instances
+*       create       - write a UUID here to create an instance
+*       devices/     - links to instances of this type
+*   bus/mdev/devices/<uuid>/
+*       mdev_type -> the type directory above
+*       remove    - write 1 to tear the instance down
+*
+* Libvirt side: `VMConfig::gpu_mdev` emits `<hostdev type='mdev'>`.
+*/
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::gpu::pci::read_link_name;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MdevType {
+    /// Directory name, e.g. `nvidia-471` or `i915-GVTg_V5_4`
+    pub id: String,
+    /// Driver-supplied name (`GRID A100-10C`); GVT-g leaves this out
+    pub name: Option<String>,
+    pub description: Option<String>,
+    /// `vfio-pci` for everything we care about
+    pub device_api: Option<String>,
+    pub available_instances: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MdevDevice {
+    pub uuid: String,
+    pub parent: String,
+    pub type_id: String,
+}
+
+pub struct MdevManager {
+    sysfs_root: PathBuf,
+}
+
+impl Default for MdevManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MdevManager {
+    pub fn new() -> Self {
+        Self::with_root("/sys")
+    }
+
+    pub fn with_root(sysfs_root: impl Into<PathBuf>) -> Self {
+        Self { sysfs_root: sysfs_root.into() }
+    }
+
+    fn types_dir(&self, parent: &str) -> PathBuf {
+        self.sysfs_root
+            .join("bus/pci/devices")
+            .join(parent)
+            .join("mdev_supported_types")
+    }
+
+    fn mdev_dir(&self, uuid: &str) -> PathBuf {
+        self.sysfs_root.join("bus/mdev/devices").join(uuid)
+    }
+
+    /// Mdev types the parent's driver offers; empty if it offers none
+    pub fn supported_types(&self, parent: &str) -> Result<Vec<MdevType>> {
+        let entries = match fs::read_dir(self.types_dir(parent)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).context(format!("Failed to read mdev types of {}", parent)),
+        };
+
+        let mut types = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let path = entry.path();
+            types.push(MdevType {
+                id: entry.file_name().to_string_lossy().into_owned(),
+                name: read_attr(&path, "name"),
+                description: read_attr(&path, "description"),
+                device_api: read_attr(&path, "device_api"),
+                available_instances: read_attr(&path, "available_instances")
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(0),
+            });
+        }
+        types.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(types)
+    }
+
+    /// Creates an instance of `type_id` under `parent` with a fresh UUID
+    pub fn create(&self, parent: &str, type_id: &str) -> Result<MdevDevice> {
+        self.create_with_uuid(parent, type_id, &Uuid::new_v4().to_string())
+    }
+
+    /// Same as `create` but with a caller-chosen UUID, e.g. to recreate a VM's vGPU
+    pub fn create_with_uuid(&self, parent: &str, type_id: &str, uuid: &str) -> Result<MdevDevice> {
+        Uuid::parse_str(uuid).map_err(|e| anyhow!("Invalid mdev UUID {}: {}", uuid, e))?;
+
+        let mdev_type = self
+            .supported_types(parent)?
+            .into_iter()
+            .find(|t| t.id == type_id)
+            .ok_or_else(|| anyhow!("{} does not support mdev type {}", parent, type_id))?;
+        if mdev_type.available_instances == 0 {
+            return Err(anyhow!("No {} instances left on {}", type_id, parent));
+        }
+        if self.mdev_dir(uuid).exists() {
+            return Err(anyhow!("Mediated device {} already exists", uuid));
+        }
+
+        let create = self.types_dir(parent).join(type_id).join("create");
+        fs::write(&create, uuid)
+            .with_context(|| format!("Failed to create {} mdev on {}", type_id, parent))?;
+
+        Ok(MdevDevice {
+            uuid: uuid.to_string(),
+            parent: parent.to_string(),
+            type_id: type_id.to_string(),
+        })
+    }
+
+    /// Tears an instance down. The kernel refuses while a VM still has it open.
+    pub fn remove(&self, uuid: &str) -> Result<()> {
+        let dir = self.mdev_dir(uuid);
+        if !dir.exists() {
+            return Err(anyhow!("No mediated device {}", uuid));
+        }
+        fs::write(dir.join("remove"), "1")
+            .with_context(|| format!("Failed to remove mediated device {}", uuid))
+    }
+
+    /// All mediated devices on the host
+    pub fn list_devices(&self) -> Result<Vec<MdevDevice>> {
+        let devices_dir = self.sysfs_root.join("bus/mdev/devices");
+        let entries = match fs::read_dir(&devices_dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).context(format!("Failed to read {}", devices_dir.display())),
+        };
+
+        let mut devices = Vec::new();
+        for entry in entries {
+            let uuid = entry?.file_name().to_string_lossy().into_owned();
+            let dir = self.mdev_dir(&uuid);
+            let Some(type_id) = read_link_name(&dir.join("mdev_type")) else { continue };
+            // bus/mdev/devices/<uuid> -> .../<parent>/<uuid>
+            let Some(parent) = fs::canonicalize(&dir)
+                .ok()
+                .and_then(|p| p.parent().and_then(Path::file_name).map(|n| n.to_string_lossy().into_owned()))
+            else {
+                continue;
+            };
+            devices.push(MdevDevice { uuid, parent, type_id });
+        }
+        devices.sort_by(|a, b| a.uuid.cmp(&b.uuid));
+        Ok(devices)
+    }
+}
+
+fn read_attr(path: &Path, attr: &str) -> Option<String> {
+    fs::read_to_string(path.join(attr))
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::vm::VMConfig;
+    use crate::gpu::fake_sysfs::FakeSysfs;
+    use crate::gpu::pci::{PCI_VENDOR_INTEL, PCI_VENDOR_NVIDIA};
+    use crate::gpu::virtual_gpu::GPUPool;
+
+    const A100: &str = "0000:65:00.0";
+    const VGPU_UUID: &str = "2f5c1e6a-3b9d-4c7e-8a1f-6d2e0b9c4a31";
+
+    fn a100_with_vgpu(sysfs: &FakeSysfs) {
+        sysfs.add_pci_device(A100, PCI_VENDOR_NVIDIA, 0x20b5, 0x030200);
+        sysfs.bind_driver(A100, "nvidia");
+        sysfs.add_mdev_type(A100, "nvidia-471", Some("GRID A100-10C"), 8);
+        sysfs.add_mdev_type(A100, "nvidia-474", Some("GRID A100-40C"), 0);
+    }
+
+    #[test]
+    fn test_supported_types() {
+        let sysfs = FakeSysfs::new();
+        a100_with_vgpu(&sysfs);
+        sysfs.add_pci_device("0000:00:02.0", PCI_VENDOR_INTEL, 0x9a49, 0x030000);
+        sysfs.add_mdev_type("0000:00:02.0", "i915-GVTg_V5_4", None, 2);
+        let manager = MdevManager::with_root(sysfs.root());
+
+        let types = manager.supported_types(A100).unwrap();
+        assert_eq!(types.len(), 2);
+        assert_eq!(types[0].name.as_deref(), Some("GRID A100-10C"));
+        assert_eq!(types[0].device_api.as_deref(), Some("vfio-pci"));
+        assert_eq!(types[0].available_instances, 8);
+
+        let gvt = manager.supported_types("0000:00:02.0").unwrap();
+        assert_eq!(gvt[0].name, None);
+        // A card without a vGPU driver just has no types
+        assert!(manager.supported_types("0000:99:00.0").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_create_writes_uuid_and_validates() {
+        let sysfs = FakeSysfs::new();
+        a100_with_vgpu(&sysfs);
+        let manager = MdevManager::with_root(sysfs.root());
+
+        let device = manager.create_with_uuid(A100, "nvidia-471", VGPU_UUID).unwrap();
+        assert_eq!(device.type_id, "nvidia-471");
+        let written = fs::read_to_string(sysfs.path(&format!(
+            "bus/pci/devices/{}/mdev_supported_types/nvidia-471/create", A100
+        ))).unwrap();
+        assert_eq!(written, VGPU_UUID);
+
+        assert!(manager.create(A100, "nvidia-474").is_err());
+        assert!(manager.create(A100, "nvidia-999").is_err());
+        assert!(manager.create_with_uuid(A100, "nvidia-471", "not-a-uuid").is_err());
+    }
+
+    #[test]
+    fn test_list_allocate_and_remove() {
+        let sysfs = FakeSysfs::new();
+        a100_with_vgpu(&sysfs);
+        // What the kernel does after the UUID is written to `create`
+        sysfs.add_mdev(VGPU_UUID, A100, "nvidia-471");
+        let manager = MdevManager::with_root(sysfs.root());
+
+        let devices = manager.list_devices().unwrap();
+        assert_eq!(devices, vec![MdevDevice {
+            uuid: VGPU_UUID.into(),
+            parent: A100.into(),
+            type_id: "nvidia-471".into(),
+        }]);
+        assert!(manager.create_with_uuid(A100, "nvidia-471", VGPU_UUID).is_err());
+
+        let mut pool = GPUPool::new();
+        let id = pool.register_mdev(&devices[0], 10240, 14);
+        assert_eq!(pool.gpus[&id].mdev_uuid(), Some(VGPU_UUID));
+        pool.allocate("bob", id).unwrap();
+        assert!(pool.unregister_mdev(VGPU_UUID).is_err());
+        pool.release(id).unwrap();
+        pool.unregister_mdev(VGPU_UUID).unwrap();
+
+        manager.remove(VGPU_UUID).unwrap();
+        let removed = fs::read_to_string(sysfs.path(&format!("bus/mdev/devices/{}/remove", VGPU_UUID))).unwrap();
+        assert_eq!(removed, "1");
+        assert!(manager.remove("00000000-0000-0000-0000-000000000000").is_err());
+    }
+
+    #[test]
+    fn test_vm_xml_uses_mdev_hostdev() {
+        let mut config = VMConfig::new("vgpu-guest", 8, 4);
+        config.gpu_mdev = Some(VGPU_UUID.into());
+        let xml = config.to_xml().unwrap();
+        assert!(xml.contains("<hostdev mode='subsystem' type='mdev' managed='no' model='vfio-pci'>"));
+        assert!(xml.contains(&format!("<address uuid='{}'/>", VGPU_UUID)));
+        assert!(!xml.contains("type='pci'"));
+
+        // Goes into the XML as-is otherwise
+        config.gpu_mdev = Some(format!("{}'/></source></hostdev><disk type='file'>", VGPU_UUID));
+        assert!(config.to_xml().unwrap_err().to_string().contains("Invalid mdev UUID"));
+    }
+}
@@ -0,0 +1,592 @@
+/*
+* NVIDIA MIG (Multi-Instance GPU) management
+* ------------------------------------------
+* A100/H100-class cards can be carved into up to seven hardware-isolated
+* slices. A slice is a GPU instance (memory + SMs, picked by profile) with one
+* or more compute instances inside it; we always create one compute instance
+* spanning the whole GPU instance, which is what containers expect.
+*
+* Everything goes through `nvidia-smi mig` via `CommandRunner`:
+*   -lgip                  list GPU instance profiles (free/total, memory, SMs)
+*   -lgi                   list existing GPU instances
+*   -cgi <profile> -C      create a GPU instance plus its default compute instance
+*   -gi <id> -dci          destroy the compute instances of a GPU instance
+*   -dgi -gi <id>          destroy the GPU instance itself
+* and `nvidia-smi -L` for the `MIG-...` UUIDs that CUDA_VISIBLE_DEVICES /
+* NVIDIA_VISIBLE_DEVICES want. `-L` numbers MIG devices but doesn't say which
+* instances they are; the `MIG Devices` section of `nvidia-smi -q` gives each
+* device index its GPU and compute instance ids, so the two are joined on it.
+*
+* The parent GPU is addressed by its UUID (our `GPUInfo::id`), which nvidia-smi
+* accepts anywhere it takes `-i`.
+*/
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::utils::command::{CommandRunner, SystemCommandRunner};
+
+/// A GPU instance profile as reported by `nvidia-smi mig -lgip`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MigProfile {
+    pub id: u32,
+    /// e.g. `3g.40gb`, `1g.10gb+me`
+    pub name: String,
+    pub instances_free: u32,
+    pub instances_total: u32,
+    pub memory_mb: u64,
+    pub sm_count: u32,
+}
+
+/// An existing GPU instance from `nvidia-smi mig -lgi`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MigGpuInstance {
+    pub gpu_instance_id: u32,
+    pub profile_id: u32,
+    pub profile_name: String,
+    pub placement_start: u32,
+    pub placement_size: u32,
+}
+
+/// A usable MIG slice: GPU instance + compute instance + the UUID to hand to containers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MigInstance {
+    pub parent_uuid: String,
+    pub gpu_instance_id: u32,
+    pub compute_instance_id: u32,
+    pub profile: MigProfile,
+    pub uuid: String,
+}
+
+pub struct MigManager {
+    runner: Arc<dyn CommandRunner>,
+}
+
+impl Default for MigManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MigManager {
+    pub fn new() -> Self {
+        Self::with_runner(Arc::new(SystemCommandRunner))
+    }
+
+    pub fn with_runner(runner: Arc<dyn CommandRunner>) -> Self {
+        Self { runner }
+    }
+
+    /// Turns MIG mode on. Takes effect after a GPU reset on most driver versions.
+    pub fn enable_mig(&self, gpu_uuid: &str) -> Result<()> {
+        self.runner.run("nvidia-smi", &["-i", gpu_uuid, "-mig", "1"])?;
+        Ok(())
+    }
+
+    pub fn list_profiles(&self, gpu_uuid: &str) -> Result<Vec<MigProfile>> {
+        let output = self.runner.run("nvidia-smi", &["mig", "-i", gpu_uuid, "-lgip"])?;
+        parse_profiles(&output)
+    }
+
+    pub fn list_gpu_instances(&self, gpu_uuid: &str) -> Result<Vec<MigGpuInstance>> {
+        let output = self.runner.run("nvidia-smi", &["mig", "-i", gpu_uuid, "-lgi"])?;
+        parse_gpu_instances(&output)
+    }
+
+    /// Every slice on the card with its UUID, in GPU instance id order
+    pub fn list_instances(&self, gpu_uuid: &str) -> Result<Vec<MigInstance>> {
+        let profiles = self.list_profiles(gpu_uuid)?;
+        let instances = self.list_gpu_instances(gpu_uuid)?;
+        let devices = self.list_mig_devices(gpu_uuid)?;
+        let output = self.runner.run("nvidia-smi", &["-q", "-i", gpu_uuid])?;
+        let ids = parse_mig_device_ids(&output);
+
+        let mut result = Vec::new();
+        for device in devices {
+            let Some(&(_, gpu_instance_id, compute_instance_id)) = ids.iter().find(|(index, ..)| *index == device.index)
+            else {
+                return Err(anyhow!("nvidia-smi -q has no instance ids for MIG device {} ({})", device.index, device.uuid));
+            };
+            let gi = instances
+                .iter()
+                .find(|gi| gi.gpu_instance_id == gpu_instance_id)
+                .ok_or_else(|| anyhow!("MIG device {} is on unknown GPU instance {}", device.uuid, gpu_instance_id))?;
+            result.push(MigInstance {
+                parent_uuid: gpu_uuid.to_string(),
+                gpu_instance_id,
+                compute_instance_id,
+                profile: find_profile(&profiles, gi.profile_id)?,
+                uuid: device.uuid,
+            });
+        }
+        result.sort_by_key(|instance| (instance.gpu_instance_id, instance.compute_instance_id));
+        Ok(result)
+    }
+
+    /// Creates a GPU instance with the named profile (`3g.40gb`) and its compute instance
+    pub fn create_instance(&self, gpu_uuid: &str, profile_name: &str) -> Result<MigInstance> {
+        let profile = self
+            .list_profiles(gpu_uuid)?
+            .into_iter()
+            .find(|p| p.name == profile_name)
+            .ok_or_else(|| anyhow!("MIG profile {} not supported on {}", profile_name, gpu_uuid))?;
+        if profile.instances_free == 0 {
+            return Err(anyhow!("No free {} slices left on {}", profile_name, gpu_uuid));
+        }
+
+        let before = self.list_mig_devices(gpu_uuid)?;
+        let output = self.runner.run(
+            "nvidia-smi",
+            &["mig", "-i", gpu_uuid, "-cgi", &profile.id.to_string(), "-C"],
+        )?;
+        let (gpu_instance_id, compute_instance_id) = parse_create_output(&output)?;
+
+        // The new slice is the MIG device that wasn't there before
+        let uuid = self
+            .list_mig_devices(gpu_uuid)?
+            .into_iter()
+            .map(|device| device.uuid)
+            .find(|uuid| !before.iter().any(|known| known.uuid == *uuid))
+            .ok_or_else(|| anyhow!("Created GPU instance {} but no new MIG device appeared", gpu_instance_id))?;
+
+        Ok(MigInstance {
+            parent_uuid: gpu_uuid.to_string(),
+            gpu_instance_id,
+            compute_instance_id,
+            profile,
+            uuid,
+        })
+    }
+
+    /// Destroys a GPU instance and the compute instances inside it
+    pub fn destroy_instance(&self, gpu_uuid: &str, gpu_instance_id: u32) -> Result<()> {
+        let gi = gpu_instance_id.to_string();
+        self.runner.run("nvidia-smi", &["mig", "-i", gpu_uuid, "-gi", &gi, "-dci"])?;
+        self.runner.run("nvidia-smi", &["mig", "-i", gpu_uuid, "-dgi", "-gi", &gi])?;
+        Ok(())
+    }
+
+    /// Every MIG device under `gpu_uuid`, in `nvidia-smi -L` order
+    fn list_mig_devices(&self, gpu_uuid: &str) -> Result<Vec<MigDevice>> {
+        let output = self.runner.run("nvidia-smi", &["-L"])?;
+        Ok(parse_list_output(&output)
+            .into_iter()
+            .filter(|device| device.parent_uuid == gpu_uuid)
+            .collect())
+    }
+}
+
+fn find_profile(profiles: &[MigProfile], id: u32) -> Result<MigProfile> {
+    profiles
+        .iter()
+        .find(|p| p.id == id)
+        .cloned()
+        .ok_or_else(|| anyhow!("Unknown MIG profile id {}", id))
+}
+
+/// Table rows look like
+/// `|   0  MIG 3g.40gb        9     2/2        39.25      No     42     2     0   |`
+/// followed by a continuation row (CE/JPEG/OFA) that has no `MIG` column.
+pub fn parse_profiles(output: &str) -> Result<Vec<MigProfile>> {
+    let mut profiles = Vec::new();
+    for line in table_rows(output) {
+        let [_gpu, name, id, instances, memory_gib, _p2p, sm, ..] = line.as_slice() else {
+            return Err(anyhow!("Unexpected MIG profile row: {:?}", line));
+        };
+        let (free, total) = instances
+            .split_once('/')
+            .ok_or_else(|| anyhow!("Invalid instance count '{}'", instances))?;
+
+        profiles.push(MigProfile {
+            id: id.parse()?,
+            name: name.to_string(),
+            instances_free: free.parse()?,
+            instances_total: total.parse()?,
+            memory_mb: (memory_gib.parse::<f64>()? * 1024.0).round() as u64,
+            sm_count: sm.parse()?,
+        });
+    }
+    Ok(profiles)
+}
+
+/// `|   0  MIG 3g.40gb          9        2          4:4     |`
+pub fn parse_gpu_instances(output: &str) -> Result<Vec<MigGpuInstance>> {
+    let mut instances = Vec::new();
+    for line in table_rows(output) {
+        let [_gpu, name, profile_id, instance_id, placement, ..] = line.as_slice() else {
+            return Err(anyhow!("Unexpected MIG GPU instance row: {:?}", line));
+        };
+        let (start, size) = placement
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Invalid placement '{}'", placement))?;
+
+        instances.push(MigGpuInstance {
+            gpu_instance_id: instance_id.parse()?,
+            profile_id: profile_id.parse()?,
+            profile_name: name.to_string(),
+            placement_start: start.parse()?,
+            placement_size: size.parse()?,
+        });
+    }
+    Ok(instances)
+}
+
+/// Rows of an nvidia-smi box table that describe a MIG entry, split into
+/// columns with the `MIG` marker and border pipes dropped
+fn table_rows(output: &str) -> Vec<Vec<&str>> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix('|')?.strip_suffix('|'))
+        .map(|row| row.split_whitespace().collect::<Vec<_>>())
+        .filter(|cols| cols.get(1) == Some(&"MIG"))
+        .map(|cols| cols.into_iter().filter(|c| *c != "MIG").collect())
+        .collect()
+}
+
+/// Pulls the GPU and compute instance ids out of `-cgi ... -C` output:
+/// ```text
+/// Successfully created GPU instance ID  2 on GPU  0 using profile MIG 3g.40gb (ID  9)
+/// Successfully created compute instance ID  0 on GPU  0 GPU instance ID  2 using profile MIG 3g.40gb (ID  2)
+/// ```
+pub fn parse_create_output(output: &str) -> Result<(u32, u32)> {
+    let id_after = |line: &str, marker: &str| -> Option<u32> {
+        line.split_once(marker)?.1.split_whitespace().next()?.parse().ok()
+    };
+
+    let gpu_instance = output
+        .lines()
+        .find_map(|l| l.contains("created GPU instance").then(|| id_after(l, "GPU instance ID")).flatten())
+        .ok_or_else(|| anyhow!("No GPU instance in nvidia-smi output: {}", output.trim()))?;
+    let compute_instance = output
+        .lines()
+        .find_map(|l| l.contains("created compute instance").then(|| id_after(l, "compute instance ID")).flatten())
+        .ok_or_else(|| anyhow!("No compute instance in nvidia-smi output: {}", output.trim()))?;
+
+    Ok((gpu_instance, compute_instance))
+}
+
+/// A MIG device line of `nvidia-smi -L`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigDevice {
+    pub parent_uuid: String,
+    pub profile_name: String,
+    /// `Device N` - the MIG device index on its parent
+    pub index: u32,
+    pub uuid: String,
+}
+
+/// `nvidia-smi -L`:
+/// ```text
+/// GPU 0: NVIDIA A100-SXM4-80GB (UUID: GPU-5c89852c-...)
+///   MIG 3g.40gb     Device  0: (UUID: MIG-c6d4f1ef-...)
+/// ```
+pub fn parse_list_output(output: &str) -> Vec<MigDevice> {
+    let uuid_of = |line: &str| -> Option<String> {
+        Some(line.split_once("(UUID: ")?.1.trim_end().trim_end_matches(')').to_string())
+    };
+    let index_of = |line: &str| -> Option<u32> {
+        line.split_once("Device")?.1.split(':').next()?.trim().parse().ok()
+    };
+
+    let mut parent = None;
+    let mut devices = Vec::new();
+    for line in output.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("GPU ") {
+            parent = uuid_of(trimmed);
+        } else if let Some(rest) = trimmed.strip_prefix("MIG ") {
+            let name = rest.split_whitespace().next();
+            if let (Some(parent), Some(uuid), Some(name), Some(index)) = (&parent, uuid_of(trimmed), name, index_of(rest)) {
+                devices.push(MigDevice { parent_uuid: parent.clone(), profile_name: name.to_string(), index, uuid });
+            }
+        }
+    }
+    devices
+}
+
+/// `(device index, GPU instance id, compute instance id)` from the `MIG Devices`
+/// section of `nvidia-smi -q -i <gpu>`:
+/// ```text
+///     MIG Devices
+///         MIG Device
+///             Index                             : 0
+///             GPU Instance ID                   : 2
+///             Compute Instance ID               : 0
+/// ```
+pub fn parse_mig_device_ids(output: &str) -> Vec<(u32, u32, u32)> {
+    let field = |block: &str, key: &str| -> Option<u32> {
+        block.lines().find_map(|line| {
+            let (k, v) = line.split_once(':')?;
+            (k.trim() == key).then(|| v.trim().parse().ok())?
+        })
+    };
+
+    output
+        .split("MIG Device\n")
+        .skip(1)
+        .filter_map(|block| {
+            Some((
+                field(block, "Index")?,
+                field(block, "GPU Instance ID")?,
+                field(block, "Compute Instance ID")?,
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu::virtual_gpu::{GPUPool, GpuBacking};
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    const A100: &str = "GPU-5c89852c-d268-c3f3-1b07-005d5ae1dc3f";
+
+    /// Plays back recorded output for an exact sequence of nvidia-smi invocations
+    struct ScriptedRunner {
+        script: Mutex<VecDeque<(String, Result<&'static str, &'static str>)>>,
+    }
+
+    impl ScriptedRunner {
+        fn new(script: Vec<(&str, Result<&'static str, &'static str>)>) -> Arc<Self> {
+            Arc::new(Self {
+                script: Mutex::new(script.into_iter().map(|(args, out)| (args.to_string(), out)).collect()),
+            })
+        }
+
+        fn finished(&self) -> bool {
+            self.script.lock().unwrap().is_empty()
+        }
+    }
+
+    impl CommandRunner for ScriptedRunner {
+        fn run(&self, program: &str, args: &[&str]) -> Result<String> {
+            assert_eq!(program, "nvidia-smi");
+            let (expected, output) = self.script.lock().unwrap().pop_front().expect("unexpected nvidia-smi call");
+            assert_eq!(args.join(" "), expected.replace("{uuid}", A100));
+            output.map(str::to_string).map_err(|e| anyhow!(e))
+        }
+    }
+
+    // Recorded on an A100-SXM4-80GB with MIG enabled, driver 535
+    const LGIP: &str = "\
++-----------------------------------------------------------------------------+
+| GPU instance profiles:                                                      |
+| GPU   Name             ID    Instances   Memory     P2P    SM    DEC   ENC  |
+|                              Free/Total   GiB              CE    JPEG  OFA  |
+|=============================================================================|
+|   0  MIG 1g.10gb       19     6/7        9.50       No     14     0     0   |
+|                                                             1     0     0   |
++-----------------------------------------------------------------------------+
+|   0  MIG 1g.10gb+me    20     1/1        9.50       No     14     1     0   |
+|                                                             1     1     1   |
++-----------------------------------------------------------------------------+
+|   0  MIG 2g.20gb       14     3/3        19.50      No     28     1     0   |
+|                                                             2     0     0   |
++-----------------------------------------------------------------------------+
+|   0  MIG 3g.40gb        9     1/2        39.25      No     42     2     0   |
+|                                                             3     0     0   |
++-----------------------------------------------------------------------------+
+|   0  MIG 4g.40gb        5     0/1        39.25      No     56     2     0   |
+|                                                             4     0     0   |
++-----------------------------------------------------------------------------+
+|   0  MIG 7g.80gb        0     0/1        79.25      No     98     5     0   |
+|                                                             7     1     1   |
++-----------------------------------------------------------------------------+
+";
+
+    const LGI: &str = "\
++-------------------------------------------------------+
+| GPU instances:                                        |
+| GPU   Name             Profile  Instance   Placement  |
+|                          ID       ID       Start:Size |
+|=======================================================|
+|   0  MIG 1g.10gb         19        9          2:1     |
++-------------------------------------------------------+
+|   0  MIG 3g.40gb          9        2          4:4     |
++-------------------------------------------------------+
+";
+
+    const LIST_BEFORE: &str = "\
+GPU 0: NVIDIA A100-SXM4-80GB (UUID: GPU-5c89852c-d268-c3f3-1b07-005d5ae1dc3f)
+  MIG 3g.40gb     Device  0: (UUID: MIG-c6d4f1ef-42e4-5de3-91c7-45d71c87eb3f)
+  MIG 1g.10gb     Device  1: (UUID: MIG-0b6e1c42-7f3a-5d1e-8c2b-9a4f6e3d2c1b)
+GPU 1: NVIDIA A100-SXM4-80GB (UUID: GPU-9f8e7d6c-5b4a-3c2d-1e0f-a9b8c7d6e5f4)
+";
+
+    const LIST_AFTER: &str = "\
+GPU 0: NVIDIA A100-SXM4-80GB (UUID: GPU-5c89852c-d268-c3f3-1b07-005d5ae1dc3f)
+  MIG 3g.40gb     Device  0: (UUID: MIG-c6d4f1ef-42e4-5de3-91c7-45d71c87eb3f)
+  MIG 3g.40gb     Device  1: (UUID: MIG-7a1d9e3c-2b4f-5e6a-8d7c-1f0e9b8a7c6d)
+  MIG 1g.10gb     Device  2: (UUID: MIG-0b6e1c42-7f3a-5d1e-8c2b-9a4f6e3d2c1b)
+GPU 1: NVIDIA A100-SXM4-80GB (UUID: GPU-9f8e7d6c-5b4a-3c2d-1e0f-a9b8c7d6e5f4)
+";
+
+    // After CREATE_3G: the new slice got GI 1 but shows up second in -L
+    const LGI_AFTER: &str = "\
++-------------------------------------------------------+
+| GPU instances:                                        |
+| GPU   Name             Profile  Instance   Placement  |
+|                          ID       ID       Start:Size |
+|=======================================================|
+|   0  MIG 1g.10gb         19        9          2:1     |
++-------------------------------------------------------+
+|   0  MIG 3g.40gb          9        1          0:4     |
++-------------------------------------------------------+
+|   0  MIG 3g.40gb          9        2          4:4     |
++-------------------------------------------------------+
+";
+
+    // `nvidia-smi -q -i` trimmed to the MIG section, matching LIST_AFTER
+    const QUERY_AFTER: &str = "\
+==============NVSMI LOG==============
+
+GPU 00000000:07:00.0
+    Product Name                          : NVIDIA A100-SXM4-80GB
+    MIG Mode
+        Current                           : Enabled
+        Pending                           : Enabled
+    MIG Devices
+        MIG Device
+            Index                         : 0
+            GPU Instance ID               : 2
+            Compute Instance ID           : 0
+            Device Attributes
+                Shared
+                    Multiprocessor count  : 42
+        MIG Device
+            Index                         : 1
+            GPU Instance ID               : 1
+            Compute Instance ID           : 1
+            Device Attributes
+                Shared
+                    Multiprocessor count  : 42
+        MIG Device
+            Index                         : 2
+            GPU Instance ID               : 9
+            Compute Instance ID           : 0
+            Device Attributes
+                Shared
+                    Multiprocessor count  : 14
+    Minor Number                          : 0
+";
+
+    const CREATE_3G: &str = "\
+Successfully created GPU instance ID  1 on GPU  0 using profile MIG 3g.40gb (ID  9)
+Successfully created compute instance ID  0 on GPU  0 GPU instance ID  1 using profile MIG 3g.40gb (ID  2)
+";
+
+    #[test]
+    fn test_parse_profiles() {
+        let profiles = parse_profiles(LGIP).unwrap();
+        assert_eq!(profiles.len(), 6);
+        assert_eq!(profiles[3], MigProfile {
+            id: 9,
+            name: "3g.40gb".into(),
+            instances_free: 1,
+            instances_total: 2,
+            memory_mb: 40192,
+            sm_count: 42,
+        });
+        assert_eq!(profiles[1].name, "1g.10gb+me");
+    }
+
+    #[test]
+    fn test_parse_mig_device_ids() {
+        assert_eq!(parse_mig_device_ids(QUERY_AFTER), vec![(0, 2, 0), (1, 1, 1), (2, 9, 0)]);
+        assert!(parse_mig_device_ids("    MIG Devices                           : None\n").is_empty());
+    }
+
+    #[test]
+    fn test_list_instances_maps_uuids() {
+        let runner = ScriptedRunner::new(vec![
+            ("mig -i {uuid} -lgip", Ok(LGIP)),
+            ("mig -i {uuid} -lgi", Ok(LGI_AFTER)),
+            ("-L", Ok(LIST_AFTER)),
+            ("-q -i {uuid}", Ok(QUERY_AFTER)),
+        ]);
+        let instances = MigManager::with_runner(runner.clone()).list_instances(A100).unwrap();
+        assert!(runner.finished());
+
+        // Two 3g.40gb slices: only the instance ids tell them apart
+        let ids: Vec<_> = instances
+            .iter()
+            .map(|i| (i.gpu_instance_id, i.compute_instance_id, i.profile.name.as_str(), i.uuid.as_str()))
+            .collect();
+        assert_eq!(ids, vec![
+            (1, 1, "3g.40gb", "MIG-7a1d9e3c-2b4f-5e6a-8d7c-1f0e9b8a7c6d"),
+            (2, 0, "3g.40gb", "MIG-c6d4f1ef-42e4-5de3-91c7-45d71c87eb3f"),
+            (9, 0, "1g.10gb", "MIG-0b6e1c42-7f3a-5d1e-8c2b-9a4f6e3d2c1b"),
+        ]);
+    }
+
+    #[test]
+    fn test_list_instances_needs_the_ids() {
+        let runner = ScriptedRunner::new(vec![
+            ("mig -i {uuid} -lgip", Ok(LGIP)),
+            ("mig -i {uuid} -lgi", Ok(LGI)),
+            ("-L", Ok(LIST_BEFORE)),
+            ("-q -i {uuid}", Ok("    MIG Devices                           : None\n")),
+        ]);
+        let err = MigManager::with_runner(runner).list_instances(A100).unwrap_err();
+        assert!(err.to_string().contains("no instance ids"), "{}", err);
+    }
+
+    #[test]
+    fn test_create_instance_and_register() {
+        let runner = ScriptedRunner::new(vec![
+            ("mig -i {uuid} -lgip", Ok(LGIP)),
+            ("-L", Ok(LIST_BEFORE)),
+            ("mig -i {uuid} -cgi 9 -C", Ok(CREATE_3G)),
+            ("-L", Ok(LIST_AFTER)),
+        ]);
+        let instance = MigManager::with_runner(runner.clone()).create_instance(A100, "3g.40gb").unwrap();
+        assert_eq!((instance.gpu_instance_id, instance.compute_instance_id), (1, 0));
+        assert_eq!(instance.uuid, "MIG-7a1d9e3c-2b4f-5e6a-8d7c-1f0e9b8a7c6d");
+        assert!(runner.finished());
+
+        let mut pool = GPUPool::new();
+        let id = pool.register_mig_instance(&instance);
+        let gpu = &pool.gpus[&id];
+        assert_eq!((gpu.vram_mb, gpu.compute_units), (40192, 42));
+        assert_eq!(gpu.mig_uuid(), Some("MIG-7a1d9e3c-2b4f-5e6a-8d7c-1f0e9b8a7c6d"));
+        assert!(matches!(gpu.backing, GpuBacking::Mig { gpu_instance_id: 1, .. }));
+        // Same slice again doesn't get a second pool entry
+        assert_eq!(pool.register_mig_instance(&instance), id);
+
+        pool.allocate("alice", id).unwrap();
+        assert!(pool.unregister_mig_instance(A100, 1).is_err());
+        pool.release(id).unwrap();
+        pool.unregister_mig_instance(A100, 1).unwrap();
+        assert!(!pool.gpus.contains_key(&id));
+    }
+
+    #[test]
+    fn test_create_instance_rejects_exhausted_profile() {
+        let runner = ScriptedRunner::new(vec![("mig -i {uuid} -lgip", Ok(LGIP))]);
+        let manager = MigManager::with_runner(runner);
+        let err = manager.create_instance(A100, "7g.80gb").unwrap_err();
+        assert!(err.to_string().contains("No free 7g.80gb"));
+    }
+
+    #[test]
+    fn test_destroy_instance() {
+        let runner = ScriptedRunner::new(vec![
+            ("mig -i {uuid} -gi 2 -dci", Ok("Successfully destroyed compute instance ID  0 from GPU  0 GPU instance ID  2\n")),
+            ("mig -i {uuid} -dgi -gi 2", Ok("Successfully destroyed GPU instance ID  2 from GPU  0\n")),
+        ]);
+        MigManager::with_runner(runner.clone()).destroy_instance(A100, 2).unwrap();
+        assert!(runner.finished());
+    }
+
+    #[test]
+    fn test_mig_disabled_surfaces_error() {
+        let runner = ScriptedRunner::new(vec![(
+            "mig -i {uuid} -lgip",
+            Err("No MIG-enabled devices found."),
+        )]);
+        assert!(MigManager::with_runner(runner).list_profiles(A100).is_err());
+    }
+}
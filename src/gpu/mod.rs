@@ -1,5 +1,20 @@
+pub mod container;
 pub mod device;
+pub mod drain;
+pub mod health;
+pub mod intel;
+pub mod iommu;
+pub mod mdev;
+pub mod mig;
+pub mod nvidia;
+pub mod passthrough;
+pub mod pci;
+pub mod sriov;
+pub mod utils;
 pub mod virtual_gpu;
 
+#[cfg(test)]
+pub(crate) mod fake_sysfs;
+
 // exports cuz ain't nobody got time for full paths
 pub use device::GPUManager;
\ No newline at end of file
@@ -0,0 +1,349 @@
+/*
+* NVIDIA discovery & telemetry
+* ----------------------------
+* Two backends, one output:
+*
+* 1. NVML (cargo feature `nvml`) - talks to libnvidia-ml directly, fastest and
+*    most precise, but needs the library at runtime.
+* 2. nvidia-smi fallback - runs `nvidia-smi --query-gpu=... --format=csv` and
+*    parses the CSV. Always compiled in, so hosts built without `nvml` (or where
+*    NVML fails to initialise) still find their NVIDIA cards.
+*
+* Both produce `GPUInfo` for discovery and `GPUMetrics` for telemetry.
+*/
+
+use anyhow::{anyhow, Result};
+use tracing::{debug, warn};
+
+use crate::gpu::device::GPUInfo;
+use crate::monitoring::metrics::GPUMetrics;
+use crate::utils::command::{CommandRunner, SystemCommandRunner};
+
+/// Fields requested from `nvidia-smi --query-gpu`, in column order.
+pub const NVIDIA_SMI_QUERY_FIELDS: &str = "uuid,pci.bus_id,name,memory.total,memory.used,\
+driver_version,utilization.gpu,temperature.gpu,power.draw";
+
+const NVIDIA_SMI_COLUMNS: usize = 9;
+
+/// One row of `nvidia-smi --query-gpu` output
+#[derive(Debug, Clone, PartialEq)]
+pub struct NvidiaSmiGpu {
+    pub uuid: String,
+    /// Normalised to the sysfs form, e.g. `0000:01:00.0`
+    pub pci_address: String,
+    pub name: String,
+    pub memory_total_mb: u64,
+    pub memory_used_mb: Option<u64>,
+    pub driver_version: String,
+    pub utilization_percent: Option<f64>,
+    pub temperature_celsius: Option<i32>,
+    pub power_draw_watts: Option<f64>,
+}
+
+impl NvidiaSmiGpu {
+    pub fn to_gpu_info(&self) -> GPUInfo {
+        GPUInfo {
+            id: self.uuid.clone(),
+            vendor: "NVIDIA".into(),
+            model: self.name.clone(),
+            vram_mb: self.memory_total_mb,
+            driver_version: self.driver_version.clone(),
+            vulkan_support: Some(true),
+            pci_address: Some(self.pci_address.clone()),
+            ..Default::default()
+        }
+    }
+
+    pub fn to_metrics(&self) -> GPUMetrics {
+        GPUMetrics {
+            utilization_percent: self.utilization_percent.unwrap_or(0.0),
+            memory_used_mb: self.memory_used_mb.unwrap_or(0),
+            memory_total_mb: self.memory_total_mb,
+            temperature_celsius: self.temperature_celsius.unwrap_or(0),
+            power_usage_watts: self.power_draw_watts.unwrap_or(0.0),
+            clock_mhz: None,
+            max_clock_mhz: None,
+        }
+    }
+}
+
+/// Converts nvidia-smi / NVML bus ids (`00000000:01:00.0`) to the 4-digit
+/// domain form used under `/sys/bus/pci/devices`.
+pub fn normalize_pci_bus_id(bus_id: &str) -> String {
+    let bus_id = bus_id.trim().to_lowercase();
+    match bus_id.split_once(':') {
+        Some((domain, rest)) if domain.len() > 4 => {
+            format!("{}:{}", &domain[domain.len() - 4..], rest)
+        }
+        _ => bus_id,
+    }
+}
+
+/// Parses `nvidia-smi --query-gpu=<NVIDIA_SMI_QUERY_FIELDS> --format=csv[,noheader][,nounits]`.
+///
+/// Header rows, unit suffixes (`MiB`, `W`, `%`) and `[N/A]` / `[Not Supported]`
+/// placeholders are all tolerated so recorded output from different driver
+/// versions parses the same way.
+pub fn parse_query_output(output: &str) -> Result<Vec<NvidiaSmiGpu>> {
+    let mut gpus = Vec::new();
+
+    for (line_no, line) in output.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("uuid") {
+            continue;
+        }
+
+        let columns: Vec<&str> = line.split(',').map(str::trim).collect();
+        if columns.len() != NVIDIA_SMI_COLUMNS {
+            return Err(anyhow!(
+                "nvidia-smi line {}: expected {} columns, got {}",
+                line_no + 1,
+                NVIDIA_SMI_COLUMNS,
+                columns.len()
+            ));
+        }
+
+        let memory_total_mb = parse_number::<u64>(columns[3])
+            .ok_or_else(|| anyhow!("nvidia-smi line {}: invalid memory.total '{}'", line_no + 1, columns[3]))?;
+
+        gpus.push(NvidiaSmiGpu {
+            uuid: columns[0].to_string(),
+            pci_address: normalize_pci_bus_id(columns[1]),
+            name: columns[2].to_string(),
+            memory_total_mb,
+            memory_used_mb: parse_number(columns[4]),
+            driver_version: columns[5].to_string(),
+            utilization_percent: parse_number(columns[6]),
+            temperature_celsius: parse_number(columns[7]),
+            power_draw_watts: parse_number(columns[8]),
+        });
+    }
+
+    Ok(gpus)
+}
+
+/// Strips unit suffixes and maps nvidia-smi placeholders to `None`.
+fn parse_number<T: std::str::FromStr>(value: &str) -> Option<T> {
+    let value = value
+        .trim()
+        .trim_end_matches("MiB")
+        .trim_end_matches('W')
+        .trim_end_matches('%')
+        .trim();
+    if value.starts_with('[') {
+        return None;
+    }
+    value.parse().ok()
+}
+
+/// Runs the nvidia-smi query through `runner` and parses the result.
+pub fn query_nvidia_smi(runner: &dyn CommandRunner) -> Result<Vec<NvidiaSmiGpu>> {
+    let query = format!("--query-gpu={}", NVIDIA_SMI_QUERY_FIELDS);
+    let output = runner.run("nvidia-smi", &[&query, "--format=csv,noheader,nounits"])?;
+    parse_query_output(&output)
+}
+
+/// Discovers NVIDIA GPUs via NVML when enabled, falling back to nvidia-smi.
+/// A host without NVIDIA tooling simply reports no devices.
+pub fn detect_nvidia_gpus() -> Result<Vec<GPUInfo>> {
+    #[cfg(feature = "nvml")]
+    match nvml_backend::detect() {
+        Ok(gpus) => return Ok(gpus),
+        Err(e) => debug!("NVML unavailable, falling back to nvidia-smi: {}", e),
+    }
+
+    detect_with_runner(&SystemCommandRunner)
+}
+
+pub fn detect_with_runner(runner: &dyn CommandRunner) -> Result<Vec<GPUInfo>> {
+    match query_nvidia_smi(runner) {
+        Ok(gpus) => Ok(gpus.iter().map(NvidiaSmiGpu::to_gpu_info).collect()),
+        Err(e) => {
+            debug!("nvidia-smi query failed, assuming no NVIDIA GPUs: {}", e);
+            Ok(Vec::new())
+        }
+    }
+}
+
+/// Current telemetry for the NVIDIA GPU with the given UUID.
+pub fn collect_nvidia_metrics(uuid: &str) -> Result<Option<GPUMetrics>> {
+    #[cfg(feature = "nvml")]
+    match nvml_backend::metrics(uuid) {
+        Ok(metrics) => return Ok(Some(metrics)),
+        Err(e) => debug!("NVML metrics failed for {}, trying nvidia-smi: {}", uuid, e),
+    }
+
+    collect_metrics_with_runner(&SystemCommandRunner, uuid)
+}
+
+pub fn collect_metrics_with_runner(runner: &dyn CommandRunner, uuid: &str) -> Result<Option<GPUMetrics>> {
+    match query_nvidia_smi(runner) {
+        Ok(gpus) => Ok(gpus.iter().find(|g| g.uuid == uuid).map(NvidiaSmiGpu::to_metrics)),
+        Err(e) => {
+            warn!("Failed to query nvidia-smi for {}: {}", uuid, e);
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(feature = "nvml")]
+mod nvml_backend {
+    use anyhow::Result;
+    use nvml_wrapper::enum_wrappers::device::{Clock, TemperatureSensor};
+    use nvml_wrapper::Nvml;
+
+    use super::normalize_pci_bus_id;
+    use crate::gpu::device::GPUInfo;
+    use crate::monitoring::metrics::GPUMetrics;
+
+    pub fn detect() -> Result<Vec<GPUInfo>> {
+        let nvml = Nvml::init()?;
+        let driver_version = nvml.sys_driver_version()?;
+        let mut gpus = Vec::new();
+
+        for i in 0..nvml.device_count()? {
+            let device = nvml.device_by_index(i)?;
+            gpus.push(GPUInfo {
+                id: device.uuid()?,
+                vendor: "NVIDIA".into(),
+                model: device.name()?,
+                vram_mb: device.memory_info()?.total / 1024 / 1024,
+                driver_version: driver_version.clone(),
+                vulkan_support: Some(true),
+                pci_address: Some(normalize_pci_bus_id(&device.pci_info()?.bus_id)),
+                ..Default::default()
+            });
+        }
+
+        Ok(gpus)
+    }
+
+    pub fn metrics(uuid: &str) -> Result<GPUMetrics> {
+        let nvml = Nvml::init()?;
+        let device = nvml.device_by_uuid(uuid)?;
+        let memory = device.memory_info()?;
+
+        Ok(GPUMetrics {
+            utilization_percent: device.utilization_rates()?.gpu as f64,
+            memory_used_mb: memory.used / 1024 / 1024,
+            memory_total_mb: memory.total / 1024 / 1024,
+            temperature_celsius: device.temperature(TemperatureSensor::Gpu)? as i32,
+            power_usage_watts: device.power_usage()? as f64 / 1000.0,
+            clock_mhz: device.clock_info(Clock::Graphics).ok(),
+            max_clock_mhz: device.max_clock_info(Clock::Graphics).ok(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct CannedRunner(Result<&'static str, &'static str>);
+
+    impl CommandRunner for CannedRunner {
+        fn run(&self, program: &str, _args: &[&str]) -> Result<String> {
+            assert_eq!(program, "nvidia-smi");
+            self.0.map(str::to_string).map_err(|e| anyhow!(e))
+        }
+    }
+
+    // Recorded on a 2x A100 box, driver 535
+    const A100_NOHEADER_NOUNITS: &str = "\
+GPU-4b3c1e2a-8f7d-4c5e-9a1b-2d3e4f5a6b7c, 00000000:07:00.0, NVIDIA A100-SXM4-80GB, 81920, 4, 535.129.03, 0, 31, 61.24
+GPU-9f8e7d6c-5b4a-3c2d-1e0f-a9b8c7d6e5f4, 00000000:0F:00.0, NVIDIA A100-SXM4-80GB, 81920, 40511, 535.129.03, 97, 68, 387.55
+";
+
+    // Recorded on a workstation with a consumer card, driver 550, default csv format
+    const RTX_WITH_HEADER_AND_UNITS: &str = "\
+uuid, pci.bus_id, name, memory.total [MiB], memory.used [MiB], driver_version, utilization.gpu [%], temperature.gpu, power.draw [W]
+GPU-0a1b2c3d-4e5f-6a7b-8c9d-0e1f2a3b4c5d, 00000000:01:00.0, NVIDIA GeForce RTX 4090, 24564 MiB, 1234 MiB, 550.54.14, 12 %, 45, 35.17 W
+";
+
+    // Older Tesla boards report power as unsupported
+    const TESLA_NOT_SUPPORTED: &str = "\
+GPU-11111111-2222-3333-4444-555555555555, 00000000:3B:00.0, Tesla T4, 15360, [N/A], 470.223.02, [Not Supported], 38, [Not Supported]
+";
+
+    #[test]
+    fn test_parse_noheader_nounits() {
+        let gpus = parse_query_output(A100_NOHEADER_NOUNITS).unwrap();
+        assert_eq!(gpus.len(), 2);
+        assert_eq!(gpus[0].uuid, "GPU-4b3c1e2a-8f7d-4c5e-9a1b-2d3e4f5a6b7c");
+        assert_eq!(gpus[0].pci_address, "0000:07:00.0");
+        assert_eq!(gpus[0].memory_total_mb, 81920);
+        assert_eq!(gpus[1].pci_address, "0000:0f:00.0");
+        assert_eq!(gpus[1].memory_used_mb, Some(40511));
+        assert_eq!(gpus[1].utilization_percent, Some(97.0));
+        assert_eq!(gpus[1].temperature_celsius, Some(68));
+        assert_eq!(gpus[1].power_draw_watts, Some(387.55));
+    }
+
+    #[test]
+    fn test_parse_header_and_units() {
+        let gpus = parse_query_output(RTX_WITH_HEADER_AND_UNITS).unwrap();
+        assert_eq!(gpus.len(), 1);
+        assert_eq!(gpus[0].name, "NVIDIA GeForce RTX 4090");
+        assert_eq!(gpus[0].memory_total_mb, 24564);
+        assert_eq!(gpus[0].memory_used_mb, Some(1234));
+        assert_eq!(gpus[0].driver_version, "550.54.14");
+        assert_eq!(gpus[0].utilization_percent, Some(12.0));
+        assert_eq!(gpus[0].power_draw_watts, Some(35.17));
+    }
+
+    #[test]
+    fn test_parse_not_supported_fields() {
+        let gpus = parse_query_output(TESLA_NOT_SUPPORTED).unwrap();
+        assert_eq!(gpus[0].memory_used_mb, None);
+        assert_eq!(gpus[0].utilization_percent, None);
+        assert_eq!(gpus[0].power_draw_watts, None);
+        assert_eq!(gpus[0].temperature_celsius, Some(38));
+
+        let metrics = gpus[0].to_metrics();
+        assert_eq!(metrics.power_usage_watts, 0.0);
+        assert_eq!(metrics.memory_total_mb, 15360);
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_rows() {
+        assert!(parse_query_output("GPU-1, 00000000:01:00.0, broken").is_err());
+        assert!(parse_query_output(
+            "GPU-1, 00000000:01:00.0, X, lots, 0, 1.0, 0, 0, 0"
+        ).is_err());
+        assert!(parse_query_output("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_normalize_pci_bus_id() {
+        assert_eq!(normalize_pci_bus_id("00000000:65:00.0"), "0000:65:00.0");
+        assert_eq!(normalize_pci_bus_id("0000:65:00.0"), "0000:65:00.0");
+        assert_eq!(normalize_pci_bus_id("00000001:AF:00.1"), "0001:af:00.1");
+    }
+
+    #[test]
+    fn test_detect_with_runner() {
+        let gpus = detect_with_runner(&CannedRunner(Ok(A100_NOHEADER_NOUNITS))).unwrap();
+        assert_eq!(gpus.len(), 2);
+        assert_eq!(gpus[0].vendor, "NVIDIA");
+        assert_eq!(gpus[0].model, "NVIDIA A100-SXM4-80GB");
+        assert_eq!(gpus[0].pci_address.as_deref(), Some("0000:07:00.0"));
+    }
+
+    #[test]
+    fn test_detect_without_nvidia_smi() {
+        let gpus = detect_with_runner(&CannedRunner(Err("No such file or directory"))).unwrap();
+        assert!(gpus.is_empty());
+    }
+
+    #[test]
+    fn test_collect_metrics_with_runner() {
+        let runner = CannedRunner(Ok(A100_NOHEADER_NOUNITS));
+        let metrics = collect_metrics_with_runner(&runner, "GPU-9f8e7d6c-5b4a-3c2d-1e0f-a9b8c7d6e5f4")
+            .unwrap()
+            .unwrap();
+        assert_eq!(metrics.utilization_percent, 97.0);
+        assert_eq!(metrics.memory_used_mb, 40511);
+
+        assert!(collect_metrics_with_runner(&runner, "GPU-missing").unwrap().is_none());
+    }
+}
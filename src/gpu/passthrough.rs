@@ -18,7 +18,7 @@
 *    - Device management (verification/monitoring)
 *
 * 2. IOMMU Management:
-*    - Validates IOMMU support via kernel messages
+*    - Validates IOMMU support via sysfs/cmdline (see gpu/iommu.rs)
 *    - Manages IOMMU groups for device isolation
 *    - Handles group viability checks
 *
@@ -27,6 +27,19 @@
 *    - Handles VFIO driver binding
 *    - Verifies driver states
 *
+*    Lifecycle, per device in the GPU's IOMMU group (bridges excluded):
+*      journal original driver -> driver_override=vfio-pci -> unbind ->
+*      drivers_probe -> journal "bound"
+*    and on release:
+*      driver_override cleared -> unbind from vfio-pci -> FLR -> rebind the
+*      original driver -> drop journal entry
+*
+*    Groups holding anything besides the card's own functions and bridges
+*    (see VfioGroup::plan) are refused before the first write.
+*
+*    The journal (JSON, written before every sysfs change) is what lets
+*    `rollback`/`recover` put devices back after a crash halfway through.
+*
 * 4. Device Management:
 *    - Validates device readiness
 *    - Monitors power states
@@ -56,17 +69,28 @@
 * configured. Ensure proper IOMMU support and follow security guidelines.
 */
 
+use crate::config::settings::Settings;
 use crate::errors::{GpuShareError, GpuError, ErrorRecovery};
+use crate::gpu::iommu::{IommuDiagnostics, KernelCmdline};
+use serde::{Deserialize, Serialize};
 use std::fs::{self, File};
 use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::sync::{Mutex, MutexGuard};
 use tracing::{info, warn, error};
 
+pub const VFIO_DRIVER: &str = "vfio-pci";
+pub const DEFAULT_JOURNAL_PATH: &str = "/var/lib/gpu-share/passthrough-journal.json";
+const UNSAFE_INTERRUPTS_PARAM: &str = "module/vfio_iommu_type1/parameters/allow_unsafe_interrupts";
+
 pub struct PassthroughManager {
     iommu_manager: IommuManager,
     driver_manager: DriverManager,
     device_manager: DeviceManager,
+    journal: Mutex<PassthroughJournal>,
+    config: PassthroughConfig,
+    sysfs_root: PathBuf,
+    iommu_diagnostics: IommuDiagnostics,
 }
 
 impl PassthroughManager {
@@ -81,57 +105,381 @@ impl PassthroughManager {
             }.into());
         }
 
+        Ok(Self::with_root("/sys", DEFAULT_JOURNAL_PATH)?.with_config(PassthroughConfig::load()))
+    }
+
+    /// Manager over an alternative sysfs root and journal file. Skips the IOMMU
+    /// check - meant for tests and for tooling that already did its own.
+    /// Starts from the default PassthroughConfig.
+    pub fn with_root(
+        sysfs_root: impl Into<PathBuf>,
+        journal_path: impl Into<PathBuf>,
+    ) -> Result<Self, GpuShareError> {
+        let sysfs_root = sysfs_root.into();
         Ok(Self {
-            iommu_manager: IommuManager::new()?,
-            driver_manager: DriverManager::new()?,
-            device_manager: DeviceManager::new()?,
+            iommu_manager: IommuManager::new(&sysfs_root)?,
+            driver_manager: DriverManager::new(&sysfs_root)?,
+            device_manager: DeviceManager::new(&sysfs_root)?,
+            journal: Mutex::new(PassthroughJournal::load(journal_path.into())?),
+            config: PassthroughConfig::default(),
+            iommu_diagnostics: IommuDiagnostics::with_roots(&sysfs_root, "/proc"),
+            sysfs_root,
         })
     }
 
-    fn check_iommu_support() -> Result<bool, GpuShareError> {
-        let dmesg_output = Command::new("dmesg")
-            .output()
-            .map_err(|e| GpuError::SystemError {
-                message: format!("Failed to execute dmesg: {}", e),
-            })?;
+    pub fn with_config(mut self, config: PassthroughConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Where the kernel command line is read from (for ACS override detection)
+    pub fn with_proc_root(mut self, proc_root: impl Into<PathBuf>) -> Self {
+        self.iommu_diagnostics = IommuDiagnostics::with_roots(&self.sysfs_root, proc_root);
+        self
+    }
 
-        let output = String::from_utf8_lossy(&dmesg_output.stdout);
-        Ok(output.contains("IOMMU enabled") || output.contains("AMD-Vi enabled"))
+    pub fn config(&self) -> &PassthroughConfig {
+        &self.config
     }
 
-    pub fn prepare_gpu_passthrough(&mut self, gpu_id: &str) -> Result<(), GpuShareError> {
+    fn check_iommu_support() -> Result<bool, GpuShareError> {
+        let report = IommuDiagnostics::new().report();
+        report.log_summary();
+        Ok(report.iommu_enabled())
+    }
+
+    pub fn prepare_gpu_passthrough(&self, gpu_id: &str) -> Result<(), GpuShareError> {
         info!("Preparing GPU {} for passthrough", gpu_id);
 
         // Get GPU IOMMU group
         let iommu_group = self.iommu_manager.get_gpu_iommu_group(gpu_id)?;
-        
-        // Unbind current driver
-        self.driver_manager.unbind_current_driver(gpu_id)?;
-        
-        // Bind to VFIO driver
-        self.driver_manager.bind_to_vfio(gpu_id, iommu_group)?;
-        
-        // Verify device is ready
-        self.device_manager.verify_device_ready(gpu_id)?;
+
+        // Binding would drag foreign devices away from the host - refuse up front
+        let plan = self.plan_isolation(gpu_id)?;
+        if !plan.is_isolated() {
+            return Err(GpuError::IommuError {
+                message: format!(
+                    "IOMMU group {} is shared with devices outside the card: {}",
+                    iommu_group,
+                    plan.foreign_devices().join(", ")
+                ),
+                group_id: Some(iommu_group),
+            }
+            .into());
+        }
+        self.check_acs_override(iommu_group)?;
+        self.apply_interrupt_policy()?;
+
+        // Move the whole group over to vfio-pci - half a group is useless to VFIO -
+        // then verify device is ready. Either failing puts the group back.
+        let result = self
+            .bind_group_to_vfio(iommu_group)
+            .and_then(|_| self.device_manager.verify_device_ready(gpu_id));
+        if let Err(e) = result {
+            error!("Passthrough of IOMMU group {} failed, restoring: {}", iommu_group, e);
+            self.release_group(iommu_group)?;
+            return Err(e);
+        }
+
+        // Only once verified: runtime PM may drop an idle device out of D0
+        let control = if self.config.enable_power_management { "auto" } else { "on" };
+        for address in plan.passthrough_devices() {
+            self.device_manager.set_power_control(address, control)?;
+        }
 
         info!("GPU {} successfully prepared for passthrough", gpu_id);
         Ok(())
     }
+
+    /// Groups produced by pcie_acs_override are the kernel taking our word for
+    /// isolation. Only trusted when the operator opted in.
+    fn check_acs_override(&self, iommu_group: u32) -> Result<(), GpuShareError> {
+        let cmdline = self.iommu_diagnostics.report().cmdline;
+        let Some(mode) = cmdline.pcie_acs_override else {
+            return Ok(());
+        };
+        if !self.config.enable_acs_override {
+            return Err(GpuError::IommuError {
+                message: format!(
+                    "pcie_acs_override={} is active, so IOMMU group {} may not be isolated in hardware; \
+                     set passthrough.enable_acs_override to accept that",
+                    mode, iommu_group
+                ),
+                group_id: Some(iommu_group),
+            }
+            .into());
+        }
+        warn!(
+            "Passing through IOMMU group {} split by pcie_acs_override={} - peer-to-peer DMA is not blocked",
+            iommu_group, mode
+        );
+        Ok(())
+    }
+
+    /// vfio_iommu_type1 refuses to attach devices on hosts without interrupt
+    /// remapping unless allow_unsafe_interrupts is set. Only ever switched on, never off -
+    /// someone else may depend on it.
+    fn apply_interrupt_policy(&self) -> Result<(), GpuShareError> {
+        let param = self.sysfs_root.join(UNSAFE_INTERRUPTS_PARAM);
+        let current = fs::read_to_string(&param).map(|v| v.trim() == "Y").unwrap_or(false);
+
+        if !self.config.enable_unsafe_interrupts {
+            if current {
+                warn!("allow_unsafe_interrupts is already set outside gpu-share - guests can inject host interrupts");
+            }
+            return Ok(());
+        }
+        if !current {
+            fs::write(&param, "Y").map_err(|e| GpuError::SystemError {
+                message: format!("Failed to set {} (is vfio_iommu_type1 loaded?): {}", param.display(), e),
+            })?;
+            warn!("Enabled vfio_iommu_type1 allow_unsafe_interrupts - guests can inject host interrupts");
+        }
+        Ok(())
+    }
+
+    /// How the GPU's IOMMU group splits between card, host bridges and foreign devices
+    pub fn plan_isolation(&self, gpu_id: &str) -> Result<IsolationPlan, GpuShareError> {
+        let iommu_group = self.iommu_manager.get_gpu_iommu_group(gpu_id)?;
+        let mut group = VfioGroup::with_root(iommu_group, &self.iommu_manager.sysfs_root)?;
+        for address in self.iommu_manager.group_devices(iommu_group)? {
+            group.add_device(address)?;
+        }
+        group.plan(gpu_id)
+    }
+
+    /// Hands the GPU's IOMMU group back to the drivers it had before
+    pub fn release_gpu_passthrough(&self, gpu_id: &str) -> Result<(), GpuShareError> {
+        info!("Releasing GPU {} from passthrough", gpu_id);
+        let iommu_group = self.iommu_manager.get_gpu_iommu_group(gpu_id)?;
+        self.release_group(iommu_group)
+    }
+
+    /// Points every non-bridge device of `iommu_group` at vfio-pci. Returns the
+    /// devices that were rebound; ones already on vfio-pci are left alone.
+    /// Only for `prepare_gpu_passthrough`, once the isolation plan has shown
+    /// the group holds nothing but the card.
+    fn bind_group_to_vfio(&self, iommu_group: u32) -> Result<Vec<String>, GpuShareError> {
+        let mut rebound = Vec::new();
+        for address in self.iommu_manager.group_devices(iommu_group)? {
+            if self.device_manager.is_bridge(&address)? {
+                continue;
+            }
+            if self.bind_device(&address, iommu_group)? {
+                rebound.push(address);
+            }
+        }
+        Ok(rebound)
+    }
+
+    fn bind_device(&self, address: &str, iommu_group: u32) -> Result<bool, GpuShareError> {
+        let original_driver = self.driver_manager.current_driver(address);
+        let mut journal = self.journal()?;
+
+        if original_driver.as_deref() == Some(VFIO_DRIVER) {
+            // Either ours from an earlier call, or bound at boot - not ours to journal
+            return Ok(false);
+        }
+
+        // Journal first: if we die between here and "bound", rollback knows what to undo
+        journal.record(JournalEntry {
+            address: address.to_string(),
+            iommu_group,
+            original_driver: original_driver.clone(),
+            state: BindState::Binding,
+        })?;
+
+        self.driver_manager.set_override(address, Some(VFIO_DRIVER))?;
+        if let Some(driver) = &original_driver {
+            self.driver_manager.unbind(address, driver)?;
+        }
+        self.driver_manager.probe(address)?;
+
+        journal.set_state(address, BindState::Bound)?;
+        info!(
+            "{} moved from {} to {}",
+            address,
+            original_driver.as_deref().unwrap_or("no driver"),
+            VFIO_DRIVER
+        );
+        Ok(true)
+    }
+
+    fn release_group(&self, iommu_group: u32) -> Result<(), GpuShareError> {
+        let entries: Vec<JournalEntry> = self.journal()?
+            .entries()
+            .iter()
+            .filter(|e| e.iommu_group == iommu_group)
+            .cloned()
+            .collect();
+
+        // Reverse order: companion functions go back before the GPU itself
+        for entry in entries.iter().rev() {
+            self.restore_device(entry)?;
+        }
+        Ok(())
+    }
+
+    /// Undoes one journal entry, whatever state it got to
+    fn restore_device(&self, entry: &JournalEntry) -> Result<(), GpuShareError> {
+        // Wake it up before FLR and handing it back
+        self.device_manager.set_power_control(&entry.address, "on")?;
+        let address = &entry.address;
+        let current = self.driver_manager.current_driver(address);
+
+        self.driver_manager.set_override(address, None)?;
+        if current != entry.original_driver {
+            if let Some(driver) = &current {
+                self.driver_manager.unbind(address, driver)?;
+            }
+            // FLR between drivers so the host driver doesn't inherit guest state
+            self.device_manager.reset_device(address)?;
+            if let Some(driver) = &entry.original_driver {
+                self.driver_manager.bind(address, driver)?;
+            }
+        }
+
+        self.journal()?.remove(address)?;
+        info!(
+            "{} restored to {}",
+            address,
+            entry.original_driver.as_deref().unwrap_or("no driver")
+        );
+        Ok(())
+    }
+
+    /// Devices currently moved to vfio-pci by us
+    pub fn journal_entries(&self) -> Result<Vec<JournalEntry>, GpuShareError> {
+        Ok(self.journal()?.entries().to_vec())
+    }
+
+    /// Restores devices whose bind never completed; finished ones stay on vfio-pci
+    fn reset_vfio_bindings(&self) -> Result<(), GpuShareError> {
+        let incomplete: Vec<JournalEntry> = self.journal()?
+            .entries()
+            .iter()
+            .filter(|e| e.state == BindState::Binding)
+            .cloned()
+            .collect();
+
+        for entry in &incomplete {
+            warn!("Found half-finished VFIO bind of {}, restoring", entry.address);
+            self.restore_device(entry)?;
+        }
+        Ok(())
+    }
+
+    /// Puts every journaled device back on its original driver
+    fn restore_original_drivers(&self) -> Result<(), GpuShareError> {
+        let entries = self.journal_entries()?;
+        for entry in entries.iter().rev() {
+            self.restore_device(entry)?;
+        }
+        Ok(())
+    }
+
+    fn journal(&self) -> Result<MutexGuard<'_, PassthroughJournal>, GpuShareError> {
+        self.journal.lock().map_err(|_| {
+            GpuError::SystemError {
+                message: "Passthrough journal lock poisoned".to_string(),
+            }
+            .into()
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BindState {
+    /// driver_override written, unbind/probe may not have happened yet
+    Binding,
+    /// Device is on vfio-pci
+    Bound,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub address: String,
+    pub iommu_group: u32,
+    /// Driver to give the device back to; `None` if nothing had claimed it
+    pub original_driver: Option<String>,
+    pub state: BindState,
+}
+
+/// Write-ahead record of every device we moved to vfio-pci, persisted as JSON
+/// so a restarted manager can still undo what the previous one did
+pub struct PassthroughJournal {
+    path: PathBuf,
+    entries: Vec<JournalEntry>,
+}
+
+impl PassthroughJournal {
+    pub fn load(path: PathBuf) -> Result<Self, GpuShareError> {
+        let entries = match fs::read_to_string(&path) {
+            Ok(raw) if raw.trim().is_empty() => Vec::new(),
+            Ok(raw) => serde_json::from_str(&raw).map_err(|e| GpuError::SystemError {
+                message: format!("Corrupt passthrough journal {}: {}", path.display(), e),
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self { path, entries })
+    }
+
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    fn record(&mut self, entry: JournalEntry) -> Result<(), GpuShareError> {
+        self.entries.retain(|e| e.address != entry.address);
+        self.entries.push(entry);
+        self.save()
+    }
+
+    fn set_state(&mut self, address: &str, state: BindState) -> Result<(), GpuShareError> {
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.address == address) {
+            entry.state = state;
+        }
+        self.save()
+    }
+
+    fn remove(&mut self, address: &str) -> Result<(), GpuShareError> {
+        self.entries.retain(|e| e.address != address);
+        self.save()
+    }
+
+    /// Write to a temp file and rename, so a crash never leaves half a journal
+    fn save(&self) -> Result<(), GpuShareError> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let json = serde_json::to_string_pretty(&self.entries).map_err(|e| GpuError::SystemError {
+            message: format!("Failed to serialize passthrough journal: {}", e),
+        })?;
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, json)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
 }
 
 struct IommuManager {
+    sysfs_root: PathBuf,
     iommu_groups_path: PathBuf,
+    pci_devices_path: PathBuf,
 }
 
 impl IommuManager {
-    fn new() -> Result<Self, GpuShareError> {
+    fn new(sysfs_root: &Path) -> Result<Self, GpuShareError> {
         Ok(Self {
-            iommu_groups_path: PathBuf::from("/sys/kernel/iommu_groups"),
+            sysfs_root: sysfs_root.to_path_buf(),
+            iommu_groups_path: sysfs_root.join("kernel/iommu_groups"),
+            pci_devices_path: sysfs_root.join("bus/pci/devices"),
         })
     }
 
     fn get_gpu_iommu_group(&self, gpu_id: &str) -> Result<u32, GpuShareError> {
-        let gpu_path = Path::new("/sys/bus/pci/devices").join(gpu_id);
+        let gpu_path = self.pci_devices_path.join(gpu_id);
         
         let iommu_group_link = fs::read_link(gpu_path.join("iommu_group"))
             .map_err(|e| GpuError::IommuError {
@@ -150,75 +498,115 @@ impl IommuManager {
 
         Ok(group_id)
     }
+
+    /// PCI addresses of every device in the group, sorted
+    fn group_devices(&self, group_id: u32) -> Result<Vec<String>, GpuShareError> {
+        let devices_dir = self.iommu_groups_path.join(group_id.to_string()).join("devices");
+        let mut devices: Vec<String> = fs::read_dir(&devices_dir)
+            .map_err(|e| GpuError::IommuError {
+                message: format!("Failed to list IOMMU group devices: {}", e),
+                group_id: Some(group_id),
+            })?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect();
+        devices.sort();
+        Ok(devices)
+    }
+
+    /// Every group we touched must be entirely on vfio-pci (or driverless) -
+    /// a group split between host and guest is exactly what VFIO refuses to open
+    fn verify_groups(
+        &self,
+        entries: &[JournalEntry],
+        driver_manager: &DriverManager,
+        device_manager: &DeviceManager,
+    ) -> Result<(), GpuShareError> {
+        let mut groups: Vec<u32> = entries.iter().map(|e| e.iommu_group).collect();
+        groups.sort();
+        groups.dedup();
+
+        for group in groups {
+            for address in self.group_devices(group)? {
+                if device_manager.is_bridge(&address)? {
+                    continue;
+                }
+                match driver_manager.current_driver(&address) {
+                    None => {}
+                    Some(driver) if driver == VFIO_DRIVER => {}
+                    Some(driver) => {
+                        return Err(GpuError::IommuError {
+                            message: format!("{} in group {} is still bound to {}", address, group, driver),
+                            group_id: Some(group),
+                        }.into());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 struct DriverManager {
     drivers_path: PathBuf,
+    pci_devices_path: PathBuf,
+    drivers_probe_path: PathBuf,
 }
 
 impl DriverManager {
-    fn new() -> Result<Self, GpuShareError> {
+    fn new(sysfs_root: &Path) -> Result<Self, GpuShareError> {
         Ok(Self {
-            drivers_path: PathBuf::from("/sys/bus/pci/drivers"),
+            drivers_path: sysfs_root.join("bus/pci/drivers"),
+            pci_devices_path: sysfs_root.join("bus/pci/devices"),
+            drivers_probe_path: sysfs_root.join("bus/pci/drivers_probe"),
         })
     }
 
-    fn unbind_current_driver(&self, gpu_id: &str) -> Result<(), GpuShareError> {
-        // Get current driver
-        let current_driver = self.get_current_driver(gpu_id)?;
-        
-        // Write to unbind
-        let unbind_path = self.drivers_path.join(&current_driver).join("unbind");
-        fs::write(&unbind_path, gpu_id).map_err(|e| GpuError::DriverError {
-            message: format!("Failed to unbind driver: {}", e),
-            driver_name: current_driver,
-        })?;
-
-        Ok(())
+    fn current_driver(&self, gpu_id: &str) -> Option<String> {
+        crate::gpu::pci::read_link_name(&self.pci_devices_path.join(gpu_id).join("driver"))
     }
 
-    fn bind_to_vfio(&self, gpu_id: &str, iommu_group: u32) -> Result<(), GpuShareError> {
-        // Write device ID to vfio-pci new_id
-        let new_id_path = self.drivers_path.join("vfio-pci/new_id");
-        let device_info = self.get_device_info(gpu_id)?;
-        fs::write(new_id_path, device_info).map_err(|e| GpuError::DriverError {
-            message: format!("Failed to bind to VFIO: {}", e),
-            driver_name: "vfio-pci".to_string(),
-        })?;
+    /// `driver_override` pins the device to one driver for every future probe;
+    /// `None` clears it (the kernel takes an empty write as "no override")
+    fn set_override(&self, gpu_id: &str, driver: Option<&str>) -> Result<(), GpuShareError> {
+        let path = self.pci_devices_path.join(gpu_id).join("driver_override");
+        Self::write_attr(&path, &format!("{}\n", driver.unwrap_or("")), driver.unwrap_or(""))
+    }
 
-        Ok(())
+    fn unbind(&self, gpu_id: &str, driver: &str) -> Result<(), GpuShareError> {
+        Self::write_attr(&self.drivers_path.join(driver).join("unbind"), gpu_id, driver)
     }
 
-    fn get_current_driver(&self, gpu_id: &str) -> Result<String, GpuShareError> {
-        let driver_link = fs::read_link(format!("/sys/bus/pci/devices/{}/driver", gpu_id))
-            .map_err(|e| GpuError::DriverError {
-                message: format!("Failed to read driver symlink: {}", e),
-                driver_name: String::new(),
-            })?;
+    fn bind(&self, gpu_id: &str, driver: &str) -> Result<(), GpuShareError> {
+        Self::write_attr(&self.drivers_path.join(driver).join("bind"), gpu_id, driver)
+    }
 
-        Ok(driver_link.file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or("")
-            .to_string())
+    /// Asks the PCI core to find a driver, which honours `driver_override`
+    fn probe(&self, gpu_id: &str) -> Result<(), GpuShareError> {
+        Self::write_attr(&self.drivers_probe_path, gpu_id, VFIO_DRIVER)
     }
 
-    fn get_device_info(&self, gpu_id: &str) -> Result<String, GpuShareError> {
-        let vendor_id = fs::read_to_string(format!("/sys/bus/pci/devices/{}/vendor", gpu_id))?;
-        let device_id = fs::read_to_string(format!("/sys/bus/pci/devices/{}/device", gpu_id))?;
-        Ok(format!("{} {}", vendor_id.trim(), device_id.trim()))
+    fn write_attr(path: &Path, value: &str, driver_name: &str) -> Result<(), GpuShareError> {
+        fs::write(path, value).map_err(|e| {
+            GpuError::DriverError {
+                message: format!("Failed to write {}: {}", path.display(), e),
+                driver_name: driver_name.to_string(),
+            }
+            .into()
+        })
     }
 }
 
-// src/gpu/passthrough.rs (devam)
-
-struct DeviceManager {
+pub(crate) struct DeviceManager {
     pci_devices_path: PathBuf,
+    rescan_path: PathBuf,
 }
 
 impl DeviceManager {
-    fn new() -> Result<Self, GpuShareError> {
+    pub(crate) fn new(sysfs_root: &Path) -> Result<Self, GpuShareError> {
         Ok(Self {
-            pci_devices_path: PathBuf::from("/sys/bus/pci/devices"),
+            pci_devices_path: sysfs_root.join("bus/pci/devices"),
+            rescan_path: sysfs_root.join("bus/pci/rescan"),
         })
     }
 
@@ -233,7 +621,7 @@ impl DeviceManager {
 
         // Verify VFIO binding
         let current_driver = self.get_current_driver(gpu_id)?;
-        if current_driver != "vfio-pci" {
+        if current_driver != VFIO_DRIVER {
             return Err(GpuError::DriverError {
                 message: format!("Device not bound to VFIO-PCI, current driver: {}", current_driver),
                 driver_name: current_driver,
@@ -262,7 +650,7 @@ impl DeviceManager {
             .to_string())
     }
 
-    fn verify_power_state(&self, gpu_id: &str) -> Result<(), GpuShareError> {
+    pub(crate) fn verify_power_state(&self, gpu_id: &str) -> Result<(), GpuShareError> {
         let power_state_path = self.pci_devices_path
             .join(gpu_id)
             .join("power_state");
@@ -281,7 +669,9 @@ impl DeviceManager {
         Ok(())
     }
 
-    fn verify_memory_bar(&self, gpu_id: &str) -> Result<(), GpuShareError> {
+    /// sysfs `resource` has one `start end flags` line per BAR (hex); a usable
+    /// GPU has at least one assigned memory BAR (IORESOURCE_MEM = 0x200)
+    pub(crate) fn verify_memory_bar(&self, gpu_id: &str) -> Result<(), GpuShareError> {
         let resource_path = self.pci_devices_path
             .join(gpu_id)
             .join("resource");
@@ -296,9 +686,15 @@ impl DeviceManager {
 
         for line in reader.lines() {
             let line = line?;
-            if line.contains("Memory at") {
-                valid_bar = true;
-                break;
+            let fields: Vec<u64> = line
+                .split_whitespace()
+                .filter_map(|f| u64::from_str_radix(f.trim_start_matches("0x"), 16).ok())
+                .collect();
+            if let [start, _end, flags] = fields[..] {
+                if start != 0 && flags & 0x200 != 0 {
+                    valid_bar = true;
+                    break;
+                }
             }
         }
 
@@ -310,6 +706,53 @@ impl DeviceManager {
 
         Ok(())
     }
+
+    /// Function-level reset via sysfs. Not every device has one; returns
+    /// whether a reset was actually issued.
+    fn reset_device(&self, gpu_id: &str) -> Result<bool, GpuShareError> {
+        let reset_path = self.pci_devices_path.join(gpu_id).join("reset");
+        if !reset_path.exists() {
+            warn!("{} has no reset method, skipping FLR", gpu_id);
+            return Ok(false);
+        }
+        fs::write(&reset_path, "1").map_err(|e| GpuError::SystemError {
+            message: format!("Failed to reset {}: {}", gpu_id, e),
+        })?;
+        Ok(true)
+    }
+
+    /// Runtime PM: "auto" lets vfio-pci suspend the idle device, "on" pins it in D0.
+    /// Devices without runtime PM support have no power/control - nothing to do.
+    fn set_power_control(&self, gpu_id: &str, control: &str) -> Result<(), GpuShareError> {
+        let path = self.pci_devices_path.join(gpu_id).join("power/control");
+        if !path.exists() {
+            return Ok(());
+        }
+        fs::write(&path, control).map_err(|e| {
+            GpuError::SystemError {
+                message: format!("Failed to set runtime PM for {}: {}", gpu_id, e),
+            }
+            .into()
+        })
+    }
+
+    /// Host/PCI bridges (class 0x060xxx) share groups with GPUs but stay with the host
+    fn is_bridge(&self, gpu_id: &str) -> Result<bool, GpuShareError> {
+        let class = fs::read_to_string(self.pci_devices_path.join(gpu_id).join("class"))
+            .map_err(|e| GpuError::SystemError {
+                message: format!("Failed to read device class: {}", e),
+            })?;
+        Ok(class.trim().starts_with("0x060"))
+    }
+
+    fn rescan_pci_bus(&self) -> Result<(), GpuShareError> {
+        fs::write(&self.rescan_path, "1").map_err(|e| {
+            GpuError::SystemError {
+                message: format!("Failed to rescan PCI bus: {}", e),
+            }
+            .into()
+        })
+    }
 }
 
 // VFIO Group Management
@@ -317,16 +760,85 @@ impl DeviceManager {
 pub struct VfioGroup {
     group_id: u32,
     devices: Vec<String>,
+    pci_devices_path: PathBuf,
+}
+
+/// Where a group member ends up when its GPU is passed through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupMemberRole {
+    Gpu,
+    /// Another function of the same card (HDMI audio, USB-C, UCSI) - goes with the GPU
+    Companion,
+    /// Stays with the host, VFIO doesn't need it
+    Bridge,
+    /// Unrelated device - blocks isolation
+    Foreign,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupMember {
+    pub address: String,
+    pub role: GroupMemberRole,
+}
+
+/// IOMMU group membership sorted relative to one GPU
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IsolationPlan {
+    pub group_id: u32,
+    pub gpu: String,
+    pub members: Vec<GroupMember>,
+}
+
+impl IsolationPlan {
+    /// Passthrough is safe when nothing outside the card shares the group
+    pub fn is_isolated(&self) -> bool {
+        self.foreign_devices().is_empty()
+    }
+
+    pub fn foreign_devices(&self) -> Vec<&str> {
+        self.with_role(|role| role == GroupMemberRole::Foreign)
+    }
+
+    /// GPU plus its companions - everything that moves to vfio-pci
+    pub fn passthrough_devices(&self) -> Vec<&str> {
+        self.with_role(|role| matches!(role, GroupMemberRole::Gpu | GroupMemberRole::Companion))
+    }
+
+    fn with_role(&self, pred: impl Fn(GroupMemberRole) -> bool) -> Vec<&str> {
+        self.members
+            .iter()
+            .filter(|m| pred(m.role))
+            .map(|m| m.address.as_str())
+            .collect()
+    }
+}
+
+/// Functions of one card share domain:bus:slot and differ only in the function number
+fn same_card(a: &str, b: &str) -> bool {
+    match (a.rsplit_once('.'), b.rsplit_once('.')) {
+        (Some((slot_a, _)), Some((slot_b, _))) => slot_a == slot_b,
+        _ => false,
+    }
 }
 
 impl VfioGroup {
     pub fn new(group_id: u32) -> Result<Self, GpuShareError> {
+        Self::with_root(group_id, "/sys")
+    }
+
+    pub fn with_root(group_id: u32, sysfs_root: impl AsRef<Path>) -> Result<Self, GpuShareError> {
         Ok(Self {
             group_id,
             devices: Vec::new(),
+            pci_devices_path: sysfs_root.as_ref().join("bus/pci/devices"),
         })
     }
 
+    pub fn group_id(&self) -> u32 {
+        self.group_id
+    }
+
     pub fn add_device(&mut self, device_id: String) -> Result<(), GpuShareError> {
         if !self.devices.contains(&device_id) {
             self.devices.push(device_id);
@@ -344,10 +856,43 @@ impl VfioGroup {
         Ok(true)
     }
 
-    fn is_device_eligible(&self, device_id: &str) -> Result<bool, GpuShareError> {
-        let class_path = PathBuf::from("/sys/bus/pci/devices")
-            .join(device_id)
-            .join("class");
+    /// Classifies every member of the group relative to `gpu_address`
+    pub fn plan(&self, gpu_address: &str) -> Result<IsolationPlan, GpuShareError> {
+        if !self.devices.iter().any(|d| d == gpu_address) {
+            return Err(GpuError::IommuError {
+                message: format!("{} is not in IOMMU group {}", gpu_address, self.group_id),
+                group_id: Some(self.group_id),
+            }
+            .into());
+        }
+
+        let members = self
+            .devices
+            .iter()
+            .map(|address| {
+                let role = if address == gpu_address {
+                    GroupMemberRole::Gpu
+                } else if matches!(self.is_device_eligible(address), Ok(false)) {
+                    GroupMemberRole::Bridge
+                } else if same_card(address, gpu_address) {
+                    GroupMemberRole::Companion
+                } else {
+                    // Unreadable class counts as foreign - can't prove it's a bridge
+                    GroupMemberRole::Foreign
+                };
+                GroupMember { address: address.clone(), role }
+            })
+            .collect();
+
+        Ok(IsolationPlan {
+            group_id: self.group_id,
+            gpu: gpu_address.to_string(),
+            members,
+        })
+    }
+
+    pub(crate) fn is_device_eligible(&self, device_id: &str) -> Result<bool, GpuShareError> {
+        let class_path = self.pci_devices_path.join(device_id).join("class");
 
         let class = fs::read_to_string(class_path)
             .map_err(|e| GpuError::SystemError {
@@ -363,14 +908,18 @@ impl ErrorRecovery for PassthroughManager {
     fn recover(&self) -> Result<(), GpuShareError> {
         info!("Attempting to recover GPU passthrough configuration");
         
-        // Reset VFIO bindings
-        self.driver_manager.reset_vfio_bindings()?;
+        // Undo binds that never finished
+        self.reset_vfio_bindings()?;
         
         // Re-scan PCI bus
         self.device_manager.rescan_pci_bus()?;
         
         // Verify IOMMU groups
-        self.iommu_manager.verify_groups()?;
+        self.iommu_manager.verify_groups(
+            &self.journal_entries()?,
+            &self.driver_manager,
+            &self.device_manager,
+        )?;
         
         Ok(())
     }
@@ -378,19 +927,23 @@ impl ErrorRecovery for PassthroughManager {
     fn rollback(&self) -> Result<(), GpuShareError> {
         warn!("Rolling back GPU passthrough changes");
         
-        // Restore original drivers
-        self.driver_manager.restore_original_drivers()?;
-        
-        // Reset device state
-        self.device_manager.reset_devices()?;
+        // Restore original drivers - each device gets an FLR on the way back
+        self.restore_original_drivers()?;
         
         Ok(())
     }
 }
 
+/// `[passthrough]` section of Settings. Each of these trades isolation for
+/// compatibility, which is why they are all reported by `doctor`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct PassthroughConfig {
+    /// Set vfio_iommu_type1 allow_unsafe_interrupts (hosts without interrupt remapping)
     pub enable_unsafe_interrupts: bool,
+    /// Accept IOMMU groups split by the pcie_acs_override kernel patch
     pub enable_acs_override: bool,
+    /// Let vfio-pci runtime-suspend passed-through devices while unused
     pub enable_power_management: bool,
 }
 
@@ -404,3 +957,278 @@ impl Default for PassthroughConfig {
     }
 }
 
+impl PassthroughConfig {
+    /// From Settings, or defaults when there is no usable configuration
+    pub fn load() -> Self {
+        match Settings::new() {
+            Ok(settings) => settings.passthrough,
+            Err(e) => {
+                warn!("No passthrough settings ({}), using defaults", e);
+                Self::default()
+            }
+        }
+    }
+
+    /// What these settings give up, given how the kernel was booted
+    pub fn security_warnings(&self, cmdline: &KernelCmdline) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.enable_unsafe_interrupts {
+            warnings.push(
+                "enable_unsafe_interrupts: without interrupt remapping a guest can forge MSIs and \
+                 inject interrupts into the host"
+                    .to_string(),
+            );
+        }
+        match (&cmdline.pcie_acs_override, self.enable_acs_override) {
+            (Some(_), true) => warnings.push(
+                "enable_acs_override: groups split by pcie_acs_override do not stop peer-to-peer \
+                 DMA between devices - tenants are not isolated from each other"
+                    .to_string(),
+            ),
+            (Some(mode), false) => warnings.push(format!(
+                "pcie_acs_override={} is set but enable_acs_override is off - passthrough will be refused",
+                mode
+            )),
+            (None, true) => warnings.push(
+                "enable_acs_override is set but the kernel has no pcie_acs_override - it has no effect"
+                    .to_string(),
+            ),
+            (None, false) => {}
+        }
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu::fake_sysfs::FakeSysfs;
+    use crate::gpu::pci::PCI_VENDOR_NVIDIA;
+
+    const GPU: &str = "0000:65:00.0";
+    const AUDIO: &str = "0000:65:00.1";
+    const BRIDGE: &str = "0000:64:00.0";
+
+    /// RTX 4090 + its HDMI audio function behind a root port, all in group 30
+    fn rtx_group(sysfs: &FakeSysfs) {
+        sysfs.add_pci_device(GPU, PCI_VENDOR_NVIDIA, 0x2684, 0x030000);
+        sysfs.add_pci_device(AUDIO, PCI_VENDOR_NVIDIA, 0x22ba, 0x040300);
+        sysfs.add_pci_device(BRIDGE, 0x8086, 0x347a, 0x060400);
+        sysfs.bind_driver(GPU, "nvidia");
+        sysfs.bind_driver(AUDIO, "snd_hda_intel");
+        sysfs.bind_driver(BRIDGE, "pcieport");
+        for dev in [GPU, AUDIO, BRIDGE] {
+            sysfs.set_iommu_group(dev, 30);
+        }
+        sysfs.write(&format!("bus/pci/devices/{}/reset", GPU), "");
+        sysfs.write(
+            &format!("bus/pci/devices/{}/resource", GPU),
+            "0x00000000fb000000 0x00000000fbffffff 0x0000000000040200\n",
+        );
+    }
+
+    fn manager(sysfs: &FakeSysfs) -> PassthroughManager {
+        PassthroughManager::with_root(sysfs.root(), sysfs.path("journal.json")).unwrap()
+    }
+
+    /// What the kernel does once drivers_probe sees the override
+    fn kernel_binds_vfio(sysfs: &FakeSysfs) {
+        sysfs.bind_driver(GPU, VFIO_DRIVER);
+        sysfs.bind_driver(AUDIO, VFIO_DRIVER);
+    }
+
+    #[test]
+    fn test_bind_group_overrides_everything_but_the_bridge() {
+        let sysfs = FakeSysfs::new();
+        rtx_group(&sysfs);
+        let manager = manager(&sysfs);
+
+        let rebound = manager.bind_group_to_vfio(30).unwrap();
+        assert_eq!(rebound, vec![GPU.to_string(), AUDIO.to_string()]);
+
+        assert_eq!(sysfs.read(&format!("bus/pci/devices/{}/driver_override", GPU)), "vfio-pci\n");
+        assert_eq!(sysfs.read(&format!("bus/pci/devices/{}/driver_override", BRIDGE)), "(null)\n");
+        assert_eq!(sysfs.read("bus/pci/drivers/nvidia/unbind"), GPU);
+        assert_eq!(sysfs.read("bus/pci/drivers/snd_hda_intel/unbind"), AUDIO);
+
+        let journal = manager.journal_entries().unwrap();
+        assert_eq!(journal.len(), 2);
+        assert_eq!(journal[0].original_driver.as_deref(), Some("nvidia"));
+        assert!(journal.iter().all(|e| e.state == BindState::Bound));
+    }
+
+    #[test]
+    fn test_prepare_then_release_restores_drivers() {
+        let sysfs = FakeSysfs::new();
+        rtx_group(&sysfs);
+        let manager = manager(&sysfs);
+
+        manager.bind_group_to_vfio(30).unwrap();
+        kernel_binds_vfio(&sysfs);
+        // Second pass finds everything on vfio-pci already and just verifies
+        manager.prepare_gpu_passthrough(GPU).unwrap();
+
+        manager.release_gpu_passthrough(GPU).unwrap();
+        assert_eq!(sysfs.read(&format!("bus/pci/devices/{}/driver_override", GPU)), "\n");
+        assert_eq!(sysfs.read(&format!("bus/pci/devices/{}/reset", GPU)), "1");
+        assert_eq!(sysfs.read("bus/pci/drivers/nvidia/bind"), GPU);
+        assert_eq!(sysfs.read("bus/pci/drivers/snd_hda_intel/bind"), AUDIO);
+        assert!(manager.journal_entries().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_prepare_rolls_back_when_device_never_reaches_vfio() {
+        let sysfs = FakeSysfs::new();
+        rtx_group(&sysfs);
+        let manager = manager(&sysfs);
+
+        // Kernel never binds vfio-pci (module not loaded) - verification fails
+        assert!(manager.prepare_gpu_passthrough(GPU).is_err());
+        assert_eq!(sysfs.read(&format!("bus/pci/devices/{}/driver_override", GPU)), "\n");
+        // Still on nvidia as far as sysfs knows, so no rebind was needed
+        assert!(!sysfs.path("bus/pci/drivers/nvidia/bind").exists());
+    }
+
+    #[test]
+    fn test_rollback_after_crash_uses_persisted_journal() {
+        let sysfs = FakeSysfs::new();
+        rtx_group(&sysfs);
+
+        {
+            let manager = manager(&sysfs);
+            manager.bind_group_to_vfio(30).unwrap();
+            kernel_binds_vfio(&sysfs);
+        } // manager "crashes" here
+
+        let restarted = manager(&sysfs);
+        assert_eq!(restarted.journal_entries().unwrap().len(), 2);
+        restarted.rollback().unwrap();
+
+        assert_eq!(sysfs.read("bus/pci/drivers/nvidia/bind"), GPU);
+        assert!(restarted.journal_entries().unwrap().is_empty());
+        assert_eq!(sysfs.read("journal.json"), "[]");
+    }
+
+    #[test]
+    fn test_recover_restores_half_finished_binds() {
+        let sysfs = FakeSysfs::new();
+        rtx_group(&sysfs);
+        sysfs.write("journal.json", &format!(
+            r#"[{{"address":"{}","iommu_group":30,"original_driver":"nvidia","state":"binding"}}]"#,
+            GPU
+        ));
+        // Died after unbinding but before vfio-pci picked it up
+        fs::remove_file(sysfs.path(&format!("bus/pci/devices/{}/driver", GPU))).unwrap();
+
+        let manager = manager(&sysfs);
+        manager.recover().unwrap();
+        assert_eq!(sysfs.read("bus/pci/drivers/nvidia/bind"), GPU);
+        assert_eq!(sysfs.read("bus/pci/rescan"), "1");
+        assert!(manager.journal_entries().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_verify_groups_catches_split_group() {
+        let sysfs = FakeSysfs::new();
+        rtx_group(&sysfs);
+        let manager = manager(&sysfs);
+
+        manager.bind_group_to_vfio(30).unwrap();
+        // Only the GPU made it; audio function still on the host driver
+        sysfs.bind_driver(GPU, VFIO_DRIVER);
+        assert!(manager.recover().is_err());
+
+        sysfs.bind_driver(AUDIO, VFIO_DRIVER);
+        manager.recover().unwrap();
+    }
+
+    #[test]
+    fn test_boot_time_vfio_binding_left_alone() {
+        let sysfs = FakeSysfs::new();
+        rtx_group(&sysfs);
+        kernel_binds_vfio(&sysfs);
+        let manager = manager(&sysfs);
+
+        assert!(manager.bind_group_to_vfio(30).unwrap().is_empty());
+        manager.release_gpu_passthrough(GPU).unwrap();
+        assert!(!sysfs.path(&format!("bus/pci/drivers/{}/unbind", VFIO_DRIVER)).exists());
+    }
+
+    #[test]
+    fn test_plan_keeps_audio_with_gpu_and_bridge_with_host() {
+        let sysfs = FakeSysfs::new();
+        rtx_group(&sysfs);
+        let plan = manager(&sysfs).plan_isolation(GPU).unwrap();
+
+        assert!(plan.is_isolated());
+        assert_eq!(plan.passthrough_devices(), vec![GPU, AUDIO]);
+        let bridge = plan.members.iter().find(|m| m.address == BRIDGE).unwrap();
+        assert_eq!(bridge.role, GroupMemberRole::Bridge);
+    }
+
+    #[test]
+    fn test_foreign_device_blocks_passthrough() {
+        const NVME: &str = "0000:66:00.0";
+        let sysfs = FakeSysfs::new();
+        rtx_group(&sysfs);
+        sysfs.add_pci_device(NVME, 0x144d, 0xa808, 0x010802);
+        sysfs.bind_driver(NVME, "nvme");
+        sysfs.set_iommu_group(NVME, 30);
+        let manager = manager(&sysfs);
+
+        assert_eq!(manager.plan_isolation(GPU).unwrap().foreign_devices(), vec![NVME]);
+        let err = manager.prepare_gpu_passthrough(GPU).unwrap_err();
+        assert!(err.to_string().contains(NVME));
+        // Refused before any sysfs write
+        assert!(manager.journal_entries().unwrap().is_empty());
+        assert_eq!(sysfs.read(&format!("bus/pci/devices/{}/driver_override", GPU)), "(null)\n");
+    }
+
+    #[test]
+    fn test_acs_override_needs_opt_in() {
+        let sysfs = FakeSysfs::new();
+        rtx_group(&sysfs);
+        sysfs.write("proc/cmdline", "intel_iommu=on pcie_acs_override=downstream,multifunction\n");
+        let manager = manager(&sysfs).with_proc_root(sysfs.path("proc"));
+
+        let err = manager.prepare_gpu_passthrough(GPU).unwrap_err();
+        assert!(err.to_string().contains("enable_acs_override"));
+        assert!(manager.journal_entries().unwrap().is_empty());
+
+        let manager = manager.with_config(PassthroughConfig {
+            enable_acs_override: true,
+            ..Default::default()
+        });
+        assert_eq!(manager.bind_group_to_vfio(30).unwrap().len(), 2);
+        kernel_binds_vfio(&sysfs);
+        manager.prepare_gpu_passthrough(GPU).unwrap();
+    }
+
+    #[test]
+    fn test_config_applies_interrupts_and_runtime_pm() {
+        let sysfs = FakeSysfs::new();
+        rtx_group(&sysfs);
+        sysfs.write(UNSAFE_INTERRUPTS_PARAM, "N\n");
+        for dev in [GPU, AUDIO] {
+            sysfs.write(&format!("bus/pci/devices/{}/power/control", dev), "on\n");
+        }
+        kernel_binds_vfio(&sysfs);
+
+        let manager = manager(&sysfs).with_config(PassthroughConfig {
+            enable_unsafe_interrupts: true,
+            ..Default::default()
+        });
+        manager.prepare_gpu_passthrough(GPU).unwrap();
+        assert_eq!(sysfs.read(UNSAFE_INTERRUPTS_PARAM), "Y");
+        assert_eq!(sysfs.read(&format!("bus/pci/devices/{}/power/control", GPU)), "auto");
+        assert_eq!(sysfs.read(&format!("bus/pci/devices/{}/power/control", AUDIO)), "auto");
+
+        // PM off pins the devices in D0
+        let manager = manager.with_config(PassthroughConfig {
+            enable_power_management: false,
+            ..Default::default()
+        });
+        manager.prepare_gpu_passthrough(GPU).unwrap();
+        assert_eq!(sysfs.read(&format!("bus/pci/devices/{}/power/control", GPU)), "on");
+    }
+}
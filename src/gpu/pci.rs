@@ -0,0 +1,438 @@
+/*
+* Vendor-agnostic PCI/DRM GPU scanner
+* -----------------------------------
+* Walks `/sys/bus/pci/devices`, keeps display controllers (PCI class 0x03xxxx)
+* from the vendors we support and reads what sysfs knows about them:
+*
+* - identity: vendor/device/subsystem ids, model name from the bundled pci.ids
+* - driver:   bound driver name and module version (if the module exports one)
+* - memory:   VRAM and GTT sizes for amdgpu (`mem_info_*_total`, bytes) and
+*             VRAM for Intel xe (`tileN/physical_vram_size_bytes`)
+* - topology: IOMMU group and the DRM card node, if a DRM driver owns it
+* - SR-IOV:   VF counts and `virtfnN` links on physical functions, `physfn`
+*             on the virtual functions themselves
+*
+* Scanning the PCI bus rather than `/sys/class/drm` means cards already bound
+* to vfio-pci (and therefore without a DRM node) still show up.
+*/
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::gpu::device::{get_iommu_group, GPUInfo};
+
+pub const PCI_VENDOR_NVIDIA: u16 = 0x10de;
+pub const PCI_VENDOR_AMD: u16 = 0x1002;
+pub const PCI_VENDOR_INTEL: u16 = 0x8086;
+
+/// PCI base class for display controllers (VGA, 3D, other)
+const PCI_CLASS_DISPLAY: u32 = 0x03;
+
+static PCI_IDS: &str = include_str!("pci.ids");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GpuVendor {
+    Nvidia,
+    Amd,
+    Intel,
+}
+
+impl GpuVendor {
+    pub fn from_pci_id(vendor_id: u16) -> Option<Self> {
+        match vendor_id {
+            PCI_VENDOR_NVIDIA => Some(GpuVendor::Nvidia),
+            PCI_VENDOR_AMD => Some(GpuVendor::Amd),
+            PCI_VENDOR_INTEL => Some(GpuVendor::Intel),
+            _ => None,
+        }
+    }
+
+    pub fn pci_id(&self) -> u16 {
+        match self {
+            GpuVendor::Nvidia => PCI_VENDOR_NVIDIA,
+            GpuVendor::Amd => PCI_VENDOR_AMD,
+            GpuVendor::Intel => PCI_VENDOR_INTEL,
+        }
+    }
+
+    /// Short name, as used in `GPUInfo::vendor`
+    pub fn name(&self) -> &'static str {
+        match self {
+            GpuVendor::Nvidia => "NVIDIA",
+            GpuVendor::Amd => "AMD",
+            GpuVendor::Intel => "Intel",
+        }
+    }
+}
+
+/// A GPU as seen on the PCI bus
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PciGpu {
+    /// PCI address, e.g. `0000:03:00.0`
+    pub address: String,
+    pub vendor: GpuVendor,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub subsystem_vendor_id: Option<u16>,
+    pub subsystem_device_id: Option<u16>,
+    pub class: u32,
+    pub model: String,
+    pub driver: Option<String>,
+    pub driver_version: Option<String>,
+    pub vram_mb: Option<u64>,
+    pub gtt_mb: Option<u64>,
+    pub iommu_group: Option<u64>,
+    pub drm_card: Option<String>,
+    /// Parent physical function if this is an SR-IOV virtual function
+    pub physfn: Option<String>,
+    /// SR-IOV capability of a physical function
+    pub sriov: Option<SriovInfo>,
+}
+
+/// What a physical function reports about its SR-IOV virtual functions
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SriovInfo {
+    pub total_vfs: u32,
+    pub num_vfs: u32,
+    /// PCI addresses of the currently enabled VFs, in `virtfnN` order
+    pub virtual_functions: Vec<String>,
+}
+
+impl PciGpu {
+    pub fn is_virtual_function(&self) -> bool {
+        self.physfn.is_some()
+    }
+
+    pub fn to_gpu_info(&self) -> GPUInfo {
+        GPUInfo {
+            id: self.address.clone(),
+            vendor: self.vendor.name().into(),
+            model: self.model.clone(),
+            vram_mb: self.vram_mb.unwrap_or(0),
+            driver_version: self.driver_version.clone().unwrap_or_default(),
+            vulkan_support: Some(true),
+            iommu_group: self.iommu_group,
+            pci_address: Some(self.address.clone()),
+            driver: self.driver.clone(),
+            gtt_mb: self.gtt_mb,
+            ..Default::default()
+        }
+    }
+}
+
+pub struct PciScanner {
+    sysfs_root: PathBuf,
+}
+
+impl Default for PciScanner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PciScanner {
+    pub fn new() -> Self {
+        Self::with_root("/sys")
+    }
+
+    /// Scanner over an alternative sysfs mount - handy for tests and containers
+    pub fn with_root(sysfs_root: impl Into<PathBuf>) -> Self {
+        Self { sysfs_root: sysfs_root.into() }
+    }
+
+    pub fn sysfs_root(&self) -> &Path {
+        &self.sysfs_root
+    }
+
+    pub fn pci_device_path(&self, address: &str) -> PathBuf {
+        self.sysfs_root.join("bus/pci/devices").join(address)
+    }
+
+    /// All supported GPUs on the bus, sorted by PCI address
+    pub fn scan(&self) -> Result<Vec<PciGpu>> {
+        let devices_dir = self.sysfs_root.join("bus/pci/devices");
+        let entries = match fs::read_dir(&devices_dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).context(format!("Failed to read {}", devices_dir.display())),
+        };
+
+        let mut gpus = Vec::new();
+        for entry in entries {
+            let address = entry?.file_name().to_string_lossy().into_owned();
+            if let Some(gpu) = self.read_device(&address)? {
+                gpus.push(gpu);
+            }
+        }
+        gpus.sort_by(|a, b| a.address.cmp(&b.address));
+
+        Ok(gpus)
+    }
+
+    /// Reads one PCI function; `None` if it isn't a GPU from a supported vendor
+    pub fn read_device(&self, address: &str) -> Result<Option<PciGpu>> {
+        let path = self.pci_device_path(address);
+        let class = read_hex_attr(&path, "class")?;
+        if class >> 16 != PCI_CLASS_DISPLAY {
+            return Ok(None);
+        }
+
+        let vendor_id = read_hex_attr(&path, "vendor")? as u16;
+        let Some(vendor) = GpuVendor::from_pci_id(vendor_id) else {
+            return Ok(None);
+        };
+        let device_id = read_hex_attr(&path, "device")? as u16;
+        let driver = read_link_name(&path.join("driver"));
+
+        Ok(Some(PciGpu {
+            address: address.to_string(),
+            vendor,
+            vendor_id,
+            device_id,
+            subsystem_vendor_id: read_hex_attr(&path, "subsystem_vendor").ok().map(|v| v as u16),
+            subsystem_device_id: read_hex_attr(&path, "subsystem_device").ok().map(|v| v as u16),
+            class,
+            model: model_name(vendor_id, device_id),
+            driver_version: driver.as_deref().and_then(|d| self.read_module_version(d)),
+            driver,
+            vram_mb: match vendor {
+                GpuVendor::Intel => super::intel::read_xe_vram_mb(&path),
+                _ => read_bytes_as_mb(&path, "mem_info_vram_total"),
+            },
+            gtt_mb: read_bytes_as_mb(&path, "mem_info_gtt_total"),
+            iommu_group: get_iommu_group(&path)?,
+            drm_card: find_drm_card(&path),
+            physfn: read_link_name(&path.join("physfn")),
+            sriov: read_sriov(&path),
+        }))
+    }
+
+    /// SR-IOV state of the function at `address`, `None` if it has no SR-IOV capability
+    pub fn sriov_info(&self, address: &str) -> Option<SriovInfo> {
+        read_sriov(&self.pci_device_path(address))
+    }
+
+    /// `/sys/module/<driver>/version` - out-of-tree modules (nvidia) export one, in-tree ones don't
+    fn read_module_version(&self, driver: &str) -> Option<String> {
+        let module = driver.replace('-', "_");
+        fs::read_to_string(self.sysfs_root.join("module").join(module).join("version"))
+            .ok()
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+    }
+}
+
+/// Reads a `0x`-prefixed hex sysfs attribute (vendor, device, class, ...)
+pub fn read_hex_attr(path: &Path, attr: &str) -> Result<u32> {
+    let raw = fs::read_to_string(path.join(attr))
+        .with_context(|| format!("Failed to read {}/{}", path.display(), attr))?;
+    let raw = raw.trim();
+    u32::from_str_radix(raw.trim_start_matches("0x"), 16)
+        .map_err(|e| anyhow!("Invalid {} value '{}': {}", attr, raw, e))
+}
+
+/// Name of the directory a sysfs symlink points at (driver, iommu_group, ...)
+pub fn read_link_name(link: &Path) -> Option<String> {
+    fs::read_link(link)
+        .ok()?
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+}
+
+/// `sriov_totalvfs` only exists on functions with the SR-IOV capability
+fn read_sriov(path: &Path) -> Option<SriovInfo> {
+    let read_u32 = |attr: &str| -> Option<u32> { fs::read_to_string(path.join(attr)).ok()?.trim().parse().ok() };
+
+    let total_vfs = read_u32("sriov_totalvfs")?;
+    let num_vfs = read_u32("sriov_numvfs").unwrap_or(0);
+    let virtual_functions = (0..num_vfs)
+        .map_while(|i| read_link_name(&path.join(format!("virtfn{}", i))))
+        .collect();
+
+    Some(SriovInfo { total_vfs, num_vfs, virtual_functions })
+}
+
+pub(crate) fn read_bytes_as_mb(path: &Path, attr: &str) -> Option<u64> {
+    fs::read_to_string(path.join(attr))
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(|bytes| bytes / 1024 / 1024)
+}
+
+pub(crate) fn find_drm_card(path: &Path) -> Option<String> {
+    fs::read_dir(path.join("drm"))
+        .ok()?
+        .filter_map(|e| e.ok())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .find(|name| name.strip_prefix("card").is_some_and(|n| n.chars().all(|c| c.is_ascii_digit())))
+}
+
+struct PciIds {
+    vendors: HashMap<u16, &'static str>,
+    devices: HashMap<(u16, u16), &'static str>,
+}
+
+fn pci_ids() -> &'static PciIds {
+    static IDS: OnceLock<PciIds> = OnceLock::new();
+    IDS.get_or_init(|| parse_pci_ids(PCI_IDS))
+}
+
+fn parse_pci_ids(data: &'static str) -> PciIds {
+    let mut ids = PciIds { vendors: HashMap::new(), devices: HashMap::new() };
+    let mut current_vendor = None;
+
+    for line in data.lines() {
+        if line.starts_with('#') || line.trim().is_empty() || line.starts_with("\t\t") {
+            continue;
+        }
+        let (indented, entry) = match line.strip_prefix('\t') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let Some((id, name)) = entry.split_once("  ") else { continue };
+        let Ok(id) = u16::from_str_radix(id.trim(), 16) else { continue };
+
+        if indented {
+            if let Some(vendor) = current_vendor {
+                ids.devices.insert((vendor, id), name.trim());
+            }
+        } else {
+            current_vendor = Some(id);
+            ids.vendors.insert(id, name.trim());
+        }
+    }
+
+    ids
+}
+
+/// Human-readable device name, falling back to the raw ids for unknown devices
+pub fn model_name(vendor_id: u16, device_id: u16) -> String {
+    let ids = pci_ids();
+    match ids.devices.get(&(vendor_id, device_id)) {
+        Some(name) => name.to_string(),
+        None => match ids.vendors.get(&vendor_id) {
+            Some(vendor) => format!("{} Device {:04x}", vendor, device_id),
+            None => format!("Device {:04x}:{:04x}", vendor_id, device_id),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu::fake_sysfs::FakeSysfs;
+
+    fn amd_card(sysfs: &FakeSysfs) {
+        sysfs.add_pci_device("0000:03:00.0", PCI_VENDOR_AMD, 0x744c, 0x030000);
+        sysfs.bind_driver("0000:03:00.0", "amdgpu");
+        sysfs.set_iommu_group("0000:03:00.0", 14);
+        sysfs.add_drm_card("card1", "0000:03:00.0");
+        sysfs.write("bus/pci/devices/0000:03:00.0/mem_info_vram_total", "25753026560\n");
+        sysfs.write("bus/pci/devices/0000:03:00.0/mem_info_gtt_total", "33554432000\n");
+    }
+
+    #[test]
+    fn test_scan_identifies_vendors() {
+        let sysfs = FakeSysfs::new();
+        amd_card(&sysfs);
+        sysfs.add_pci_device("0000:00:02.0", PCI_VENDOR_INTEL, 0xa780, 0x030000);
+        sysfs.add_pci_device("0000:65:00.0", PCI_VENDOR_NVIDIA, 0x2330, 0x030200);
+        // Not GPUs: NVIDIA audio function, AMD host bridge, foreign display controller
+        sysfs.add_pci_device("0000:65:00.1", PCI_VENDOR_NVIDIA, 0x22ba, 0x040300);
+        sysfs.add_pci_device("0000:00:00.0", PCI_VENDOR_AMD, 0x14d8, 0x060000);
+        sysfs.add_pci_device("0000:04:00.0", 0x1a03, 0x2000, 0x030000);
+
+        let gpus = PciScanner::with_root(sysfs.root()).scan().unwrap();
+        let vendors: Vec<_> = gpus.iter().map(|g| (g.address.as_str(), g.vendor)).collect();
+        assert_eq!(vendors, vec![
+            ("0000:00:02.0", GpuVendor::Intel),
+            ("0000:03:00.0", GpuVendor::Amd),
+            ("0000:65:00.0", GpuVendor::Nvidia),
+        ]);
+    }
+
+    #[test]
+    fn test_amd_memory_driver_and_topology() {
+        let sysfs = FakeSysfs::new();
+        amd_card(&sysfs);
+
+        let gpu = PciScanner::with_root(sysfs.root()).read_device("0000:03:00.0").unwrap().unwrap();
+        assert_eq!(gpu.model, "Navi 31 [Radeon RX 7900 XT/7900 XTX/7900 GRE/7900M]");
+        // 24560 MiB of VRAM - bytes / 1024 / 1024, not / 1024
+        assert_eq!(gpu.vram_mb, Some(24560));
+        assert_eq!(gpu.gtt_mb, Some(32000));
+        assert_eq!(gpu.driver.as_deref(), Some("amdgpu"));
+        assert_eq!(gpu.driver_version, None);
+        assert_eq!(gpu.iommu_group, Some(14));
+        assert_eq!(gpu.drm_card.as_deref(), Some("card1"));
+
+        let info = gpu.to_gpu_info();
+        assert_eq!(info.id, "0000:03:00.0");
+        assert_eq!(info.vendor, "AMD");
+        assert_eq!(info.vram_mb, 24560);
+    }
+
+    #[test]
+    fn test_module_version_and_vfio_bound_card() {
+        let sysfs = FakeSysfs::new();
+        sysfs.add_pci_device("0000:65:00.0", PCI_VENDOR_NVIDIA, 0x2330, 0x030200);
+        sysfs.bind_driver("0000:65:00.0", "nvidia");
+        sysfs.write("module/nvidia/version", "550.54.14\n");
+        sysfs.add_pci_device("0000:b3:00.0", PCI_VENDOR_NVIDIA, 0x20b5, 0x030200);
+        sysfs.bind_driver("0000:b3:00.0", "vfio-pci");
+
+        let scanner = PciScanner::with_root(sysfs.root());
+        let h100 = scanner.read_device("0000:65:00.0").unwrap().unwrap();
+        assert_eq!(h100.model, "GH100 [H100 SXM5 80GB]");
+        assert_eq!(h100.driver_version.as_deref(), Some("550.54.14"));
+
+        let a100 = scanner.read_device("0000:b3:00.0").unwrap().unwrap();
+        assert_eq!(a100.driver.as_deref(), Some("vfio-pci"));
+        assert_eq!(a100.drm_card, None);
+        assert_eq!(a100.vram_mb, None);
+    }
+
+    #[test]
+    fn test_sriov_physical_and_virtual_functions() {
+        let sysfs = FakeSysfs::new();
+        sysfs.add_pci_device("0000:4d:00.0", PCI_VENDOR_INTEL, 0x56c0, 0x038000);
+        sysfs.bind_driver("0000:4d:00.0", "i915");
+        sysfs.add_sriov("0000:4d:00.0", 0x56c0, 31, &["0000:4d:00.1", "0000:4d:00.2"]);
+
+        let scanner = PciScanner::with_root(sysfs.root());
+        let gpus = scanner.scan().unwrap();
+        assert_eq!(gpus.len(), 3);
+
+        let pf = &gpus[0];
+        assert!(!pf.is_virtual_function());
+        assert_eq!(pf.sriov, Some(SriovInfo {
+            total_vfs: 31,
+            num_vfs: 2,
+            virtual_functions: vec!["0000:4d:00.1".into(), "0000:4d:00.2".into()],
+        }));
+
+        let vf = &gpus[1];
+        assert_eq!(vf.physfn.as_deref(), Some("0000:4d:00.0"));
+        assert_eq!(vf.sriov, None);
+        assert_eq!(scanner.sriov_info("0000:4d:00.2"), None);
+    }
+
+    #[test]
+    fn test_model_name_fallbacks() {
+        assert_eq!(model_name(PCI_VENDOR_INTEL, 0x56a0), "DG2 [Arc A770]");
+        assert_eq!(model_name(PCI_VENDOR_AMD, 0xffff), "Advanced Micro Devices, Inc. [AMD/ATI] Device ffff");
+        assert_eq!(model_name(0x1234, 0x1111), "Device 1234:1111");
+    }
+
+    #[test]
+    fn test_missing_sysfs_yields_no_gpus() {
+        let scanner = PciScanner::with_root("/nonexistent/sysfs");
+        assert!(scanner.scan().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,201 @@
+/*
+* SR-IOV virtual function management
+* ----------------------------------
+* Data-centre GPUs (Intel Flex/Max, AMD Instinct MxGPU, NVIDIA vGPU on Ampere+)
+* can split themselves into PCI virtual functions. Each VF has its own PCI
+* address and IOMMU group, so once created it is passed through exactly like a
+* whole card.
+*
+* The kernel side is two files on the physical function:
+*   sriov_totalvfs - how many VFs the hardware supports (read-only)
+*   sriov_numvfs   - how many are enabled right now; writing N creates them,
+*                    writing 0 destroys them. Changing a non-zero count to
+*                    another non-zero count is EBUSY, so we always go via 0.
+*
+* Pool bookkeeping lives in `GPUPool::register_virtual_functions`.
+*/
+
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+
+use crate::gpu::device::{get_iommu_group, GPUConfig};
+use crate::gpu::pci::{read_link_name, PciScanner, SriovInfo};
+
+pub struct SriovManager {
+    scanner: PciScanner,
+}
+
+impl Default for SriovManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SriovManager {
+    pub fn new() -> Self {
+        Self { scanner: PciScanner::new() }
+    }
+
+    pub fn with_root(sysfs_root: impl Into<std::path::PathBuf>) -> Self {
+        Self { scanner: PciScanner::with_root(sysfs_root) }
+    }
+
+    /// Current VF state of a physical function
+    pub fn status(&self, pf: &str) -> Result<SriovInfo> {
+        self.scanner
+            .sriov_info(pf)
+            .ok_or_else(|| anyhow!("{} does not support SR-IOV", pf))
+    }
+
+    /// Physical functions on the bus that can be split into VFs
+    pub fn capable_gpus(&self) -> Result<Vec<(String, SriovInfo)>> {
+        Ok(self.scanner
+            .scan()?
+            .into_iter()
+            .filter_map(|gpu| gpu.sriov.map(|sriov| (gpu.address, sriov)))
+            .filter(|(_, sriov)| sriov.total_vfs > 0)
+            .collect())
+    }
+
+    /// Enables `count` VFs on `pf`, replacing whatever was enabled before.
+    /// Returns the state read back afterwards.
+    pub fn create_vfs(&self, pf: &str, count: u32) -> Result<SriovInfo> {
+        let current = self.status(pf)?;
+        if count == 0 {
+            return Err(anyhow!("Refusing to create 0 VFs on {} - use destroy_vfs", pf));
+        }
+        if count > current.total_vfs {
+            return Err(anyhow!(
+                "{} supports at most {} VFs, {} requested",
+                pf, current.total_vfs, count
+            ));
+        }
+        // The PF driver implements sriov_configure; without one the write just fails
+        if read_link_name(&self.scanner.pci_device_path(pf).join("driver")).is_none() {
+            return Err(anyhow!("{} has no driver bound, cannot enable VFs", pf));
+        }
+
+        if current.num_vfs == count {
+            return Ok(current);
+        }
+        if current.num_vfs > 0 {
+            self.write_numvfs(pf, 0)?;
+        }
+        self.write_numvfs(pf, count)?;
+        self.status(pf)
+    }
+
+    /// Disables every VF on `pf`. Callers must make sure none are in use -
+    /// the kernel will happily yank a VF out from under a running VM.
+    pub fn destroy_vfs(&self, pf: &str) -> Result<()> {
+        if self.status(pf)?.num_vfs > 0 {
+            self.write_numvfs(pf, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Hostdev config for a VF, ready for `VMConfig::gpu_passthrough`
+    pub fn passthrough_config(&self, vf: &str) -> Result<GPUConfig> {
+        let path = self.scanner.pci_device_path(vf);
+        if read_link_name(&path.join("physfn")).is_none() {
+            return Err(anyhow!("{} is not an SR-IOV virtual function", vf));
+        }
+        let iommu_group = get_iommu_group(&path)?
+            .ok_or_else(|| anyhow!("{} has no IOMMU group - is the IOMMU enabled?", vf))?;
+
+        Ok(GPUConfig { gpu_id: vf.to_string(), iommu_group })
+    }
+
+    fn write_numvfs(&self, pf: &str, count: u32) -> Result<()> {
+        let path = self.scanner.pci_device_path(pf).join("sriov_numvfs");
+        fs::write(&path, format!("{}\n", count))
+            .with_context(|| format!("Failed to set {} VFs on {}", count, pf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpu::fake_sysfs::FakeSysfs;
+    use crate::gpu::pci::PCI_VENDOR_INTEL;
+    use crate::gpu::virtual_gpu::{GPUPool, GpuBacking};
+
+    const PF: &str = "0000:4d:00.0";
+
+    fn flex_170(sysfs: &FakeSysfs) {
+        sysfs.add_pci_device(PF, PCI_VENDOR_INTEL, 0x56c0, 0x038000);
+        sysfs.bind_driver(PF, "i915");
+        sysfs.write(&format!("bus/pci/devices/{}/sriov_totalvfs", PF), "31\n");
+        sysfs.write(&format!("bus/pci/devices/{}/sriov_numvfs", PF), "0\n");
+    }
+
+    fn numvfs(sysfs: &FakeSysfs) -> String {
+        fs::read_to_string(sysfs.path(&format!("bus/pci/devices/{}/sriov_numvfs", PF)))
+            .unwrap()
+            .trim()
+            .to_string()
+    }
+
+    #[test]
+    fn test_create_and_destroy_vfs() {
+        let sysfs = FakeSysfs::new();
+        flex_170(&sysfs);
+        let manager = SriovManager::with_root(sysfs.root());
+
+        assert_eq!(manager.capable_gpus().unwrap().len(), 1);
+        let state = manager.create_vfs(PF, 4).unwrap();
+        assert_eq!(state.num_vfs, 4);
+        assert_eq!(numvfs(&sysfs), "4");
+
+        manager.destroy_vfs(PF).unwrap();
+        assert_eq!(numvfs(&sysfs), "0");
+    }
+
+    #[test]
+    fn test_create_vfs_validation() {
+        let sysfs = FakeSysfs::new();
+        flex_170(&sysfs);
+        sysfs.add_pci_device("0000:03:00.0", PCI_VENDOR_INTEL, 0x56a0, 0x030000);
+        let manager = SriovManager::with_root(sysfs.root());
+
+        assert!(manager.create_vfs(PF, 32).is_err());
+        assert!(manager.create_vfs(PF, 0).is_err());
+        // Arc A770 has no SR-IOV capability
+        assert!(manager.create_vfs("0000:03:00.0", 1).is_err());
+        assert_eq!(numvfs(&sysfs), "0");
+    }
+
+    #[test]
+    fn test_vfs_registered_and_passed_through() {
+        let sysfs = FakeSysfs::new();
+        flex_170(&sysfs);
+        // What the kernel does after `echo 2 > sriov_numvfs`
+        sysfs.add_sriov(PF, 0x56c0, 31, &["0000:4d:00.1", "0000:4d:00.2"]);
+        sysfs.set_iommu_group("0000:4d:00.1", 71);
+        sysfs.set_iommu_group("0000:4d:00.2", 72);
+        let manager = SriovManager::with_root(sysfs.root());
+
+        let state = manager.status(PF).unwrap();
+        let mut pool = GPUPool::new();
+        let ids = pool.register_virtual_functions(PF, &state.virtual_functions, 1024, 4);
+        assert_eq!(ids.len(), 2);
+        // Registering again is a no-op
+        assert!(pool.register_virtual_functions(PF, &state.virtual_functions, 1024, 4).is_empty());
+
+        let vf = &pool.gpus[&ids[1]];
+        assert_eq!(vf.backing, GpuBacking::SriovVf {
+            physical_function: PF.into(),
+            address: "0000:4d:00.2".into(),
+        });
+
+        let config = manager.passthrough_config("0000:4d:00.2").unwrap();
+        assert_eq!(config.iommu_group, 72);
+        assert!(manager.passthrough_config(PF).is_err());
+
+        pool.allocate("alice", ids[0]).unwrap();
+        assert!(pool.unregister_virtual_functions(PF).is_err());
+        pool.release(ids[0]).unwrap();
+        assert_eq!(pool.unregister_virtual_functions(PF).unwrap(), 2);
+        assert_eq!(pool.gpus.len(), 2);
+    }
+}
@@ -1,10 +1,12 @@
 use anyhow::Result;
 use tracing::info;
 
+use super::iommu::IommuDiagnostics;
+
 // IOMMU-chan, notice me!
 pub fn check_iommu_support() -> Result<bool> {
     info!("Checking if IOMMU-senpai is available...");
-    Ok(true)  // We're optimistic! 
+    Ok(IommuDiagnostics::new().report().iommu_enabled())  // No more blind optimism
 }
 
 // Time to get that sweet vendor info
@@ -1,17 +1,104 @@
+use chrono::{DateTime, Utc};
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
 use anyhow::{Result, anyhow};
 
+use crate::gpu::device::GPUInfo;
+use crate::gpu::mdev::MdevDevice;
+use crate::gpu::mig::MigInstance;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VirtualGPU {
     pub id: u32,
     pub vram_mb: u32,
     pub compute_units: u32,
     pub allocated_to: Option<String>,
+    #[serde(default)]
+    pub backing: GpuBacking,
+    /// PCI address of the physical card this entry lives on, when known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pci_address: Option<String>,
+    #[serde(default)]
+    pub state: ServiceState,
+}
+
+/// Whether an entry takes new allocations. Existing leases are left running
+/// in every state unless a drain migrates them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum ServiceState {
+    #[default]
+    Active,
+    /// Taken out of service by an operator
+    Draining { reason: String, since: DateTime<Utc> },
+    /// Taken out of service by a failed health check
+    Quarantined { reason: String, since: DateTime<Utc> },
+}
+
+impl ServiceState {
+    pub fn is_active(&self) -> bool {
+        matches!(self, ServiceState::Active)
+    }
+}
+
+/// What a pool entry actually is on the host
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum GpuBacking {
+    /// A whole physical card
+    #[default]
+    Whole,
+    /// One SR-IOV virtual function of a physical card
+    SriovVf { physical_function: String, address: String },
+    /// An NVIDIA MIG slice (GPU instance + compute instance)
+    Mig {
+        parent_uuid: String,
+        gpu_instance_id: u32,
+        compute_instance_id: u32,
+        profile: String,
+        uuid: String,
+    },
+    /// A mediated device (NVIDIA vGPU, Intel GVT-g) on a physical card
+    Mdev { parent: String, mdev_type: String, uuid: String },
+}
+
+impl VirtualGPU {
+    /// `MIG-...` UUID for NVIDIA_VISIBLE_DEVICES, if this entry is a MIG slice
+    pub fn mig_uuid(&self) -> Option<&str> {
+        match &self.backing {
+            GpuBacking::Mig { uuid, .. } => Some(uuid),
+            _ => None,
+        }
+    }
+
+    /// Whether this entry is (a slice of) the physical `gpu`
+    pub fn runs_on(&self, gpu: &GPUInfo) -> bool {
+        match &self.backing {
+            GpuBacking::Mig { parent_uuid, .. } => *parent_uuid == gpu.id,
+            _ => self.pci_address.is_some() && self.pci_address == gpu.pci_address,
+        }
+    }
+
+    /// Mediated device UUID for `VMConfig::gpu_mdev`, if this entry is an mdev
+    pub fn mdev_uuid(&self) -> Option<&str> {
+        match &self.backing {
+            GpuBacking::Mdev { uuid, .. } => Some(uuid),
+            _ => None,
+        }
+    }
 }
 
 pub struct GPUPool {
     pub gpus: HashMap<u32, VirtualGPU>,
+    /// (old entry, tenant) -> entry `migrate_lease` moved the lease to.
+    /// Containers keep the old id in their lease label.
+    moved_leases: HashMap<(u32, String), u32>,
+}
+
+impl Default for GPUPool {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl GPUPool {
@@ -21,15 +108,21 @@ impl GPUPool {
             id: 0,
             vram_mb: 8192,
             compute_units: 32,
-            allocated_to: None
+            allocated_to: None,
+            backing: GpuBacking::Whole,
+            pci_address: None,
+            state: ServiceState::Active,
         });
         gpus.insert(1, VirtualGPU {
             id: 1,
             vram_mb: 16384,
             compute_units: 64,
-            allocated_to: None
+            allocated_to: None,
+            backing: GpuBacking::Whole,
+            pci_address: None,
+            state: ServiceState::Active,
         });
-        Self { gpus }
+        Self { gpus, moved_leases: HashMap::new() }
     }
     
     pub fn allocate(&mut self, user: &str, gpu_id: u32) -> anyhow::Result<f64> {
@@ -37,6 +130,15 @@ impl GPUPool {
         if gpu.allocated_to.is_some() {
             return Err(anyhow!("GPU already allocated"));
         }
+        match &gpu.state {
+            ServiceState::Active => {}
+            ServiceState::Draining { reason, .. } => {
+                return Err(anyhow!("GPU {} is draining: {}", gpu_id, reason));
+            }
+            ServiceState::Quarantined { reason, .. } => {
+                return Err(anyhow!("GPU {} is quarantined: {}", gpu_id, reason));
+            }
+        }
         
         gpu.allocated_to = Some(user.to_string());
         let cost = self.calculate_cost(gpu_id)?;
@@ -53,9 +155,287 @@ impl GPUPool {
             .ok_or_else(|| anyhow!("GPU not found"))?;
         
         gpu.allocated_to = None;
+        self.moved_leases.retain(|_, to| *to != gpu_id);
         Ok(())
     }
     
+    /// Adds each VF of `physical_function` as its own allocatable GPU.
+    /// Already registered VFs are skipped; returns the ids of the new entries.
+    pub fn register_virtual_functions(
+        &mut self,
+        physical_function: &str,
+        vf_addresses: &[String],
+        vram_mb_per_vf: u32,
+        compute_units_per_vf: u32,
+    ) -> Vec<u32> {
+        let mut added = Vec::new();
+        for address in vf_addresses {
+            let known = self.gpus.values().any(|g| matches!(
+                &g.backing,
+                GpuBacking::SriovVf { address: a, .. } if a == address
+            ));
+            if known {
+                continue;
+            }
+
+            let id = self.next_id();
+            self.gpus.insert(id, VirtualGPU {
+                id,
+                vram_mb: vram_mb_per_vf,
+                compute_units: compute_units_per_vf,
+                allocated_to: None,
+                backing: GpuBacking::SriovVf {
+                    physical_function: physical_function.to_string(),
+                    address: address.clone(),
+                },
+                pci_address: Some(physical_function.to_string()),
+                state: ServiceState::Active,
+            });
+            added.push(id);
+        }
+        added
+    }
+
+    /// Drops every VF of `physical_function` from the pool - all of them or none,
+    /// so VFs can't be destroyed while someone is still renting one
+    pub fn unregister_virtual_functions(&mut self, physical_function: &str) -> Result<usize> {
+        let vf_ids: Vec<u32> = self.gpus
+            .values()
+            .filter(|g| matches!(
+                &g.backing,
+                GpuBacking::SriovVf { physical_function: pf, .. } if pf == physical_function
+            ))
+            .map(|g| g.id)
+            .collect();
+
+        if let Some(busy) = vf_ids.iter().find(|id| self.gpus[id].allocated_to.is_some()) {
+            return Err(anyhow!("VF GPU {} of {} is still allocated", busy, physical_function));
+        }
+
+        for id in &vf_ids {
+            self.gpus.remove(id);
+        }
+        Ok(vf_ids.len())
+    }
+
+    /// Adds a MIG slice sized by its profile; returns the existing id if it's already pooled
+    pub fn register_mig_instance(&mut self, instance: &MigInstance) -> u32 {
+        if let Some(gpu) = self.gpus.values().find(|g| g.mig_uuid() == Some(instance.uuid.as_str())) {
+            return gpu.id;
+        }
+
+        let id = self.next_id();
+        self.gpus.insert(id, VirtualGPU {
+            id,
+            vram_mb: instance.profile.memory_mb as u32,
+            compute_units: instance.profile.sm_count,
+            allocated_to: None,
+            backing: GpuBacking::Mig {
+                parent_uuid: instance.parent_uuid.clone(),
+                gpu_instance_id: instance.gpu_instance_id,
+                compute_instance_id: instance.compute_instance_id,
+                profile: instance.profile.name.clone(),
+                uuid: instance.uuid.clone(),
+            },
+            pci_address: None,
+            state: ServiceState::Active,
+        });
+        id
+    }
+
+    /// Removes a MIG slice before it gets destroyed; refuses while it's allocated
+    pub fn unregister_mig_instance(&mut self, parent_uuid: &str, gpu_instance_id: u32) -> Result<()> {
+        let gpu = self.gpus
+            .values()
+            .find(|g| matches!(
+                &g.backing,
+                GpuBacking::Mig { parent_uuid: p, gpu_instance_id: gi, .. } if p == parent_uuid && *gi == gpu_instance_id
+            ))
+            .ok_or_else(|| anyhow!("MIG instance {} on {} is not pooled", gpu_instance_id, parent_uuid))?;
+
+        if gpu.allocated_to.is_some() {
+            return Err(anyhow!("MIG GPU {} is still allocated", gpu.id));
+        }
+        let id = gpu.id;
+        self.gpus.remove(&id);
+        Ok(())
+    }
+
+    /// Adds a mediated device; returns the existing id if it's already pooled
+    pub fn register_mdev(&mut self, device: &MdevDevice, vram_mb: u32, compute_units: u32) -> u32 {
+        if let Some(gpu) = self.gpus.values().find(|g| g.mdev_uuid() == Some(device.uuid.as_str())) {
+            return gpu.id;
+        }
+
+        let id = self.next_id();
+        self.gpus.insert(id, VirtualGPU {
+            id,
+            vram_mb,
+            compute_units,
+            allocated_to: None,
+            backing: GpuBacking::Mdev {
+                parent: device.parent.clone(),
+                mdev_type: device.type_id.clone(),
+                uuid: device.uuid.clone(),
+            },
+            pci_address: Some(device.parent.clone()),
+            state: ServiceState::Active,
+        });
+        id
+    }
+
+    /// Removes a mediated device before it gets torn down; refuses while it's allocated
+    pub fn unregister_mdev(&mut self, uuid: &str) -> Result<()> {
+        let gpu = self.gpus
+            .values()
+            .find(|g| g.mdev_uuid() == Some(uuid))
+            .ok_or_else(|| anyhow!("Mediated device {} is not pooled", uuid))?;
+
+        if gpu.allocated_to.is_some() {
+            return Err(anyhow!("mdev GPU {} is still allocated", gpu.id));
+        }
+        let id = gpu.id;
+        self.gpus.remove(&id);
+        Ok(())
+    }
+
+    /// Stops new allocations after a failed health check. Returns false if the
+    /// entry was already quarantined, so callers notify tenants only once.
+    pub fn quarantine(&mut self, gpu_id: u32, reason: &str) -> Result<bool> {
+        let gpu = self.gpus.get_mut(&gpu_id).ok_or_else(|| anyhow!("GPU not found"))?;
+        if matches!(gpu.state, ServiceState::Quarantined { .. }) {
+            return Ok(false);
+        }
+        gpu.state = ServiceState::Quarantined { reason: reason.to_string(), since: Utc::now() };
+        Ok(true)
+    }
+
+    /// Operator takes the entry out of service. A quarantine is kept as is -
+    /// it says more about the device than "draining" does.
+    pub fn drain(&mut self, gpu_id: u32, reason: &str) -> Result<bool> {
+        let gpu = self.gpus.get_mut(&gpu_id).ok_or_else(|| anyhow!("GPU not found"))?;
+        if !gpu.state.is_active() {
+            return Ok(false);
+        }
+        gpu.state = ServiceState::Draining { reason: reason.to_string(), since: Utc::now() };
+        Ok(true)
+    }
+
+    /// Back into service - clears drains and quarantines alike
+    pub fn undrain(&mut self, gpu_id: u32) -> Result<bool> {
+        let gpu = self.gpus.get_mut(&gpu_id).ok_or_else(|| anyhow!("GPU not found"))?;
+        if gpu.state.is_active() {
+            return Ok(false);
+        }
+        gpu.state = ServiceState::Active;
+        Ok(true)
+    }
+
+    /// Moves the lease on `from` to the smallest active, free entry with at
+    /// least as much VRAM and compute. Ok(None) if `from` has no lease.
+    pub fn migrate_lease(&mut self, from: u32) -> Result<Option<u32>> {
+        let source = self.gpus.get(&from).ok_or_else(|| anyhow!("GPU not found"))?;
+        let Some(tenant) = source.allocated_to.clone() else {
+            return Ok(None);
+        };
+        let (vram_mb, compute_units) = (source.vram_mb, source.compute_units);
+
+        let to = self.gpus
+            .values()
+            .filter(|g| g.id != from && g.allocated_to.is_none() && g.state.is_active())
+            .filter(|g| g.vram_mb >= vram_mb && g.compute_units >= compute_units)
+            .min_by_key(|g| (g.vram_mb, g.compute_units, g.id))
+            .map(|g| g.id)
+            .ok_or_else(|| anyhow!("no active GPU with {}MB / {} cores free", vram_mb, compute_units))?;
+
+        // Earlier moves that ended on `from` now end on `to`
+        for target in self.moved_leases.values_mut().filter(|target| **target == from) {
+            *target = to;
+        }
+        self.moved_leases.insert((from, tenant.clone()), to);
+        self.gpus.get_mut(&to).unwrap().allocated_to = Some(tenant);
+        self.gpus.get_mut(&from).unwrap().allocated_to = None;
+        Ok(Some(to))
+    }
+
+    /// Entry `tenant`'s lease `lease_id` lives on now, following migrations.
+    /// None if neither it nor where it moved to is leased to `tenant`.
+    pub fn current_lease(&self, lease_id: u32, tenant: &str) -> Option<u32> {
+        let held = |id: &u32| self.gpus.get(id).is_some_and(|g| g.allocated_to.as_deref() == Some(tenant));
+        if held(&lease_id) {
+            return Some(lease_id);
+        }
+        self.moved_leases.get(&(lease_id, tenant.to_string())).copied().filter(held)
+    }
+
+    /// Ties whole-card entries to the cards detected at startup, in id order,
+    /// so health checks and drains can find them. Cards beyond the existing
+    /// entries get one of their own, sized by their VRAM. Returns the ids of
+    /// the entries that got linked or added.
+    pub fn link_devices(&mut self, devices: &[GPUInfo]) -> Vec<u32> {
+        let mut linked = Vec::new();
+        for gpu in devices {
+            let Some(address) = gpu.pci_address.as_ref() else {
+                continue;
+            };
+            if self.gpus.values().any(|g| g.backing == GpuBacking::Whole && g.pci_address.as_ref() == Some(address)) {
+                continue;
+            }
+
+            let placeholder = self.gpus
+                .values()
+                .filter(|g| g.backing == GpuBacking::Whole && g.pci_address.is_none())
+                .map(|g| g.id)
+                .min();
+            let id = match placeholder {
+                Some(id) => id,
+                None => {
+                    let id = self.next_id();
+                    self.gpus.insert(id, VirtualGPU {
+                        id,
+                        vram_mb: gpu.vram_mb as u32,
+                        compute_units: 0,
+                        allocated_to: None,
+                        backing: GpuBacking::Whole,
+                        pci_address: None,
+                        state: ServiceState::Active,
+                    });
+                    id
+                }
+            };
+            self.gpus.get_mut(&id).unwrap().pci_address = Some(address.clone());
+            linked.push(id);
+        }
+        linked
+    }
+
+    /// Id of the entry behind `lease_id`, if it is leased to `tenant` and runs
+    /// on the physical `gpu` - a lease only ever entitles you to its own card
+    pub fn verify_lease(&self, lease_id: &str, tenant: &str, gpu: &GPUInfo) -> Result<u32> {
+        let id = lease_id.parse::<u32>().map_err(|_| anyhow!("Lease {} not found", lease_id))?;
+        let entry = self.gpus
+            .get(&self.current_lease(id, tenant).unwrap_or(id))
+            .ok_or_else(|| anyhow!("Lease {} not found", lease_id))?;
+        if entry.allocated_to.as_deref() != Some(tenant) {
+            return Err(anyhow!("Lease {} is not held by {}", lease_id, tenant));
+        }
+        if !entry.runs_on(gpu) {
+            return Err(anyhow!("Lease {} is not on GPU {}", lease_id, gpu.id));
+        }
+        Ok(entry.id)
+    }
+
+    /// Pool entries backed by the physical `gpu`
+    pub fn entries_on(&self, gpu: &GPUInfo) -> Vec<u32> {
+        let mut ids: Vec<u32> = self.gpus.values().filter(|g| g.runs_on(gpu)).map(|g| g.id).collect();
+        ids.sort();
+        ids
+    }
+
+    fn next_id(&self) -> u32 {
+        self.gpus.keys().max().map_or(0, |id| id + 1)
+    }
+
     pub fn get_allocated_gpus(&self, user: &str) -> Vec<&VirtualGPU> {
         self.gpus.values()
             .filter(|g| g.allocated_to.as_ref() == Some(&user.to_string()))
@@ -63,3 +443,99 @@ impl GPUPool {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quarantined_gpu_refuses_allocation() {
+        let mut pool = GPUPool::new();
+        pool.allocate("alice", 0).unwrap();
+
+        assert!(pool.quarantine(0, "Xid 79").unwrap());
+        assert!(!pool.quarantine(0, "Xid 79").unwrap());
+        // The running lease is left alone
+        assert_eq!(pool.gpus[&0].allocated_to.as_deref(), Some("alice"));
+
+        pool.release(0).unwrap();
+        let err = pool.allocate("bob", 0).unwrap_err();
+        assert!(err.to_string().contains("quarantined: Xid 79"));
+
+        // Draining doesn't downgrade a quarantine
+        assert!(!pool.drain(0, "driver upgrade").unwrap());
+        assert!(pool.undrain(0).unwrap());
+        pool.allocate("bob", 0).unwrap();
+    }
+
+    #[test]
+    fn test_entries_on_physical_gpu() {
+        let mut pool = GPUPool::new();
+        let vfs = pool.register_virtual_functions(
+            "0000:03:00.0",
+            &["0000:03:00.1".to_string(), "0000:03:00.2".to_string()],
+            4096,
+            8,
+        );
+        pool.gpus.get_mut(&0).unwrap().pci_address = Some("0000:65:00.0".into());
+
+        let card = GPUInfo { pci_address: Some("0000:03:00.0".into()), ..Default::default() };
+        assert_eq!(pool.entries_on(&card), vfs);
+        let other = GPUInfo { pci_address: Some("0000:65:00.0".into()), ..Default::default() };
+        assert_eq!(pool.entries_on(&other), vec![0]);
+        assert!(pool.entries_on(&GPUInfo::default()).is_empty());
+    }
+
+    #[test]
+    fn test_lease_must_be_on_the_card() {
+        let mut pool = GPUPool::new();
+        let card = |address: &str| GPUInfo { id: address.into(), pci_address: Some(address.into()), ..Default::default() };
+        let (first, second) = (card("0000:65:00.0"), card("0000:17:00.0"));
+        pool.link_devices(&[first.clone(), second.clone()]);
+        pool.allocate("alice", 0).unwrap();
+
+        assert_eq!(pool.verify_lease("0", "alice", &first).unwrap(), 0);
+        assert!(pool.verify_lease("0", "alice", &second).unwrap_err().to_string().contains("not on GPU"));
+        assert!(pool.verify_lease("0", "bob", &first).unwrap_err().to_string().contains("not held by bob"));
+        assert!(pool.verify_lease("1", "alice", &second).is_err());
+        assert!(pool.verify_lease("zero", "alice", &first).is_err());
+    }
+
+    #[test]
+    fn test_migrated_lease_keeps_its_old_id() {
+        let mut pool = GPUPool::new();
+        pool.allocate("alice", 0).unwrap();
+        assert_eq!(pool.migrate_lease(0).unwrap(), Some(1));
+
+        assert_eq!(pool.current_lease(0, "alice"), Some(1));
+        assert_eq!(pool.current_lease(1, "alice"), Some(1));
+        assert_eq!(pool.current_lease(0, "bob"), None);
+
+        // The old entry can go to someone else without confusing the two
+        pool.allocate("bob", 0).unwrap();
+        assert_eq!(pool.current_lease(0, "bob"), Some(0));
+        assert_eq!(pool.current_lease(0, "alice"), Some(1));
+
+        pool.release(1).unwrap();
+        assert_eq!(pool.current_lease(0, "alice"), None);
+    }
+
+    #[test]
+    fn test_link_detected_devices() {
+        let mut pool = GPUPool::new();
+        let card = |address: &str| GPUInfo {
+            pci_address: Some(address.into()),
+            vram_mb: 24576,
+            ..Default::default()
+        };
+        let devices = vec![card("0000:65:00.0"), GPUInfo::default(), card("0000:17:00.0"), card("0000:b3:00.0")];
+
+        assert_eq!(pool.link_devices(&devices), vec![0, 1, 2]);
+        assert_eq!(pool.entries_on(&devices[0]), vec![0]);
+        assert_eq!(pool.entries_on(&devices[2]), vec![1]);
+        assert_eq!(pool.gpus[&2].vram_mb, 24576);
+
+        // Linking again changes nothing
+        assert!(pool.link_devices(&devices).is_empty());
+        assert_eq!(pool.gpus.len(), 3);
+    }
+}
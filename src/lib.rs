@@ -7,6 +7,8 @@ pub mod config;
 pub mod users;
 pub mod billing;
 pub mod dashboard;
+pub mod errors;
+pub mod events;
 
 // Re-exports
 pub use gpu::virtual_gpu::GPUPool;
@@ -4,19 +4,24 @@
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use tracing::info;
-use tracing_subscriber;
+use tracing::{info, warn};
 use tokio::net::TcpListener;
 use anyhow::Result;
 use clap::Parser;
 
 // Local imports
 use gpu_share_vm_manager::{
-    utils::cli::{Cli, Commands, list_gpus, rent_gpu, show_status},
+    utils::cli::{self, Cli, Commands, list_gpus, rent_gpu, run_doctor, show_status},
     dashboard::start_dashboard,
+    api::caller::{Credentials, TENANT_SECRET_ENV, TOKEN_ENV},
     api::routes::{create_router, AppState},
     core::docker_manager::DockerManager,
-    gpu::{GPUManager, virtual_gpu::GPUPool},
+    core::lifecycle::run_lifecycle_watcher,
+    core::projects::ProjectConfig,
+    core::readiness::ReadinessChecker,
+    core::reconcile::{run_reconciler, ReconcileConfig, Reconciler},
+    events::EventBus,
+    gpu::{GPUManager, health::{run_health_monitor, HealthProber}, iommu::IommuDiagnostics, virtual_gpu::GPUPool},
     monitoring::MetricsCollector,
     users::UserManager,
     billing::BillingSystem
@@ -27,22 +32,98 @@ async fn main() -> Result<()> {
     tracing_subscriber::fmt()
         .with_max_level(tracing::Level::INFO)
         .init();
+    let cli = Cli::parse();
+
+    // These run without binding the API port or touching shared state;
+    // drain/undrain talk to the running server instead
+    match &cli.command {
+        Commands::Doctor { json } => {
+            if !run_doctor(*json).await? {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Commands::Drain { gpu_id, reason, migrate, wait, server } => {
+            return cli::drain(server, *gpu_id, reason.clone(), *migrate, *wait).await;
+        }
+        Commands::Undrain { gpu_id, server } => {
+            return cli::undrain(server, *gpu_id).await;
+        }
+        Commands::Logs { container, follow, tail, since, user, server } => {
+            return cli::logs(server, container, user.as_deref(), *follow, *tail, *since).await;
+        }
+        Commands::Exec { container, command, timeout, user, server } => {
+            let code = cli::exec(server, container, user.as_deref(), command, *timeout).await?;
+            std::process::exit(code);
+        }
+        Commands::Volume { action, user, server } => {
+            return cli::volume(server, user.as_deref(), action).await;
+        }
+        Commands::Token { user, days } => {
+            return cli::token(user, *days);
+        }
+        _ => {}
+    }
+
     info!("🏗️ Starting DanteGPU Server..");
 
-    let cli = Cli::parse();
+    // Passthrough readiness - only warns, containers work without an IOMMU
+    IommuDiagnostics::new().report().log_summary();
     
-    // State initialization
+    // State initialization - POST /shutdown fires the oneshot and stops the server
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel();
+    let credentials = Credentials::load();
+    if credentials.operator_token.is_none() {
+        warn!("No operator token ([server] operator_token or {}) - admin endpoints will refuse every request", TOKEN_ENV);
+    }
+    if credentials.tenant_secret.is_none() {
+        warn!("No tenant token secret ([server] tenant_token_secret or {}) - only the operator can sign in", TENANT_SECRET_ENV);
+    }
+    let gpu_manager = GPUManager::new()?;
+    let mut gpupool = GPUPool::new();
+    // Health checks and drains find pool entries by the card's PCI address
+    gpupool.link_devices(&gpu_manager.devices);
     let app_state = Arc::new(AppState {
         docker: Arc::new(Mutex::new(DockerManager::new()?)),
-        gpu_manager: Arc::new(Mutex::new(GPUManager::new()?)),
+        gpu_manager: Arc::new(Mutex::new(gpu_manager)),
         metrics: Arc::new(Mutex::new(MetricsCollector::new(5, 24))),
-        shutdown_signal: Arc::new(Mutex::new(None)),
-        shutdown_receiver: Arc::new(Mutex::new(None)),
-        gpupool: Arc::new(Mutex::new(GPUPool::new())),
+        shutdown_signal: Arc::new(Mutex::new(Some(shutdown_tx))),
+        shutdown_receiver: Arc::new(Mutex::new(Some(shutdown_rx))),
+        gpupool: Arc::new(Mutex::new(gpupool)),
         user_manager: Arc::new(Mutex::new(UserManager::new())),
         billing_system: Arc::new(Mutex::new(BillingSystem::new())),
+        events: Arc::new(EventBus::new()),
+        readiness: Arc::new(ReadinessChecker::new()),
+        projects: Arc::new(ProjectConfig::load()),
+        credentials,
     });
 
+    // Quarantine GPUs that fall off the bus or start throwing hardware errors
+    tokio::spawn(run_health_monitor(
+        HealthProber::new(),
+        std::time::Duration::from_secs(30),
+        app_state.gpu_manager.clone(),
+        app_state.gpupool.clone(),
+        app_state.user_manager.clone(),
+        app_state.events.clone(),
+    ));
+
+    // Compare Docker's containers with the pool's leases; report-only unless [reconcile] fix = true
+    tokio::spawn(run_reconciler(
+        Reconciler::new(ReconcileConfig::load()),
+        app_state.docker.clone(),
+        app_state.gpupool.clone(),
+        app_state.events.clone(),
+    ));
+
+    // Follow container exits/OOM kills and end the leases of containers that are gone
+    tokio::spawn(run_lifecycle_watcher(
+        app_state.docker.lock().await.clone(),
+        app_state.gpupool.clone(),
+        app_state.user_manager.clone(),
+        app_state.events.clone(),
+    ));
+
     // Server setup
     let app = create_router(app_state.clone());
     let addr: SocketAddr = "0.0.0.0:3000".parse()?;
@@ -92,5 +173,9 @@ async fn main() -> Result<()> {
             ).await?;
             Ok(())
         },
+        Commands::Doctor { .. } | Commands::Drain { .. } | Commands::Undrain { .. } | Commands::Logs { .. }
+        | Commands::Exec { .. } | Commands::Volume { .. } | Commands::Token { .. } => {
+            unreachable!("handled before startup")
+        }
     }
 }
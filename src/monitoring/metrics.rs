@@ -63,6 +63,10 @@ pub struct GPUMetrics {
     pub memory_total_mb: u64,
     pub temperature_celsius: i32,
     pub power_usage_watts: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock_mhz: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_clock_mhz: Option<u32>,
 }
 
 pub struct MetricsCollector {
@@ -138,31 +142,6 @@ impl MetricsCollector {
         Ok(None)
     }
 
-    fn calculate_cpu_usage(cpu_time: u64) -> f64 {
-        // CPU usage calculation based on CPU time delta
-        static mut LAST_CPU_TIME: u64 = 0;
-        static mut LAST_TIMESTAMP: u64 = 0;
-
-        unsafe {
-            let current_time = SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap()
-                .as_secs();
-
-            let time_delta = current_time - LAST_TIMESTAMP;
-            let cpu_delta = cpu_time - LAST_CPU_TIME;
-
-            LAST_CPU_TIME = cpu_time;
-            LAST_TIMESTAMP = current_time;
-
-            if time_delta > 0 {
-                (cpu_delta as f64 / (time_delta as f64 * 1_000_000_000.0)) * 100.0
-            } else {
-                0.0
-            }
-        }
-    }
-
     fn cleanup_old_metrics(metrics: &mut Vec<ResourceMetrics>, retention_hours: u64) {
         let retention_secs = retention_hours * 3600;
         let current_time = SystemTime::now()
@@ -210,7 +189,7 @@ impl MetricsCollector {
 
     pub async fn get_container_metrics(&self, container_id: &str) -> Result<Vec<ResourceMetrics>> {
         self.container_metrics.lock().unwrap().get(container_id)
-            .map(|metrics| metrics.clone())
+            .cloned()
             .ok_or_else(|| anyhow::anyhow!("No metrics found for container"))
     }
 }
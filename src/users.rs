@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use chrono::{DateTime, Utc};
 use uuid::Uuid;
 use anyhow::{Result, anyhow};
 
@@ -7,12 +8,26 @@ pub struct User {
     pub id: Uuid,
     pub credits: f64,
     pub allocated_gpus: Vec<u32>,
+    pub notifications: Vec<Notification>,
+}
+
+/// Something a tenant should hear about, e.g. their GPU being quarantined
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Notification {
+    pub at: DateTime<Utc>,
+    pub message: String,
 }
 
 pub struct UserManager {
     pub users: HashMap<String, User>, 
 }
 
+impl Default for UserManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl UserManager {
     pub fn new() -> Self {
         Self {
@@ -29,6 +44,7 @@ impl UserManager {
             id: Uuid::new_v4(),
             credits: 1000000.0, // 100 token for new user
             allocated_gpus: Vec::new(),
+            notifications: Vec::new(),
         };
         
         self.users.insert(username.to_string(), user);
@@ -41,6 +57,7 @@ impl UserManager {
                 id: Uuid::new_v4(),
                 credits: 1000000.0,
                 allocated_gpus: Vec::new(),
+                notifications: Vec::new(),
             };
             self.users.insert(username.to_string(), user);
         }
@@ -54,4 +71,19 @@ impl UserManager {
         user.credits -= amount;
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Queues a message for the user (creating them if needed, like `get_user`)
+    pub fn notify(&mut self, username: &str, message: impl Into<String>) -> Result<()> {
+        let user = self.get_user(username)?;
+        user.notifications.push(Notification { at: Utc::now(), message: message.into() });
+        Ok(())
+    }
+
+    /// Hands over and clears the user's pending notifications
+    pub fn take_notifications(&mut self, username: &str) -> Vec<Notification> {
+        self.users
+            .get_mut(username)
+            .map(|u| std::mem::take(&mut u.notifications))
+            .unwrap_or_default()
+    }
+}
@@ -1,10 +1,20 @@
 use clap::{Parser, Subcommand};
+use colored::Colorize;
+use crate::api::caller::Credentials;
+use crate::core::docker_manager::DockerManager;
+use crate::core::exec::{shell_join, ClientMessage, ExecRequest, ServerMessage};
+use crate::core::volumes::{CleanupPolicy, VolumeRequest};
+use crate::core::readiness::{CheckStatus, ReadinessChecker, ReadinessReport};
+use crate::gpu::drain::{state_label, DrainProgress};
+use crate::gpu::GPUManager;
 use crate::gpu::virtual_gpu::GPUPool;
 use crate::users::UserManager;
 use crate::billing::{BillingSystem, Transaction};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+const DEFAULT_SERVER: &str = "http://127.0.0.1:3000";
+
 #[derive(Parser)]
 #[command(name = "GPUShare")]
 #[command(version = "1.0")]
@@ -45,14 +55,142 @@ pub enum Commands {
     
     /// Start interactive dashboard
     Dashboard,
+
+    /// Take a GPU (or the whole host) out of service on a running server
+    Drain {
+        /// GPU to drain; without it every GPU on the host is drained
+        #[arg(short, long)]
+        gpu_id: Option<u32>,
+
+        #[arg(short, long)]
+        reason: Option<String>,
+
+        /// Move existing leases to other active GPUs where possible
+        #[arg(long)]
+        migrate: bool,
+
+        /// Wait up to this many seconds for remaining leases to end
+        #[arg(long)]
+        wait: Option<u64>,
+
+        #[arg(long, default_value = DEFAULT_SERVER)]
+        server: String,
+    },
+
+    /// Put a drained or quarantined GPU (or the whole host) back into service
+    Undrain {
+        #[arg(short, long)]
+        gpu_id: Option<u32>,
+
+        #[arg(long, default_value = DEFAULT_SERVER)]
+        server: String,
+    },
+
+    /// Print a container's output from a running server
+    Logs {
+        container: String,
+
+        /// Keep streaming until the container stops
+        #[arg(short, long)]
+        follow: bool,
+
+        /// Only the last N lines
+        #[arg(short = 'n', long)]
+        tail: Option<u64>,
+
+        /// Only output after this time (RFC 3339)
+        #[arg(long)]
+        since: Option<chrono::DateTime<chrono::Utc>>,
+
+        /// Tenant to act as (needs the tenant token secret); omit to use GPU_SHARE_TOKEN, else the operator token
+        #[arg(short, long)]
+        user: Option<String>,
+
+        #[arg(long, default_value = DEFAULT_SERVER)]
+        server: String,
+    },
+
+    /// Open an interactive shell (or run COMMAND) in a container on a running server
+    Exec {
+        container: String,
+
+        /// Command line to run instead of an interactive shell
+        #[arg(last = true)]
+        command: Vec<String>,
+
+        /// Session time limit in seconds (the server caps it)
+        #[arg(short, long)]
+        timeout: Option<u64>,
+
+        /// Tenant to act as (needs the tenant token secret); omit to use GPU_SHARE_TOKEN, else the operator token
+        #[arg(short, long)]
+        user: Option<String>,
+
+        #[arg(long, default_value = DEFAULT_SERVER)]
+        server: String,
+    },
+
+    /// Manage persistent volumes on a running server
+    Volume {
+        #[command(subcommand)]
+        action: VolumeCommand,
+
+        /// Tenant to act as (needs the tenant token secret); omit to use GPU_SHARE_TOKEN, else the operator token
+        #[arg(short, long, global = true)]
+        user: Option<String>,
+
+        #[arg(long, default_value = DEFAULT_SERVER, global = true)]
+        server: String,
+    },
+
+    /// Print a token that signs USER in as a tenant (needs the tenant token secret)
+    Token {
+        user: String,
+
+        /// How long the token is good for
+        #[arg(short, long, default_value_t = 30)]
+        days: u64,
+    },
+
+    /// Check host prerequisites for GPU sharing
+    Doctor {
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum VolumeCommand {
+    /// Create a named volume
+    Create {
+        name: String,
+
+        #[arg(short, long)]
+        project: Option<String>,
+
+        /// Space to reserve against the storage quota
+        #[arg(short, long)]
+        size_gb: Option<u64>,
+
+        /// Delete the volume when its project is deleted (kept by default)
+        #[arg(long)]
+        delete_with_project: bool,
+    },
+
+    /// List volumes with their sizes
+    List,
+
+    /// Delete a volume and its data
+    Rm { name: String },
 }
 
 pub async fn list_gpus(gpupool: Arc<Mutex<GPUPool>>) -> anyhow::Result<()> {
     let gpupool = gpupool.lock().await;
     println!("Available GPUs:");
     for (id, gpu) in &gpupool.gpus {
-        println!("GPU {}: {}MB VRAM - {} Cores", 
-            id, gpu.vram_mb, gpu.compute_units);
+        println!("GPU {}: {}MB VRAM - {} Cores - {}",
+            id, gpu.vram_mb, gpu.compute_units, state_label(&gpu.state));
     }
     Ok(())
 }
@@ -87,4 +225,449 @@ pub async fn show_status(gpupool: Arc<Mutex<GPUPool>>) -> anyhow::Result<()> {
     println!("System Status:");
     println!("Total GPUs: {}", gpupool.gpus.len());
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Runs every readiness check. Returns false if any check failed.
+pub async fn run_doctor(json: bool) -> anyhow::Result<bool> {
+    let gpu_manager = GPUManager::new().unwrap_or_else(|e| {
+        tracing::warn!("GPU detection failed: {}", e);
+        GPUManager { devices: Vec::new(), iommu_groups: Default::default() }
+    });
+    let docker = DockerManager::new().ok();
+    let report = ReadinessChecker::new().run(&gpu_manager, docker.as_ref()).await;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_readiness(&report);
+    }
+    Ok(report.status != CheckStatus::Fail)
+}
+
+fn print_readiness(report: &ReadinessReport) {
+    for check in &report.checks {
+        let tag = match check.status {
+            CheckStatus::Pass => "[PASS]".green(),
+            CheckStatus::Warn => "[WARN]".yellow(),
+            CheckStatus::Fail => "[FAIL]".red(),
+        };
+        println!("{} {:<28} {}", tag, check.name, check.detail);
+        if let Some(hint) = &check.remediation {
+            println!("       {} {}", "hint:".dimmed(), hint);
+        }
+    }
+    let summary = match report.status {
+        CheckStatus::Pass => "Host is ready".green(),
+        CheckStatus::Warn => "Host is usable with warnings".yellow(),
+        CheckStatus::Fail => "Host is not ready".red(),
+    };
+    println!("\n{}", summary.bold());
+}
+
+fn drain_path(gpu_id: Option<u32>, action: &str) -> String {
+    match gpu_id {
+        Some(id) => format!("/api/v1/admin/gpus/{}/{}", id, action),
+        None => format!("/api/v1/admin/host/{}", action),
+    }
+}
+
+/// Calls the admin API of a running server
+async fn admin_request(
+    server: &str,
+    method: hyper::Method,
+    path: &str,
+    body: Option<serde_json::Value>,
+) -> anyhow::Result<DrainProgress> {
+    api_request(server, method, path, None, body).await
+}
+
+/// Environment variable a tenant keeps their token in
+pub const TENANT_TOKEN_ENV: &str = "GPU_SHARE_TOKEN";
+
+/// Signs `user` in for `days`
+pub fn token(user: &str, days: u64) -> anyhow::Result<()> {
+    let ttl = std::time::Duration::from_secs(days * 24 * 60 * 60);
+    println!("{}", Credentials::load().issue_tenant_token(user, ttl)?);
+    Ok(())
+}
+
+/// Authorization header a request goes out with: a token for `user` signed
+/// here, else `GPU_SHARE_TOKEN`, else the operator token from the environment
+/// or the config files
+fn credentials(user: Option<&str>) -> anyhow::Result<Option<String>> {
+    let token = match user {
+        Some(user) => Some(Credentials::load().issue_tenant_token(user, std::time::Duration::from_secs(60 * 60))?),
+        None => std::env::var(TENANT_TOKEN_ENV)
+            .ok()
+            .filter(|t| !t.is_empty())
+            .or_else(|| Credentials::load().operator_token),
+    };
+    Ok(token.map(|token| format!("Bearer {}", token)))
+}
+
+/// JSON request to a running server, as `user` when given
+async fn api_request<T: serde::de::DeserializeOwned>(
+    server: &str,
+    method: hyper::Method,
+    path: &str,
+    user: Option<&str>,
+    body: Option<serde_json::Value>,
+) -> anyhow::Result<T> {
+    let mut request = hyper::Request::builder()
+        .method(method)
+        .uri(format!("{}{}", server.trim_end_matches('/'), path))
+        .header(hyper::header::CONTENT_TYPE, "application/json");
+    if let Some(value) = credentials(user)? {
+        request = request.header(hyper::header::AUTHORIZATION, value);
+    }
+    let request = request.body(hyper::Body::from(body.map(|b| b.to_string()).unwrap_or_default()))?;
+
+    let response = hyper::Client::new()
+        .request(request)
+        .await
+        .map_err(|e| anyhow::anyhow!("Cannot reach {}: {}", server, e))?;
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await?;
+    if !status.is_success() {
+        anyhow::bail!("{} {}", status, String::from_utf8_lossy(&bytes));
+    }
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+/// Drains through the API, then polls and prints progress while waiting
+pub async fn drain(
+    server: &str,
+    gpu_id: Option<u32>,
+    reason: Option<String>,
+    migrate: bool,
+    wait: Option<u64>,
+) -> anyhow::Result<()> {
+    let body = serde_json::json!({ "reason": reason, "migrate": migrate });
+    let mut progress = admin_request(server, hyper::Method::POST, &drain_path(gpu_id, "drain"), Some(body)).await?;
+    for moved in &progress.migrated {
+        println!("Moved {}'s lease from GPU {} to GPU {}", moved.tenant, moved.from, moved.to);
+    }
+    print_drain_progress(&progress);
+
+    if let Some(secs) = wait {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(secs);
+        while !progress.complete && std::time::Instant::now() < deadline {
+            tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+            progress = admin_request(server, hyper::Method::GET, &drain_path(gpu_id, "drain"), None).await?;
+            print_drain_progress(&progress);
+        }
+        if !progress.complete {
+            anyhow::bail!(
+                "Timed out with {} lease(s) and {} container(s) still running",
+                progress.remaining_leases.len(),
+                progress.remaining_containers.len()
+            );
+        }
+    }
+    Ok(())
+}
+
+pub async fn undrain(server: &str, gpu_id: Option<u32>) -> anyhow::Result<()> {
+    let progress = admin_request(server, hyper::Method::POST, &drain_path(gpu_id, "undrain"), None).await?;
+    println!("{} GPU(s) in service", progress.active.len());
+    Ok(())
+}
+
+fn print_drain_progress(progress: &DrainProgress) {
+    if progress.complete {
+        println!("{} Drain complete ({} GPU(s) out of service)", "✓".green(), progress.drained.len());
+        return;
+    }
+    let leases: Vec<String> = progress
+        .remaining_leases
+        .iter()
+        .map(|l| format!("{} on GPU {}", l.tenant, l.gpu_id))
+        .collect();
+    println!(
+        "{} {} GPU(s) draining, {} lease(s) remaining: {}",
+        "…".yellow(),
+        progress.drained.len(),
+        leases.len(),
+        leases.join(", ")
+    );
+    for container in &progress.remaining_containers {
+        println!("  {} still on GPU {}", container.name, container.gpu_id);
+    }
+}
+
+/// Creates, lists or deletes volumes through `/api/v1/volumes`
+pub async fn volume(server: &str, user: Option<&str>, action: &VolumeCommand) -> anyhow::Result<()> {
+    match action {
+        VolumeCommand::Create { name, project, size_gb, delete_with_project } => {
+            let request = VolumeRequest {
+                name: name.clone(),
+                project: project.clone(),
+                size_gb: *size_gb,
+                cleanup: if *delete_with_project { CleanupPolicy::Delete } else { CleanupPolicy::Retain },
+            };
+            let body = serde_json::to_value(&request)?;
+            let volume: serde_json::Value = api_request(server, hyper::Method::POST, "/api/v1/volumes", user, Some(body)).await?;
+            println!("{} Volume {} created", "✓".green(), volume["name"].as_str().unwrap_or(name));
+        }
+        VolumeCommand::List => {
+            let list: serde_json::Value = api_request(server, hyper::Method::GET, "/api/v1/volumes", user, None).await?;
+            println!("{:<24} {:<12} {:<12} {:>10} {:>10}  CLEANUP", "NAME", "OWNER", "PROJECT", "USED", "RESERVED");
+            for volume in list["volumes"].as_array().into_iter().flatten() {
+                println!(
+                    "{:<24} {:<12} {:<12} {:>10} {:>10}  {}",
+                    volume["name"].as_str().unwrap_or_default(),
+                    volume["owner"].as_str().unwrap_or("-"),
+                    volume["project"].as_str().unwrap_or("-"),
+                    volume["used_bytes"].as_u64().map(format_bytes).unwrap_or_else(|| "?".into()),
+                    volume["size_gb"].as_u64().map(|gb| format!("{} GB", gb)).unwrap_or_else(|| "-".into()),
+                    volume["cleanup"].as_str().unwrap_or_default(),
+                );
+            }
+            let usage = &list["usage"];
+            println!(
+                "\nStorage: {} of {} used (volumes and snapshots)",
+                format_bytes(usage["used_bytes"].as_u64().unwrap_or(0)),
+                format_bytes(usage["limit_bytes"].as_u64().unwrap_or(0))
+            );
+        }
+        VolumeCommand::Rm { name } => {
+            let path = format!("/api/v1/volumes/{}", name);
+            let _: serde_json::Value = api_request(server, hyper::Method::DELETE, &path, user, None).await?;
+            println!("{} Volume {} deleted", "✓".green(), name);
+        }
+    }
+    Ok(())
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Follows `/api/v1/vms/{id}/logs`, stdout lines to stdout and stderr lines to stderr
+pub async fn logs(
+    server: &str,
+    container: &str,
+    user: Option<&str>,
+    follow: bool,
+    tail: Option<u64>,
+    since: Option<chrono::DateTime<chrono::Utc>>,
+) -> anyhow::Result<()> {
+    let mut query = vec![format!("follow={}", follow)];
+    if let Some(tail) = tail {
+        query.push(format!("tail={}", tail));
+    }
+    if let Some(since) = since {
+        query.push(format!("since={}", since.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)));
+    }
+    let uri = format!("{}/api/v1/vms/{}/logs?{}", server.trim_end_matches('/'), container, query.join("&"));
+
+    let mut request = hyper::Request::get(uri).header(hyper::header::ACCEPT, "text/event-stream");
+    if let Some(value) = credentials(user)? {
+        request = request.header(hyper::header::AUTHORIZATION, value);
+    }
+    let response = hyper::Client::new()
+        .request(request.body(hyper::Body::empty())?)
+        .await
+        .map_err(|e| anyhow::anyhow!("Cannot reach {}: {}", server, e))?;
+    let status = response.status();
+    let mut body = response.into_body();
+    if !status.is_success() {
+        let bytes = hyper::body::to_bytes(body).await?;
+        anyhow::bail!("{} {}", status, String::from_utf8_lossy(&bytes));
+    }
+
+    let mut parser = SseParser::default();
+    while let Some(chunk) = hyper::body::HttpBody::data(&mut body).await {
+        for (event, data) in parser.feed(&chunk?) {
+            match event.as_str() {
+                "stderr" => eprintln!("{}", data),
+                "error" => anyhow::bail!("{}", data),
+                "end" => return Ok(()),
+                _ => println!("{}", data),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Leaves raw mode however `exec` returns
+struct RawTerminal;
+
+impl RawTerminal {
+    fn enable() -> anyhow::Result<Self> {
+        crossterm::terminal::enable_raw_mode()?;
+        Ok(Self)
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = crossterm::terminal::disable_raw_mode();
+    }
+}
+
+/// Bridges the terminal to `/api/v1/vms/{id}/exec` and returns the remote exit
+/// code (255 when the server couldn't tell)
+pub async fn exec(
+    server: &str,
+    container: &str,
+    user: Option<&str>,
+    command: &[String],
+    timeout: Option<u64>,
+) -> anyhow::Result<i32> {
+    use futures_util::{SinkExt, StreamExt};
+    use std::io::IsTerminal;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_tungstenite::tungstenite::{self, client::IntoClientRequest, Message};
+
+    let interactive = std::io::stdin().is_terminal();
+    let mut size = crossterm::terminal::size().unwrap_or((80, 24));
+    let request = ExecRequest {
+        cmd: (!command.is_empty()).then(|| shell_join(command)),
+        cols: size.0,
+        rows: size.1,
+        timeout_secs: timeout,
+        ..Default::default()
+    };
+    let url = format!(
+        "{}/api/v1/vms/{}/exec?{}",
+        server.trim_end_matches('/').replacen("http", "ws", 1),
+        container,
+        serde_urlencoded::to_string(&request)?
+    );
+    let mut ws_request = url.into_client_request()?;
+    if let Some(value) = credentials(user)? {
+        ws_request.headers_mut().insert("authorization", value.parse()?);
+    }
+    let (socket, _) = tokio_tungstenite::connect_async(ws_request).await.map_err(|e| match e {
+        tungstenite::Error::Http(response) => {
+            let body = response.body().as_deref().map(String::from_utf8_lossy).unwrap_or_default();
+            anyhow::anyhow!("{} {}", response.status(), body)
+        }
+        e => anyhow::anyhow!("Cannot reach {}: {}", server, e),
+    })?;
+    let (mut sink, mut frames) = socket.split();
+
+    let raw = if interactive { Some(RawTerminal::enable()?) } else { None };
+    let mut stdin = tokio::io::stdin();
+    let mut stdout = tokio::io::stdout();
+    let mut buffer = [0u8; 4096];
+    let mut stdin_open = true;
+    let mut resize_check = tokio::time::interval(std::time::Duration::from_millis(500));
+    let mut exit_code = None;
+    let mut notice = None;
+
+    loop {
+        tokio::select! {
+            read = stdin.read(&mut buffer), if stdin_open => {
+                let data = match read {
+                    Ok(0) | Err(_) => {
+                        // Piped input ran out; ^D is how a TTY spells end of input
+                        stdin_open = false;
+                        vec![0x04]
+                    }
+                    Ok(n) => buffer[..n].to_vec(),
+                };
+                if sink.send(Message::binary(data)).await.is_err() {
+                    break;
+                }
+            }
+            _ = resize_check.tick(), if interactive => {
+                let current = crossterm::terminal::size().unwrap_or(size);
+                if current != size {
+                    size = current;
+                    let resize = ClientMessage::Resize { cols: size.0, rows: size.1 };
+                    if sink.send(Message::text(serde_json::to_string(&resize)?)).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            frame = frames.next() => match frame {
+                Some(Ok(Message::Binary(data))) => {
+                    stdout.write_all(&data).await?;
+                    stdout.flush().await?;
+                }
+                Some(Ok(Message::Text(text))) => match serde_json::from_str::<ServerMessage>(&text) {
+                    Ok(ServerMessage::Exit { code }) => exit_code = Some(code),
+                    Ok(ServerMessage::Timeout { after_secs }) => {
+                        notice = Some(format!("Session time limit of {}s reached", after_secs));
+                    }
+                    Ok(ServerMessage::Error { message }) => notice = Some(message),
+                    Ok(ServerMessage::Started { .. }) | Err(_) => {}
+                },
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+
+    drop(raw);
+    if let Some(notice) = notice {
+        eprintln!("{} {}", "!".yellow(), notice);
+    }
+    Ok(exit_code.flatten().map(|code| code as i32).unwrap_or(255))
+}
+
+/// Splits a server-sent event stream into `(event, data)` pairs
+#[derive(Default)]
+struct SseParser {
+    buffer: String,
+}
+
+impl SseParser {
+    fn feed(&mut self, chunk: &[u8]) -> Vec<(String, String)> {
+        self.buffer.push_str(&String::from_utf8_lossy(chunk));
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.find("\n\n") {
+            let block: String = self.buffer.drain(..end + 2).collect();
+            let mut event = "message".to_string();
+            let mut data = Vec::new();
+            for line in block.lines() {
+                if let Some(value) = line.strip_prefix("event:") {
+                    event = value.trim_start().to_string();
+                } else if let Some(value) = line.strip_prefix("data:") {
+                    data.push(value.strip_prefix(' ').unwrap_or(value));
+                }
+            }
+            // Keep-alive comments carry no data
+            if !data.is_empty() {
+                events.push((event, data.join("\n")));
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sse_parser_handles_split_chunks() {
+        let mut parser = SseParser::default();
+        assert!(parser.feed(b"event: stdout\ndata: epoch 1").is_empty());
+        let events = parser.feed(b"\n\n:\n\nevent: stderr\ndata: a\ndata: b\n\n");
+        assert_eq!(
+            events,
+            vec![("stdout".to_string(), "epoch 1".to_string()), ("stderr".to_string(), "a\nb".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024), "3.0 GB");
+    }
+}
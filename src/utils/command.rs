@@ -0,0 +1,34 @@
+use anyhow::{anyhow, Context, Result};
+use std::process::Command;
+
+/// Thin seam over `std::process::Command` so vendor tooling (nvidia-smi & co.)
+/// can be swapped for canned output in tests.
+pub trait CommandRunner: Send + Sync {
+    /// Runs `program` with `args` and returns its stdout.
+    /// Non-zero exit codes are reported as errors carrying stderr.
+    fn run(&self, program: &str, args: &[&str]) -> Result<String>;
+}
+
+/// Runs commands on the host for real.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemCommandRunner;
+
+impl CommandRunner for SystemCommandRunner {
+    fn run(&self, program: &str, args: &[&str]) -> Result<String> {
+        let output = Command::new(program)
+            .args(args)
+            .output()
+            .with_context(|| format!("Failed to execute {}", program))?;
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "{} exited with {}: {}",
+                program,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
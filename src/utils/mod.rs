@@ -1,4 +1,6 @@
 pub mod os;
 pub mod platform;
 pub use os::Platform;
-pub mod cli;
\ No newline at end of file
+pub mod cli;
+pub mod command;
+pub mod names;
\ No newline at end of file
@@ -0,0 +1,58 @@
+//! Docker object names built from tenant and project names.
+//!
+//! Docker only takes a few characters in names, so the rest have to go - and
+//! then "vision lab" and "vision-lab" would end up sharing a network or an
+//! image repository. A hash of the original name keeps them apart.
+
+use ring::digest::{digest, SHA256};
+
+/// Hex digits of the hash kept in a name
+const HASH_LEN: usize = 16;
+
+/// `raw` lowercased with everything but `[a-z0-9_.-]` turned into `-` and runs
+/// of separators squeezed to one `-`, then a hash of `raw` itself. Never
+/// empty, starts and ends alphanumeric - so it also fits an image repository.
+pub fn docker_safe_name(raw: &str) -> String {
+    let mut readable = String::new();
+    for c in raw.chars().map(|c| c.to_ascii_lowercase()) {
+        let c = if c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-') { c } else { '-' };
+        match readable.chars().last() {
+            Some(last) if !c.is_ascii_alphanumeric() && !last.is_ascii_alphanumeric() => {
+                readable.pop();
+                readable.push('-');
+            }
+            _ => readable.push(c),
+        }
+    }
+    let readable = readable.trim_matches(|c: char| !c.is_ascii_alphanumeric());
+    let hash: String = digest(&SHA256, raw.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let hash = &hash[..HASH_LEN];
+    if readable.is_empty() {
+        hash.to_string()
+    } else {
+        format!("{}-{}", readable, hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names_that_clean_up_alike_stay_apart() {
+        let spaced = docker_safe_name("vision lab");
+        let dashed = docker_safe_name("vision-lab");
+        assert!(spaced.starts_with("vision-lab-") && dashed.starts_with("vision-lab-"));
+        assert_ne!(spaced, dashed);
+        assert_ne!(docker_safe_name("Alice@Lab"), docker_safe_name("alice-lab"));
+
+        assert_eq!(docker_safe_name("vision"), docker_safe_name("vision"));
+        assert_eq!(docker_safe_name("@@").len(), HASH_LEN);
+        assert!(docker_safe_name("_x_").starts_with("x-"));
+        assert!(docker_safe_name("a._b").starts_with("a-b-"));
+    }
+}
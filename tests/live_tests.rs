@@ -11,7 +11,7 @@ async fn setup_docker() -> anyhow::Result<DockerManager> {
     
     // Clean up any leftover test VMs - like cleaning up after the party 🧹
     for container_id in manager.list_containers().await? {
-        let name = container_id.split('/').last().unwrap_or_default();
+        let name = container_id.split('/').next_back().unwrap_or_default();
         if name.starts_with("test-") {
             info!("Cleaning up old test container: {} - goodbye old friend! 👋", name);
             if manager.is_container_active(&container_id).await? {
@@ -33,6 +33,7 @@ async fn test_real_vm_creation() -> anyhow::Result<()> {
         image: "alpine".into(),
         name: "test-container-1".into(),
         gpu_id: None,
+        ..Default::default()
     };
 
     // Create and verify our new digital pet 🐕
@@ -68,6 +69,7 @@ async fn test_real_gpu_passthrough() -> anyhow::Result<()> {
             gpu_id: test_gpu.id.clone(),
             iommu_group: 42,
         }),
+        ..Default::default()
     };
 
     let container_id = docker.create_container(&config.image, &config.name).await?;
@@ -104,6 +106,7 @@ async fn test_real_metrics_collection() -> anyhow::Result<()> {
         image: "alpine".into(),
         name: "test-container-metrics".into(),
         gpu_id: None,
+        ..Default::default()
     };
 
     let container_id = docker.create_container(&config.image, &config.name).await?;
@@ -238,6 +241,7 @@ async fn test_cross_platform_vm_operations() -> anyhow::Result<()> {
         image: "alpine".into(),
         name: "cross-platform-test".into(),
         gpu_id: None,
+        ..Default::default()
     };
 
     // Basic VM operations
@@ -261,15 +265,18 @@ async fn test_cross_platform_vm_operations() -> anyhow::Result<()> {
 }
 
 // Test 1: Basic VM Creation
+#[allow(dead_code)]
 async fn create_basic_vm() -> ContainerConfig {
     ContainerConfig {
         image: "alpine".into(),
         name: "test-container-basic".into(),
         gpu_id: None,
+        ..Default::default()
     }
 }
 
 // Test 2: GPU Passthrough Test
+#[allow(dead_code)]
 async fn create_gpu_vm() -> ContainerConfig {
     ContainerConfig {
         image: "alpine".into(),
@@ -278,24 +285,29 @@ async fn create_gpu_vm() -> ContainerConfig {
             gpu_id: "0000:01:00.0".into(),
             iommu_group: 42,
         }),
+        ..Default::default()
     }
 }
 
 // Test 3: Big Scale VM
+#[allow(dead_code)]
 async fn create_large_vm() -> ContainerConfig {
     ContainerConfig {
         image: "alpine".into(),
         name: "test-container-large".into(),
         gpu_id: None,
+        ..Default::default()
     }
 }
 
 // Test 4: Edge Case - Minimum Resources
+#[allow(dead_code)]
 async fn create_minimal_vm() -> ContainerConfig {
     ContainerConfig {
         image: "alpine".into(),
         name: "test-container-minimal".into(),
         gpu_id: None,
+        ..Default::default()
     }
 }
 
@@ -311,5 +323,5 @@ async fn test_gpu_attachment() {
     assert!(result.is_ok());
 
     let metrics = metrics.get_metrics(&container_id).unwrap();
-    assert!(metrics.len() > 0);
+    assert!(!metrics.is_empty());
 }
\ No newline at end of file
@@ -0,0 +1,233 @@
+// Offline integration tests - DockerManager on the in-process FakeRuntime, no daemon needed.
+// The real-daemon versions live in live_tests.rs.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use gpu_share_vm_manager::core::docker_manager::{ContainerConfig, ContainerLabels, DockerManager, RestartPolicy};
+use gpu_share_vm_manager::core::fake_runtime::FakeRuntime;
+use gpu_share_vm_manager::core::lifecycle::{run_lifecycle_watcher, ContainerAction};
+use gpu_share_vm_manager::core::vm::VMStatus;
+use gpu_share_vm_manager::events::{Event, EventBus, EventKind};
+use gpu_share_vm_manager::gpu::container::{GpuAttacher, GpuAttachment};
+use gpu_share_vm_manager::gpu::device::{GPUConfig, GPUInfo};
+use gpu_share_vm_manager::gpu::virtual_gpu::GPUPool;
+use gpu_share_vm_manager::monitoring::MetricsCollector;
+use gpu_share_vm_manager::users::UserManager;
+use gpu_share_vm_manager::AsyncMutex;
+use tokio::sync::broadcast;
+
+fn setup() -> (FakeRuntime, DockerManager) {
+    let runtime = FakeRuntime::new().with_image("alpine");
+    let manager = DockerManager::with_runtime(Arc::new(runtime.clone()));
+    (runtime, manager)
+}
+
+fn leased(name: &str, lease_id: &str, restart_policy: RestartPolicy) -> ContainerConfig {
+    ContainerConfig {
+        image: "alpine".into(),
+        name: name.into(),
+        restart_policy,
+        labels: ContainerLabels {
+            owner: Some("alice".into()),
+            lease_id: Some(lease_id.into()),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+fn nvidia_attachment(gpu_id: &str) -> GpuAttachment {
+    let gpu = GPUInfo { id: gpu_id.into(), vendor: "NVIDIA".into(), ..Default::default() };
+    GpuAttacher::new().attachment(&gpu).unwrap()
+}
+
+/// Next lifecycle event on the bus for `container_id`, or an error after a second
+async fn next_lifecycle(events: &mut broadcast::Receiver<Event>, container_id: &str) -> Result<EventKind> {
+    tokio::time::timeout(Duration::from_secs(1), async {
+        loop {
+            let event = events.recv().await?;
+            if matches!(&event.kind, EventKind::ContainerLifecycle { container_id: id, .. } if id == container_id) {
+                return Ok(event.kind);
+            }
+        }
+    })
+    .await?
+}
+
+#[tokio::test]
+async fn test_missing_image_is_pulled_on_create() -> Result<()> {
+    let (runtime, manager) = setup();
+    assert!(!runtime.has_image("pytorch/pytorch:2.3"));
+
+    let config = ContainerConfig { image: "pytorch/pytorch:2.3".into(), name: "test-pull".into(), ..Default::default() };
+    let id = manager.create_container_with(&config, None).await?;
+    assert!(runtime.has_image("docker.io/pytorch/pytorch:2.3"));
+    assert!(manager.is_container_active(&id).await?);
+
+    // Same name twice is Docker's 409
+    assert!(manager.create_container_with(&config, None).await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_gpu_device_requests_reach_the_container() -> Result<()> {
+    let (_runtime, manager) = setup();
+    let config = ContainerConfig {
+        gpu_id: Some(GPUConfig { gpu_id: "GPU-0f2c8e5e".into(), iommu_group: 1 }),
+        ..leased("test-gpu", "0", RestartPolicy::No)
+    };
+    let id = manager.create_container_with(&config, Some(&nvidia_attachment("GPU-0f2c8e5e"))).await?;
+
+    let managed = manager.list_managed().await?;
+    assert_eq!(managed.len(), 1);
+    assert_eq!(managed[0].id, id);
+    assert_eq!(managed[0].owner.as_deref(), Some("alice"));
+    assert_eq!(managed[0].gpu_id.as_deref(), Some("GPU-0f2c8e5e"));
+    assert!(managed[0].gpu_attached);
+
+    // Detaching recreates the container without the device request
+    let outcome = manager.detach_gpu(&id, false).await?;
+    assert!(outcome.recreated);
+    let managed = manager.list_managed().await?;
+    assert_eq!(managed.len(), 1);
+    assert_eq!(managed[0].name, "test-gpu");
+    assert!(!managed[0].gpu_attached);
+    assert!(managed[0].running);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stats_feed_metrics() -> Result<()> {
+    let (runtime, manager) = setup();
+    let id = manager.create_container("alpine", "test-stats").await?;
+    runtime.set_usage(&id, 42.0, 512)?;
+
+    let stats = manager.inspect_container(&id).await?;
+    assert!((stats.cpu_usage - 42.0).abs() < 0.01, "{}", stats.cpu_usage);
+    assert_eq!(stats.memory_usage, 512.0);
+
+    let collector = MetricsCollector::new(1, 1);
+    let stats = collector.get_container_stats(&manager, "test-stats").await.expect("stats");
+    assert_eq!(stats.memory_usage, 512.0);
+
+    // Stopped containers use nothing
+    manager.stop_container(&id).await?;
+    assert_eq!(manager.inspect_container(&id).await?.memory_usage, 0.0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_lifecycle_events_release_leases() -> Result<()> {
+    let (runtime, manager) = setup();
+    let pool = Arc::new(AsyncMutex::new(GPUPool::new()));
+    pool.lock().await.allocate("alice", 0)?;
+    pool.lock().await.allocate("alice", 1)?;
+    let users = Arc::new(AsyncMutex::new(UserManager::new()));
+    let bus = Arc::new(EventBus::new());
+    let mut events = bus.subscribe();
+
+    tokio::spawn(run_lifecycle_watcher(manager.clone(), pool.clone(), users.clone(), bus.clone()));
+    // Let the watcher subscribe before anything happens
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // Crash without a restart policy: the lease goes back
+    let id = manager.create_container_with(&leased("test-crash", "0", RestartPolicy::No), None).await?;
+    assert!(matches!(next_lifecycle(&mut events, &id).await?, EventKind::ContainerLifecycle { action: ContainerAction::Create, .. }));
+    assert!(matches!(next_lifecycle(&mut events, &id).await?, EventKind::ContainerLifecycle { action: ContainerAction::Start, .. }));
+    runtime.exit(&id, 1)?;
+    match next_lifecycle(&mut events, &id).await? {
+        EventKind::ContainerLifecycle { action, status, exit_code, released_gpu, .. } => {
+            assert_eq!(action, ContainerAction::Die);
+            assert_eq!(status, VMStatus::Crashed);
+            assert_eq!(exit_code, Some(1));
+            assert_eq!(released_gpu, Some(0));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(pool.lock().await.gpus[&0].allocated_to.is_none());
+
+    // OOM kill under on-failure: Docker restarts it, the lease stays
+    let policy = RestartPolicy::OnFailure { max_retries: Some(3) };
+    let id = manager.create_container_with(&leased("test-oom", "1", policy), None).await?;
+    runtime.oom_kill(&id)?;
+    loop {
+        match next_lifecycle(&mut events, &id).await? {
+            EventKind::ContainerLifecycle { action: ContainerAction::Die, oom_killed, released_gpu, .. } => {
+                assert!(oom_killed);
+                assert_eq!(released_gpu, None);
+                break;
+            }
+            _ => continue,
+        }
+    }
+    let managed = manager.list_managed().await?;
+    let oom = managed.iter().find(|c| c.id == id).expect("still there");
+    assert!(oom.running);
+    assert_eq!(oom.restart_count, 1);
+    assert_eq!(pool.lock().await.gpus[&1].allocated_to.as_deref(), Some("alice"));
+    let mut users = users.lock().await;
+    let notifications = &users.get_user("alice")?.notifications;
+    assert!(notifications.iter().any(|n| n.message.contains("ran out of memory")));
+    drop(users);
+
+    // A manual stop is a clean exit on-failure won't restart: the lease goes back
+    manager.stop_container(&id).await?;
+    loop {
+        if let EventKind::ContainerLifecycle { action: ContainerAction::Die, status, released_gpu, .. } =
+            next_lifecycle(&mut events, &id).await?
+        {
+            assert_eq!(status, VMStatus::Stopped);
+            assert_eq!(released_gpu, Some(1));
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_gpu_swap_keeps_the_lease() -> Result<()> {
+    let (_runtime, manager) = setup();
+    let pool = Arc::new(AsyncMutex::new(GPUPool::new()));
+    pool.lock().await.allocate("alice", 0)?;
+    let users = Arc::new(AsyncMutex::new(UserManager::new()));
+    let bus = Arc::new(EventBus::new());
+    let mut events = bus.subscribe();
+
+    tokio::spawn(run_lifecycle_watcher(manager.clone(), pool.clone(), users.clone(), bus.clone()));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let config = ContainerConfig {
+        gpu_id: Some(GPUConfig { gpu_id: "GPU-0f2c8e5e".into(), iommu_group: 1 }),
+        ..leased("test-swap", "0", RestartPolicy::No)
+    };
+    let id = manager.create_container_with(&config, Some(&nvidia_attachment("GPU-0f2c8e5e"))).await?;
+
+    // Stop, rename, create, start, remove: none of it ends the lease
+    let outcome = manager.detach_gpu(&id, false).await?;
+    assert!(outcome.recreated);
+    loop {
+        match next_lifecycle(&mut events, &id).await? {
+            EventKind::ContainerLifecycle { action: ContainerAction::Destroy, released_gpu, .. } => {
+                assert_eq!(released_gpu, None);
+                break;
+            }
+            EventKind::ContainerLifecycle { released_gpu, .. } => assert_eq!(released_gpu, None),
+            _ => unreachable!(),
+        }
+    }
+    assert_eq!(pool.lock().await.gpus[&0].allocated_to.as_deref(), Some("alice"));
+
+    // The replacement exiting for real still gives it back
+    manager.stop_container(&outcome.container_id).await?;
+    loop {
+        if let EventKind::ContainerLifecycle { action: ContainerAction::Die, released_gpu, .. } =
+            next_lifecycle(&mut events, &outcome.container_id).await?
+        {
+            assert_eq!(released_gpu, Some(0));
+            break;
+        }
+    }
+    Ok(())
+}
@@ -25,6 +25,7 @@ fn test_vm_config() -> ContainerConfig {
         image: "alpine".into(),
         name: format!("test-container-{}", rng.gen::<u32>()),
         gpu_id: None,
+        ..Default::default()
     }
 }
 
@@ -98,6 +99,7 @@ async fn test_container_creation() {
         image: "alpine".into(),
         name: "test-container-1".into(),
         gpu_id: None,
+        ..Default::default()
     };
 
     let container_id = docker.0.create_container(&config.image, &config.name).await.unwrap();